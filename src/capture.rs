@@ -0,0 +1,292 @@
+//! Packet-level captures of the messages exchanged between server and client,
+//! enabled with the `inspect` feature.
+//!
+//! Tests and debug builds record the messages of a session into a `Capture`. Users attach the
+//! written capture file to desync reports, `Capture::replay` turns it back into the messages
+//! the client received, which can be fed to a `ClientWorld` with `ClientWorld::inject`.
+
+use std::fmt::{self, Display, Formatter};
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use net_sync::{re_exports::bincode, synchronisation::CommandFrame, transport};
+
+use crate::{error::ErrorKind, protocol::ServerMessage, world::default_options};
+
+/// The version of the capture file format, see `Capture::to_bytes`.
+pub const CAPTURE_VERSION: u16 = 1;
+
+/// The endpoint a captured message was sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    ServerToClient,
+    ClientToServer,
+}
+
+/// The type of a captured message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MessageKind {
+    InitialStateSync,
+    StateUpdate,
+    Command,
+    Message,
+}
+
+/// A captured message with its encoded transport envelope as payload.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CapturedPacket {
+    pub direction: Direction,
+    pub kind: MessageKind,
+    /// The command frame of the endpoint that recorded the message.
+    pub command_frame: CommandFrame,
+    pub payload: Vec<u8>,
+}
+
+impl CapturedPacket {
+    /// The size of the payload in bytes.
+    pub fn size(&self) -> usize {
+        self.payload.len()
+    }
+}
+
+/// A structured log of the messages exchanged during a session, in the order they were recorded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Capture {
+    version: u16,
+    packets: Vec<CapturedPacket>,
+}
+
+impl Capture {
+    pub fn new() -> Capture {
+        Capture {
+            version: CAPTURE_VERSION,
+            packets: Vec::new(),
+        }
+    }
+
+    /// Records a message the server sent to the client.
+    pub fn record_server_to_client<M: Serialize, C: Serialize>(
+        &mut self,
+        command_frame: CommandFrame,
+        message: &transport::ServerToClientMessage<ServerMessage<M, C>>,
+    ) -> Result<(), ErrorKind> {
+        let kind = match message {
+            transport::ServerToClientMessage::InitialStateSync(_) => MessageKind::InitialStateSync,
+            transport::ServerToClientMessage::StateUpdate(_) => MessageKind::StateUpdate,
+            transport::ServerToClientMessage::Message(ServerMessage::SerializedStateUpdate(_)) => {
+                MessageKind::StateUpdate
+            }
+            transport::ServerToClientMessage::Message(_) => MessageKind::Message,
+        };
+
+        self.record(Direction::ServerToClient, kind, command_frame, message)
+    }
+
+    /// Records a message the client sent to the server.
+    pub fn record_client_to_server<M: Serialize, C: Serialize>(
+        &mut self,
+        command_frame: CommandFrame,
+        message: &transport::ClientToServerMessage<M, C>,
+    ) -> Result<(), ErrorKind> {
+        let kind = match message {
+            transport::ClientToServerMessage::Command(..) => MessageKind::Command,
+            _ => MessageKind::Message,
+        };
+
+        self.record(Direction::ClientToServer, kind, command_frame, message)
+    }
+
+    fn record(
+        &mut self,
+        direction: Direction,
+        kind: MessageKind,
+        command_frame: CommandFrame,
+        message: &impl Serialize,
+    ) -> Result<(), ErrorKind> {
+        let payload = default_options()
+            .serialize(message)
+            .map_err(|e| ErrorKind::SerializationError(e.to_string()))?;
+
+        self.packets.push(CapturedPacket {
+            direction,
+            kind,
+            command_frame,
+            payload,
+        });
+
+        Ok(())
+    }
+
+    pub fn packets(&self) -> &[CapturedPacket] {
+        &self.packets
+    }
+
+    /// Decodes the messages the client received, in order.
+    pub fn replay<M: DeserializeOwned, C: DeserializeOwned>(
+        &self,
+    ) -> Result<Vec<transport::ServerToClientMessage<ServerMessage<M, C>>>, ErrorKind> {
+        self.packets
+            .iter()
+            .filter(|packet| packet.direction == Direction::ServerToClient)
+            .map(|packet| {
+                default_options()
+                    .deserialize(&packet.payload)
+                    .map_err(|e| ErrorKind::SerializationError(e.to_string()))
+            })
+            .collect()
+    }
+
+    /// Cuts a fast-forward for a late joining spectator out of a server recording: the last
+    /// initial sync at or before `since` as keyframe, and the state updates after it.
+    ///
+    /// Injected into a fresh `ClientWorld` it shows the action since `since` before going live.
+    /// Returns `None` if there is no keyframe at or before the command frame.
+    pub fn fast_forward(&self, since: CommandFrame) -> Option<Capture> {
+        let keyframe = self.packets.iter().rposition(|packet| {
+            packet.direction == Direction::ServerToClient
+                && packet.kind == MessageKind::InitialStateSync
+                && packet.command_frame <= since
+        })?;
+
+        let packets = self.packets[keyframe..]
+            .iter()
+            .enumerate()
+            .filter(|(index, packet)| {
+                *index == 0
+                    || (packet.direction == Direction::ServerToClient
+                        && packet.kind == MessageKind::StateUpdate)
+            })
+            .map(|(_, packet)| packet.clone())
+            .collect();
+
+        Some(Capture {
+            version: CAPTURE_VERSION,
+            packets,
+        })
+    }
+
+    /// Encodes the capture file.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ErrorKind> {
+        default_options()
+            .serialize(self)
+            .map_err(|e| ErrorKind::SerializationError(e.to_string()))
+    }
+
+    /// Decodes a capture file written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Capture, ErrorKind> {
+        let capture = default_options()
+            .deserialize::<Capture>(bytes)
+            .map_err(|e| ErrorKind::SerializationError(e.to_string()))?;
+
+        if capture.version != CAPTURE_VERSION {
+            return Err(ErrorKind::SerializationError(format!(
+                "Capture version {} is not supported, expected {}",
+                capture.version, CAPTURE_VERSION
+            )));
+        }
+
+        Ok(capture)
+    }
+}
+
+impl Default for Capture {
+    fn default() -> Self {
+        Capture::new()
+    }
+}
+
+impl Display for Capture {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for packet in self.packets.iter() {
+            writeln!(
+                f,
+                "frame {} {:?} {:?} {} bytes",
+                packet.command_frame,
+                packet.direction,
+                packet.kind,
+                packet.size()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use net_sync::{synchronisation::WorldState, transport};
+
+    use crate::{
+        capture::{Capture, Direction, MessageKind},
+        protocol::ServerMessage,
+    };
+
+    #[test]
+    fn capture_round_trip_test() {
+        let mut capture = Capture::new();
+        capture
+            .record_server_to_client::<u32, u32>(
+                4,
+                &transport::ServerToClientMessage::Message(ServerMessage::User(7)),
+            )
+            .unwrap();
+        capture
+            .record_client_to_server::<u32, u32>(
+                5,
+                &transport::ClientToServerMessage::Command(5, 3),
+            )
+            .unwrap();
+
+        let capture = Capture::from_bytes(&capture.to_bytes().unwrap()).unwrap();
+
+        assert_eq!(capture.packets().len(), 2);
+        assert_eq!(capture.packets()[1].direction, Direction::ClientToServer);
+        assert_eq!(capture.packets()[1].kind, MessageKind::Command);
+
+        let replayed = capture.replay::<u32, u32>().unwrap();
+        assert_eq!(replayed.len(), 1);
+        assert!(matches!(
+            replayed[0],
+            transport::ServerToClientMessage::Message(ServerMessage::User(7))
+        ));
+    }
+
+    #[test]
+    fn fast_forward_starts_at_a_keyframe_test() {
+        let mut capture = Capture::new();
+        for frame in 0..10 {
+            if frame % 4 == 0 {
+                capture
+                    .record_server_to_client::<u32, u32>(
+                        frame,
+                        &transport::ServerToClientMessage::InitialStateSync(vec![frame as u8]),
+                    )
+                    .unwrap();
+            }
+            capture
+                .record_server_to_client::<u32, u32>(
+                    frame,
+                    &transport::ServerToClientMessage::StateUpdate(WorldState::new(frame)),
+                )
+                .unwrap();
+            capture
+                .record_server_to_client::<u32, u32>(
+                    frame,
+                    &transport::ServerToClientMessage::Message(ServerMessage::User(7)),
+                )
+                .unwrap();
+        }
+
+        // The keyframe of frame 4 and the updates of frames 4 to 9.
+        let fast_forward = capture.fast_forward(6).unwrap();
+        assert_eq!(fast_forward.packets()[0].kind, MessageKind::InitialStateSync);
+        assert_eq!(fast_forward.packets()[0].command_frame, 4);
+        assert_eq!(fast_forward.packets().len(), 7);
+        assert!(fast_forward.packets()[1..]
+            .iter()
+            .all(|packet| packet.kind == MessageKind::StateUpdate));
+
+        assert!(Capture::new().fast_forward(6).is_none());
+    }
+}
@@ -0,0 +1,299 @@
+//! Chat between the players, layered on the user-message channel.
+//!
+//! Game code embeds `ChatMessage` in its own client and server message types, e.g. a
+//! `Chat(ChatMessage)` variant. The server routes a received chat message with
+//! `ServerWorld::send_chat`, which applies the rate limit and the filters of the `ChatRelay` and
+//! sends it to the recipients of its channel. Clients deliver the received chat messages into
+//! their `ChatInbox`.
+//!
+//! Enabled with the `chat` feature.
+
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use net_sync::{synchronisation::CommandFrame, transport::ClientId};
+
+/// The recipients of a chat message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ChatChannel {
+    /// All connected clients.
+    Global,
+    /// The clients of a team, see `ChatRelay::set_team`.
+    Team(u32),
+    /// A single client.
+    Whisper(ClientId),
+}
+
+/// A chat message, the server sets the sender before it is delivered.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub channel: ChatChannel,
+    pub sender: Option<ClientId>,
+    pub text: String,
+}
+
+impl ChatMessage {
+    pub fn new(channel: ChatChannel, text: impl Into<String>) -> ChatMessage {
+        ChatMessage {
+            channel,
+            sender: None,
+            text: text.into(),
+        }
+    }
+}
+
+/// Why the server did not deliver a chat message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatRejection {
+    /// The sender exceeded the rate limit of the `ChatRelay`.
+    RateLimited,
+    /// A filter of the `ChatRelay` rejected the message.
+    Filtered,
+    /// The sender is not in the team of the channel.
+    NotInTeam,
+    /// The whispered client is not connected.
+    UnknownRecipient,
+}
+
+/// Server-side hook that inspects every chat message before it is delivered, e.g. to mute a
+/// client or to mask words. The message may be rewritten, returns whether it is delivered.
+pub trait ChatFilter: Send + Sync + 'static {
+    fn filter(&self, sender: ClientId, message: &mut ChatMessage) -> bool;
+}
+
+impl<F> ChatFilter for F
+where
+    F: Fn(ClientId, &mut ChatMessage) -> bool + Send + Sync + 'static,
+{
+    fn filter(&self, sender: ClientId, message: &mut ChatMessage) -> bool {
+        self(sender, message)
+    }
+}
+
+/// Server resource that routes the chat messages, enabled with `ServerWorldBuilder::with_chat`.
+///
+/// A client may send `messages` chat messages within `frames` command frames, messages over the
+/// limit are rejected. Rejected messages are not sent back, game code may notify the sender.
+pub struct ChatRelay {
+    messages: usize,
+    frames: CommandFrame,
+    sent: HashMap<ClientId, VecDeque<CommandFrame>>,
+    teams: HashMap<ClientId, u32>,
+    filters: Vec<Box<dyn ChatFilter>>,
+}
+
+impl ChatRelay {
+    pub fn new(messages: usize, frames: CommandFrame) -> ChatRelay {
+        ChatRelay {
+            messages,
+            frames,
+            sent: HashMap::new(),
+            teams: HashMap::new(),
+            filters: Vec::new(),
+        }
+    }
+
+    /// Adds a filter, the filters run in the order they were added.
+    pub fn with_filter(mut self, filter: impl ChatFilter) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Puts the client in a team, it only reads and writes the `ChatChannel::Team` of its team.
+    pub fn set_team(&mut self, client: ClientId, team: u32) {
+        self.teams.insert(client, team);
+    }
+
+    pub fn team(&self, client: ClientId) -> Option<u32> {
+        self.teams.get(&client).copied()
+    }
+
+    /// Validates, rate limits and filters the message of the sender at the command frame.
+    ///
+    /// Sets the sender of the message and returns its recipients among the connected clients.
+    /// The sender of a whisper receives it as well, so both see the same conversation.
+    pub fn route(
+        &mut self,
+        sender: ClientId,
+        message: &mut ChatMessage,
+        command_frame: CommandFrame,
+        connected: &[ClientId],
+    ) -> Result<Vec<ClientId>, ChatRejection> {
+        let recipients = match message.channel {
+            ChatChannel::Global => connected.to_vec(),
+            ChatChannel::Team(team) => {
+                if self.team(sender) != Some(team) {
+                    return Err(ChatRejection::NotInTeam);
+                }
+
+                connected
+                    .iter()
+                    .copied()
+                    .filter(|client| self.team(*client) == Some(team))
+                    .collect()
+            }
+            ChatChannel::Whisper(recipient) => {
+                if !connected.contains(&recipient) {
+                    return Err(ChatRejection::UnknownRecipient);
+                }
+
+                let mut recipients = vec![recipient];
+                if recipient != sender {
+                    recipients.push(sender);
+                }
+                recipients
+            }
+        };
+
+        let sent = self.sent.entry(sender).or_insert_with(VecDeque::new);
+        while sent
+            .front()
+            .map_or(false, |frame| command_frame.saturating_sub(*frame) >= self.frames)
+        {
+            sent.pop_front();
+        }
+
+        if sent.len() >= self.messages {
+            return Err(ChatRejection::RateLimited);
+        }
+        sent.push_back(command_frame);
+
+        message.sender = Some(sender);
+
+        if !self.filters.iter().all(|filter| filter.filter(sender, message)) {
+            return Err(ChatRejection::Filtered);
+        }
+
+        Ok(recipients)
+    }
+
+    /// Forgets the team and the rate limit of a disconnected client.
+    pub fn remove_client(&mut self, client: ClientId) {
+        self.sent.remove(&client);
+        self.teams.remove(&client);
+    }
+
+    /// Moves the team and the rate limit to another client id, see `ServerWorld::migrate_client`.
+    pub fn rebind(&mut self, from: ClientId, to: ClientId) {
+        if let Some(sent) = self.sent.remove(&from) {
+            self.sent.insert(to, sent);
+        }
+        if let Some(team) = self.teams.remove(&from) {
+            self.teams.insert(to, team);
+        }
+    }
+}
+
+/// Client resource with the received chat messages, enabled with
+/// `ClientWorldBuilder::with_chat_inbox`.
+///
+/// Game code delivers the chat messages it receives from the server, the oldest messages are
+/// dropped when the inbox is full.
+#[derive(Debug, Clone)]
+pub struct ChatInbox {
+    capacity: usize,
+    messages: VecDeque<ChatMessage>,
+    unread: usize,
+}
+
+impl ChatInbox {
+    pub fn new(capacity: usize) -> ChatInbox {
+        ChatInbox {
+            capacity,
+            messages: VecDeque::with_capacity(capacity),
+            unread: 0,
+        }
+    }
+
+    pub fn deliver(&mut self, message: ChatMessage) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.messages.len() == self.capacity {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(message);
+        self.unread = (self.unread + 1).min(self.capacity);
+    }
+
+    /// The received messages, oldest first.
+    pub fn messages(&self) -> impl Iterator<Item = &ChatMessage> + '_ {
+        self.messages.iter()
+    }
+
+    /// The messages of a channel, oldest first.
+    pub fn channel(&self, channel: ChatChannel) -> impl Iterator<Item = &ChatMessage> + '_ {
+        self.messages.iter().filter(move |x| x.channel == channel)
+    }
+
+    /// Takes the messages that were delivered since the last call, oldest first.
+    pub fn read_unread(&mut self) -> impl Iterator<Item = &ChatMessage> + '_ {
+        let skip = self.messages.len() - self.unread;
+        self.unread = 0;
+        self.messages.iter().skip(skip)
+    }
+
+    pub fn unread(&self) -> usize {
+        self.unread
+    }
+
+    pub fn clear(&mut self) {
+        self.messages.clear();
+        self.unread = 0;
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::chat::{ChatChannel, ChatInbox, ChatMessage, ChatRejection, ChatRelay};
+
+    #[test]
+    fn messages_are_routed_limited_and_filtered_test() {
+        let mut relay = ChatRelay::new(2, 10).with_filter(|_, message: &mut ChatMessage| {
+            message.text = message.text.replace("darn", "****");
+            !message.text.is_empty()
+        });
+        relay.set_team(1, 7);
+        relay.set_team(2, 7);
+        let connected = [1, 2, 3];
+
+        let mut message = ChatMessage::new(ChatChannel::Team(7), "darn it");
+        assert_eq!(relay.route(1, &mut message, 0, &connected), Ok(vec![1, 2]));
+        assert_eq!(message.sender, Some(1));
+        assert_eq!(message.text, "**** it");
+
+        let mut message = ChatMessage::new(ChatChannel::Team(7), "hi");
+        assert_eq!(
+            relay.route(3, &mut message, 0, &connected),
+            Err(ChatRejection::NotInTeam)
+        );
+
+        let mut message = ChatMessage::new(ChatChannel::Whisper(3), "hi");
+        assert_eq!(relay.route(1, &mut message, 1, &connected), Ok(vec![3, 1]));
+
+        // Two messages within ten frames.
+        let mut message = ChatMessage::new(ChatChannel::Global, "hi");
+        assert_eq!(
+            relay.route(1, &mut message, 9, &connected),
+            Err(ChatRejection::RateLimited)
+        );
+        assert_eq!(relay.route(1, &mut message, 10, &connected), Ok(vec![1, 2, 3]));
+
+        let mut message = ChatMessage::new(ChatChannel::Global, "");
+        assert_eq!(
+            relay.route(2, &mut message, 10, &connected),
+            Err(ChatRejection::Filtered)
+        );
+
+        let mut inbox = ChatInbox::new(2);
+        inbox.deliver(ChatMessage::new(ChatChannel::Global, "a"));
+        inbox.deliver(ChatMessage::new(ChatChannel::Team(7), "b"));
+        inbox.deliver(ChatMessage::new(ChatChannel::Global, "c"));
+        assert_eq!(inbox.unread(), 2);
+        assert_eq!(inbox.read_unread().count(), 2);
+        assert_eq!(inbox.unread(), 0);
+        assert_eq!(inbox.channel(ChatChannel::Global).count(), 1);
+    }
+}
@@ -2,13 +2,36 @@
 
 use std::ops::{Deref, DerefMut};
 
+use legion::{
+    query::{IntoQuery, Read},
+    Entity, World,
+};
 use serde::{Deserialize, Serialize};
 
 use net_sync::{
     track_attr::serde_diff::{self, *},
+    transport::ClientId,
     uid::Uid,
 };
 
+use crate::protocol::{LocalPlayer, RegionId};
+
+pub use self::{
+    dynamic::{type_uid, DynamicComponent},
+    net::{
+        serialize_sorted, Bits12, Bits16, Bits24, Bits4, Bits8, FixedBits, FixedRepr,
+        NetDuration, NetFixed, NetInstant, NetworkEntity, Replace, SortedMap,
+    },
+};
+
+#[cfg(feature = "spatial")]
+pub use self::spatial::{Lerp, Position2, Position3, Rotation2, Rotation3, Velocity2, Velocity3};
+
+mod dynamic;
+mod net;
+#[cfg(feature = "spatial")]
+mod spatial;
+
 /// A component with a random `UUID`.
 ///
 /// If modifications are serialized we need to know from which component they came.
@@ -50,3 +73,190 @@ impl Default for UidComponent {
 }
 
 crate::register_component_type!(UidComponent);
+
+/// The name of an entity for tools and logs, enabled with the `debug-names` feature.
+///
+/// It is replicated like the other components, the server and the clients refer to the entity
+/// by the same name, e.g. with `ServerWorld::entity_by_name` and in the `ReplicationStatus`.
+/// Names do not have to be unique, lookups return the first match.
+#[cfg(feature = "debug-names")]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Serialize, Deserialize, SerdeDiff)]
+pub struct DebugName {
+    name: String,
+}
+
+#[cfg(feature = "debug-names")]
+impl DebugName {
+    pub fn new(name: impl Into<String>) -> DebugName {
+        DebugName { name: name.into() }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[cfg(feature = "debug-names")]
+crate::register_component_type!(DebugName);
+
+/// Correlates a server spawned entity with the entity the client predicted for it.
+///
+/// The client sends the id returned by `ClientWorld::spawn_ephemeral_predicted` in its command,
+/// the server adds this component with that id to the entity it spawns for the command.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Serialize, Deserialize, SerdeDiff,
+)]
+pub struct CorrelationId {
+    id: u32,
+}
+
+impl CorrelationId {
+    pub fn new(id: u32) -> CorrelationId {
+        CorrelationId { id }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+crate::register_component_type!(CorrelationId);
+
+/// Control component that suspends the prediction of an entity, e.g. during a cutscene.
+///
+/// The server adds it to the entities it moves with scripted motion. While it is present,
+/// the client does not compare its predictions of the entity and does not resimulate it,
+/// the server changes are applied as they are. Prediction and interpolation systems leave the
+/// entity alone with a `!component::<Frozen>()` filter.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Serialize, Deserialize, SerdeDiff,
+)]
+pub struct Frozen {
+    id: u32,
+}
+
+impl Frozen {
+    /// Freezes the entity with an application defined id, e.g. of the cutscene.
+    pub fn new(id: u32) -> Frozen {
+        Frozen { id }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+crate::register_component_type!(Frozen);
+
+/// The streaming region of an entity, see `RegionStreaming`.
+///
+/// Clients that declared their resident regions only receive the entities of those regions,
+/// entities without a region are replicated to every client. Moving an entity to another
+/// region removes it from the clients that do not have the new region resident.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Serialize, Deserialize, SerdeDiff,
+)]
+pub struct Region {
+    id: RegionId,
+}
+
+impl Region {
+    pub fn new(id: RegionId) -> Region {
+        Region { id }
+    }
+
+    pub fn id(&self) -> RegionId {
+        self.id
+    }
+}
+
+crate::register_component_type!(Region);
+
+/// The local player that owns the entity, kept in sync with the `PlayerOwnership` of the server
+/// when it enforces `OwnershipRules`.
+///
+/// It is replicated like the other components, so the clients know which entities their
+/// commands may affect.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Serialize, Deserialize, SerdeDiff,
+)]
+pub struct Owner {
+    client: ClientId,
+    player: LocalPlayer,
+}
+
+impl Owner {
+    pub fn new(client: ClientId, player: LocalPlayer) -> Owner {
+        Owner { client, player }
+    }
+
+    pub fn client(&self) -> ClientId {
+        self.client
+    }
+
+    pub fn player(&self) -> LocalPlayer {
+        self.player
+    }
+}
+
+crate::register_component_type!(Owner);
+
+/// Replicated bitmask of application defined tags, e.g. `DEBUG = 1 << 0`.
+///
+/// The tags are a lightweight alternative to marker components for coarse filtering,
+/// they are sent with the entity when it is inserted. Clients filter on them locally with
+/// `ClientWorld::tagged` and `ClientWorld::without_tags`, servers use `tagged` to fill the
+/// `InterestScopes` of clients, e.g. to only replicate debug entities to developers.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Serialize, Deserialize, SerdeDiff,
+)]
+pub struct EntityTags {
+    mask: u32,
+}
+
+impl EntityTags {
+    pub fn new(mask: u32) -> EntityTags {
+        EntityTags { mask }
+    }
+
+    pub fn mask(&self) -> u32 {
+        self.mask
+    }
+
+    /// Whether all tags of the mask are set.
+    pub fn contains(&self, mask: u32) -> bool {
+        self.mask & mask == mask
+    }
+
+    /// Whether any tag of the mask is set.
+    pub fn intersects(&self, mask: u32) -> bool {
+        self.mask & mask != 0
+    }
+
+    pub fn insert(&mut self, mask: u32) {
+        self.mask |= mask;
+    }
+
+    pub fn remove(&mut self, mask: u32) {
+        self.mask &= !mask;
+    }
+
+    /// Returns the entities that have any tag of the mask.
+    pub fn tagged(world: &World, mask: u32) -> Vec<Entity> {
+        <(Entity, Read<EntityTags>)>::query()
+            .iter(world)
+            .filter(|(_, tags)| tags.intersects(mask))
+            .map(|(entity, _)| *entity)
+            .collect()
+    }
+}
+
+crate::register_component_type!(EntityTags);
+
+/// Transient marker on the entities that were touched by the last applied server state update.
+///
+/// The client world adds it when applying a state update,
+/// it is removed after the user systems ran in the next tick.
+/// It is not synchronized.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReplicatedThisFrame;
@@ -0,0 +1,130 @@
+use std::fmt;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use net_sync::{
+    track_attr::serde_diff::{self, *},
+    uid::Uid,
+};
+
+/// A component without a compile-time rust type.
+///
+/// Scripting layers (Lua/JS mods) can use this component to replicate data by name.
+/// The value is replaced as a whole when it changes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, SerdeDiff)]
+pub struct DynamicComponent {
+    type_name: String,
+    #[serde_diff(opaque)]
+    value: DynamicValue,
+}
+
+impl DynamicComponent {
+    pub fn new(type_name: impl Into<String>, value: serde_json::Value) -> DynamicComponent {
+        DynamicComponent {
+            type_name: type_name.into(),
+            value: DynamicValue(value),
+        }
+    }
+
+    /// Returns the name of the scripted type.
+    pub fn type_name(&self) -> &str {
+        &self.type_name
+    }
+
+    /// Returns the uid of the scripted type, see [type_uid](fn.type_uid.html).
+    pub fn type_uid(&self) -> Uid {
+        type_uid(&self.type_name)
+    }
+
+    pub fn value(&self) -> &serde_json::Value {
+        &self.value.0
+    }
+
+    pub fn value_mut(&mut self) -> &mut serde_json::Value {
+        &mut self.value.0
+    }
+}
+
+impl Default for DynamicComponent {
+    fn default() -> Self {
+        DynamicComponent::new(String::new(), serde_json::Value::Null)
+    }
+}
+
+crate::register_component_type!(DynamicComponent);
+
+/// Hashes the name of a dynamic type into a uid.
+///
+/// FNV-1a is used because it is stable across platforms, runs and compiler versions.
+pub fn type_uid(type_name: &str) -> Uid {
+    let mut hash: u32 = 0x811c_9dc5;
+
+    for byte in type_name.as_bytes() {
+        hash ^= *byte as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+
+    hash as Uid
+}
+
+/// Json value which is (de)serialized as a json string.
+///
+/// Binary formats such as bincode can not deserialize self describing values directly.
+#[derive(Debug, Clone, PartialEq)]
+struct DynamicValue(serde_json::Value);
+
+impl Serialize for DynamicValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for DynamicValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct JsonStringVisitor;
+
+        impl<'de> de::Visitor<'de> for JsonStringVisitor {
+            type Value = DynamicValue;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a json encoded string")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                serde_json::from_str(value)
+                    .map(DynamicValue)
+                    .map_err(de::Error::custom)
+            }
+        }
+
+        deserializer.deserialize_str(JsonStringVisitor)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use net_sync::re_exports::bincode;
+
+    use crate::components::{dynamic::type_uid, DynamicComponent};
+
+    #[test]
+    fn type_uid_is_stable_test() {
+        assert_eq!(type_uid(""), 0x811c_9dc5);
+        assert_eq!(type_uid("a"), 0xe40c_292c);
+        assert_eq!(
+            DynamicComponent::new("mod::Health", serde_json::json!(10)).type_uid(),
+            type_uid("mod::Health")
+        );
+    }
+
+    #[test]
+    fn dynamic_component_binary_roundtrip_test() {
+        let component =
+            DynamicComponent::new("mod::Inventory", serde_json::json!({ "slots": [1, 2, 3] }));
+
+        let bytes = bincode::serialize(&component).unwrap();
+        let deserialized: DynamicComponent = bincode::deserialize(&bytes).unwrap();
+
+        assert_eq!(component, deserialized);
+    }
+}
@@ -0,0 +1,529 @@
+//! Networked primitives that can be used as fields of synchronized components.
+//!
+//! Floats and `std::time` types do not have a stable representation across platforms,
+//! these wrappers have one and implement `SerdeDiff`, so they can be used as is:
+//!
+//! ```ignore
+//! #[sync]
+//! #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+//! pub struct Ability {
+//!     cooldown: NetDuration,
+//!     ready_at: NetInstant,
+//!     range: NetFixed<i32, Bits16>,
+//! }
+//! ```
+//!
+//! `Vec`, fixed-size array and `HashMap` fields are diffed element-wise: only the changed,
+//! added and removed elements are sent. Collections that are rewritten as a whole, e.g. a sorted
+//! list, are cheaper to replace on change. Mark the field with `#[serde_diff(opaque)]`,
+//! which requires `PartialEq`, or wrap it in `Replace`:
+//!
+//! ```ignore
+//! #[sync]
+//! #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+//! pub struct Inventory {
+//!     slots: Vec<Item>,
+//!     hotbar: [u32; 8],
+//!     ranking: Replace<Vec<Uid>>,
+//! }
+//! ```
+//!
+//! A `HashMap` serializes and diffs its entries in the random order of its hasher, so equal
+//! maps can have different bytes. Predictions and components with `DiffPolicy::Whole` are
+//! compared by their bytes, so their maps should be a `SortedMap`. `serialize_sorted` sorts a
+//! plain `HashMap` field that is only serialized whole:
+//!
+//! ```ignore
+//! #[sync]
+//! #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+//! pub struct Cooldowns {
+//!     abilities: SortedMap<u32, NetInstant>,
+//! }
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    hash::Hash,
+    iter::FromIterator,
+    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+    time::Duration,
+};
+
+use serde::{de, ser::SerializeSeq, Deserialize, Deserializer, Serialize, Serializer};
+
+use net_sync::{
+    synchronisation::CommandFrame,
+    track_attr::serde_diff::{self, *},
+    uid::Uid,
+};
+
+/// A duration with microsecond precision.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+    SerdeDiff,
+)]
+pub struct NetDuration {
+    micros: u64,
+}
+
+impl NetDuration {
+    pub fn from_micros(micros: u64) -> NetDuration {
+        NetDuration { micros }
+    }
+
+    pub fn from_millis(millis: u64) -> NetDuration {
+        NetDuration::from_micros(millis * 1000)
+    }
+
+    pub fn as_micros(&self) -> u64 {
+        self.micros
+    }
+
+    pub fn as_duration(&self) -> Duration {
+        Duration::from_micros(self.micros)
+    }
+}
+
+impl From<Duration> for NetDuration {
+    fn from(duration: Duration) -> Self {
+        NetDuration::from_micros(duration.as_micros() as u64)
+    }
+}
+
+impl From<NetDuration> for Duration {
+    fn from(duration: NetDuration) -> Self {
+        duration.as_duration()
+    }
+}
+
+/// A point in time relative to the start of a command frame.
+///
+/// Server and client agree on command frames but not on wall clock time,
+/// so instants are sent as a command frame with an offset into that frame.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+    SerdeDiff,
+)]
+pub struct NetInstant {
+    command_frame: CommandFrame,
+    offset: NetDuration,
+}
+
+impl NetInstant {
+    pub fn new(command_frame: CommandFrame, offset: NetDuration) -> NetInstant {
+        NetInstant {
+            command_frame,
+            offset,
+        }
+    }
+
+    /// The start of the given command frame.
+    pub fn at_frame(command_frame: CommandFrame) -> NetInstant {
+        NetInstant::new(command_frame, NetDuration::default())
+    }
+
+    pub fn command_frame(&self) -> CommandFrame {
+        self.command_frame
+    }
+
+    pub fn offset(&self) -> NetDuration {
+        self.offset
+    }
+
+    /// Returns the time elapsed since `earlier`, with the given duration of a command frame.
+    ///
+    /// Returns a zero duration if `earlier` is later than this instant.
+    pub fn duration_since(&self, earlier: NetInstant, frame_duration: Duration) -> Duration {
+        let to_micros = |instant: &NetInstant| {
+            instant.command_frame as u128 * frame_duration.as_micros()
+                + instant.offset.micros as u128
+        };
+
+        let micros = to_micros(self).saturating_sub(to_micros(&earlier));
+        Duration::from_micros(micros as u64)
+    }
+}
+
+/// The integer type that stores the value of a `NetFixed`.
+pub trait FixedRepr:
+    Debug
+    + Copy
+    + Default
+    + PartialEq
+    + Serialize
+    + for<'de> Deserialize<'de>
+    + Send
+    + Sync
+    + 'static
+{
+    fn to_f64(self) -> f64;
+
+    /// Converts with rounding to the nearest value, saturating at the bounds of the type.
+    fn from_f64(value: f64) -> Self;
+}
+
+macro_rules! impl_fixed_repr {
+    ($($repr:ty),*) => {
+        $(
+            impl FixedRepr for $repr {
+                fn to_f64(self) -> f64 {
+                    self as f64
+                }
+
+                fn from_f64(value: f64) -> Self {
+                    // Float to integer casts saturate.
+                    value.round() as $repr
+                }
+            }
+        )*
+    };
+}
+
+impl_fixed_repr!(i16, i32, i64);
+
+/// The number of fractional bits of a `NetFixed`.
+pub trait FixedBits: Debug + Copy + Default + PartialEq + Send + Sync + 'static {
+    const BITS: u32;
+}
+
+macro_rules! fixed_bits {
+    ($($name:ident => $bits:expr, $doc:literal);*) => {
+        $(
+            #[doc = $doc]
+            #[derive(Debug, Clone, Copy, Default, PartialEq)]
+            pub struct $name;
+
+            impl FixedBits for $name {
+                const BITS: u32 = $bits;
+            }
+        )*
+    };
+}
+
+fixed_bits!(
+    Bits4 => 4, "4 fractional bits, a precision of 1/16.";
+    Bits8 => 8, "8 fractional bits, a precision of 1/256.";
+    Bits12 => 12, "12 fractional bits, a precision of 1/4096.";
+    Bits16 => 16, "16 fractional bits, a precision of 1/65536.";
+    Bits24 => 24, "24 fractional bits, a precision of 1/16777216."
+);
+
+/// A fixed-point number stored in `I` with `F` fractional bits.
+///
+/// Every platform decodes exactly the same value, float arithmetic is not deterministic.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, SerdeDiff)]
+pub struct NetFixed<I: FixedRepr, F: FixedBits> {
+    #[serde_diff(opaque)]
+    raw: I,
+    #[serde(skip)]
+    #[serde_diff(skip)]
+    bits: PhantomData<F>,
+}
+
+impl<I: FixedRepr, F: FixedBits> NetFixed<I, F> {
+    pub fn from_raw(raw: I) -> NetFixed<I, F> {
+        NetFixed {
+            raw,
+            bits: PhantomData,
+        }
+    }
+
+    /// Converts the value to the nearest representable fixed-point value.
+    pub fn from_f64(value: f64) -> NetFixed<I, F> {
+        NetFixed::from_raw(I::from_f64(value * (1u64 << F::BITS) as f64))
+    }
+
+    pub fn from_f32(value: f32) -> NetFixed<I, F> {
+        NetFixed::from_f64(value as f64)
+    }
+
+    pub fn raw(&self) -> I {
+        self.raw
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.raw.to_f64() / (1u64 << F::BITS) as f64
+    }
+
+    pub fn to_f32(&self) -> f32 {
+        self.to_f64() as f32
+    }
+}
+
+/// A reference to another replicated entity.
+///
+/// Entities differ between server and client, the uid refers to the same entity on both.
+/// See `EntityReferences` for what happens with references to a removed entity.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+    SerdeDiff,
+)]
+pub struct NetworkEntity {
+    #[serde_diff(opaque)]
+    uid: Option<Uid>,
+}
+
+impl NetworkEntity {
+    pub fn new(uid: Uid) -> NetworkEntity {
+        NetworkEntity { uid: Some(uid) }
+    }
+
+    /// A reference to no entity.
+    pub fn null() -> NetworkEntity {
+        NetworkEntity { uid: None }
+    }
+
+    pub fn uid(&self) -> Option<Uid> {
+        self.uid
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.uid.is_none()
+    }
+
+    pub fn refers_to(&self, uid: Uid) -> bool {
+        self.uid == Some(uid)
+    }
+
+    pub fn set_null(&mut self) {
+        self.uid = None;
+    }
+}
+
+/// A field that is sent whole when it changed, instead of diffed.
+///
+/// Encoded like `T`, so it can be added to or removed from a component without changing
+/// the size of its full serialization.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+    SerdeDiff,
+)]
+#[serde(transparent)]
+pub struct Replace<T: PartialEq + Serialize + for<'de> Deserialize<'de>> {
+    #[serde_diff(opaque)]
+    value: T,
+}
+
+impl<T: PartialEq + Serialize + for<'de> Deserialize<'de>> Replace<T> {
+    pub fn new(value: T) -> Replace<T> {
+        Replace { value }
+    }
+
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T: PartialEq + Serialize + for<'de> Deserialize<'de>> From<T> for Replace<T> {
+    fn from(value: T) -> Self {
+        Replace::new(value)
+    }
+}
+
+/// A `HashMap` that serializes and diffs its entries in the order of their keys.
+///
+/// Encoded like the map, so a `HashMap` field can be changed into a `SortedMap` without
+/// changing the size of its full serialization. Diffing converts both maps into a `BTreeMap`,
+/// which costs a clone of the entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortedMap<K: Eq + Hash, V> {
+    map: HashMap<K, V>,
+}
+
+impl<K: Eq + Hash, V> SortedMap<K, V> {
+    pub fn new() -> SortedMap<K, V> {
+        SortedMap {
+            map: HashMap::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> HashMap<K, V> {
+        self.map
+    }
+}
+
+impl<K: Eq + Hash, V> Default for SortedMap<K, V> {
+    fn default() -> Self {
+        SortedMap::new()
+    }
+}
+
+impl<K: Eq + Hash, V> Deref for SortedMap<K, V> {
+    type Target = HashMap<K, V>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+impl<K: Eq + Hash, V> DerefMut for SortedMap<K, V> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.map
+    }
+}
+
+impl<K: Eq + Hash, V> From<HashMap<K, V>> for SortedMap<K, V> {
+    fn from(map: HashMap<K, V>) -> Self {
+        SortedMap { map }
+    }
+}
+
+impl<K: Eq + Hash, V> FromIterator<(K, V)> for SortedMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        SortedMap {
+            map: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Ord + Serialize, V: Serialize> Serialize for SortedMap<K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_sorted(&self.map, serializer)
+    }
+}
+
+impl<'de, K, V> Deserialize<'de> for SortedMap<K, V>
+where
+    K: Eq + Hash + Deserialize<'de>,
+    V: Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        HashMap::deserialize(deserializer).map(SortedMap::from)
+    }
+}
+
+impl<K, V> SerdeDiff for SortedMap<K, V>
+where
+    K: SerdeDiff + Serialize + for<'a> Deserialize<'a> + Eq + Hash + Ord + Clone,
+    V: SerdeDiff + Serialize + for<'a> Deserialize<'a> + Clone,
+{
+    fn diff<'a, S: SerializeSeq>(
+        &self,
+        ctx: &mut DiffContext<'a, S>,
+        other: &Self,
+    ) -> Result<bool, S::Error> {
+        let sorted = |map: &HashMap<K, V>| {
+            map.iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect::<BTreeMap<K, V>>()
+        };
+
+        sorted(&self.map).diff(ctx, &sorted(&other.map))
+    }
+
+    fn apply<'de, A>(
+        &mut self,
+        seq: &mut A,
+        ctx: &mut ApplyContext,
+    ) -> Result<bool, <A as de::SeqAccess<'de>>::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        let mut sorted = mem::take(&mut self.map)
+            .into_iter()
+            .collect::<BTreeMap<K, V>>();
+        let changed = sorted.apply(seq, ctx);
+
+        self.map = sorted.into_iter().collect();
+        changed
+    }
+}
+
+/// Serializes the map in the order of its keys,
+/// use it with `#[serde(serialize_with = "serialize_sorted")]` on a `HashMap` field.
+pub fn serialize_sorted<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    K: Ord + Serialize,
+    V: Serialize,
+    S: Serializer,
+{
+    let mut entries = map.iter().collect::<Vec<(&K, &V)>>();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    serializer.collect_map(entries)
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::{collections::HashMap, time::Duration};
+
+    use net_sync::{
+        re_exports::bincode,
+        track_attr::serde_diff::{Config, FieldPathMode},
+    };
+
+    use crate::components::{Bits8, NetDuration, NetFixed, NetInstant, SortedMap};
+
+    #[test]
+    fn instant_duration_since_test() {
+        let frame_duration = Duration::from_millis(10);
+        let earlier = NetInstant::new(2, NetDuration::from_millis(5));
+        let later = NetInstant::at_frame(4);
+
+        assert_eq!(
+            later.duration_since(earlier, frame_duration),
+            Duration::from_millis(15)
+        );
+        assert_eq!(
+            earlier.duration_since(later, frame_duration),
+            Duration::from_secs(0)
+        );
+    }
+
+    #[test]
+    fn fixed_round_trip_test() {
+        let value = NetFixed::<i32, Bits8>::from_f32(1.5);
+
+        assert_eq!(value.raw(), 384);
+        assert_eq!(value.to_f32(), 1.5);
+        // Values are rounded to the nearest multiple of 1/256.
+        assert_eq!(NetFixed::<i32, Bits8>::from_f64(0.001).raw(), 0);
+        assert_eq!(NetFixed::<i16, Bits8>::from_f64(1e9).raw(), i16::max_value());
+    }
+
+    #[test]
+    fn sorted_maps_encode_the_same_test() {
+        // Every map has its own random hasher, the entries are visited in different orders.
+        let older = (0..32u32)
+            .map(|key| (key, key * 10))
+            .collect::<SortedMap<u32, u32>>();
+        let maps = (0..8)
+            .map(|_| {
+                let mut map = HashMap::with_capacity(64);
+                for key in (0..32u32).rev() {
+                    map.insert(key, if key % 4 == 0 { key } else { key * 10 });
+                }
+                SortedMap::from(map)
+            })
+            .collect::<Vec<_>>();
+
+        let bytes = bincode::serialize(&maps[0]).unwrap();
+        let diff = |changed: &SortedMap<u32, u32>| {
+            let diff = Config::new()
+                .with_field_path_mode(FieldPathMode::Index)
+                .serializable_diff(&older, changed);
+            bincode::serialize(&diff).unwrap()
+        };
+        let diff_bytes = diff(&maps[0]);
+
+        for map in maps.iter() {
+            assert_eq!(bincode::serialize(map).unwrap(), bytes);
+            assert_eq!(diff(map), diff_bytes);
+        }
+
+        // Encoded like a map.
+        let decoded = bincode::deserialize::<HashMap<u32, u32>>(&bytes).unwrap();
+        assert_eq!(SortedMap::from(decoded), maps[0]);
+    }
+}
@@ -0,0 +1,246 @@
+//! Synchronized spatial components, enabled with the `spatial` feature.
+//!
+//! Values are quantized to fixed-point numbers, so every axis is a small, deterministic diff.
+//! Conversions from and to the [mint](https://docs.rs/mint) types make them usable with any
+//! math crate.
+//!
+//! | Component | Precision | Range |
+//! |-----------|-----------|-------|
+//! | `Position2`, `Position3`, `Velocity2`, `Velocity3` | 1/256 | ±8388608 |
+//! | `Rotation2` (radians) | 1/4096 | ±524288 |
+//! | `Rotation3` (quaternion) | 1/4096 | ±8 |
+
+use serde::{Deserialize, Serialize};
+
+use net_sync::track_attr::serde_diff::{self, *};
+
+use crate::components::{Bits12, Bits8, NetFixed};
+
+/// Linear interpolation between two states of a component.
+pub trait Lerp {
+    /// Returns the state at `t` between `self` (`0.0`) and `other` (`1.0`).
+    fn lerp(&self, other: &Self, t: f32) -> Self;
+}
+
+fn lerp(from: f32, to: f32, t: f32) -> f32 {
+    from + (to - from) * t
+}
+
+macro_rules! spatial_vector {
+    ($(#[$attr:meta])* $name:ident, $mint:ident, $($axis:ident),+) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, SerdeDiff)]
+        pub struct $name {
+            $($axis: NetFixed<i32, Bits8>,)+
+        }
+
+        impl $name {
+            pub fn new($($axis: f32),+) -> $name {
+                $name {
+                    $($axis: NetFixed::from_f32($axis),)+
+                }
+            }
+
+            $(
+                pub fn $axis(&self) -> f32 {
+                    self.$axis.to_f32()
+                }
+            )+
+        }
+
+        impl Lerp for $name {
+            fn lerp(&self, other: &Self, t: f32) -> Self {
+                $name::new($(lerp(self.$axis(), other.$axis(), t)),+)
+            }
+        }
+
+        impl From<mint::$mint<f32>> for $name {
+            fn from(value: mint::$mint<f32>) -> Self {
+                $name::new($(value.$axis),+)
+            }
+        }
+
+        impl From<$name> for mint::$mint<f32> {
+            fn from(value: $name) -> Self {
+                mint::$mint {
+                    $($axis: value.$axis(),)+
+                }
+            }
+        }
+
+        crate::register_component_type!($name);
+    };
+}
+
+spatial_vector!(
+    /// A 2D position.
+    Position2, Point2, x, y
+);
+spatial_vector!(
+    /// A 3D position.
+    Position3, Point3, x, y, z
+);
+spatial_vector!(
+    /// A 2D velocity, in units per second.
+    Velocity2, Vector2, x, y
+);
+spatial_vector!(
+    /// A 3D velocity, in units per second.
+    Velocity3, Vector3, x, y, z
+);
+
+/// A 2D rotation as an angle in radians.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, SerdeDiff)]
+pub struct Rotation2 {
+    angle: NetFixed<i32, Bits12>,
+}
+
+impl Rotation2 {
+    pub fn new(angle: f32) -> Rotation2 {
+        Rotation2 {
+            angle: NetFixed::from_f32(angle),
+        }
+    }
+
+    pub fn angle(&self) -> f32 {
+        self.angle.to_f32()
+    }
+}
+
+impl Lerp for Rotation2 {
+    /// Interpolates over the shortest arc.
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        use std::f32::consts::PI;
+
+        let delta = (other.angle() - self.angle() + PI).rem_euclid(2. * PI) - PI;
+        Rotation2::new(self.angle() + delta * t)
+    }
+}
+
+crate::register_component_type!(Rotation2);
+
+/// A 3D rotation as a unit quaternion.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, SerdeDiff)]
+pub struct Rotation3 {
+    x: NetFixed<i16, Bits12>,
+    y: NetFixed<i16, Bits12>,
+    z: NetFixed<i16, Bits12>,
+    w: NetFixed<i16, Bits12>,
+}
+
+impl Rotation3 {
+    pub fn new(x: f32, y: f32, z: f32, w: f32) -> Rotation3 {
+        Rotation3 {
+            x: NetFixed::from_f32(x),
+            y: NetFixed::from_f32(y),
+            z: NetFixed::from_f32(z),
+            w: NetFixed::from_f32(w),
+        }
+    }
+
+    pub fn identity() -> Rotation3 {
+        Rotation3::new(0., 0., 0., 1.)
+    }
+
+    /// Returns the `x`, `y`, `z` and `w` components of the quaternion.
+    pub fn components(&self) -> [f32; 4] {
+        [
+            self.x.to_f32(),
+            self.y.to_f32(),
+            self.z.to_f32(),
+            self.w.to_f32(),
+        ]
+    }
+}
+
+impl Default for Rotation3 {
+    fn default() -> Self {
+        Rotation3::identity()
+    }
+}
+
+impl Lerp for Rotation3 {
+    /// Normalized linear interpolation over the shortest arc.
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        let from = self.components();
+        let mut to = other.components();
+
+        let dot: f32 = from.iter().zip(to.iter()).map(|(a, b)| a * b).sum();
+        if dot < 0. {
+            for value in to.iter_mut() {
+                *value = -*value;
+            }
+        }
+
+        let mut result = [0.; 4];
+        for i in 0..4 {
+            result[i] = lerp(from[i], to[i], t);
+        }
+
+        let length = result.iter().map(|value| value * value).sum::<f32>().sqrt();
+        if length == 0. {
+            return *other;
+        }
+
+        Rotation3::new(
+            result[0] / length,
+            result[1] / length,
+            result[2] / length,
+            result[3] / length,
+        )
+    }
+}
+
+impl From<mint::Quaternion<f32>> for Rotation3 {
+    fn from(value: mint::Quaternion<f32>) -> Self {
+        Rotation3::new(value.v.x, value.v.y, value.v.z, value.s)
+    }
+}
+
+impl From<Rotation3> for mint::Quaternion<f32> {
+    fn from(value: Rotation3) -> Self {
+        let [x, y, z, w] = value.components();
+
+        mint::Quaternion {
+            v: mint::Vector3 { x, y, z },
+            s: w,
+        }
+    }
+}
+
+crate::register_component_type!(Rotation3);
+
+#[cfg(test)]
+pub mod test {
+    use std::f32::consts::PI;
+
+    use crate::components::{Lerp, Position3, Rotation2, Rotation3};
+
+    #[test]
+    fn position_is_quantized_test() {
+        let position = Position3::new(1.5, -2.25, 0.001);
+
+        assert_eq!(position.x(), 1.5);
+        assert_eq!(position.y(), -2.25);
+        assert_eq!(position.z(), 0.);
+
+        let point: mint::Point3<f32> = position.into();
+        assert_eq!(Position3::from(point), position);
+    }
+
+    #[test]
+    fn lerp_test() {
+        let from = Position3::new(0., 0., 0.);
+        let to = Position3::new(2., 4., -8.);
+        assert_eq!(from.lerp(&to, 0.5), Position3::new(1., 2., -4.));
+
+        // The shortest arc between 170 and -170 degrees passes 180 degrees.
+        let from = Rotation2::new(PI - 0.2);
+        let to = Rotation2::new(-PI + 0.2);
+        assert!((from.lerp(&to, 0.5).angle().abs() - PI).abs() < 0.01);
+
+        let rotation = Rotation3::identity().lerp(&Rotation3::new(0., 0., 1., 0.), 0.5);
+        let [_, _, z, w] = rotation.components();
+        assert!((z - w).abs() < 0.01);
+    }
+}
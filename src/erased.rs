@@ -0,0 +1,237 @@
+//! Type-erased user messages and commands.
+//!
+//! The worlds are generic over the message and command types of the game, every game type
+//! instantiates the whole synchronisation layer again. `ErasedMessage` carries any registered
+//! type as a type id with its serialized payload, `DynServerWorld` and `DynClientWorld` are
+//! instantiated once for all games. This trades a registry lookup and a downcast per message for
+//! shorter compile times and smaller binaries.
+//!
+//! ```ignore
+//! let mut registry = MessageRegistry::new();
+//! registry.register::<Chat>();
+//! registry.register::<Move>();
+//!
+//! postbox.send(registry.encode(&Chat::new("hello"))?);
+//!
+//! if let Some(chat) = registry.decode::<Chat>(&message) {
+//!     let chat = chat?;
+//! }
+//! ```
+//!
+//! The client and the server must register the same types in the same order.
+//!
+//! Enabled with the `erased` feature.
+
+use std::{
+    any::{type_name, Any, TypeId},
+    collections::HashMap,
+};
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use net_sync::{
+    compression::lz4::Lz4,
+    synchronisation::{NetworkCommand, NetworkMessage},
+};
+
+use crate::{
+    error::ErrorKind,
+    tracking::re_exports::bincode,
+    world::{
+        client::{ClientWorld, ClientWorldBuilder},
+        default_options,
+        server::{ServerWorld, ServerWorldBuilder},
+    },
+};
+
+/// The id of a registered type, its index in the `MessageRegistry`.
+pub type MessageId = u16;
+
+/// A registered message or command type with its serialized payload, see `MessageRegistry`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErasedMessage {
+    id: MessageId,
+    payload: Vec<u8>,
+}
+
+impl ErasedMessage {
+    pub fn id(&self) -> MessageId {
+        self.id
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+}
+
+impl NetworkMessage for ErasedMessage {}
+
+impl NetworkCommand for ErasedMessage {}
+
+pub type DynServerWorld = ServerWorld<ErasedMessage, ErasedMessage, ErasedMessage>;
+pub type DynServerWorldBuilder = ServerWorldBuilder<ErasedMessage, ErasedMessage, ErasedMessage>;
+pub type DynClientWorld = ClientWorld<ErasedMessage, ErasedMessage, ErasedMessage, Lz4>;
+pub type DynClientWorldBuilder =
+    ClientWorldBuilder<ErasedMessage, ErasedMessage, ErasedMessage, Lz4>;
+
+type Decoder = fn(&[u8]) -> Result<Box<dyn Any + Send>, ErrorKind>;
+
+struct Registration {
+    type_id: TypeId,
+    type_name: &'static str,
+    decoder: Decoder,
+}
+
+/// The message and command types that are sent as `ErasedMessage`.
+#[derive(Default)]
+pub struct MessageRegistry {
+    registrations: Vec<Registration>,
+    ids: HashMap<TypeId, MessageId>,
+}
+
+impl MessageRegistry {
+    pub fn new() -> MessageRegistry {
+        MessageRegistry::default()
+    }
+
+    /// Registers the type and returns its id, registering a type twice returns the same id.
+    ///
+    /// # Panics
+    ///
+    /// Panics when more than `MessageId::MAX` types are registered.
+    pub fn register<T: Serialize + DeserializeOwned + Send + 'static>(&mut self) -> MessageId {
+        let type_id = TypeId::of::<T>();
+
+        if let Some(id) = self.ids.get(&type_id) {
+            return *id;
+        }
+
+        assert!(
+            self.registrations.len() < MessageId::MAX as usize,
+            "too many message types registered"
+        );
+
+        let id = self.registrations.len() as MessageId;
+        self.registrations.push(Registration {
+            type_id,
+            type_name: type_name::<T>(),
+            decoder: decode_boxed::<T>,
+        });
+        self.ids.insert(type_id, id);
+        id
+    }
+
+    /// The id of the type, `None` if it was not registered.
+    pub fn id_of<T: 'static>(&self) -> Option<MessageId> {
+        self.ids.get(&TypeId::of::<T>()).copied()
+    }
+
+    /// The type name of the registered id.
+    pub fn type_name(&self, id: MessageId) -> Option<&'static str> {
+        self.registrations
+            .get(id as usize)
+            .map(|registration| registration.type_name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.registrations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.registrations.is_empty()
+    }
+
+    /// Serializes the message, fails if its type was not registered.
+    pub fn encode<T: Serialize + 'static>(&self, message: &T) -> Result<ErasedMessage, ErrorKind> {
+        let id = self.id_of::<T>().ok_or_else(|| {
+            ErrorKind::SerializationError(format!("{} is not registered", type_name::<T>()))
+        })?;
+
+        let payload = default_options()
+            .serialize(message)
+            .map_err(|e| ErrorKind::SerializationError(e.to_string()))?;
+
+        Ok(ErasedMessage { id, payload })
+    }
+
+    /// Whether the message carries a `T`.
+    pub fn is<T: 'static>(&self, message: &ErasedMessage) -> bool {
+        self.registrations
+            .get(message.id as usize)
+            .map_or(false, |registration| registration.type_id == TypeId::of::<T>())
+    }
+
+    /// Deserializes the message if it carries a `T`, `None` if it carries another type.
+    pub fn decode<T: DeserializeOwned + 'static>(
+        &self,
+        message: &ErasedMessage,
+    ) -> Option<Result<T, ErrorKind>> {
+        if !self.is::<T>(message) {
+            return None;
+        }
+
+        Some(
+            default_options()
+                .deserialize(&message.payload)
+                .map_err(|e| ErrorKind::SerializationError(e.to_string())),
+        )
+    }
+
+    /// Deserializes the message into its registered type, to be downcast by the caller.
+    pub fn decode_any(&self, message: &ErasedMessage) -> Result<Box<dyn Any + Send>, ErrorKind> {
+        let registration = self.registrations.get(message.id as usize).ok_or_else(|| {
+            ErrorKind::SerializationError(format!("unknown message id {}", message.id))
+        })?;
+
+        (registration.decoder)(&message.payload)
+    }
+}
+
+fn decode_boxed<T: DeserializeOwned + Send + 'static>(
+    bytes: &[u8],
+) -> Result<Box<dyn Any + Send>, ErrorKind> {
+    default_options()
+        .deserialize::<T>(bytes)
+        .map(|value| Box::new(value) as Box<dyn Any + Send>)
+        .map_err(|e| ErrorKind::SerializationError(e.to_string()))
+}
+
+#[cfg(test)]
+pub mod test {
+    use serde::{Deserialize, Serialize};
+
+    use crate::erased::MessageRegistry;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Chat(String);
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Move {
+        x: i32,
+        y: i32,
+    }
+
+    #[test]
+    fn registered_messages_round_trip_test() {
+        let mut registry = MessageRegistry::new();
+        assert_eq!(registry.register::<Chat>(), 0);
+        assert_eq!(registry.register::<Move>(), 1);
+        assert_eq!(registry.register::<Chat>(), 0);
+
+        let message = registry.encode(&Move { x: 1, y: -2 }).unwrap();
+        assert_eq!(message.id(), 1);
+        assert!(registry.decode::<Chat>(&message).is_none());
+        assert_eq!(
+            registry.decode::<Move>(&message).unwrap().unwrap(),
+            Move { x: 1, y: -2 }
+        );
+
+        let any = registry
+            .decode_any(&registry.encode(&Chat("hi".to_string())).unwrap())
+            .unwrap();
+        assert_eq!(any.downcast_ref::<Chat>(), Some(&Chat("hi".to_string())));
+
+        assert!(registry.encode(&5u8).is_err());
+    }
+}
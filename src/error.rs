@@ -9,6 +9,8 @@ use std::{
 pub enum ErrorKind {
     IoError(io::Error),
     NetSyncError(net_sync::error::ErrorKind),
+    /// A component could not be serialized or deserialized.
+    SerializationError(String),
 }
 
 impl Display for ErrorKind {
@@ -18,6 +20,7 @@ impl Display for ErrorKind {
             ErrorKind::NetSyncError(e) => {
                 write!(fmt, "Network synchronisation error occurred: {:?}", e)
             }
+            ErrorKind::SerializationError(e) => write!(fmt, "Serialization error occurred: {}", e),
         }
     }
 }
@@ -7,6 +7,14 @@ use serde::export::{fmt::Error, Formatter};
 use crate::{resources::RegisteredComponentsResource, world::WorldAbstraction};
 use legion::world::Event;
 
+pub use self::{
+    client::{ClientEvent, ClientEvents},
+    server::{ServerEvent, ServerEvents},
+};
+
+mod client;
+mod server;
+
 #[derive(Copy, Clone, Eq, PartialEq, Hash)]
 pub enum LegionEvent {
     ComponentAdded(Entity, usize),
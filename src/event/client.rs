@@ -0,0 +1,72 @@
+use std::vec::Drain;
+
+use legion::Entity;
+
+use net_sync::{synchronisation::CommandFrame, uid::Uid};
+
+use crate::{
+    protocol::{ContextId, DisconnectReason, RegionManifest},
+    resources::SchemaViolation,
+};
+
+/// Events raised by the client synchronisation layer for game code.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientEvent {
+    /// The server closed the connection, e.g. to show the player why.
+    Disconnected(DisconnectReason),
+    /// The client entered or left a streaming region, with the entities of the region.
+    RegionManifest(RegionManifest),
+    /// The server did not update the entity since `last_updated`, see `StaleSweep`.
+    PossiblyStale {
+        entity: Entity,
+        uid: Uid,
+        last_updated: CommandFrame,
+    },
+    /// A misprediction at `command_frame` was applied without replaying the `depth` frames since,
+    /// see `ClientWorldBuilder::with_max_resimulation_frames`.
+    ResimulationSkipped {
+        command_frame: CommandFrame,
+        depth: CommandFrame,
+        /// The uids of the mispredicted entities.
+        entities: Vec<Uid>,
+    },
+    /// The initial sync of the server could not be deserialized, it was dropped.
+    /// The world keeps its previous state until the server sends another initial sync.
+    InitialSyncFailed(String),
+    /// The initial sync of a replication context could not be deserialized, it was dropped.
+    ContextSyncFailed { context: ContextId, error: String },
+    /// Received data violated the `StrictSchema`, the message was dropped.
+    SchemaViolation(SchemaViolation),
+}
+
+/// Resource containing the events raised since they were last drained.
+#[derive(Debug, Default)]
+pub struct ClientEvents {
+    events: Vec<ClientEvent>,
+}
+
+impl ClientEvents {
+    pub fn new() -> ClientEvents {
+        ClientEvents::default()
+    }
+
+    pub fn push(&mut self, event: ClientEvent) {
+        self.events.push(event);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ClientEvent> {
+        self.events.iter()
+    }
+
+    pub fn drain(&mut self) -> Drain<'_, ClientEvent> {
+        self.events.drain(..)
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
@@ -0,0 +1,95 @@
+use std::{time::Duration, vec::Drain};
+
+use net_sync::{synchronisation::CommandFrame, transport::ClientId, uid::Uid};
+
+use crate::{
+    protocol::DisconnectReason,
+    resources::{ConnectionQuality, Fidelity, MatchPhase, TickerEvent},
+};
+
+/// Events raised by the server synchronisation layer for game code.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerEvent {
+    /// The quality of a client connection changed.
+    ConnectionQualityChanged {
+        client: ClientId,
+        quality: ConnectionQuality,
+    },
+    /// A component of an entity could not be serialized, it was left out of the state update.
+    ///
+    /// With `ServerConfig::quarantine_failed_entities` the entity is not replicated anymore
+    /// until it is released with `ServerWorld::release_quarantined`.
+    SerializationFailed {
+        entity: Uid,
+        component: &'static str,
+        error: String,
+    },
+    /// The adaptive interest radius of a client changed, see `InterestRadii`.
+    InterestRadiusChanged { client: ClientId, radius: f32 },
+    /// A client connected, see `ConnectionLifecycle`.
+    ClientConnected { client: ClientId },
+    /// The server disconnected a client with `ServerWorld::disconnect`, or its connection was
+    /// lost with the `DisconnectReason::Timeout`.
+    ClientDisconnected {
+        client: ClientId,
+        reason: DisconnectReason,
+    },
+    /// The server missed more command frames than it catches up, handled by the
+    /// `ServerConfig::stall_policy`.
+    TickStalled(TickerEvent),
+    /// A client received the frame-zero state of a staged match, see `MatchBarrier`.
+    MatchClientReady {
+        client: ClientId,
+        ready: usize,
+        expected: usize,
+    },
+    /// A match was staged or started, see `MatchBarrier`.
+    MatchPhaseChanged(MatchPhase),
+    /// The replication fidelity changed with the server load, see `LoadShedding`.
+    FidelityChanged {
+        fidelity: Fidelity,
+        tick_duration: Duration,
+    },
+    /// A client that lost its connection continues as a new client id, see
+    /// `ServerWorld::reconnect`.
+    ClientReconnected { from: ClientId, to: ClientId },
+    /// A command of the client affected an entity it does not own, it was dropped,
+    /// see `OwnershipRules`.
+    CommandRejected {
+        client: ClientId,
+        command_frame: CommandFrame,
+        entity: Uid,
+    },
+}
+
+/// Resource containing the events raised since they were last drained.
+#[derive(Debug, Default)]
+pub struct ServerEvents {
+    events: Vec<ServerEvent>,
+}
+
+impl ServerEvents {
+    pub fn new() -> ServerEvents {
+        ServerEvents::default()
+    }
+
+    pub fn push(&mut self, event: ServerEvent) {
+        self.events.push(event);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ServerEvent> {
+        self.events.iter()
+    }
+
+    pub fn drain(&mut self) -> Drain<'_, ServerEvent> {
+        self.events.drain(..)
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
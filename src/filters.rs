@@ -0,0 +1,115 @@
+//! Filters to select synchronized entities in legion queries and world serialization.
+
+use std::{collections::HashSet, ops::BitAnd, sync::Arc};
+
+use legion::{
+    query::{ActiveFilter, FilterResult, GroupMatcher, LayoutFilter},
+    storage::ComponentTypeId,
+};
+
+pub mod filter_fns {
+    use legion::query::{EntityFilterTuple, Passthrough};
+
+    use crate::{filters::RegisteredFilter, resources::RegisteredComponentsResource};
+
+    /// Selects entities of which all components are registered with the `sync` attribute.
+    pub fn registered(
+        registered: &RegisteredComponentsResource,
+    ) -> EntityFilterTuple<RegisteredFilter, Passthrough> {
+        EntityFilterTuple::new(registered.filter(), Passthrough)
+    }
+}
+
+/// Combinators for the filters of this module and the legion filters.
+///
+/// ```ignore
+/// let query = <Read<Position>>::query().filter(registered(&components).and(component::<Player>()));
+/// ```
+///
+/// To select the entities changed by the last state updates, see `ReplicatedChanges`.
+pub trait FilterExt: Sized {
+    /// Matches entities that match both filters, same as `self & rhs`.
+    fn and<Rhs>(self, rhs: Rhs) -> <Self as BitAnd<Rhs>>::Output
+    where
+        Self: BitAnd<Rhs>,
+    {
+        self & rhs
+    }
+}
+
+impl<F> FilterExt for F {}
+
+/// Matches archetypes of which all components are registered with the `sync` attribute.
+///
+/// Archetypes containing unregistered components can not be serialized by the legion registry,
+/// therefore this filter is used for world snapshots. The initial state sync copies the
+/// registered components of the replicated entities instead, see `world::initial_sync_world`.
+///
+/// The default filter has no registered components, it is replaced when a query is filtered.
+#[derive(Debug, Clone, Default)]
+pub struct RegisteredFilter {
+    registered: Arc<HashSet<ComponentTypeId>>,
+}
+
+impl RegisteredFilter {
+    pub(crate) fn new(registered: Arc<HashSet<ComponentTypeId>>) -> RegisteredFilter {
+        RegisteredFilter { registered }
+    }
+}
+
+impl GroupMatcher for RegisteredFilter {
+    fn can_match_group() -> bool {
+        false
+    }
+
+    fn group_components() -> Vec<ComponentTypeId> {
+        Vec::new()
+    }
+}
+
+impl ActiveFilter for RegisteredFilter {}
+
+impl LayoutFilter for RegisteredFilter {
+    fn matches_layout(&self, components: &[ComponentTypeId]) -> FilterResult {
+        FilterResult::Match(
+            components
+                .iter()
+                .all(|component| self.registered.contains(component)),
+        )
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use legion::World;
+
+    use legion::{query::component, IntoQuery, Read};
+
+    use crate::{
+        components::{DynamicComponent, UidComponent},
+        filters::{filter_fns::registered, FilterExt},
+        resources::RegisteredComponentsResource,
+    };
+
+    struct Unregistered;
+
+    #[test]
+    fn registered_and_component_filter_test() {
+        let components = RegisteredComponentsResource::new();
+
+        let mut world = World::default();
+        world.push((UidComponent::new(1),));
+        world.push((UidComponent::new(2), DynamicComponent::default()));
+        world.push((UidComponent::new(3), DynamicComponent::default(), Unregistered));
+
+        let mut query = <Read<UidComponent>>::query()
+            .filter(registered(&components).and(component::<DynamicComponent>()));
+
+        let uids = query
+            .iter(&world)
+            .map(|uid| uid.uid())
+            .collect::<Vec<_>>();
+
+        assert_eq!(uids, vec![2]);
+    }
+}
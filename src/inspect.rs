@@ -0,0 +1,374 @@
+//! Pretty printing of captured payloads, enabled with the `inspect` feature.
+//!
+//! Useful when debugging interop with proxies or analyzing replays without running a client.
+
+use std::{
+    fmt::{self, Display, Formatter, Write},
+    mem::size_of,
+    ops::AddAssign,
+};
+
+use bincode::Options;
+
+use net_sync::re_exports::bincode;
+
+use crate::{
+    error::ErrorKind,
+    protocol::{CommandFrame, ComponentData, InitialSync, Uid, WorldState},
+    schema::Schema,
+};
+
+/// Decodes a captured `WorldState` or `InitialSync` payload and formats it for humans,
+/// component types are named with the schema exported by the server.
+///
+/// Component data is printed as hex, it is a serialized component or diff. Inserted entities
+/// with a `DebugName` are printed with their name.
+pub fn decode_state_dump(bytes: &[u8], schema: &Schema) -> Result<String, ErrorKind> {
+    // Trailing bytes are rejected, so a payload only decodes as the type it was encoded as.
+    let options = bincode::DefaultOptions::new().with_fixint_encoding();
+
+    let mut dump = String::new();
+
+    if let Ok(state) = options.deserialize::<WorldState>(bytes) {
+        write_state(&mut dump, &state, schema);
+    } else if let Ok(initial_sync) = options.deserialize::<InitialSync>(bytes) {
+        let _ = writeln!(
+            dump,
+            "InitialSync rng_seed={} world={} bytes",
+            initial_sync.rng_seed,
+            initial_sync.world.len()
+        );
+    } else {
+        return Err(ErrorKind::SerializationError(format!(
+            "{} bytes are neither a WorldState nor an InitialSync",
+            bytes.len()
+        )));
+    }
+
+    Ok(dump)
+}
+
+/// Bytes of one part of a `WireAudit`, by what they encode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WireBytes {
+    pub entity_ids: usize,
+    pub component_ids: usize,
+    /// The serialized components and diffs.
+    pub payload: usize,
+    /// The lengths of the lists and component data.
+    pub framing: usize,
+}
+
+impl WireBytes {
+    pub fn total(&self) -> usize {
+        self.entity_ids + self.component_ids + self.payload + self.framing
+    }
+}
+
+impl AddAssign for WireBytes {
+    fn add_assign(&mut self, other: WireBytes) {
+        self.entity_ids += other.entity_ids;
+        self.component_ids += other.component_ids;
+        self.payload += other.payload;
+        self.framing += other.framing;
+    }
+}
+
+/// Where the bytes of a serialized `WorldState` go, see `audit_state_update`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WireAudit {
+    /// The command frame and its offset.
+    pub header: usize,
+    pub removed: WireBytes,
+    pub inserted: WireBytes,
+    pub component_removed: WireBytes,
+    pub component_added: WireBytes,
+    pub changed: WireBytes,
+}
+
+impl WireAudit {
+    /// The bytes of all changes by what they encode, without the header.
+    pub fn changes(&self) -> WireBytes {
+        let mut changes = self.removed;
+        changes += self.inserted;
+        changes += self.component_removed;
+        changes += self.component_added;
+        changes += self.changed;
+        changes
+    }
+
+    /// The size of the serialized state.
+    pub fn total(&self) -> usize {
+        self.header + self.changes().total()
+    }
+
+    /// The share of the payload in the serialized state, between `0.0` and `1.0`.
+    pub fn payload_ratio(&self) -> f32 {
+        self.changes().payload as f32 / self.total() as f32
+    }
+}
+
+impl Display for WireAudit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<18} {:>8} {:>10} {:>13} {:>8} {:>8}",
+            "", "total", "entity ids", "component ids", "payload", "framing"
+        )?;
+
+        let parts = [
+            ("removed", self.removed),
+            ("inserted", self.inserted),
+            ("component removed", self.component_removed),
+            ("component added", self.component_added),
+            ("changed", self.changed),
+            ("changes", self.changes()),
+        ];
+
+        for (name, bytes) in parts.iter() {
+            writeln!(
+                f,
+                "{:<18} {:>8} {:>10} {:>13} {:>8} {:>8}",
+                name,
+                bytes.total(),
+                bytes.entity_ids,
+                bytes.component_ids,
+                bytes.payload,
+                bytes.framing
+            )?;
+        }
+
+        write!(f, "{:<18} {:>8} (header {})", "state", self.total(), self.header)
+    }
+}
+
+/// Bincode encodes the length of a `Vec` as `u64`.
+const VEC_LEN_SIZE: usize = size_of::<u64>();
+const UID_SIZE: usize = size_of::<Uid>();
+
+/// Decodes a serialized `WorldState` and breaks its size down by what the bytes encode,
+/// computed from the wire format described in `protocol::conformance`.
+///
+/// Use it to see where the bytes of the state updates go, e.g. to estimate what a proposed
+/// encoding of the entity ids would save. The audit adds up to the size of the payload.
+pub fn audit_state_update(bytes: &[u8]) -> Result<WireAudit, ErrorKind> {
+    let state = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .deserialize::<WorldState>(bytes)
+        .map_err(|e| ErrorKind::SerializationError(e.to_string()))?;
+
+    let component = |component: &ComponentData| WireBytes {
+        entity_ids: 0,
+        component_ids: UID_SIZE,
+        payload: component.data().len(),
+        framing: VEC_LEN_SIZE,
+    };
+    let list = |len: usize| WireBytes {
+        framing: VEC_LEN_SIZE,
+        entity_ids: len * UID_SIZE,
+        ..WireBytes::default()
+    };
+
+    let mut audit = WireAudit {
+        header: size_of::<CommandFrame>() + size_of::<i32>(),
+        removed: list(state.removed.len()),
+        inserted: list(state.inserted.len()),
+        component_removed: list(state.component_removed.len()),
+        component_added: list(state.component_added.len()),
+        changed: list(state.changed.len()),
+    };
+
+    for inserted in state.inserted.iter() {
+        audit.inserted.framing += VEC_LEN_SIZE;
+
+        for data in inserted.components() {
+            audit.inserted += component(data);
+        }
+    }
+
+    audit.component_removed.component_ids += state.component_removed.len() * UID_SIZE;
+
+    for added in state.component_added.iter() {
+        audit.component_added += component(added.component_data());
+    }
+
+    for changed in state.changed.iter() {
+        audit.changed += component(changed.component_data());
+    }
+
+    Ok(audit)
+}
+
+fn write_state(dump: &mut String, state: &WorldState, schema: &Schema) {
+    let _ = writeln!(
+        dump,
+        "WorldState command_frame={} offset={}",
+        state.command_frame, state.command_frame_offset
+    );
+
+    for entity_id in state.removed.iter() {
+        let _ = writeln!(dump, "  removed entity {}", entity_id);
+    }
+
+    for inserted in state.inserted.iter() {
+        let name = inserted
+            .components()
+            .iter()
+            .find_map(|component| debug_name(component, schema));
+
+        let _ = match name {
+            Some(name) => writeln!(dump, "  inserted entity {} {:?}", inserted.entity_id(), name),
+            None => writeln!(dump, "  inserted entity {}", inserted.entity_id()),
+        };
+
+        for component in inserted.components() {
+            let _ = writeln!(dump, "    {}", component_line(component, schema));
+        }
+    }
+
+    for removed in state.component_removed.iter() {
+        let _ = writeln!(
+            dump,
+            "  removed from entity {}: {}",
+            removed.entity_id(),
+            type_name(removed.component_id(), schema)
+        );
+    }
+
+    for added in state.component_added.iter() {
+        let _ = writeln!(
+            dump,
+            "  added to entity {}: {}",
+            added.entity_id(),
+            component_line(added.component_data(), schema)
+        );
+    }
+
+    for changed in state.changed.iter() {
+        let _ = writeln!(
+            dump,
+            "  changed on entity {}: {}",
+            changed.entity_id(),
+            component_line(changed.component_data(), schema)
+        );
+    }
+}
+
+fn component_line(component: &ComponentData, schema: &Schema) -> String {
+    let hex = component
+        .data()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    format!(
+        "{} ({} bytes) [{}]",
+        type_name(component.component_id(), schema),
+        component.data().len(),
+        hex
+    )
+}
+
+/// Decodes a serialized `DebugName`, it is recognized by its type name so the dump does not
+/// need the `debug-names` feature.
+fn debug_name(component: &ComponentData, schema: &Schema) -> Option<String> {
+    let component_schema = schema.component(component.component_id())?;
+
+    if component_schema.type_name.rsplit("::").next() != Some("DebugName") {
+        return None;
+    }
+
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .deserialize::<String>(component.data())
+        .ok()
+}
+
+fn type_name(uid: Uid, schema: &Schema) -> String {
+    match schema.component(uid) {
+        Some(component) => format!("{} (uid {})", component.type_name, uid),
+        None => format!("unknown (uid {})", uid),
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use bincode::Options;
+
+    use crate::{
+        inspect::{audit_state_update, decode_state_dump},
+        protocol::{ComponentData, WorldState},
+        schema::{ComponentSchema, Schema},
+        tracking::re_exports::bincode,
+        world::default_options,
+    };
+
+    #[test]
+    fn decode_state_dump_test() {
+        let schema = Schema {
+            components: vec![ComponentSchema {
+                uid: 1,
+                type_name: "Position".to_string(),
+            }],
+            dynamic_components: Vec::new(),
+        };
+
+        let mut state = WorldState::new(4);
+        state.remove_entity(1);
+        state.change(2, ComponentData::new(1, vec![10, 255]));
+        state.remove_component(2, 3);
+
+        let bytes = default_options().serialize(&state).unwrap();
+
+        assert_eq!(
+            decode_state_dump(&bytes, &schema).unwrap(),
+            "WorldState command_frame=4 offset=0\n\
+             \x20 removed entity 1\n\
+             \x20 removed from entity 2: unknown (uid 3)\n\
+             \x20 changed on entity 2: Position (uid 1) (2 bytes) [0a ff]\n"
+        );
+
+        assert!(decode_state_dump(&[1, 2, 3], &schema).is_err());
+    }
+
+    #[test]
+    fn inserted_entities_are_named_test() {
+        let schema = Schema {
+            components: vec![ComponentSchema {
+                uid: 1,
+                type_name: "legion_sync::components::DebugName".to_string(),
+            }],
+            dynamic_components: Vec::new(),
+        };
+
+        let name = default_options().serialize(&"player".to_string()).unwrap();
+        let mut state = WorldState::new(4);
+        state.insert_entity(2, vec![ComponentData::new(1, name)]);
+
+        let bytes = default_options().serialize(&state).unwrap();
+        let dump = decode_state_dump(&bytes, &schema).unwrap();
+
+        assert!(dump.contains("  inserted entity 2 \"player\"\n"));
+    }
+
+    #[test]
+    fn audit_adds_up_to_the_payload_test() {
+        let mut state = WorldState::new(4);
+        state.remove_entity(1);
+        state.insert_entity(2, vec![ComponentData::new(1, vec![1, 2, 3])]);
+        state.remove_component(3, 1);
+        state.change(3, ComponentData::new(2, vec![4, 5]));
+
+        let bytes = default_options().serialize(&state).unwrap();
+        let audit = audit_state_update(&bytes).unwrap();
+
+        assert_eq!(audit.total(), bytes.len());
+        assert_eq!(audit.changes().payload, 5);
+        assert_eq!(audit.changes().entity_ids, 4 * 4);
+        assert_eq!(audit.inserted.framing, 3 * 8);
+        assert_eq!(audit.component_removed.component_ids, 4);
+
+        assert!(audit_state_update(&[1, 2, 3]).is_err());
+    }
+}
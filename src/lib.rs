@@ -1,12 +1,40 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod protocol;
+
+#[cfg(feature = "inspect")]
+pub mod capture;
+#[cfg(feature = "chat")]
+pub mod chat;
+#[cfg(feature = "std")]
 pub mod components;
+#[cfg(feature = "erased")]
+pub mod erased;
+#[cfg(feature = "std")]
 pub mod error;
+#[cfg(feature = "std")]
+pub mod filters;
+#[cfg(feature = "inspect")]
+pub mod inspect;
+#[cfg(feature = "std")]
+pub mod prelude;
+#[cfg(feature = "std")]
 pub mod resources;
+#[cfg(feature = "std")]
 pub mod systems;
+#[cfg(feature = "std")]
 #[macro_use]
 pub mod register;
+#[cfg(feature = "std")]
 pub mod event;
+#[cfg(feature = "std")]
+pub mod schema;
+#[cfg(feature = "std")]
 pub mod world;
 
+#[cfg(feature = "std")]
 pub mod tracking {
     //! Re-export of the [track](LINK) crate.
     //!
@@ -0,0 +1,41 @@
+//! The types most games need, re-exported with stable paths.
+//!
+//! ```ignore
+//! use legion_sync::prelude::*;
+//!
+//! #[sync]
+//! #[derive(Debug, Clone)]
+//! pub struct Position {
+//!     pub x: u16,
+//!     pub y: u16,
+//! }
+//!
+//! let server: ServerWorld<Message, Message, Command> = ServerWorldBuilder::default().build()?;
+//! ```
+//!
+//! The paths of the prelude do not change when the modules are restructured, prefer them over
+//! the module paths and `tracking::re_exports`.
+
+pub use net_sync::{
+    compression::lz4::Lz4,
+    synchronisation::{CommandFrame, NetworkCommand, NetworkMessage},
+    track_attr::serde_diff::{self, SerdeDiff},
+    transport::ClientId,
+    uid::Uid,
+};
+
+pub use crate::{
+    components::{NetworkEntity, UidComponent},
+    error::ErrorKind,
+    event::{ClientEvent, ClientEvents, ServerEvent, ServerEvents},
+    filters::{filter_fns::registered, FilterExt},
+    protocol::{ClientMessage, DisconnectReason, ServerMessage},
+    register_component_type,
+    resources::RegisteredComponentsResource,
+    tracking::{inventory, sync},
+    world::{
+        client::{ClientPostBox, ClientWorld, ClientWorldBuilder},
+        server::{ServerConfig, ServerPostBox, ServerPostOffice, ServerWorld, ServerWorldBuilder},
+        BuildError, BuildReport, WorldBuilder,
+    },
+};
@@ -0,0 +1,51 @@
+//! The wire types of the synchronisation protocol.
+//!
+//! This module only depends on `alloc` and can be compiled without `std` (`default-features = false`).
+//! It allows embedded targets or sans-io consumers to encode and decode the protocol
+//! without pulling in legion and the socket machinery.
+
+pub use self::{
+    machine::{
+        order_state_updates, ClientAction, ClientProtocol, ServerAction, ServerProtocol,
+        StateFrame, COMMAND_FRAME_LEAD,
+    },
+    message::{
+        ClientMessage, ClientToServer, CommandOutcome, CommandResult, DisconnectReason,
+        InitialSync, LocalPlayer, PlayerCommand, RegionManifest, ServerMessage, ServerToClient,
+    },
+    split::{StateReassembler, StateUpdatePart},
+    state::{
+        ComponentAdded, ComponentChanged, ComponentData, ComponentRemoved, EntityInserted,
+        WorldState,
+    },
+};
+
+pub mod conformance;
+#[cfg(feature = "std")]
+mod convert;
+mod machine;
+mod message;
+mod split;
+mod state;
+
+/// Identifier of an entity or component type that is shared between server and client.
+#[cfg(feature = "std")]
+pub use net_sync::uid::Uid;
+/// Identifier of an entity or component type that is shared between server and client.
+#[cfg(not(feature = "std"))]
+pub type Uid = u32;
+
+/// Identifier of a replication context, multiple contexts can share one connection.
+///
+/// Context `0` is the default context of the server and client world.
+pub type ContextId = u16;
+
+/// Identifier of a streaming region of the world, assigned by game code.
+pub type RegionId = u32;
+
+/// The command frame a message belongs to.
+#[cfg(feature = "std")]
+pub use net_sync::synchronisation::CommandFrame;
+/// The command frame a message belongs to.
+#[cfg(not(feature = "std"))]
+pub type CommandFrame = u32;
@@ -0,0 +1,510 @@
+//! Canonical encoded samples of the protocol messages.
+//!
+//! The wire format is bincode with fixed int encoding: integers are little endian,
+//! enum variants are `u32` indices and the lengths of sequences are `u64`.
+//! Implementations in other languages, or future versions of this crate, can check their
+//! byte-level compatibility with `validate` or by comparing against `test_vectors` directly.
+//!
+//! The samples use `u32` for user messages and commands.
+
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use core::time::Duration;
+
+use super::{
+    ClientMessage, ClientToServer, CommandOutcome, CommandResult, ComponentData, DisconnectReason,
+    InitialSync, RegionManifest, ServerMessage, ServerToClient, StateUpdatePart, WorldState,
+};
+
+/// A sample message of one of the protocol message types.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sample {
+    ServerToClient(ServerToClient<ServerMessage<u32, u32>>),
+    ClientToServer(ClientToServer<ClientMessage<u32, u32>, u32>),
+    InitialSync(InitialSync),
+}
+
+/// A sample message with its canonical encoding.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestVector {
+    pub name: &'static str,
+    pub message: Sample,
+    pub bytes: &'static [u8],
+}
+
+/// The implementation of the wire format under test.
+pub trait Codec {
+    fn encode(&mut self, message: &Sample) -> Vec<u8>;
+
+    /// Decodes the bytes as the message type of `like`, `None` if they are invalid.
+    fn decode(&mut self, like: &Sample, bytes: &[u8]) -> Option<Sample>;
+}
+
+/// A test vector the codec did not encode or decode canonically.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mismatch {
+    Encoded {
+        name: &'static str,
+        expected: &'static [u8],
+        actual: Vec<u8>,
+    },
+    Decoded {
+        name: &'static str,
+        actual: Option<Sample>,
+    },
+}
+
+/// Encodes and decodes all test vectors with the codec, returns the ones that did not match.
+pub fn validate(codec: &mut impl Codec) -> Result<(), Vec<Mismatch>> {
+    let mut mismatches = Vec::new();
+
+    for vector in test_vectors() {
+        let encoded = codec.encode(&vector.message);
+        if encoded != vector.bytes {
+            mismatches.push(Mismatch::Encoded {
+                name: vector.name,
+                expected: vector.bytes,
+                actual: encoded,
+            });
+        }
+
+        let decoded = codec.decode(&vector.message, vector.bytes);
+        if decoded.as_ref() != Some(&vector.message) {
+            mismatches.push(Mismatch::Decoded {
+                name: vector.name,
+                actual: decoded,
+            });
+        }
+    }
+
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(mismatches)
+    }
+}
+
+/// Returns the sample messages with their canonical encoding.
+pub fn test_vectors() -> Vec<TestVector> {
+    vec![
+        TestVector {
+            name: "server_initial_state_sync",
+            message: Sample::ServerToClient(ServerToClient::InitialStateSync(vec![1, 2, 3])),
+            bytes: SERVER_INITIAL_STATE_SYNC,
+        },
+        TestVector {
+            name: "server_state_update",
+            message: Sample::ServerToClient(ServerToClient::StateUpdate(state_update())),
+            bytes: SERVER_STATE_UPDATE,
+        },
+        TestVector {
+            name: "server_user_message",
+            message: Sample::ServerToClient(ServerToClient::Message(ServerMessage::User(7))),
+            bytes: SERVER_USER_MESSAGE,
+        },
+        TestVector {
+            name: "server_command_accepted",
+            message: command_result(CommandOutcome::Accepted),
+            bytes: SERVER_COMMAND_ACCEPTED,
+        },
+        TestVector {
+            name: "server_command_rejected",
+            message: command_result(CommandOutcome::Rejected(String::from("no"))),
+            bytes: SERVER_COMMAND_REJECTED,
+        },
+        TestVector {
+            name: "server_command_transformed",
+            message: command_result(CommandOutcome::Transformed(3)),
+            bytes: SERVER_COMMAND_TRANSFORMED,
+        },
+        TestVector {
+            name: "server_context_initial_sync",
+            message: Sample::ServerToClient(ServerToClient::Message(
+                ServerMessage::ContextInitialSync(1, vec![1]),
+            )),
+            bytes: SERVER_CONTEXT_INITIAL_SYNC,
+        },
+        TestVector {
+            name: "server_context_state_update",
+            message: Sample::ServerToClient(ServerToClient::Message(
+                ServerMessage::ContextStateUpdate(1, WorldState::new(5)),
+            )),
+            bytes: SERVER_CONTEXT_STATE_UPDATE,
+        },
+        TestVector {
+            name: "server_state_update_part",
+            message: Sample::ServerToClient(ServerToClient::Message(
+                ServerMessage::StateUpdatePart(StateUpdatePart {
+                    part: 1,
+                    complete: true,
+                    state: WorldState::new(5),
+                }),
+            )),
+            bytes: SERVER_STATE_UPDATE_PART,
+        },
+        TestVector {
+            name: "server_disconnect",
+            message: Sample::ServerToClient(ServerToClient::Message(ServerMessage::Disconnect(
+                DisconnectReason::ProtocolError(3),
+            ))),
+            bytes: SERVER_DISCONNECT,
+        },
+        TestVector {
+            name: "server_resync",
+            message: Sample::ServerToClient(ServerToClient::Message(ServerMessage::Resync(5))),
+            bytes: SERVER_RESYNC,
+        },
+        TestVector {
+            name: "server_region_manifest",
+            message: Sample::ServerToClient(ServerToClient::Message(
+                ServerMessage::RegionManifest(RegionManifest {
+                    region: 3,
+                    resident: true,
+                    entities: vec![1, 2],
+                }),
+            )),
+            bytes: SERVER_REGION_MANIFEST,
+        },
+        TestVector {
+            name: "server_bundle",
+            message: Sample::ServerToClient(ServerToClient::Message(ServerMessage::Bundle(vec![
+                ServerMessage::Resync(5),
+                ServerMessage::User(7),
+            ]))),
+            bytes: SERVER_BUNDLE,
+        },
+        TestVector {
+            name: "server_ping",
+            message: Sample::ServerToClient(ServerToClient::Message(ServerMessage::Ping(
+                Duration::new(2, 5),
+            ))),
+            bytes: SERVER_PING,
+        },
+        TestVector {
+            name: "server_state_baseline",
+            message: Sample::ServerToClient(ServerToClient::Message(ServerMessage::StateBaseline(
+                9, 6,
+            ))),
+            bytes: SERVER_STATE_BASELINE,
+        },
+        TestVector {
+            name: "server_serialized_state_update",
+            message: Sample::ServerToClient(ServerToClient::Message(
+                ServerMessage::SerializedStateUpdate(Arc::new(vec![1, 2])),
+            )),
+            bytes: SERVER_SERIALIZED_STATE_UPDATE,
+        },
+        TestVector {
+            name: "client_command",
+            message: Sample::ClientToServer(ClientToServer::Command(6, 3)),
+            bytes: CLIENT_COMMAND,
+        },
+        TestVector {
+            name: "client_message",
+            message: Sample::ClientToServer(ClientToServer::Message(ClientMessage::User(7))),
+            bytes: CLIENT_MESSAGE,
+        },
+        TestVector {
+            name: "client_pong",
+            message: Sample::ClientToServer(ClientToServer::Message(ClientMessage::Pong(
+                Duration::new(2, 5),
+            ))),
+            bytes: CLIENT_PONG,
+        },
+        TestVector {
+            name: "client_state_ack",
+            message: Sample::ClientToServer(ClientToServer::Message(ClientMessage::StateAck(9))),
+            bytes: CLIENT_STATE_ACK,
+        },
+        TestVector {
+            name: "client_bundle",
+            message: Sample::ClientToServer(ClientToServer::Message(ClientMessage::Bundle(vec![
+                ClientMessage::StateAck(9),
+                ClientMessage::Command(6, 3),
+            ]))),
+            bytes: CLIENT_BUNDLE,
+        },
+        TestVector {
+            name: "initial_sync",
+            message: Sample::InitialSync(InitialSync {
+                rng_seed: 42,
+                world: vec![1, 2],
+            }),
+            bytes: INITIAL_SYNC,
+        },
+    ]
+}
+
+fn state_update() -> WorldState {
+    let mut state = WorldState::new(4);
+    state.command_frame_offset = -1;
+    state.remove_entity(1);
+    state.insert_entity(2, vec![ComponentData::new(1, vec![1, 2, 3])]);
+    state.remove_component(3, 1);
+    state.add_component(3, ComponentData::new(2, vec![4]));
+    state.change(4, ComponentData::new(1, vec![5, 6]));
+    state
+}
+
+fn command_result(outcome: CommandOutcome<u32>) -> Sample {
+    Sample::ServerToClient(ServerToClient::Message(ServerMessage::CommandResult(
+        CommandResult {
+            command_frame: 9,
+            outcome,
+        },
+    )))
+}
+
+#[rustfmt::skip]
+const SERVER_INITIAL_STATE_SYNC: &[u8] = &[
+    0, 0, 0, 0, // ServerToClient::InitialStateSync
+    3, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, // bytes
+];
+
+#[rustfmt::skip]
+const SERVER_STATE_UPDATE: &[u8] = &[
+    1, 0, 0, 0, // ServerToClient::StateUpdate
+    4, 0, 0, 0, // command_frame
+    255, 255, 255, 255, // command_frame_offset
+    1, 0, 0, 0, 0, 0, 0, 0, // removed
+    1, 0, 0, 0, // entity_id
+    1, 0, 0, 0, 0, 0, 0, 0, // inserted
+    2, 0, 0, 0, // entity_id
+    1, 0, 0, 0, 0, 0, 0, 0, // components
+    1, 0, 0, 0, // component_id
+    3, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, // data
+    1, 0, 0, 0, 0, 0, 0, 0, // component_removed
+    3, 0, 0, 0, // entity_id
+    1, 0, 0, 0, // component_id
+    1, 0, 0, 0, 0, 0, 0, 0, // component_added
+    3, 0, 0, 0, // entity_id
+    2, 0, 0, 0, // component_id
+    1, 0, 0, 0, 0, 0, 0, 0, 4, // data
+    1, 0, 0, 0, 0, 0, 0, 0, // changed
+    4, 0, 0, 0, // entity_id
+    1, 0, 0, 0, // component_id
+    2, 0, 0, 0, 0, 0, 0, 0, 5, 6, // data
+];
+
+#[rustfmt::skip]
+const SERVER_USER_MESSAGE: &[u8] = &[
+    2, 0, 0, 0, // ServerToClient::Message
+    0, 0, 0, 0, // ServerMessage::User
+    7, 0, 0, 0, // message
+];
+
+#[rustfmt::skip]
+const SERVER_COMMAND_ACCEPTED: &[u8] = &[
+    2, 0, 0, 0, // ServerToClient::Message
+    1, 0, 0, 0, // ServerMessage::CommandResult
+    9, 0, 0, 0, // command_frame
+    0, 0, 0, 0, // CommandOutcome::Accepted
+];
+
+#[rustfmt::skip]
+const SERVER_COMMAND_REJECTED: &[u8] = &[
+    2, 0, 0, 0, // ServerToClient::Message
+    1, 0, 0, 0, // ServerMessage::CommandResult
+    9, 0, 0, 0, // command_frame
+    1, 0, 0, 0, // CommandOutcome::Rejected
+    2, 0, 0, 0, 0, 0, 0, 0, b'n', b'o', // reason
+];
+
+#[rustfmt::skip]
+const SERVER_COMMAND_TRANSFORMED: &[u8] = &[
+    2, 0, 0, 0, // ServerToClient::Message
+    1, 0, 0, 0, // ServerMessage::CommandResult
+    9, 0, 0, 0, // command_frame
+    2, 0, 0, 0, // CommandOutcome::Transformed
+    3, 0, 0, 0, // command
+];
+
+#[rustfmt::skip]
+const SERVER_CONTEXT_INITIAL_SYNC: &[u8] = &[
+    2, 0, 0, 0, // ServerToClient::Message
+    2, 0, 0, 0, // ServerMessage::ContextInitialSync
+    1, 0, // context
+    1, 0, 0, 0, 0, 0, 0, 0, 1, // bytes
+];
+
+#[rustfmt::skip]
+const SERVER_CONTEXT_STATE_UPDATE: &[u8] = &[
+    2, 0, 0, 0, // ServerToClient::Message
+    3, 0, 0, 0, // ServerMessage::ContextStateUpdate
+    1, 0, // context
+    5, 0, 0, 0, // command_frame
+    0, 0, 0, 0, // command_frame_offset
+    0, 0, 0, 0, 0, 0, 0, 0, // removed
+    0, 0, 0, 0, 0, 0, 0, 0, // inserted
+    0, 0, 0, 0, 0, 0, 0, 0, // component_removed
+    0, 0, 0, 0, 0, 0, 0, 0, // component_added
+    0, 0, 0, 0, 0, 0, 0, 0, // changed
+];
+
+#[rustfmt::skip]
+const SERVER_STATE_UPDATE_PART: &[u8] = &[
+    2, 0, 0, 0, // ServerToClient::Message
+    4, 0, 0, 0, // ServerMessage::StateUpdatePart
+    1, 0, // part
+    1, // complete
+    5, 0, 0, 0, // command_frame
+    0, 0, 0, 0, // command_frame_offset
+    0, 0, 0, 0, 0, 0, 0, 0, // removed
+    0, 0, 0, 0, 0, 0, 0, 0, // inserted
+    0, 0, 0, 0, 0, 0, 0, 0, // component_removed
+    0, 0, 0, 0, 0, 0, 0, 0, // component_added
+    0, 0, 0, 0, 0, 0, 0, 0, // changed
+];
+
+#[rustfmt::skip]
+const SERVER_DISCONNECT: &[u8] = &[
+    2, 0, 0, 0, // ServerToClient::Message
+    5, 0, 0, 0, // ServerMessage::Disconnect
+    3, 0, 0, 0, // DisconnectReason::ProtocolError
+    3, 0, // code
+];
+
+#[rustfmt::skip]
+const SERVER_RESYNC: &[u8] = &[
+    2, 0, 0, 0, // ServerToClient::Message
+    6, 0, 0, 0, // ServerMessage::Resync
+    5, 0, 0, 0, 0, 0, 0, 0, // rng seed
+];
+
+#[rustfmt::skip]
+const SERVER_REGION_MANIFEST: &[u8] = &[
+    2, 0, 0, 0, // ServerToClient::Message
+    7, 0, 0, 0, // ServerMessage::RegionManifest
+    3, 0, 0, 0, // region
+    1, // resident
+    2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, // entities
+];
+
+#[rustfmt::skip]
+const SERVER_BUNDLE: &[u8] = &[
+    2, 0, 0, 0, // ServerToClient::Message
+    8, 0, 0, 0, // ServerMessage::Bundle
+    2, 0, 0, 0, 0, 0, 0, 0, // message count
+    6, 0, 0, 0, // ServerMessage::Resync
+    5, 0, 0, 0, 0, 0, 0, 0, // rng seed
+    0, 0, 0, 0, // ServerMessage::User
+    7, 0, 0, 0, // message
+];
+
+#[rustfmt::skip]
+const SERVER_PING: &[u8] = &[
+    2, 0, 0, 0, // ServerToClient::Message
+    9, 0, 0, 0, // ServerMessage::Ping
+    2, 0, 0, 0, 0, 0, 0, 0, // secs
+    5, 0, 0, 0, // nanos
+];
+
+#[rustfmt::skip]
+const SERVER_STATE_BASELINE: &[u8] = &[
+    2, 0, 0, 0, // ServerToClient::Message
+    10, 0, 0, 0, // ServerMessage::StateBaseline
+    9, 0, 0, 0, // command_frame
+    6, 0, 0, 0, // baseline
+];
+
+#[rustfmt::skip]
+const SERVER_SERIALIZED_STATE_UPDATE: &[u8] = &[
+    2, 0, 0, 0, // ServerToClient::Message
+    11, 0, 0, 0, // ServerMessage::SerializedStateUpdate
+    2, 0, 0, 0, 0, 0, 0, 0, 1, 2, // bytes
+];
+
+#[rustfmt::skip]
+const CLIENT_COMMAND: &[u8] = &[
+    0, 0, 0, 0, // ClientToServer::Command
+    6, 0, 0, 0, // command_frame
+    3, 0, 0, 0, // command
+];
+
+#[rustfmt::skip]
+const CLIENT_MESSAGE: &[u8] = &[
+    1, 0, 0, 0, // ClientToServer::Message
+    0, 0, 0, 0, // ClientMessage::User
+    7, 0, 0, 0, // message
+];
+
+#[rustfmt::skip]
+const CLIENT_PONG: &[u8] = &[
+    1, 0, 0, 0, // ClientToServer::Message
+    1, 0, 0, 0, // ClientMessage::Pong
+    2, 0, 0, 0, 0, 0, 0, 0, // secs
+    5, 0, 0, 0, // nanos
+];
+
+#[rustfmt::skip]
+const CLIENT_STATE_ACK: &[u8] = &[
+    1, 0, 0, 0, // ClientToServer::Message
+    2, 0, 0, 0, // ClientMessage::StateAck
+    9, 0, 0, 0, // command_frame
+];
+
+#[rustfmt::skip]
+const CLIENT_BUNDLE: &[u8] = &[
+    1, 0, 0, 0, // ClientToServer::Message
+    3, 0, 0, 0, // ClientMessage::Bundle
+    2, 0, 0, 0, 0, 0, 0, 0, // message count
+    2, 0, 0, 0, // ClientMessage::StateAck
+    9, 0, 0, 0, // command_frame
+    4, 0, 0, 0, // ClientMessage::Command
+    6, 0, 0, 0, // command_frame
+    3, 0, 0, 0, // command
+];
+
+#[rustfmt::skip]
+const INITIAL_SYNC: &[u8] = &[
+    42, 0, 0, 0, 0, 0, 0, 0, // rng_seed
+    2, 0, 0, 0, 0, 0, 0, 0, 1, 2, // world
+];
+
+#[cfg(all(test, feature = "std"))]
+pub mod test {
+    use bincode::Options;
+
+    use crate::{
+        protocol::{
+            conformance::{validate, Codec, Sample},
+            ClientMessage, ClientToServer, InitialSync, ServerMessage, ServerToClient,
+        },
+        tracking::re_exports::bincode,
+        world::default_options,
+    };
+
+    struct BincodeCodec;
+
+    impl Codec for BincodeCodec {
+        fn encode(&mut self, message: &Sample) -> Vec<u8> {
+            match message {
+                Sample::ServerToClient(message) => default_options().serialize(message),
+                Sample::ClientToServer(message) => default_options().serialize(message),
+                Sample::InitialSync(message) => default_options().serialize(message),
+            }
+            .unwrap()
+        }
+
+        fn decode(&mut self, like: &Sample, bytes: &[u8]) -> Option<Sample> {
+            match like {
+                Sample::ServerToClient(_) => default_options()
+                    .deserialize::<ServerToClient<ServerMessage<u32, u32>>>(bytes)
+                    .map(Sample::ServerToClient)
+                    .ok(),
+                Sample::ClientToServer(_) => default_options()
+                    .deserialize::<ClientToServer<ClientMessage<u32, u32>, u32>>(bytes)
+                    .map(Sample::ClientToServer)
+                    .ok(),
+                Sample::InitialSync(_) => default_options()
+                    .deserialize::<InitialSync>(bytes)
+                    .map(Sample::InitialSync)
+                    .ok(),
+            }
+        }
+    }
+
+    #[test]
+    fn crate_encoding_matches_test_vectors_test() {
+        assert_eq!(validate(&mut BincodeCodec), Ok(()));
+    }
+}
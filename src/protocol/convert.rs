@@ -0,0 +1,119 @@
+//! Conversions between the `protocol` types and the `net-sync` types used by the worlds.
+
+use net_sync::{
+    synchronisation::{self, NetworkCommand, NetworkMessage},
+    transport,
+};
+
+use super::{
+    ClientMessage, CommandFrame, ComponentData, PlayerCommand, ServerMessage, ServerToClient,
+    StateFrame, WorldState,
+};
+
+impl<M: NetworkMessage, C: NetworkCommand> NetworkMessage for ServerMessage<M, C> {}
+
+impl<M: NetworkMessage, C: NetworkCommand> NetworkMessage for ClientMessage<M, C> {}
+
+impl<C: NetworkCommand> NetworkCommand for PlayerCommand<C> {}
+
+impl From<&synchronisation::ComponentData> for ComponentData {
+    fn from(data: &synchronisation::ComponentData) -> Self {
+        ComponentData::new(data.component_id(), data.data().to_vec())
+    }
+}
+
+impl From<ComponentData> for synchronisation::ComponentData {
+    fn from(data: ComponentData) -> Self {
+        synchronisation::ComponentData::new(data.component_id, data.data)
+    }
+}
+
+impl From<&synchronisation::WorldState> for WorldState {
+    fn from(state: &synchronisation::WorldState) -> Self {
+        let mut result = WorldState::new(state.command_frame);
+        result.command_frame_offset = state.command_frame_offset;
+
+        for removed in state.removed.iter() {
+            result.remove_entity(*removed);
+        }
+
+        for inserted in state.inserted.iter() {
+            result.insert_entity(
+                inserted.entity_id(),
+                inserted.components().iter().map(ComponentData::from).collect(),
+            );
+        }
+
+        for removed in state.component_removed.iter() {
+            result.remove_component(removed.entity_id(), removed.component_id());
+        }
+
+        for added in state.component_added.iter() {
+            result.add_component(added.entity_id(), added.component_data().into());
+        }
+
+        for changed in state.changed.iter() {
+            result.change(changed.entity_id(), changed.component_data().into());
+        }
+
+        result
+    }
+}
+
+impl From<WorldState> for synchronisation::WorldState {
+    fn from(state: WorldState) -> Self {
+        let mut result = synchronisation::WorldState::new(state.command_frame);
+        result.command_frame_offset = state.command_frame_offset;
+
+        for removed in state.removed {
+            result.remove_entity(removed);
+        }
+
+        for inserted in state.inserted {
+            result.insert_entity(
+                inserted.entity_id,
+                inserted.components.into_iter().map(Into::into).collect(),
+            );
+        }
+
+        for removed in state.component_removed {
+            result.remove_component(removed.entity_id, removed.component_id);
+        }
+
+        for added in state.component_added {
+            result.add_component(added.entity_id, added.component_data.into());
+        }
+
+        for changed in state.changed {
+            result.change(changed.entity_id, changed.component_data.into());
+        }
+
+        result
+    }
+}
+
+impl StateFrame for synchronisation::WorldState {
+    fn command_frame(&self) -> CommandFrame {
+        self.command_frame
+    }
+
+    fn command_frame_offset(&self) -> i32 {
+        self.command_frame_offset
+    }
+}
+
+impl<M> From<transport::ServerToClientMessage<M>>
+    for ServerToClient<M, synchronisation::WorldState>
+{
+    fn from(message: transport::ServerToClientMessage<M>) -> Self {
+        match message {
+            transport::ServerToClientMessage::InitialStateSync(initial_sync) => {
+                ServerToClient::InitialStateSync(initial_sync)
+            }
+            transport::ServerToClientMessage::StateUpdate(state) => {
+                ServerToClient::StateUpdate(state)
+            }
+            transport::ServerToClientMessage::Message(message) => ServerToClient::Message(message),
+        }
+    }
+}
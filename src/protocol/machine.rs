@@ -0,0 +1,663 @@
+//! Sans-io state machines of the client and server side of the protocol.
+//!
+//! The machines do not touch sockets or worlds, they are fed with the received messages
+//! and return the actions the caller has to perform. The client and server worlds are the
+//! adapters that perform those actions on legion worlds with the net-sync transport.
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::{mem, time::Duration};
+
+use super::{
+    CommandFrame, CommandResult, ContextId, DisconnectReason, RegionManifest, ServerMessage,
+    ServerToClient, StateReassembler, WorldState,
+};
+
+/// The number of command frames the client runs ahead of the first received state update.
+pub const COMMAND_FRAME_LEAD: CommandFrame = 3;
+
+/// A state update that knows the command frame it belongs to.
+pub trait StateFrame {
+    fn command_frame(&self) -> CommandFrame;
+
+    /// The offset between the command frame of the client and the server, as seen by the server.
+    fn command_frame_offset(&self) -> i32;
+}
+
+impl StateFrame for WorldState {
+    fn command_frame(&self) -> CommandFrame {
+        self.command_frame
+    }
+
+    fn command_frame_offset(&self) -> i32 {
+        self.command_frame_offset
+    }
+}
+
+/// What the client has to do in reaction to a received message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientAction<M, C, S = WorldState> {
+    /// Apply the serialized [InitialSync](struct.InitialSync.html).
+    ApplyInitialSync(Vec<u8>),
+    /// Speed the simulation up or down, the client is `offset` frames away from where it should be.
+    AdjustSimulation {
+        command_frame: CommandFrame,
+        offset: i32,
+    },
+    /// Jump to the given command frame, done once when the first state update arrives.
+    SetCommandFrame(CommandFrame),
+    /// Apply the changes of one command frame.
+    ApplyStateUpdate(S),
+    /// Deserialize the state update and hand it to `ClientProtocol::decoded_state_update`.
+    DecodeStateUpdate(Arc<Vec<u8>>),
+    /// Report the outcome of a command.
+    CommandResult(CommandResult<C>),
+    /// Apply the serialized initial sync of an additional replication context.
+    ApplyContextInitialSync(ContextId, Vec<u8>),
+    /// Apply the changes of one command frame of an additional replication context.
+    ApplyContextStateUpdate(ContextId, WorldState),
+    /// The server closed the connection, a reconnect starts with a new initial sync.
+    Disconnected(DisconnectReason),
+    /// Report the population of a region the client entered or left.
+    RegionManifest(RegionManifest),
+    /// Answer the ping of the server with `ClientMessage::Pong` and the time of the ping.
+    Pong(Duration),
+    /// The next state update of `command_frame` holds the changes since `baseline`, restore the
+    /// changed components to their values at `baseline` before applying it.
+    StateBaseline {
+        command_frame: CommandFrame,
+        baseline: CommandFrame,
+    },
+    /// Deliver a user defined message.
+    User(M),
+    /// Re-seed the `SyncedRng` with the seed of the server, its command frame jumped.
+    Reseed(u64),
+}
+
+/// The client side of the protocol.
+#[derive(Debug, Default)]
+pub struct ClientProtocol {
+    synced: bool,
+    received_first_update: bool,
+    reassembler: StateReassembler,
+}
+
+impl ClientProtocol {
+    pub fn new() -> ClientProtocol {
+        ClientProtocol::default()
+    }
+
+    /// Returns whether the initial state sync was received.
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    /// Handles a message of the server and returns the actions to perform, in order.
+    pub fn handle<M, C, S>(
+        &mut self,
+        message: ServerToClient<ServerMessage<M, C>, S>,
+    ) -> Vec<ClientAction<M, C, S>>
+    where
+        S: StateFrame + From<WorldState>,
+    {
+        match message {
+            ServerToClient::InitialStateSync(initial_sync) => {
+                self.synced = true;
+                vec![ClientAction::ApplyInitialSync(initial_sync)]
+            }
+            ServerToClient::StateUpdate(state) => self.state_update(state),
+            ServerToClient::Message(ServerMessage::User(message)) => {
+                vec![ClientAction::User(message)]
+            }
+            ServerToClient::Message(ServerMessage::CommandResult(result)) => {
+                vec![ClientAction::CommandResult(result)]
+            }
+            ServerToClient::Message(ServerMessage::ContextInitialSync(id, initial_sync)) => {
+                vec![ClientAction::ApplyContextInitialSync(id, initial_sync)]
+            }
+            ServerToClient::Message(ServerMessage::ContextStateUpdate(id, state)) => {
+                vec![ClientAction::ApplyContextStateUpdate(id, state)]
+            }
+            ServerToClient::Message(ServerMessage::StateUpdatePart(part)) => {
+                // Split state updates are only applied once all their parts arrived.
+                match self.reassembler.push(part) {
+                    Some(state) => self.state_update(S::from(state)),
+                    None => Vec::new(),
+                }
+            }
+            ServerToClient::Message(ServerMessage::Disconnect(reason)) => {
+                *self = ClientProtocol::new();
+                vec![ClientAction::Disconnected(reason)]
+            }
+            ServerToClient::Message(ServerMessage::Resync(seed)) => {
+                self.received_first_update = false;
+                vec![ClientAction::Reseed(seed)]
+            }
+            ServerToClient::Message(ServerMessage::RegionManifest(manifest)) => {
+                vec![ClientAction::RegionManifest(manifest)]
+            }
+            ServerToClient::Message(ServerMessage::Bundle(messages)) => messages
+                .into_iter()
+                .flat_map(|message| self.handle(ServerToClient::Message(message)))
+                .collect(),
+            ServerToClient::Message(ServerMessage::Ping(sent)) => vec![ClientAction::Pong(sent)],
+            ServerToClient::Message(ServerMessage::StateBaseline(command_frame, baseline)) => {
+                vec![ClientAction::StateBaseline {
+                    command_frame,
+                    baseline,
+                }]
+            }
+            ServerToClient::Message(ServerMessage::SerializedStateUpdate(bytes)) => {
+                vec![ClientAction::DecodeStateUpdate(bytes)]
+            }
+        }
+    }
+
+    /// Handles a state update of a `ClientAction::DecodeStateUpdate`, returns the actions to
+    /// perform in its place.
+    pub fn decoded_state_update<M, C, S: StateFrame>(
+        &mut self,
+        state: S,
+    ) -> Vec<ClientAction<M, C, S>> {
+        self.state_update(state)
+    }
+
+    fn state_update<M, C, S: StateFrame>(&mut self, state: S) -> Vec<ClientAction<M, C, S>> {
+        let mut actions = vec![ClientAction::AdjustSimulation {
+            command_frame: state.command_frame(),
+            offset: state.command_frame_offset(),
+        }];
+
+        if !self.received_first_update {
+            self.received_first_update = true;
+            actions.push(ClientAction::SetCommandFrame(
+                state.command_frame() + COMMAND_FRAME_LEAD,
+            ));
+        }
+
+        actions.push(ClientAction::ApplyStateUpdate(state));
+        actions
+    }
+}
+
+/// Orders the state updates of a burst, e.g. after a hitch, by their command frame.
+///
+/// Only the updates between initial syncs and disconnects are reordered, they start a new
+/// baseline. The other actions keep their position.
+pub fn order_state_updates<M, C, S: StateFrame>(
+    actions: Vec<ClientAction<M, C, S>>,
+) -> Vec<ClientAction<M, C, S>> {
+    fn fill<M, C, S: StateFrame>(
+        ordered: &mut Vec<Option<ClientAction<M, C, S>>>,
+        slots: &mut Vec<usize>,
+        states: &mut Vec<S>,
+    ) {
+        // Stable, updates of the same frame keep their order.
+        states.sort_by_key(|state| state.command_frame());
+
+        for (slot, state) in slots.drain(..).zip(states.drain(..)) {
+            ordered[slot] = Some(ClientAction::ApplyStateUpdate(state));
+        }
+    }
+
+    let mut ordered = Vec::with_capacity(actions.len());
+    let mut slots = Vec::new();
+    let mut states = Vec::new();
+
+    for action in actions {
+        match action {
+            ClientAction::ApplyStateUpdate(state) => {
+                slots.push(ordered.len());
+                states.push(state);
+                ordered.push(None);
+            }
+            ClientAction::ApplyInitialSync(_) | ClientAction::Disconnected(_) => {
+                fill(&mut ordered, &mut slots, &mut states);
+                ordered.push(Some(action));
+            }
+            action => ordered.push(Some(action)),
+        }
+    }
+    fill(&mut ordered, &mut slots, &mut states);
+
+    ordered.into_iter().flatten().collect()
+}
+
+/// What the server has to send to a client.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerAction<K, S = WorldState> {
+    /// Send the initial state sync to a client that just connected.
+    SendInitialSync(K),
+    /// Send the changes of one command frame.
+    SendStateUpdate(K, S),
+    /// Send the merged changes of several command frames, the state is specific to the client.
+    SendMergedStateUpdate(K, S),
+}
+
+/// The server side of the protocol, `K` identifies a client connection.
+///
+/// Clients receive the initial state sync on the first frame after they connected.
+/// State updates are sent every `interval` frames, they are queued in between.
+/// With `with_merge` the queued updates are sent as one update.
+#[derive(Debug)]
+pub struct ServerProtocol<K, S = WorldState> {
+    synced: Vec<K>,
+    pending: Vec<(K, Vec<S>)>,
+    // Clients of which a queued update holds the changes of several command frames.
+    merged: Vec<K>,
+    merge: Option<fn(S, S) -> S>,
+}
+
+impl<K: Copy + PartialEq, S: Clone> ServerProtocol<K, S> {
+    pub fn new() -> ServerProtocol<K, S> {
+        ServerProtocol {
+            synced: Vec::new(),
+            pending: Vec::new(),
+            merged: Vec::new(),
+            merge: None,
+        }
+    }
+
+    /// Merges the queued state updates of a client into one update with `merge(older, newer)`
+    /// when they are sent, instead of sending them one after the other.
+    pub fn with_merge(mut self, merge: fn(S, S) -> S) -> Self {
+        self.merge = Some(merge);
+        self
+    }
+
+    pub fn is_synced(&self, client: K) -> bool {
+        self.synced.contains(&client)
+    }
+
+    /// The number of state updates that are queued for the client until its next send frame.
+    pub fn pending(&self, client: K) -> usize {
+        self.pending
+            .iter()
+            .find(|(pending, _)| *pending == client)
+            .map_or(0, |(_, states)| states.len())
+    }
+
+    /// The state updates that are queued for the client until its next send frame.
+    pub fn pending_states(&self, client: K) -> impl Iterator<Item = &S> + '_ {
+        self.pending
+            .iter()
+            .filter(move |(pending, _)| *pending == client)
+            .flat_map(|(_, states)| states.iter())
+    }
+
+    /// Folds the queued state updates of the client into the newest one with
+    /// `merge(older, newer)`, returns the number of updates that were folded.
+    pub fn coalesce_pending(&mut self, client: K, mut merge: impl FnMut(S, S) -> S) -> usize {
+        let pending = match self.pending.iter_mut().find(|(pending, _)| *pending == client) {
+            Some((_, pending)) => pending,
+            None => return 0,
+        };
+
+        let folded = pending.len().saturating_sub(1);
+        let mut states = mem::take(pending).into_iter();
+
+        if let Some(oldest) = states.next() {
+            pending.push(states.fold(oldest, |older, newer| merge(older, newer)));
+        }
+
+        if folded > 0 && !self.merged.contains(&client) {
+            self.merged.push(client);
+        }
+
+        folded
+    }
+
+    /// Forgets a client, it receives a new initial sync when it connects again.
+    pub fn disconnect(&mut self, client: K) {
+        self.synced.retain(|synced| *synced != client);
+        self.pending.retain(|(pending, _)| *pending != client);
+        self.merged.retain(|merged| *merged != client);
+    }
+
+    /// Moves the synchronisation state of a client to another client id,
+    /// e.g. when the client reconnected from a new address.
+    ///
+    /// The client keeps its pending updates and does not receive a new initial sync.
+    pub fn rebind(&mut self, from: K, to: K) {
+        if from == to {
+            return;
+        }
+
+        self.disconnect(to);
+
+        for synced in self.synced.iter_mut().filter(|synced| **synced == from) {
+            *synced = to;
+        }
+        for (pending, _) in self.pending.iter_mut().filter(|(pending, _)| *pending == from) {
+            *pending = to;
+        }
+        for merged in self.merged.iter_mut().filter(|merged| **merged == from) {
+            *merged = to;
+        }
+    }
+
+    /// Handles the state of a command frame for the connected clients and their update interval.
+    ///
+    /// `state` is `None` when nothing changed in the frame.
+    pub fn frame(
+        &mut self,
+        command_frame: CommandFrame,
+        state: Option<S>,
+        clients: impl IntoIterator<Item = (K, u32)>,
+    ) -> Vec<ServerAction<K, S>> {
+        let mut actions = Vec::new();
+
+        for (client, interval) in clients {
+            if !self.synced.contains(&client) {
+                self.synced.push(client);
+                actions.push(ServerAction::SendInitialSync(client));
+            }
+
+            let pending = match self.pending.iter().position(|(id, _)| *id == client) {
+                Some(index) => &mut self.pending[index].1,
+                None => {
+                    self.pending.push((client, Vec::new()));
+                    &mut self.pending.last_mut().unwrap().1
+                }
+            };
+
+            if let Some(state) = &state {
+                pending.push(state.clone());
+            }
+
+            if command_frame % interval.max(1) != 0 {
+                continue;
+            }
+
+            let was_merged = self.merged.contains(&client);
+            self.merged.retain(|merged| *merged != client);
+
+            match self.merge {
+                Some(merge) if pending.len() > 1 || was_merged => {
+                    let mut states = pending.drain(..);
+
+                    if let Some(oldest) = states.next() {
+                        let state = states.fold(oldest, merge);
+                        actions.push(ServerAction::SendMergedStateUpdate(client, state));
+                    }
+                }
+                _ if was_merged => actions.extend(
+                    pending
+                        .drain(..)
+                        .map(|state| ServerAction::SendMergedStateUpdate(client, state)),
+                ),
+                _ => actions.extend(
+                    pending
+                        .drain(..)
+                        .map(|state| ServerAction::SendStateUpdate(client, state)),
+                ),
+            }
+        }
+
+        actions
+    }
+}
+
+impl<K: Copy + PartialEq, S: Clone> Default for ServerProtocol<K, S> {
+    fn default() -> Self {
+        ServerProtocol::new()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use alloc::{sync::Arc, vec, vec::Vec};
+    use core::time::Duration;
+
+    use crate::protocol::{
+        order_state_updates, ClientAction, ClientProtocol, CommandOutcome, CommandResult,
+        DisconnectReason, ServerAction, ServerMessage, ServerProtocol, ServerToClient, WorldState,
+    };
+
+    type Action = ClientAction<(), (), WorldState>;
+
+    fn update(command_frame: u32) -> ServerToClient<ServerMessage<(), ()>, WorldState> {
+        let mut state = WorldState::new(command_frame);
+        state.remove_entity(1);
+        ServerToClient::StateUpdate(state)
+    }
+
+    #[test]
+    fn first_update_sets_command_frame_test() {
+        let mut protocol = ClientProtocol::new();
+
+        let actions: Vec<Action> = protocol.handle(update(10));
+        assert!(actions.contains(&ClientAction::SetCommandFrame(13)));
+
+        let actions: Vec<Action> = protocol.handle(update(11));
+        assert!(!actions
+            .iter()
+            .any(|action| matches!(action, ClientAction::SetCommandFrame(_))));
+
+        let actions: Vec<Action> =
+            protocol.handle(ServerToClient::Message(ServerMessage::Resync(5)));
+        assert_eq!(actions, vec![ClientAction::Reseed(5)]);
+
+        let actions: Vec<Action> = protocol.handle(update(40));
+        assert!(actions.contains(&ClientAction::SetCommandFrame(43)));
+    }
+
+    #[test]
+    fn bundled_messages_are_handled_in_order_test() {
+        let mut protocol = ClientProtocol::new();
+        let result = CommandResult {
+            command_frame: 4,
+            outcome: CommandOutcome::Accepted,
+        };
+
+        let bundle = ServerMessage::bundle(vec![
+            ServerMessage::CommandResult(result.clone()),
+            ServerMessage::Resync(5),
+            ServerMessage::Disconnect(DisconnectReason::Kicked),
+        ]);
+        assert!(matches!(bundle, Some(ServerMessage::Bundle(_))));
+
+        let actions: Vec<Action> = protocol.handle(ServerToClient::Message(bundle.unwrap()));
+        assert_eq!(
+            actions,
+            vec![
+                ClientAction::CommandResult(result),
+                ClientAction::Reseed(5),
+                ClientAction::Disconnected(DisconnectReason::Kicked)
+            ]
+        );
+        assert_eq!(
+            ServerMessage::<(), ()>::bundle(vec![ServerMessage::Resync(5)]),
+            Some(ServerMessage::Resync(5))
+        );
+    }
+
+    #[test]
+    fn burst_updates_are_ordered_by_frame_test() {
+        let mut protocol = ClientProtocol::new();
+        let frames = |actions: &[Action]| {
+            actions
+                .iter()
+                .filter_map(|action| match action {
+                    ClientAction::ApplyStateUpdate(state) => Some(state.command_frame),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Out of order arrival within one tick.
+        let mut actions: Vec<Action> = Vec::new();
+        for command_frame in [12, 10, 11, 11].iter() {
+            actions.extend(protocol.handle(update(*command_frame)));
+        }
+        let ordered = order_state_updates(actions.clone());
+        assert_eq!(frames(&ordered), vec![10, 11, 11, 12]);
+        assert_eq!(ordered.len(), actions.len());
+        assert_eq!(ordered[0], actions[0]);
+
+        // Updates are not moved across a new baseline.
+        let mut actions: Vec<Action> = protocol.handle(update(20));
+        actions.push(ClientAction::ApplyInitialSync(Vec::new()));
+        actions.extend(protocol.handle(update(5)));
+        let ordered = order_state_updates(actions);
+        assert_eq!(frames(&ordered), vec![20, 5]);
+    }
+
+    #[test]
+    fn split_update_is_applied_once_complete_test() {
+        let mut protocol = ClientProtocol::new();
+
+        let mut state = WorldState::new(5);
+        for id in 0..10 {
+            state.remove_entity(id);
+        }
+        let parts = state.clone().split(60);
+        assert!(parts.len() > 1);
+
+        let mut applied = Vec::new();
+        for part in parts {
+            let message = ServerToClient::Message(ServerMessage::StateUpdatePart(part));
+            let actions: Vec<Action> = protocol.handle(message);
+
+            for action in actions {
+                if let ClientAction::ApplyStateUpdate(state) = action {
+                    applied.push(state);
+                }
+            }
+        }
+
+        assert_eq!(applied, vec![state]);
+    }
+
+    #[test]
+    fn disconnect_resets_sync_test() {
+        let mut protocol = ClientProtocol::new();
+        let _: Vec<Action> = protocol.handle(ServerToClient::InitialStateSync(vec![1]));
+
+        let message = ServerToClient::Message(ServerMessage::Disconnect(DisconnectReason::Kicked));
+        let actions: Vec<Action> = protocol.handle(message);
+
+        assert_eq!(
+            actions,
+            vec![ClientAction::Disconnected(DisconnectReason::Kicked)]
+        );
+        assert!(!protocol.is_synced());
+    }
+
+    #[test]
+    fn new_clients_get_initial_sync_once_test() {
+        let mut protocol = ServerProtocol::<u32, u32>::new();
+
+        let actions = protocol.frame(1, None, vec![(1, 1)]);
+        assert_eq!(actions, vec![ServerAction::SendInitialSync(1)]);
+
+        let actions = protocol.frame(2, Some(2), vec![(1, 1)]);
+        assert_eq!(actions, vec![ServerAction::SendStateUpdate(1, 2)]);
+    }
+
+    #[test]
+    fn updates_are_batched_by_interval_test() {
+        let mut protocol = ServerProtocol::<u32, u32>::new();
+        protocol.frame(0, None, vec![(1, 2)]);
+
+        assert!(protocol.frame(1, Some(1), vec![(1, 2)]).is_empty());
+        assert_eq!(protocol.pending(1), 1);
+        assert_eq!(protocol.pending_states(1).collect::<Vec<_>>(), vec![&1]);
+        assert_eq!(
+            protocol.frame(2, Some(2), vec![(1, 2)]),
+            vec![
+                ServerAction::SendStateUpdate(1, 1),
+                ServerAction::SendStateUpdate(1, 2)
+            ]
+        );
+        assert_eq!(protocol.pending(1), 0);
+
+        protocol.frame(3, Some(3), vec![(1, 2)]);
+        protocol.frame(5, Some(5), vec![(1, 3)]);
+        assert_eq!(protocol.coalesce_pending(1, |older, newer| older + newer), 1);
+        assert_eq!(protocol.pending_states(1).collect::<Vec<_>>(), vec![&8]);
+    }
+
+    #[test]
+    fn batched_updates_are_merged_test() {
+        let mut protocol =
+            ServerProtocol::<u32, u32>::new().with_merge(|older, newer| older + newer);
+        protocol.frame(0, None, vec![(1, 3), (2, 1)]);
+
+        protocol.frame(1, Some(1), vec![(1, 3), (2, 1)]);
+        protocol.frame(2, Some(2), vec![(1, 3), (2, 1)]);
+        assert_eq!(
+            protocol.frame(3, Some(3), vec![(1, 3), (2, 1)]),
+            vec![
+                ServerAction::SendMergedStateUpdate(1, 6),
+                ServerAction::SendStateUpdate(2, 3)
+            ]
+        );
+
+        // A coalesced update is sent as merged, also when it is the only one.
+        protocol.frame(4, Some(4), vec![(1, 3)]);
+        protocol.frame(5, Some(5), vec![(1, 3)]);
+        protocol.coalesce_pending(1, |older, newer| older + newer);
+        assert_eq!(
+            protocol.frame(6, None, vec![(1, 3)]),
+            vec![ServerAction::SendMergedStateUpdate(1, 9)]
+        );
+        assert_eq!(
+            protocol.frame(9, Some(9), vec![(1, 3)]),
+            vec![ServerAction::SendStateUpdate(1, 9)]
+        );
+    }
+
+    #[test]
+    fn pings_and_baselines_are_handled_test() {
+        let mut protocol = ClientProtocol::new();
+
+        let ping = ServerMessage::Ping(Duration::from_millis(40));
+        let actions: Vec<Action> = protocol.handle(ServerToClient::Message(ping));
+
+        assert_eq!(actions, vec![ClientAction::Pong(Duration::from_millis(40))]);
+
+        let baseline = ServerMessage::StateBaseline(9, 6);
+        let actions: Vec<Action> = protocol.handle(ServerToClient::Message(baseline));
+
+        assert_eq!(
+            actions,
+            vec![ClientAction::StateBaseline {
+                command_frame: 9,
+                baseline: 6
+            }]
+        );
+    }
+
+    #[test]
+    fn serialized_update_is_decoded_test() {
+        let mut protocol = ClientProtocol::new();
+
+        let bytes = Arc::new(vec![1, 2]);
+        let message = ServerMessage::SerializedStateUpdate(bytes.clone());
+        let actions: Vec<Action> = protocol.handle(ServerToClient::Message(message));
+
+        assert_eq!(actions, vec![ClientAction::DecodeStateUpdate(bytes)]);
+
+        let actions: Vec<Action> = protocol.decoded_state_update(WorldState::new(10));
+        assert!(actions.contains(&ClientAction::SetCommandFrame(13)));
+        assert!(actions.contains(&ClientAction::ApplyStateUpdate(WorldState::new(10))));
+    }
+
+    #[test]
+    fn rebound_client_keeps_sync_state_test() {
+        let mut protocol = ServerProtocol::<u32, u32>::new();
+        protocol.frame(0, None, vec![(1, 2)]);
+        protocol.frame(1, Some(1), vec![(1, 2)]);
+
+        protocol.rebind(1, 5);
+
+        assert!(!protocol.is_synced(1));
+        assert_eq!(
+            protocol.frame(2, Some(2), vec![(5, 2)]),
+            vec![
+                ServerAction::SendStateUpdate(5, 1),
+                ServerAction::SendStateUpdate(5, 2)
+            ]
+        );
+    }
+}
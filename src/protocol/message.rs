@@ -0,0 +1,178 @@
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::{CommandFrame, ContextId, RegionId, StateUpdatePart, Uid, WorldState};
+
+/// Envelope of all messages the server sends to a client.
+///
+/// `S` is the state update type, the worlds use the `net-sync` state on the wire.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ServerToClient<M, S = WorldState> {
+    /// Serialized [InitialSync](struct.InitialSync.html), sent once when a client connects.
+    InitialStateSync(Vec<u8>),
+    /// Changes of one command frame.
+    StateUpdate(S),
+    /// User defined message.
+    Message(M),
+}
+
+/// Envelope of all messages a client sends to the server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ClientToServer<M, C> {
+    /// Command executed by the client at the given command frame.
+    Command(CommandFrame, C),
+    /// User defined message.
+    Message(M),
+}
+
+/// Index of a local player of a client, for several players that share one connection.
+pub type LocalPlayer = u8;
+
+/// Command of one of the local players of a client, e.g. in split-screen games.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerCommand<C> {
+    pub player: LocalPlayer,
+    pub command: C,
+}
+
+impl<C> PlayerCommand<C> {
+    pub fn new(player: LocalPlayer, command: C) -> PlayerCommand<C> {
+        PlayerCommand { player, command }
+    }
+}
+
+/// Payload of the initial state sync.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InitialSync {
+    /// Seed of the synchronized random number generator.
+    pub rng_seed: u64,
+    /// Serialized legion world.
+    pub world: Vec<u8>,
+}
+
+/// User message or synchronisation layer message sent by the server.
+///
+/// The server and client worlds wrap user defined messages in `ServerMessage::User`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ServerMessage<M, C> {
+    /// User defined message.
+    User(M),
+    /// Result of a command, only sent to the client that issued the command.
+    CommandResult(CommandResult<C>),
+    /// Serialized [InitialSync](struct.InitialSync.html) of an additional replication context.
+    ContextInitialSync(ContextId, Vec<u8>),
+    /// Changes of one command frame of an additional replication context.
+    ContextStateUpdate(ContextId, WorldState),
+    /// Part of a state update that exceeded the maximum packet size of the server.
+    StateUpdatePart(StateUpdatePart),
+    /// The server closes the connection, sent as the last message to the client.
+    Disconnect(DisconnectReason),
+    /// The command frame of the server jumped, e.g. after a stall. The client sets its
+    /// command frame again with the next state update, and re-seeds its `SyncedRng` with the
+    /// seed of the server.
+    Resync(u64),
+    /// The entities of a region the client entered or left, sent before the state update that
+    /// inserts or removes them.
+    RegionManifest(RegionManifest),
+    /// Several messages in one transport frame, e.g. the synchronisation messages of a tick.
+    /// The messages are handled in order, user messages are delivered like the ones sent on
+    /// their own.
+    Bundle(Vec<ServerMessage<M, C>>),
+    /// Measures the round trip time, with the clock time of the server when it was sent.
+    /// The client answers with `ClientMessage::Pong`.
+    Ping(Duration),
+    /// The next state update of the command frame holds the changes since the baseline command
+    /// frame, sent to clients that acknowledge their updates. The client restores the changed
+    /// components to their values at the baseline before it applies the update, and answers
+    /// with `ClientMessage::StateAck`.
+    StateBaseline(CommandFrame, CommandFrame),
+    /// Changes of one command frame, serialized with the format of the worlds. The server
+    /// serializes a state once and shares the bytes between the clients that receive it.
+    SerializedStateUpdate(Arc<Vec<u8>>),
+}
+
+impl<M, C> ServerMessage<M, C> {
+    /// Packs the messages into one `Bundle`, a single message is returned as it is.
+    pub fn bundle(mut messages: Vec<ServerMessage<M, C>>) -> Option<ServerMessage<M, C>> {
+        match messages.len() {
+            0 => None,
+            1 => messages.pop(),
+            _ => Some(ServerMessage::Bundle(messages)),
+        }
+    }
+}
+
+/// User message or synchronisation layer message sent by a client.
+///
+/// The client and server worlds wrap user defined messages in `ClientMessage::User`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ClientMessage<M, C> {
+    /// User defined message.
+    User(M),
+    /// Answer to a `ServerMessage::Ping`, with the time of the ping.
+    Pong(Duration),
+    /// The client applied the state update of the command frame that followed a
+    /// `ServerMessage::StateBaseline`, with all the changes up to the frame.
+    StateAck(CommandFrame),
+    /// Several messages in one transport frame, e.g. the pong, acknowledgement and commands of
+    /// a tick. The messages are handled in order.
+    Bundle(Vec<ClientMessage<M, C>>),
+    /// Command executed by the client at the given command frame, like `ClientToServer::Command`.
+    /// Carries the commands of a `Bundle`.
+    Command(CommandFrame, C),
+}
+
+impl<M, C> ClientMessage<M, C> {
+    /// Packs the messages into one `Bundle`, a single message is returned as it is.
+    pub fn bundle(mut messages: Vec<ClientMessage<M, C>>) -> Option<ClientMessage<M, C>> {
+        match messages.len() {
+            0 => None,
+            1 => messages.pop(),
+            _ => Some(ClientMessage::Bundle(messages)),
+        }
+    }
+}
+
+/// The population of a streaming region at the time the client entered or left it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegionManifest {
+    pub region: RegionId,
+    /// Whether the region is resident on the client from now on.
+    pub resident: bool,
+    pub entities: Vec<Uid>,
+}
+
+/// Why the server closed the connection of a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DisconnectReason {
+    /// The client was removed by game code, e.g. by a moderator.
+    Kicked,
+    /// The server stops.
+    ServerShutdown,
+    /// The client did not respond in time.
+    Timeout,
+    /// The client sent something the server could not handle, with an application defined code.
+    ProtocolError(u16),
+    /// An application defined reason.
+    Custom(u16),
+}
+
+/// The way the server handled a command.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CommandOutcome<C> {
+    /// The command was executed as it was sent.
+    Accepted,
+    /// The command was not executed, with a reason that can be shown to the player.
+    Rejected(String),
+    /// The server executed a different command instead, e.g. a clamped movement.
+    Transformed(C),
+}
+
+/// The outcome of a command that was issued at the given command frame.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandResult<C> {
+    pub command_frame: CommandFrame,
+    pub outcome: CommandOutcome<C>,
+}
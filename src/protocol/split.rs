@@ -0,0 +1,307 @@
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::mem;
+
+use serde::{Deserialize, Serialize};
+
+use super::{state::EMPTY_STATE_SIZE, CommandFrame, ComponentData, Uid, WorldState};
+
+/// One part of a state update that was too big to be sent in a single packet.
+///
+/// The parts of a command frame are numbered from `0`, the last part has `complete` set.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateUpdatePart {
+    pub part: u16,
+    pub complete: bool,
+    pub state: WorldState,
+}
+
+impl WorldState {
+    /// Splits this state into parts with an estimated size of at most `max_size`.
+    ///
+    /// An entry that is bigger than `max_size` on its own gets a part of its own.
+    /// Concatenating the parts with `WorldState::merge` results in this state again.
+    pub fn split(self, max_size: usize) -> Vec<StateUpdatePart> {
+        let command_frame = self.command_frame;
+        let command_frame_offset = self.command_frame_offset;
+
+        let new_part = || {
+            let mut state = WorldState::new(command_frame);
+            state.command_frame_offset = command_frame_offset;
+            state
+        };
+
+        // One state per entry, in the order the entries are applied.
+        let entries = self
+            .removed
+            .into_iter()
+            .map(|entity_id| {
+                let mut state = new_part();
+                state.remove_entity(entity_id);
+                state
+            })
+            .chain(self.inserted.into_iter().map(|inserted| {
+                let mut state = new_part();
+                state.insert_entity(inserted.entity_id, inserted.components);
+                state
+            }))
+            .chain(self.component_removed.into_iter().map(|removed| {
+                let mut state = new_part();
+                state.remove_component(removed.entity_id, removed.component_id);
+                state
+            }))
+            .chain(self.component_added.into_iter().map(|added| {
+                let mut state = new_part();
+                state.add_component(added.entity_id, added.component_data);
+                state
+            }))
+            .chain(self.changed.into_iter().map(|changed| {
+                let mut state = new_part();
+                state.change(changed.entity_id, changed.component_data);
+                state
+            }));
+
+        let mut parts = Vec::new();
+        let mut current = new_part();
+
+        for entry in entries {
+            let entry_size = entry.estimated_size() - EMPTY_STATE_SIZE;
+
+            if !current.is_empty() && current.estimated_size() + entry_size > max_size {
+                parts.push(mem::replace(&mut current, entry));
+            } else {
+                current.merge(entry);
+            }
+        }
+
+        parts.push(current);
+
+        let last = parts.len() - 1;
+        parts
+            .into_iter()
+            .enumerate()
+            .map(|(index, state)| StateUpdatePart {
+                part: index as u16,
+                complete: index == last,
+                state,
+            })
+            .collect()
+    }
+
+    /// Appends the changes of the other state to this state.
+    pub fn merge(&mut self, other: WorldState) {
+        for entity_id in other.removed {
+            self.remove_entity(entity_id);
+        }
+        for inserted in other.inserted {
+            self.insert_entity(inserted.entity_id, inserted.components);
+        }
+        for removed in other.component_removed {
+            self.remove_component(removed.entity_id, removed.component_id);
+        }
+        for added in other.component_added {
+            self.add_component(added.entity_id, added.component_data);
+        }
+        for changed in other.changed {
+            self.change(changed.entity_id, changed.component_data);
+        }
+    }
+
+    /// Merges the changes of a later command frame into this state, e.g. to send the batched
+    /// updates of a client as one update.
+    ///
+    /// Changes the newer state undoes are dropped instead of sent: an entity or component that
+    /// is inserted here and removed by the newer state is left out, the changes of removed
+    /// entities and components are left out.
+    pub fn merge_newer(&mut self, newer: WorldState) {
+        for entity_id in newer.removed {
+            let was_inserted = self
+                .inserted
+                .iter()
+                .any(|inserted| inserted.entity_id == entity_id);
+
+            self.inserted
+                .retain(|inserted| inserted.entity_id != entity_id);
+            self.component_removed
+                .retain(|removed| removed.entity_id != entity_id);
+            self.component_added
+                .retain(|added| added.entity_id != entity_id);
+            self.changed.retain(|changed| changed.entity_id != entity_id);
+
+            if !was_inserted {
+                self.removed.push(entity_id);
+            }
+        }
+
+        for removed in newer.component_removed {
+            let is_removed = |entity_id: Uid, data: &ComponentData| {
+                entity_id == removed.entity_id && data.component_id == removed.component_id
+            };
+
+            let mut was_added = false;
+
+            for inserted in self
+                .inserted
+                .iter_mut()
+                .filter(|inserted| inserted.entity_id == removed.entity_id)
+            {
+                let count = inserted.components.len();
+                inserted
+                    .components
+                    .retain(|data| data.component_id != removed.component_id);
+                was_added |= inserted.components.len() != count;
+            }
+
+            let count = self.component_added.len();
+            self.component_added
+                .retain(|added| !is_removed(added.entity_id, &added.component_data));
+            was_added |= self.component_added.len() != count;
+
+            self.changed
+                .retain(|changed| !is_removed(changed.entity_id, &changed.component_data));
+
+            if !was_added {
+                self.component_removed.push(removed);
+            }
+        }
+
+        self.inserted.extend(newer.inserted);
+        self.component_added.extend(newer.component_added);
+        self.changed.extend(newer.changed);
+        self.recalculate_size();
+    }
+}
+
+/// Collects the parts of split state updates until all parts of a command frame arrived.
+#[derive(Debug, Default)]
+pub struct StateReassembler {
+    frames: BTreeMap<CommandFrame, PendingFrame>,
+}
+
+#[derive(Debug, Default)]
+struct PendingFrame {
+    parts: BTreeMap<u16, WorldState>,
+    last: Option<u16>,
+}
+
+impl StateReassembler {
+    pub fn new() -> StateReassembler {
+        StateReassembler::default()
+    }
+
+    /// Adds a part, returns the merged state once all parts of its command frame arrived.
+    ///
+    /// Incomplete frames older than a completed frame are discarded.
+    pub fn push(&mut self, part: StateUpdatePart) -> Option<WorldState> {
+        let command_frame = part.state.command_frame;
+        let frame = self.frames.entry(command_frame).or_default();
+
+        if part.complete {
+            frame.last = Some(part.part);
+        }
+        frame.parts.insert(part.part, part.state);
+
+        let is_complete = match frame.last {
+            Some(last) => frame.parts.len() == last as usize + 1,
+            None => false,
+        };
+
+        if !is_complete {
+            return None;
+        }
+
+        let frame = self.frames.remove(&command_frame)?;
+        self.frames = self.frames.split_off(&command_frame);
+
+        let mut parts = frame.parts.into_iter().map(|(_, state)| state);
+        let mut state = parts.next()?;
+
+        for part in parts {
+            state.merge(part);
+        }
+
+        Some(state)
+    }
+
+    /// The number of command frames of which parts are still missing.
+    pub fn pending(&self) -> usize {
+        self.frames.len()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use alloc::vec;
+
+    use crate::protocol::{ComponentData, StateReassembler, WorldState};
+
+    fn state() -> WorldState {
+        let mut state = WorldState::new(7);
+        state.command_frame_offset = 2;
+        state.remove_entity(1);
+        state.insert_entity(2, vec![ComponentData::new(1, vec![0; 40])]);
+        state.add_component(3, ComponentData::new(2, vec![0; 40]));
+        state.change(4, ComponentData::new(1, vec![0; 40]));
+        state.change(5, ComponentData::new(1, vec![0; 40]));
+        state
+    }
+
+    #[test]
+    fn small_state_is_one_part_test() {
+        let parts = state().split(usize::max_value());
+
+        assert_eq!(parts.len(), 1);
+        assert!(parts[0].complete);
+        assert_eq!(parts[0].state, state());
+    }
+
+    #[test]
+    fn split_parts_respect_max_size_test() {
+        let parts = state().split(120);
+
+        assert!(parts.len() > 1);
+        assert!(parts.iter().all(|part| part.state.estimated_size() <= 120));
+        assert!(parts.iter().all(|part| part.state.command_frame_offset == 2));
+        assert_eq!(parts.iter().filter(|part| part.complete).count(), 1);
+    }
+
+    #[test]
+    fn reassemble_out_of_order_test() {
+        let mut parts = state().split(120);
+        parts.reverse();
+
+        let mut reassembler = StateReassembler::new();
+        let last = parts.pop().unwrap();
+
+        for part in parts {
+            assert!(reassembler.push(part).is_none());
+        }
+
+        assert_eq!(reassembler.push(last), Some(state()));
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn undone_changes_are_dropped_when_merged_test() {
+        let mut older = WorldState::new(7);
+        older.insert_entity(1, vec![ComponentData::new(1, vec![1])]);
+        older.add_component(2, ComponentData::new(1, vec![2]));
+        older.add_component(3, ComponentData::new(2, vec![3]));
+        older.change(4, ComponentData::new(1, vec![4]));
+
+        let mut newer = WorldState::new(8);
+        newer.remove_entity(1);
+        newer.remove_component(2, 1);
+        newer.remove_entity(4);
+        newer.change(3, ComponentData::new(2, vec![5]));
+
+        older.merge_newer(newer);
+
+        let mut expected = WorldState::new(7);
+        expected.remove_entity(4);
+        expected.add_component(3, ComponentData::new(2, vec![3]));
+        expected.change(3, ComponentData::new(2, vec![5]));
+
+        assert_eq!(older, expected);
+        assert_eq!(older.estimated_size(), expected.estimated_size());
+    }
+}
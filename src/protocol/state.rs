@@ -0,0 +1,314 @@
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use serde::{Deserialize, Serialize};
+
+use super::{CommandFrame, Uid};
+
+/// Serialized data of a single component, identified by its registration uid.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ComponentData {
+    pub(crate) component_id: Uid,
+    pub(crate) data: Vec<u8>,
+}
+
+impl ComponentData {
+    pub fn new(component_id: Uid, data: Vec<u8>) -> ComponentData {
+        ComponentData { component_id, data }
+    }
+
+    pub fn component_id(&self) -> Uid {
+        self.component_id
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The size of this component data when encoded with fixed int encoding.
+    pub fn estimated_size(&self) -> usize {
+        size_of::<Uid>() + VEC_LEN_SIZE + self.data.len()
+    }
+}
+
+/// Bincode encodes the length of a `Vec` as `u64`.
+const VEC_LEN_SIZE: usize = size_of::<u64>();
+
+/// An entity that was inserted, together with all its registered components.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntityInserted {
+    pub(crate) entity_id: Uid,
+    pub(crate) components: Vec<ComponentData>,
+}
+
+impl EntityInserted {
+    pub fn new(entity_id: Uid, components: Vec<ComponentData>) -> EntityInserted {
+        EntityInserted {
+            entity_id,
+            components,
+        }
+    }
+
+    pub fn entity_id(&self) -> Uid {
+        self.entity_id
+    }
+
+    pub fn components(&self) -> &[ComponentData] {
+        &self.components
+    }
+
+    fn estimated_size(&self) -> usize {
+        size_of::<Uid>()
+            + VEC_LEN_SIZE
+            + self
+                .components
+                .iter()
+                .map(ComponentData::estimated_size)
+                .sum::<usize>()
+    }
+}
+
+/// A component that was added to an existing entity.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ComponentAdded {
+    pub(crate) entity_id: Uid,
+    pub(crate) component_data: ComponentData,
+}
+
+impl ComponentAdded {
+    pub fn new(entity_id: Uid, component_data: ComponentData) -> ComponentAdded {
+        ComponentAdded {
+            entity_id,
+            component_data,
+        }
+    }
+
+    pub fn entity_id(&self) -> Uid {
+        self.entity_id
+    }
+
+    pub fn component_data(&self) -> &ComponentData {
+        &self.component_data
+    }
+}
+
+/// A component that was removed from an existing entity.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ComponentRemoved {
+    pub(crate) entity_id: Uid,
+    pub(crate) component_id: Uid,
+}
+
+impl ComponentRemoved {
+    pub fn new(entity_id: Uid, component_id: Uid) -> ComponentRemoved {
+        ComponentRemoved {
+            entity_id,
+            component_id,
+        }
+    }
+
+    pub fn entity_id(&self) -> Uid {
+        self.entity_id
+    }
+
+    pub fn component_id(&self) -> Uid {
+        self.component_id
+    }
+}
+
+/// The serialized difference of a component that was changed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ComponentChanged {
+    pub(crate) entity_id: Uid,
+    pub(crate) component_data: ComponentData,
+}
+
+impl ComponentChanged {
+    pub fn new(entity_id: Uid, component_data: ComponentData) -> ComponentChanged {
+        ComponentChanged {
+            entity_id,
+            component_data,
+        }
+    }
+
+    pub fn entity_id(&self) -> Uid {
+        self.entity_id
+    }
+
+    pub fn component_data(&self) -> &ComponentData {
+        &self.component_data
+    }
+}
+
+/// Command frame, offset and the lengths of the five change lists.
+pub(super) const EMPTY_STATE_SIZE: usize = size_of::<CommandFrame>() + size_of::<i32>() + 5 * VEC_LEN_SIZE;
+
+/// All the changes of one command frame, in the order they have to be applied.
+///
+/// The estimated size is maintained by the methods of this type,
+/// changes made to the public fields directly are only accounted for after `recalculate_size`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldState {
+    pub command_frame: CommandFrame,
+    pub command_frame_offset: i32,
+    pub removed: Vec<Uid>,
+    pub inserted: Vec<EntityInserted>,
+    pub component_removed: Vec<ComponentRemoved>,
+    pub component_added: Vec<ComponentAdded>,
+    pub changed: Vec<ComponentChanged>,
+    #[serde(skip)]
+    estimated_size: usize,
+}
+
+impl PartialEq for WorldState {
+    fn eq(&self, other: &Self) -> bool {
+        self.command_frame == other.command_frame
+            && self.command_frame_offset == other.command_frame_offset
+            && self.removed == other.removed
+            && self.inserted == other.inserted
+            && self.component_removed == other.component_removed
+            && self.component_added == other.component_added
+            && self.changed == other.changed
+    }
+}
+
+impl Eq for WorldState {}
+
+impl WorldState {
+    pub fn new(command_frame: CommandFrame) -> WorldState {
+        WorldState {
+            command_frame,
+            command_frame_offset: 0,
+            removed: Vec::new(),
+            inserted: Vec::new(),
+            component_removed: Vec::new(),
+            component_added: Vec::new(),
+            changed: Vec::new(),
+            estimated_size: EMPTY_STATE_SIZE,
+        }
+    }
+
+    pub fn remove_entity(&mut self, entity_id: Uid) {
+        self.estimated_size += size_of::<Uid>();
+        self.removed.push(entity_id);
+    }
+
+    pub fn insert_entity(&mut self, entity_id: Uid, components: Vec<ComponentData>) {
+        let inserted = EntityInserted::new(entity_id, components);
+        self.estimated_size += inserted.estimated_size();
+        self.inserted.push(inserted);
+    }
+
+    pub fn remove_component(&mut self, entity_id: Uid, component_id: Uid) {
+        self.estimated_size += 2 * size_of::<Uid>();
+        self.component_removed
+            .push(ComponentRemoved::new(entity_id, component_id));
+    }
+
+    pub fn add_component(&mut self, entity_id: Uid, component_data: ComponentData) {
+        self.estimated_size += size_of::<Uid>() + component_data.estimated_size();
+        self.component_added
+            .push(ComponentAdded::new(entity_id, component_data));
+    }
+
+    pub fn change(&mut self, entity_id: Uid, component_data: ComponentData) {
+        self.estimated_size += size_of::<Uid>() + component_data.estimated_size();
+        self.changed
+            .push(ComponentChanged::new(entity_id, component_data));
+    }
+
+    /// The size of this state when encoded with fixed int encoding, without serializing it.
+    pub fn estimated_size(&self) -> usize {
+        self.estimated_size
+    }
+
+    /// Recalculates the estimated size, needed after changing the public fields directly
+    /// or after deserializing, the size is not sent over the wire.
+    pub fn recalculate_size(&mut self) {
+        self.estimated_size = EMPTY_STATE_SIZE
+            + self.removed.len() * size_of::<Uid>()
+            + self
+                .inserted
+                .iter()
+                .map(EntityInserted::estimated_size)
+                .sum::<usize>()
+            + self.component_removed.len() * 2 * size_of::<Uid>()
+            + self
+                .component_added
+                .iter()
+                .map(|added| size_of::<Uid>() + added.component_data.estimated_size())
+                .sum::<usize>()
+            + self
+                .changed
+                .iter()
+                .map(|changed| size_of::<Uid>() + changed.component_data.estimated_size())
+                .sum::<usize>();
+    }
+
+    /// Removes all component data of which the component uid does not match the predicate.
+    pub fn retain_components(&mut self, mut predicate: impl FnMut(Uid) -> bool) {
+        for inserted in self.inserted.iter_mut() {
+            inserted
+                .components
+                .retain(|component| predicate(component.component_id));
+        }
+
+        self.component_removed
+            .retain(|removed| predicate(removed.component_id));
+        self.component_added
+            .retain(|added| predicate(added.component_data.component_id));
+        self.changed
+            .retain(|changed| predicate(changed.component_data.component_id));
+
+        self.recalculate_size();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty()
+            && self.inserted.is_empty()
+            && self.component_removed.is_empty()
+            && self.component_added.is_empty()
+            && self.changed.is_empty()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+pub mod test {
+    use bincode::Options;
+
+    use crate::{
+        protocol::{ComponentData, WorldState},
+        tracking::re_exports::bincode,
+        world::default_options,
+    };
+
+    fn state() -> WorldState {
+        let mut state = WorldState::new(4);
+        state.remove_entity(1);
+        state.insert_entity(2, vec![ComponentData::new(1, vec![1, 2, 3])]);
+        state.remove_component(3, 1);
+        state.add_component(3, ComponentData::new(2, vec![4]));
+        state.change(4, ComponentData::new(1, vec![5, 6]));
+        state
+    }
+
+    #[test]
+    fn estimated_size_matches_serialized_size_test() {
+        let state = state();
+
+        let serialized_size = default_options().serialized_size(&state).unwrap() as usize;
+
+        assert_eq!(state.estimated_size(), serialized_size);
+    }
+
+    #[test]
+    fn recalculate_size_after_deserializing_test() {
+        let state = state();
+
+        let bytes = default_options().serialize(&state).unwrap();
+        let mut deserialized = default_options().deserialize::<WorldState>(&bytes).unwrap();
+        deserialized.recalculate_size();
+
+        assert_eq!(deserialized.estimated_size(), state.estimated_size());
+    }
+}
@@ -1,4 +1,4 @@
-use std::{any::TypeId, collections::HashMap};
+use std::{any::TypeId, collections::HashMap, mem::size_of};
 
 use legion::{
     storage::{ComponentMeta, ComponentTypeId},
@@ -16,22 +16,49 @@ use serde::{
 };
 
 use net_sync::{
-    error::ErrorKind,
-    re_exports::serde_diff,
+    re_exports::{bincode, serde_diff},
     track_attr::serde_diff::{Config, FieldPathMode, SerdeDiff},
     uid::{Uid, UidAllocator},
 };
 
+use crate::error::ErrorKind;
+
 inventory::collect!(ComponentRegistration);
 
 pub type ComponentRegistrationRef = &'static ComponentRegistration;
 pub type HashmapRegistry = HashMap<ComponentTypeId, ComponentRegistrationRef>;
 
+/// Components up to this size in bytes use `DiffPolicy::Whole` by default.
+pub const WHOLE_VALUE_THRESHOLD: usize = 16;
+
+/// How the changes of a component type are serialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffPolicy {
+    /// Only the changed fields are sent, see `serde_diff`.
+    Diff,
+    /// The whole value is sent when any field changed.
+    ///
+    /// For small components the overhead of the diff machinery exceeds the payload savings.
+    Whole,
+}
+
+impl DiffPolicy {
+    /// Returns the default policy of `T`, based on `WHOLE_VALUE_THRESHOLD`.
+    pub fn of<T>() -> DiffPolicy {
+        if size_of::<T>() <= WHOLE_VALUE_THRESHOLD {
+            DiffPolicy::Whole
+        } else {
+            DiffPolicy::Diff
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct ComponentRegistration {
     pub(crate) component_type_id: ComponentTypeId,
     pub(crate) meta: ComponentMeta,
     pub(crate) type_name: &'static str,
+    pub(crate) diff_policy: DiffPolicy,
 
     pub(crate) components_clone: fn(*const u8, *mut u8, usize),
 
@@ -49,6 +76,7 @@ pub struct ComponentRegistration {
         unchanged: &mut dyn erased_serde::Deserializer,
         changed: &mut dyn erased_serde::Deserializer,
         serializer: &mut dyn erased_serde::Serializer,
+        policy: DiffPolicy,
     ) -> Result<bool, ErrorKind>,
 
     pub(crate) serialize_difference_with_current: fn(
@@ -56,6 +84,7 @@ pub struct ComponentRegistration {
         entity: Entity,
         unchanged: &mut dyn erased_serde::Deserializer,
         serializer: &mut dyn erased_serde::Serializer,
+        policy: DiffPolicy,
     ) -> Result<bool, ErrorKind>,
 
     pub(crate) grand_write_access: fn(system_builder: SystemBuilder) -> SystemBuilder,
@@ -70,8 +99,12 @@ pub struct ComponentRegistration {
 
     pub(crate) remove_component: fn(world: &mut World, entity: Entity),
 
-    pub(crate) apply_changes:
-        fn(world: &mut World, entity: Entity, changes: &mut dyn erased_serde::Deserializer),
+    pub(crate) apply_changes: fn(
+        world: &mut World,
+        entity: Entity,
+        changes: &mut dyn erased_serde::Deserializer,
+        policy: DiffPolicy,
+    ),
 }
 
 impl Debug for ComponentRegistration {
@@ -97,6 +130,17 @@ impl ComponentRegistration {
         self.type_name
     }
 
+    /// Returns how the changes of this component are serialized, see `DiffPolicy`.
+    pub fn diff_policy(&self) -> DiffPolicy {
+        self.diff_policy
+    }
+
+    /// Overrides the default `DiffPolicy` of the component type.
+    pub fn with_diff_policy(mut self, policy: DiffPolicy) -> Self {
+        self.diff_policy = policy;
+        self
+    }
+
     pub fn exists_in_subworld(&self, world: &SubWorld, entity: Entity) -> bool {
         (self.exists_in_subworld)(world, entity)
     }
@@ -120,7 +164,7 @@ impl ComponentRegistration {
         changed: &mut dyn erased_serde::Deserializer,
         serializer: &mut dyn erased_serde::Serializer,
     ) -> Result<bool, ErrorKind> {
-        (self.serialize_difference)(unchanged, changed, serializer)
+        (self.serialize_difference)(unchanged, changed, serializer, self.diff_policy)
     }
 
     pub fn serialize_difference_with_current(
@@ -130,7 +174,13 @@ impl ComponentRegistration {
         unchanged: &mut dyn erased_serde::Deserializer,
         serializer: &mut dyn erased_serde::Serializer,
     ) -> Result<bool, ErrorKind> {
-        (self.serialize_difference_with_current)(world, entity, unchanged, serializer)
+        (self.serialize_difference_with_current)(
+            world,
+            entity,
+            unchanged,
+            serializer,
+            self.diff_policy,
+        )
     }
 
     pub fn grand_read_access(&self, system_builder: SystemBuilder) -> SystemBuilder {
@@ -168,7 +218,7 @@ impl ComponentRegistration {
         entity: Entity,
         data: &mut dyn erased_serde::Deserializer,
     ) {
-        (self.apply_changes)(world, entity, data)
+        (self.apply_changes)(world, entity, data, self.diff_policy)
     }
 
     pub fn of<
@@ -186,6 +236,7 @@ impl ComponentRegistration {
             component_type_id: ComponentTypeId::of::<T>(),
             meta: ComponentMeta::of::<T>(),
             type_name: std::any::type_name::<T>(),
+            diff_policy: DiffPolicy::of::<T>(),
             components_clone: move |src, dst, num_components| unsafe {
                 for i in 0..num_components {
                     let src_ptr = (src as *const T).add(i);
@@ -215,40 +266,22 @@ impl ComponentRegistration {
                     }
                 }
             },
-            serialize_difference: |unchanged, changed, serializer| {
-                let unchanged = erased_serde::deserialize::<T>(unchanged)
-                    .expect("failed to deserialize component");
-
-                let changed = erased_serde::deserialize::<T>(changed)
-                    .expect("failed to deserialize component");
-
-                let diff = Config::new()
-                    .with_field_path_mode(FieldPathMode::Index)
-                    .serializable_diff(&unchanged, &changed);
-
-                <serde_diff::Diff<T> as serde::ser::Serialize>::serialize(&diff, serializer)
-                    .expect("failed to serialize diff");
+            serialize_difference: |unchanged, changed, serializer, policy| {
+                let unchanged = erased_serde::deserialize::<T>(unchanged).map_err(serde_error)?;
+                let changed = erased_serde::deserialize::<T>(changed).map_err(serde_error)?;
 
-                Ok(diff.has_changes())
+                serialize_difference(&unchanged, &changed, serializer, policy)
             },
-            serialize_difference_with_current: |world, entity, unchanged, serializer| {
-                let unchanged = erased_serde::deserialize::<T>(unchanged)
-                    .expect("failed to deserialize component");
-
-                if let Some(entry) = world.entry_ref(entity) {
-                    let changed = entry.get_component::<T>().expect("failed to get component");
-
-                    let diff = Config::new()
-                        .with_field_path_mode(FieldPathMode::Index)
-                        .serializable_diff(&unchanged, &changed);
-
-                    <serde_diff::Diff<T> as serde::ser::Serialize>::serialize(&diff, serializer)
-                        .expect("failed to serialize diff");
-
-                    return Ok(diff.has_changes());
+            serialize_difference_with_current: |world, entity, unchanged, serializer, policy| {
+                let unchanged = erased_serde::deserialize::<T>(unchanged).map_err(serde_error)?;
+
+                match world
+                    .entry_ref(entity)
+                    .and_then(|entry| entry.into_component::<T>().ok())
+                {
+                    Some(changed) => serialize_difference(&unchanged, changed, serializer, policy),
+                    None => Ok(false),
                 }
-
-                Ok(false)
             },
             grand_read_access: |system_builder| system_builder.read_component::<T>(),
             grand_write_access: |system_builder| system_builder.write_component::<T>(),
@@ -271,22 +304,79 @@ impl ComponentRegistration {
                     entry.remove_component::<T>();
                 }
             },
-            apply_changes: |world, entity, data| {
+            apply_changes: |world, entity, data, policy| {
                 if let Some(mut entry) = world.entry(entity) {
-                    let mut component = entry
+                    let component = entry
                         .get_component_mut::<T>()
                         .expect("Can not apply changes to component.");
 
-                    <serde_diff::Apply<T> as serde::de::DeserializeSeed>::deserialize(
-                        serde_diff::Apply::deserializable(&mut component),
-                        data,
-                    );
+                    apply_changes(component, data, policy);
                 };
             },
         }
     }
 }
 
+fn serde_error(error: impl std::fmt::Display) -> ErrorKind {
+    ErrorKind::SerializationError(error.to_string())
+}
+
+/// Serializes the changes between both values according to the policy,
+/// returns whether there are any.
+pub(crate) fn serialize_difference<T: Serialize + SerdeDiff>(
+    unchanged: &T,
+    changed: &T,
+    serializer: &mut dyn erased_serde::Serializer,
+    policy: DiffPolicy,
+) -> Result<bool, ErrorKind> {
+    match policy {
+        DiffPolicy::Diff => {
+            let diff = Config::new()
+                .with_field_path_mode(FieldPathMode::Index)
+                .serializable_diff(unchanged, changed);
+
+            <serde_diff::Diff<T> as serde::ser::Serialize>::serialize(&diff, serializer)
+                .map_err(serde_error)?;
+
+            Ok(diff.has_changes())
+        }
+        DiffPolicy::Whole => {
+            // Comparing the bytes of a small value is cheaper than diffing it.
+            let unchanged_bytes = bincode::serialize(unchanged).map_err(serde_error)?;
+            let changed_bytes = bincode::serialize(changed).map_err(serde_error)?;
+
+            if unchanged_bytes == changed_bytes {
+                return Ok(false);
+            }
+
+            erased_serde::serialize(changed, serializer).map_err(serde_error)?;
+
+            Ok(true)
+        }
+    }
+}
+
+/// Applies changes serialized by `serialize_difference` with the same policy.
+pub(crate) fn apply_changes<T: for<'de> Deserialize<'de> + SerdeDiff>(
+    component: &mut T,
+    changes: &mut dyn erased_serde::Deserializer,
+    policy: DiffPolicy,
+) {
+    match policy {
+        DiffPolicy::Diff => {
+            <serde_diff::Apply<T> as serde::de::DeserializeSeed>::deserialize(
+                serde_diff::Apply::deserializable(component),
+                changes,
+            )
+            .expect("Can not apply changes to component.");
+        }
+        DiffPolicy::Whole => {
+            *component =
+                erased_serde::deserialize::<T>(changes).expect("failed to deserialize component");
+        }
+    }
+}
+
 pub struct ComponentRegister;
 
 impl ComponentRegister {
@@ -324,18 +414,30 @@ macro_rules! register_component_type {
              $crate::register::ComponentRegistration::of::<$component_type>()
         }
     };
+    ($component_type:ty, $diff_policy:expr) => {
+        inventory::submit! {
+             $crate::register::ComponentRegistration::of::<$component_type>()
+                .with_diff_policy($diff_policy)
+        }
+    };
 }
 
 #[cfg(test)]
 pub mod test {
-    use std::any::TypeId;
+    use std::{any::TypeId, collections::HashMap};
 
     use legion::storage::{ComponentMeta, ComponentTypeId};
 
+    use net_sync::re_exports::bincode;
+
     use crate::{
-        components::UidComponent,
-        register::{ComponentRegister, ComponentRegistration, ComponentRegistrationRef},
+        components::{Replace, UidComponent},
+        register::{
+            apply_changes, serialize_difference, ComponentRegister, ComponentRegistration,
+            ComponentRegistrationRef, DiffPolicy,
+        },
         tracking::{re_exports::serde_diff::*, track_attr::*},
+        world::default_options,
     };
 
     #[derive(Clone, Default, Debug, Serialize, Deserialize, SerdeDiff)]
@@ -343,18 +445,32 @@ pub mod test {
 
     crate::register_component_type!(Component, Bincode);
 
+    // The components the crate registers, depending on its features, plus the one above.
+    fn registered_count() -> usize {
+        let crate_components = ComponentRegister
+            .iter()
+            .filter(|component| !component.type_name().starts_with(module_path!()))
+            .count();
+
+        crate_components + 1
+    }
+
     #[test]
     fn registered_by_component_id_should_be_filled_test() {
         let registered = ComponentRegister::by_component_id();
 
-        assert_eq!(registered.len(), 2);
+        assert_eq!(registered.len(), registered_count());
+        assert!(registered.contains_key(&ComponentTypeId::of::<Component>()));
     }
 
     #[test]
     fn registered_by_uid_should_be_filled_test() {
         let registered = ComponentRegister::by_unique_uid();
 
-        assert_eq!(registered.len(), 2);
+        assert_eq!(registered.len(), registered_count());
+        assert!(registered
+            .values()
+            .any(|component| component.ty() == TypeId::of::<Component>()));
     }
 
     #[test]
@@ -391,4 +507,105 @@ pub mod test {
             ComponentTypeId::of::<UidComponent>()
         );
     }
+
+    #[derive(Clone, Default, Debug, Serialize, Deserialize, SerdeDiff, PartialEq)]
+    struct Health {
+        value: u32,
+    }
+
+    #[test]
+    fn small_component_is_sent_whole_test() {
+        assert_eq!(DiffPolicy::of::<Health>(), DiffPolicy::Whole);
+
+        let mut component = Health { value: 1 };
+
+        let mut bytes = Vec::new();
+        {
+            let mut serializer = bincode::Serializer::new(&mut bytes, default_options());
+            assert!(!serialize_difference(
+                &component,
+                &component.clone(),
+                &mut erased_serde::Serializer::erase(&mut serializer),
+                DiffPolicy::Whole,
+            )
+            .unwrap());
+            assert!(serialize_difference(
+                &component,
+                &Health { value: 2 },
+                &mut erased_serde::Serializer::erase(&mut serializer),
+                DiffPolicy::Whole,
+            )
+            .unwrap());
+        }
+
+        let mut deserializer = bincode::Deserializer::from_slice(&bytes, default_options());
+        apply_changes(
+            &mut component,
+            &mut erased_serde::Deserializer::erase(&mut deserializer),
+            DiffPolicy::Whole,
+        );
+
+        assert_eq!(component, Health { value: 2 });
+    }
+
+    #[derive(Clone, Default, Debug, Serialize, Deserialize, SerdeDiff, PartialEq)]
+    struct Inventory {
+        slots: Vec<u32>,
+        hotbar: [u8; 4],
+        stats: HashMap<u8, u32>,
+        ranking: Replace<Vec<u32>>,
+    }
+
+    /// Diffs both values, applies the diff to `unchanged` and returns the size of the diff.
+    fn diff_and_apply(unchanged: &mut Inventory, changed: &Inventory) -> usize {
+        let mut bytes = Vec::new();
+        {
+            let mut serializer = bincode::Serializer::new(&mut bytes, default_options());
+            serialize_difference(
+                &*unchanged,
+                changed,
+                &mut erased_serde::Serializer::erase(&mut serializer),
+                DiffPolicy::Diff,
+            )
+            .unwrap();
+        }
+
+        let mut deserializer = bincode::Deserializer::from_slice(&bytes, default_options());
+        apply_changes(
+            unchanged,
+            &mut erased_serde::Deserializer::erase(&mut deserializer),
+            DiffPolicy::Diff,
+        );
+
+        bytes.len()
+    }
+
+    #[test]
+    fn collection_fields_are_diffed_test() {
+        let mut client = Inventory {
+            slots: (0..64).collect(),
+            hotbar: [1, 2, 3, 4],
+            stats: vec![(1, 10), (2, 20)].into_iter().collect(),
+            ranking: Replace::new(vec![3, 1, 2]),
+        };
+        let mut server = client.clone();
+
+        // One changed element is sent instead of the whole collection.
+        server.slots[32] = 100;
+        let diff_size = diff_and_apply(&mut client, &server);
+        assert_eq!(client, server);
+        assert!(diff_size < bincode::serialize(&server.slots).unwrap().len());
+
+        server.slots.truncate(10);
+        server.hotbar[2] = 9;
+        server.stats.remove(&1);
+        server.stats.insert(3, 30);
+        server.ranking.get_mut().sort();
+        diff_and_apply(&mut client, &server);
+        assert_eq!(client, server);
+
+        server.slots.extend(vec![7, 8]);
+        diff_and_apply(&mut client, &server);
+        assert_eq!(client, server);
+    }
 }
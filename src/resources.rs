@@ -1,33 +1,123 @@
 //! A number of resources that can be used to synchronize and trace components.
 
-use std::net::{SocketAddr, TcpListener};
+use std::{
+    io,
+    net::{SocketAddr, TcpListener, UdpSocket},
+    time::Duration,
+};
 
 use legion::{systems::Resources, Entity};
 
 use net_sync::{
     compression::CompressionStrategy,
-    synchronisation::{
-        ClientCommandBuffer, CommandFrameTicker, NetworkCommand, NetworkMessage, ResimulationBuffer,
-    },
+    synchronisation::{ClientCommandBuffer, NetworkCommand, NetworkMessage, ResimulationBuffer},
     tracker::TrackResource,
     transport,
-    transport::{
-        tcp::{TcpClientResource, TcpListenerResource},
-        PostBox, PostOffice,
-    },
+    transport::{tcp::TcpListenerResource, PostBox, PostOffice},
     uid::UidAllocator,
 };
 
 pub use self::{
+    area::{AreaOfInterest, InterestFilter},
+    audit::{AuthorityAudit, AuthorityOverwrite, OverwriteReason},
+    barrier::{MatchBarrier, MatchPhase},
+    baseline::{StateAcks, StateBaselines},
     buffer::BufferResource,
+    change_events::{ChangeEvents, PostApplyChange},
+    changes::ReplicatedChanges,
+    clock::{Clock, ClockResource, ManualClock, RealClock},
+    command::{CommandResultEvents, CommandResultQueue},
     component::{HashmapRegistry, RegisteredComponentsResource},
+    connection::{ClientConnection, CommandBufferPolicy, ConnectionState},
+    constraints::ComponentConstraints,
+    ephemeral::EphemeralEntities,
     event::EventResource,
+    generations::{UidGenerations, UidRecycler},
+    history::WorldHistory,
+    input::InputSampler,
+    interest::{InterestBudget, InterestChange, InterestHooks, InterestRadii, InterestScopes},
+    interpolation::InterpolationDelay,
+    lifecycle::{ConnectionHooks, ConnectionLifecycle, DisconnectPolicy},
+    listener::ListenerGate,
+    metrics::{
+        BandwidthMetrics, ClientMetrics, ComponentBandwidthStats, ComponentPredictionStats,
+        ConnectionQuality, PredictionMetrics, QualityThresholds, ServerMetrics,
+    },
+    network::ClientNetworkThread,
+    ownership::OwnershipRules,
+    players::{LocalPlayers, PlayerCommands, PlayerOwnership},
+    references::{EntityReferences, ReferencePolicy},
+    regions::RegionStreaming,
+    relevancy::{Relevancy, RelevancyOverrides},
+    replay::{CommandReplayGuard, ReplayStats},
+    resimulation::{ResimulationExecutor, ResimulationId, ResimulationQueue, ResimulationRange},
+    rng::{FrameRng, SyncedRng},
+    rollback::{RollbackResource, RollbackResources},
+    serialization::{Bincode, SerializationResource, SerializationStrategy},
+    session::{SessionToken, SessionTokens},
+    shedding::{Fidelity, LoadShedding, SheddingConfig},
+    simulators::{SimulationMerge, SimulationRejection, TrustedSimulators},
+    socket::SocketOptions,
+    stale::{StaleAction, StalePolicy, StaleSweep},
+    state_cache::SerializedStateCache,
+    strict::{SchemaViolation, StrictSchema, ViolationKind},
+    structure::{StructuralChange, StructuralPrediction, StructuralPredictions},
+    ticker::{CommandFrameTicker, StallPolicy, TickerEvent},
+    transform::ComponentTransforms,
+    udp::{Reliability, UdpClientResource, UdpConfig, UdpServerResource},
+    uid_events::{UidEvent, UidEvents},
+    versions::{ComponentVersions, SchemaVersion},
 };
+pub(crate) use self::lifecycle::TransportEvent;
+use crate::event::{ClientEvents, ServerEvents};
 use net_sync::event::NetworkEventQueue;
 
+mod area;
+mod audit;
+mod barrier;
+mod baseline;
 mod buffer;
+mod change_events;
+mod changes;
+mod clock;
+mod command;
 mod component;
+mod connection;
+mod constraints;
+mod ephemeral;
 mod event;
+mod generations;
+mod history;
+mod input;
+mod interest;
+mod interpolation;
+mod lifecycle;
+mod listener;
+mod metrics;
+mod network;
+mod ownership;
+mod players;
+mod references;
+mod regions;
+mod relevancy;
+mod replay;
+mod resimulation;
+mod rng;
+mod rollback;
+mod serialization;
+mod session;
+mod shedding;
+mod simulators;
+mod socket;
+mod stale;
+mod state_cache;
+mod strict;
+mod structure;
+mod ticker;
+mod transform;
+mod udp;
+mod uid_events;
+mod versions;
 
 pub trait ResourcesExt {
     fn insert_server_resources<
@@ -57,8 +147,20 @@ pub trait ResourcesExt {
     >(
         &mut self,
         addr: SocketAddr,
-    );
+        options: &SocketOptions,
+    ) -> io::Result<()>;
     fn insert_tcp_listener_resources(&mut self, listener: TcpListener);
+
+    fn insert_udp_client_resources<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        addr: SocketAddr,
+        config: UdpConfig,
+    );
+    fn insert_udp_server_resources(&mut self, socket: UdpSocket, config: UdpConfig);
 }
 
 impl ResourcesExt for Resources {
@@ -76,6 +178,16 @@ impl ResourcesExt for Resources {
             ClientToServerMessage,
             ClientToServerCommand,
         >::new());
+        self.insert(SyncedRng::from_entropy());
+        self.insert(ServerMetrics::new());
+        self.insert(ServerEvents::new());
+        self.insert(InterestScopes::new());
+        self.insert(SerializedStateCache::new());
+        self.insert(EntityReferences::new());
+        self.insert(PlayerOwnership::<transport::ClientId>::new());
+        self.insert(CommandResultQueue::<ClientToServerCommand>::new());
+        self.insert(CommandReplayGuard::new());
+        self.insert(SessionTokens::new());
         self.insert_required(compression);
     }
 
@@ -90,6 +202,20 @@ impl ResourcesExt for Resources {
             10,
         ));
         self.insert(ResimulationBuffer::<ClientToServerCommand>::new());
+        self.insert(ResimulationQueue::<ClientToServerCommand>::default());
+        // The seed is replaced by the server seed on initial state sync.
+        self.insert(SyncedRng::new(0));
+        self.insert(CommandResultEvents::<ClientToServerCommand>::new());
+        self.insert(ClientEvents::new());
+        self.insert(PredictionMetrics::new());
+        self.insert(BandwidthMetrics::new());
+        self.insert(ComponentTransforms::new());
+        self.insert(EphemeralEntities::new());
+        self.insert(ReplicatedChanges::default());
+        self.insert(EntityReferences::new());
+        self.insert(ClientConnection::<ClientToServerCommand>::new(
+            CommandBufferPolicy::default(),
+        ));
         self.insert_required(compression);
     }
 
@@ -99,6 +225,8 @@ impl ResourcesExt for Resources {
         self.insert(UidAllocator::<Entity>::new());
         self.insert(TrackResource::new());
         self.insert(CommandFrameTicker::new(30.));
+        self.insert(ClockResource::default());
+        self.insert(SerializationResource::default());
         self.insert(NetworkEventQueue::new());
 
         let registered_components = RegisteredComponentsResource::new();
@@ -112,15 +240,43 @@ impl ResourcesExt for Resources {
     >(
         &mut self,
         addr: SocketAddr,
-    ) {
+        options: &SocketOptions,
+    ) -> io::Result<()> {
+        let tcp_client = options.connect(addr)?;
+
         self.insert(PostBox::<
             transport::ServerToClientMessage<ServerToClientMessage>,
             transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
         >::new());
-        self.insert(TcpClientResource::new(addr).unwrap());
+        self.insert(tcp_client);
+        Ok(())
     }
 
     fn insert_tcp_listener_resources(&mut self, listener: TcpListener) {
         self.insert(TcpListenerResource::new(Some(listener)));
+        self.insert(ListenerGate::new());
+    }
+
+    fn insert_udp_client_resources<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        addr: SocketAddr,
+        config: UdpConfig,
+    ) {
+        self.insert(PostBox::<
+            transport::ServerToClientMessage<ServerToClientMessage>,
+            transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+        >::new());
+        let now = self
+            .get::<ClockResource>()
+            .map_or_else(Duration::default, |clock| clock.now());
+        self.insert(UdpClientResource::new(addr, config, now).unwrap());
+    }
+
+    fn insert_udp_server_resources(&mut self, socket: UdpSocket, config: UdpConfig) {
+        self.insert(UdpServerResource::new(socket, config));
     }
 }
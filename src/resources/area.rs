@@ -0,0 +1,435 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    mem,
+};
+
+use legion::{
+    query::{IntoQuery, Read},
+    Entity, World,
+};
+
+use net_sync::{
+    synchronisation::{CommandFrame, WorldState},
+    transport::ClientId,
+    uid::Uid,
+};
+
+use crate::{
+    components::UidComponent,
+    error::ErrorKind,
+    resources::{
+        InterestRadii, InterestScopes, RegionStreaming, RegisteredComponentsResource, Relevancy,
+        RelevancyOverrides, SerializationResource,
+    },
+    world::{self, serialize_entity},
+};
+
+/// Decides which entities are relevant to a client, see `AreaOfInterest`.
+pub enum InterestFilter {
+    /// The entities within `radius` of the avatar of the client.
+    ///
+    /// The radius is capped by the adaptive `InterestRadii` when the server has an interest budget.
+    /// Entities without position are relevant everywhere.
+    Radius { avatar: Entity, radius: f32 },
+    /// The entities for which the closure returns `true`, e.g. the members of the team.
+    Custom(Box<dyn Fn(&World, Entity) -> bool + Send + Sync>),
+}
+
+impl InterestFilter {
+    fn passes(
+        &self,
+        world: &World,
+        entity: Entity,
+        position: fn(&World, Entity) -> Option<[f32; 3]>,
+        max_radius: Option<f32>,
+    ) -> bool {
+        match self {
+            InterestFilter::Radius { avatar, radius } => {
+                if entity == *avatar {
+                    return true;
+                }
+
+                let (center, target) = match (position(world, *avatar), position(world, entity)) {
+                    (_, None) => return true,
+                    // The avatar is gone, e.g. until it respawns.
+                    (None, Some(_)) => return false,
+                    (Some(center), Some(target)) => (center, target),
+                };
+
+                let radius = max_radius.map_or(*radius, |max_radius| radius.min(max_radius));
+                let distance_squared = center
+                    .iter()
+                    .zip(target.iter())
+                    .map(|(a, b)| (a - b) * (a - b))
+                    .sum::<f32>();
+
+                distance_squared <= radius * radius
+            }
+            InterestFilter::Custom(filter) => filter(world, entity),
+        }
+    }
+}
+
+/// Server resource that sends each client only the entities that pass its `InterestFilter`,
+/// enabled with `ServerWorldBuilder::with_area_of_interest`.
+///
+/// The filters are evaluated every frame. An entity that starts passing the filter of a client
+/// is sent as inserted entity, an entity that stops passing it as removed entity; the state
+/// updates of the client only contain the passing entities. The changes enter and leave the
+/// `InterestScopes` of the client. Clients without filter receive the whole world.
+///
+/// The relevancy overrides take precedence over the filters. A client that streams regions is
+/// filtered by its `RegionStreaming` only. Like the regions, the initial sync carries the whole
+/// world, the entities that do not pass the filter are removed with the next frame.
+pub struct AreaOfInterest {
+    position: fn(&World, Entity) -> Option<[f32; 3]>,
+    filters: HashMap<ClientId, InterestFilter>,
+    /// The entities that passed the filter of a client, clients without an entry have
+    /// the whole world.
+    passing: HashMap<ClientId, HashSet<Uid>>,
+    /// The replicated entities as of the last update.
+    entities: HashMap<Uid, Entity>,
+    /// The entities a client received whole with the transition of this frame.
+    inserted: HashMap<ClientId, HashSet<Uid>>,
+}
+
+impl AreaOfInterest {
+    /// Creates the resource with the function that reads the position of an entity for the
+    /// `InterestFilter::Radius` filters.
+    pub fn new(position: fn(&World, Entity) -> Option<[f32; 3]>) -> AreaOfInterest {
+        AreaOfInterest {
+            position,
+            filters: HashMap::new(),
+            passing: HashMap::new(),
+            entities: HashMap::new(),
+            inserted: HashMap::new(),
+        }
+    }
+
+    /// Replaces the filter of the client, the change is applied with the next frame.
+    pub fn set_filter(&mut self, client: ClientId, filter: InterestFilter) {
+        self.filters.insert(client, filter);
+    }
+
+    /// Removes the filter of the client, it receives the whole world again with the next frame.
+    pub fn remove_filter(&mut self, client: ClientId) -> Option<InterestFilter> {
+        self.filters.remove(&client)
+    }
+
+    /// Whether the client has a filter.
+    pub fn is_filtered(&self, client: ClientId) -> bool {
+        self.filters.contains_key(&client)
+    }
+
+    /// Removes the filter of a disconnected client.
+    pub fn remove_client(&mut self, client: ClientId) {
+        self.filters.remove(&client);
+        self.passing.remove(&client);
+        self.inserted.remove(&client);
+    }
+
+    /// Moves the filter to another client id, see `ServerWorld::migrate_client`.
+    pub fn rebind(&mut self, from: ClientId, to: ClientId) {
+        if let Some(filter) = self.filters.remove(&from) {
+            self.filters.insert(to, filter);
+        }
+        if let Some(passing) = self.passing.remove(&from) {
+            self.passing.insert(to, passing);
+        }
+    }
+
+    /// The client received the whole world with an initial sync,
+    /// the next update removes the entities that do not pass its filter.
+    pub(crate) fn synced(&mut self, client: ClientId) {
+        self.passing.remove(&client);
+    }
+
+    /// Whether the entity passed the filter of the client with the last update.
+    pub(crate) fn passes(&self, client: ClientId, uid: Uid) -> bool {
+        self.passing
+            .get(&client)
+            .map_or(true, |passing| passing.contains(&uid))
+    }
+
+    /// Evaluates the filters and returns the entities the clients have to insert or remove
+    /// because they started or stopped passing the filter.
+    ///
+    /// Entities that fail to serialize are left out, their errors are returned.
+    pub(crate) fn update(
+        &mut self,
+        world: &World,
+        registered: &RegisteredComponentsResource,
+        serialization: &SerializationResource,
+        quarantined: &HashSet<Uid>,
+        command_frame: CommandFrame,
+        mut scopes: Option<&mut InterestScopes>,
+        regions: Option<&RegionStreaming>,
+        relevancy: Option<&RelevancyOverrides>,
+        radii: Option<&InterestRadii>,
+    ) -> (Vec<(ClientId, WorldState)>, Vec<(Uid, ErrorKind)>) {
+        let entities = <(Entity, Read<UidComponent>)>::query()
+            .iter(world)
+            .filter(|(_, uid)| !quarantined.contains(&uid.uid()))
+            .map(|(entity, uid)| (uid.uid(), *entity))
+            .collect::<HashMap<Uid, Entity>>();
+
+        let previous = mem::replace(&mut self.entities, entities);
+        self.inserted.clear();
+
+        let mut transitions = Vec::new();
+        let mut errors = Vec::new();
+
+        // Clients whose filter was removed receive the entities they are missing once.
+        let clients = self
+            .filters
+            .keys()
+            .chain(self.passing.keys())
+            .copied()
+            .collect::<HashSet<ClientId>>();
+
+        for client in clients {
+            let was_passing = self.passing.remove(&client);
+
+            if regions.map_or(false, |regions| regions.is_streaming(client)) {
+                continue;
+            }
+
+            let filter = self.filters.get(&client);
+            let position = self.position;
+            let max_radius = radii.map(|radii| radii.radius(client));
+
+            let mut passing = HashSet::new();
+            let mut state = WorldState::new(command_frame);
+            let inserted = self.inserted.entry(client).or_default();
+
+            for (uid, entity) in self.entities.iter() {
+                let passes = filter.map_or(true, |filter| {
+                    filter.passes(world, *entity, position, max_radius)
+                });
+                if passes {
+                    passing.insert(*uid);
+                }
+
+                // Entities spawned in this frame are inserted by the state update itself.
+                if !previous.contains_key(uid) {
+                    continue;
+                }
+
+                if relevancy.map_or(false, |relevancy| relevancy.get(client, *uid).is_some()) {
+                    continue;
+                }
+
+                let passed = was_passing
+                    .as_ref()
+                    .map_or(true, |was_passing| was_passing.contains(uid));
+
+                match (passed, passes) {
+                    (true, false) => {
+                        state.remove_entity(*uid);
+
+                        if let Some(scopes) = scopes.as_deref_mut() {
+                            scopes.remove(client, *entity);
+                        }
+                    }
+                    (false, true) => {
+                        let components = serialize_entity(
+                            world,
+                            registered,
+                            serialization,
+                            *entity,
+                            *uid,
+                            &mut errors,
+                        );
+
+                        state.insert_entity(*uid, components);
+                        inserted.insert(*uid);
+
+                        if let Some(scopes) = scopes.as_deref_mut() {
+                            scopes.insert(client, *entity);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            // The removal of the entities despawned in this frame goes to the clients that had them.
+            for uid in previous
+                .keys()
+                .filter(|uid| !self.entities.contains_key(uid))
+            {
+                if was_passing
+                    .as_ref()
+                    .map_or(true, |was_passing| was_passing.contains(uid))
+                {
+                    passing.insert(*uid);
+                }
+            }
+
+            if filter.is_some() {
+                self.passing.insert(client, passing);
+            }
+            if !state.is_empty() {
+                transitions.push((client, state));
+            }
+        }
+
+        self.inserted.retain(|_, inserted| !inserted.is_empty());
+
+        (transitions, errors)
+    }
+
+    /// Whether the states of the client have to be filtered.
+    pub(crate) fn is_filtering(&self, client: ClientId) -> bool {
+        self.passing.contains_key(&client) || self.inserted.contains_key(&client)
+    }
+
+    /// Returns the part of the state that concerns the entities passing the filter of the client,
+    /// and the entities that are always relevant to it.
+    pub(crate) fn filter_state(
+        &self,
+        client: ClientId,
+        state: &WorldState,
+        relevancy: Option<&RelevancyOverrides>,
+    ) -> WorldState {
+        let inserted = self.inserted.get(&client);
+
+        world::filter_state(state, |uid| {
+            // The transition of this frame already sent the current components.
+            if inserted.map_or(false, |inserted| inserted.contains(&uid)) {
+                return false;
+            }
+
+            let always = relevancy.map_or(false, |relevancy| {
+                relevancy.get(client, uid) == Some(Relevancy::Always)
+            });
+
+            always || self.passes(client, uid)
+        })
+    }
+
+    /// The `SerializedStateCache` filter of the states filtered for the client,
+    /// clients with the same passing entities share their payloads.
+    pub(crate) fn cache_filter(&self, client: ClientId, filter: u64) -> u64 {
+        let sorted = |uids: Option<&HashSet<Uid>>| {
+            let mut uids = uids.into_iter().flatten().copied().collect::<Vec<_>>();
+            uids.sort();
+            uids
+        };
+
+        let mut hasher = DefaultHasher::new();
+        (
+            filter,
+            sorted(self.passing.get(&client)),
+            sorted(self.inserted.get(&client)),
+        )
+            .hash(&mut hasher);
+
+        // Keeps clear of `SerializedStateCache::UNFILTERED`, the version, region and relevancy
+        // filters.
+        (4 << 32) | (hasher.finish() & u32::MAX as u64)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::collections::HashSet;
+
+    use legion::{world::EntityStore, Entity, World};
+
+    use net_sync::synchronisation::{ComponentData, WorldState};
+
+    use crate::{
+        components::UidComponent,
+        resources::{
+            AreaOfInterest, InterestFilter, RegisteredComponentsResource, SerializationResource,
+        },
+    };
+
+    fn position(world: &World, entity: Entity) -> Option<[f32; 3]> {
+        world
+            .entry_ref(entity)
+            .ok()
+            .and_then(|entry| entry.get_component::<[f32; 3]>().ok().copied())
+    }
+
+    #[test]
+    fn entities_enter_and_leave_the_radius_test() {
+        let registered = RegisteredComponentsResource::new();
+        let serialization = SerializationResource::default();
+        let quarantined = HashSet::new();
+        let mut world = World::default();
+        let avatar = world.push((UidComponent::new(1), [0f32, 0., 0.]));
+        let other = world.push((UidComponent::new(2), [5f32, 0., 0.]));
+        world.push((UidComponent::new(3),));
+
+        let mut area = AreaOfInterest::new(position);
+        area.update(&world, &registered, &serialization, &quarantined, 1, None, None, None, None);
+
+        // The client has the whole world from its initial sync.
+        area.set_filter(7, InterestFilter::Radius { avatar, radius: 4. });
+        let (transitions, _) = area.update(
+            &world,
+            &registered,
+            &serialization,
+            &quarantined,
+            2,
+            None,
+            None,
+            None,
+            None,
+        );
+        assert_eq!(transitions.len(), 1);
+        assert_eq!(transitions[0].1.removed, vec![2]);
+
+        world.entry(other).unwrap().add_component([3f32, 0., 0.]);
+        let (transitions, _) = area.update(
+            &world,
+            &registered,
+            &serialization,
+            &quarantined,
+            3,
+            None,
+            None,
+            None,
+            None,
+        );
+        assert_eq!(transitions[0].1.inserted[0].entity_id(), 2);
+
+        // Entity 2 was inserted whole, the others pass the filter.
+        let mut state = WorldState::new(3);
+        for uid in 1..=3 {
+            state.change(uid, ComponentData::new(1, vec![1]));
+        }
+        assert_eq!(area.filter_state(7, &state, None).changed.len(), 2);
+
+        // Clients without filter receive everything.
+        assert!(!area.is_filtering(8));
+    }
+
+    #[test]
+    fn custom_filters_decide_per_entity_test() {
+        let registered = RegisteredComponentsResource::new();
+        let serialization = SerializationResource::default();
+        let quarantined = HashSet::new();
+        let mut world = World::default();
+        world.push((UidComponent::new(1), 1u8));
+        world.push((UidComponent::new(2), 2u8));
+
+        let mut area = AreaOfInterest::new(position);
+        area.set_filter(
+            7,
+            InterestFilter::Custom(Box::new(|world, entity| {
+                world
+                    .entry_ref(entity)
+                    .map_or(false, |entry| entry.get_component::<u8>().ok() == Some(&1))
+            })),
+        );
+        area.update(&world, &registered, &serialization, &quarantined, 1, None, None, None, None);
+
+        let mut state = WorldState::new(1);
+        state.remove_entity(1);
+        state.remove_entity(2);
+        assert_eq!(area.filter_state(7, &state, None).removed, vec![1]);
+    }
+}
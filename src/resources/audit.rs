@@ -0,0 +1,144 @@
+use std::collections::VecDeque;
+
+use bincode::Options;
+use legion::Entity;
+use serde::de::DeserializeOwned;
+
+use net_sync::{synchronisation::CommandFrame, uid::Uid};
+
+use crate::{error::ErrorKind, tracking::re_exports::bincode, world::default_options};
+
+/// Why the state of the server overwrote a local change of the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverwriteReason {
+    /// The server computed another value for the predicted change.
+    Mispredicted,
+    /// The server did not add the predicted component, it was removed again.
+    AdditionUndone,
+    /// The server did not remove the predicted component, it was restored.
+    RemovalUndone,
+}
+
+/// A local change of the client that the server state overwrote, see `AuthorityAudit`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthorityOverwrite {
+    /// The command frame of the state update that overwrote the change.
+    pub command_frame: CommandFrame,
+    pub entity: Entity,
+    /// The uid of the entity.
+    pub uid: Uid,
+    /// The type name of the component.
+    pub component: &'static str,
+    pub reason: OverwriteReason,
+    /// The serialized component the client predicted, `None` if it predicted the removal.
+    pub local_bytes: Option<Vec<u8>>,
+    /// The serialized component after the server state was applied, `None` if it was removed.
+    pub server_bytes: Option<Vec<u8>>,
+}
+
+impl AuthorityOverwrite {
+    /// Decodes the component the client predicted.
+    pub fn local<T: DeserializeOwned>(&self) -> Option<Result<T, ErrorKind>> {
+        self.local_bytes.as_deref().map(decode)
+    }
+
+    /// Decodes the component after the server state was applied.
+    pub fn server<T: DeserializeOwned>(&self) -> Option<Result<T, ErrorKind>> {
+        self.server_bytes.as_deref().map(decode)
+    }
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, ErrorKind> {
+    default_options()
+        .deserialize(bytes)
+        .map_err(|e| ErrorKind::SerializationError(e.to_string()))
+}
+
+/// Client resource with the last local changes that the server state overwrote,
+/// enabled with `ClientWorldBuilder::with_authority_audit`.
+///
+/// When players report jitter, the log tells the network corrections apart from gameplay bugs:
+/// an entity that jitters without entries is moved by game code. Only the last `capacity`
+/// entries are kept.
+#[derive(Debug)]
+pub struct AuthorityAudit {
+    capacity: usize,
+    entries: VecDeque<AuthorityOverwrite>,
+    overwrites: u64,
+}
+
+impl AuthorityAudit {
+    pub fn new(capacity: usize) -> AuthorityAudit {
+        AuthorityAudit {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+            overwrites: 0,
+        }
+    }
+
+    /// The kept entries, the oldest first.
+    pub fn entries(&self) -> impl Iterator<Item = &AuthorityOverwrite> {
+        self.entries.iter()
+    }
+
+    /// The kept entries of the entity, the oldest first.
+    pub fn entries_of(&self, entity: Entity) -> impl Iterator<Item = &AuthorityOverwrite> {
+        self.entries.iter().filter(move |entry| entry.entity == entity)
+    }
+
+    /// The number of overwrites since the audit started, including the dropped entries.
+    pub fn overwrites(&self) -> u64 {
+        self.overwrites
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub(crate) fn record(&mut self, entry: AuthorityOverwrite) {
+        self.overwrites += 1;
+
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use legion::World;
+
+    use crate::resources::{AuthorityAudit, AuthorityOverwrite, OverwriteReason};
+
+    #[test]
+    fn audit_keeps_the_last_entries_test() {
+        let mut world = World::default();
+        let entity = world.push((0u8,));
+        let other = world.push((0u8,));
+
+        let mut audit = AuthorityAudit::new(2);
+
+        for (command_frame, entity) in vec![(1, entity), (2, other), (3, entity)] {
+            audit.record(AuthorityOverwrite {
+                command_frame,
+                entity,
+                uid: 1,
+                component: "Position",
+                reason: OverwriteReason::Mispredicted,
+                local_bytes: None,
+                server_bytes: None,
+            });
+        }
+
+        assert_eq!(audit.overwrites(), 3);
+        assert_eq!(
+            audit.entries().map(|entry| entry.command_frame).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert_eq!(audit.entries_of(entity).count(), 1);
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::BTreeSet;
+
+use net_sync::{synchronisation::CommandFrame, transport::ClientId};
+
+/// The phase of a match, see `MatchBarrier`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchPhase {
+    /// The server waits for the expected clients, the systems do not run.
+    Staging,
+    /// The systems run since the command frame.
+    Running { started_at: CommandFrame },
+}
+
+/// Server resource that holds the simulation back until all players of a match are synced,
+/// enabled with `ServerWorld::stage_match`.
+///
+/// While staging, the systems and scheduled actions do not run, so every client receives the
+/// same frame-zero state with its initial sync. Once the expected number of clients is synced
+/// the match starts: the clients are resynced to the command frame of the server and
+/// `ServerEvent::MatchPhaseChanged` is raised. Game code counts the match frames from
+/// `started_at`.
+#[derive(Debug, Clone)]
+pub struct MatchBarrier {
+    expected: usize,
+    ready: BTreeSet<ClientId>,
+    phase: MatchPhase,
+}
+
+impl MatchBarrier {
+    /// Stages a match that starts once `expected` clients are synced.
+    pub fn new(expected: usize) -> MatchBarrier {
+        MatchBarrier {
+            expected,
+            ready: BTreeSet::new(),
+            phase: MatchPhase::Staging,
+        }
+    }
+
+    pub fn phase(&self) -> MatchPhase {
+        self.phase
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(self.phase, MatchPhase::Running { .. })
+    }
+
+    pub fn expected(&self) -> usize {
+        self.expected
+    }
+
+    /// The clients that are synced and wait for the match to start.
+    pub fn ready(&self) -> impl Iterator<Item = ClientId> + '_ {
+        self.ready.iter().copied()
+    }
+
+    /// Marks a synced client as ready, returns whether it was not ready before.
+    ///
+    /// Clients that are synced after the match started are not tracked.
+    pub(crate) fn set_ready(&mut self, client: ClientId) -> bool {
+        !self.is_running() && self.ready.insert(client)
+    }
+
+    /// Starts the match at the command frame if the expected clients are ready,
+    /// returns whether the phase changed.
+    pub(crate) fn try_start(&mut self, command_frame: CommandFrame) -> bool {
+        if self.is_running() || self.ready.len() < self.expected {
+            return false;
+        }
+
+        self.phase = MatchPhase::Running {
+            started_at: command_frame,
+        };
+        true
+    }
+
+    /// A client that leaves while staging is not ready anymore.
+    pub fn remove_client(&mut self, client: ClientId) {
+        self.ready.remove(&client);
+    }
+
+    /// Moves the readiness to another client id, see `ServerWorld::migrate_client`.
+    pub fn rebind(&mut self, from: ClientId, to: ClientId) {
+        if self.ready.remove(&from) {
+            self.ready.insert(to);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::resources::{MatchBarrier, MatchPhase};
+
+    #[test]
+    fn match_starts_when_expected_clients_are_ready_test() {
+        let mut barrier = MatchBarrier::new(2);
+        assert!(barrier.set_ready(1));
+        assert!(!barrier.set_ready(1));
+        assert!(!barrier.try_start(5));
+
+        barrier.rebind(1, 3);
+        barrier.remove_client(3);
+        assert_eq!(barrier.ready().count(), 0);
+
+        barrier.set_ready(1);
+        barrier.set_ready(2);
+        assert!(barrier.try_start(7));
+        assert_eq!(barrier.phase(), MatchPhase::Running { started_at: 7 });
+        assert!(!barrier.try_start(8));
+        assert!(!barrier.set_ready(4));
+    }
+}
@@ -0,0 +1,383 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, VecDeque},
+    hash::{Hash, Hasher},
+};
+
+use net_sync::{
+    synchronisation::{CommandFrame, WorldState},
+    transport::ClientId,
+    uid::Uid,
+};
+
+use crate::world;
+
+/// Server resource with the state updates the clients did not acknowledge yet,
+/// enabled with `ServerWorldBuilder::with_state_acknowledgements`.
+///
+/// A state update only contains the changes of its command frame, a lost update leaves the
+/// client with outdated components. With acknowledgements the updates are diffed against the
+/// last state the client acknowledged instead: the unacknowledged updates are merged into every
+/// new update, any update that arrives brings the client up to date.
+///
+/// The merged update holds the component diffs of all its command frames, which do not apply
+/// twice. It is announced with a `ServerMessage::StateBaseline`, the client restores the changed
+/// components to their values at the baseline before it applies the update. The client world
+/// answers with a `ClientMessage::StateAck`, which is passed to `acknowledge`.
+#[derive(Debug)]
+pub struct StateBaselines {
+    capacity: usize,
+    unacked: HashMap<ClientId, VecDeque<Unacked>>,
+    acked: HashMap<ClientId, CommandFrame>,
+}
+
+#[derive(Debug)]
+struct Unacked {
+    // The first command frame of which the state holds changes, merged states hold several.
+    first_frame: CommandFrame,
+    // The command frame of the first merged update that carried the state.
+    delta: Option<CommandFrame>,
+    state: WorldState,
+}
+
+impl StateBaselines {
+    /// Keeps up to `capacity` unacknowledged updates per client,
+    /// the oldest two are merged when more are sent.
+    pub fn new(capacity: usize) -> StateBaselines {
+        StateBaselines {
+            capacity: capacity.max(1),
+            unacked: HashMap::new(),
+            acked: HashMap::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The command frame of the last state update the client acknowledged.
+    pub fn acked(&self, client: ClientId) -> Option<CommandFrame> {
+        self.acked.get(&client).copied()
+    }
+
+    /// The number of sent state updates the client did not acknowledge yet.
+    pub fn unacked(&self, client: ClientId) -> usize {
+        self.unacked.get(&client).map_or(0, |unacked| unacked.len())
+    }
+
+    /// Acknowledges the state updates the merged update of `command_frame` carried: the client
+    /// applied it, with the changes of all updates that were sent before it. Updates sent after
+    /// it are kept, also when they are of the same frame.
+    ///
+    /// Older acknowledgements, e.g. reordered ones, are ignored. Returns whether the
+    /// acknowledgement was new.
+    pub fn acknowledge(&mut self, client: ClientId, command_frame: CommandFrame) -> bool {
+        if self.acked(client).map_or(false, |acked| acked >= command_frame) {
+            return false;
+        }
+
+        self.acked.insert(client, command_frame);
+
+        if let Some(unacked) = self.unacked.get_mut(&client) {
+            while unacked
+                .front()
+                .and_then(|unacked| unacked.delta)
+                .map_or(false, |delta| delta <= command_frame)
+            {
+                unacked.pop_front();
+            }
+        }
+
+        true
+    }
+
+    pub fn remove_client(&mut self, client: ClientId) {
+        self.unacked.remove(&client);
+        self.acked.remove(&client);
+    }
+
+    /// Moves the unacknowledged updates to another client id, see `ServerWorld::migrate_client`.
+    pub fn rebind(&mut self, from: ClientId, to: ClientId) {
+        if let Some(unacked) = self.unacked.remove(&from) {
+            self.unacked.insert(to, unacked);
+        }
+        if let Some(acked) = self.acked.remove(&from) {
+            self.acked.insert(to, acked);
+        }
+    }
+
+    /// The client received the whole world with an initial sync, it is the new baseline.
+    pub(crate) fn synced(&mut self, client: ClientId) {
+        self.unacked.remove(&client);
+    }
+
+    /// Records a state update that was sent to the client as it is, e.g. the entities of a
+    /// region it started streaming. The next update sent with `delta` repeats its changes.
+    pub(crate) fn record(&mut self, client: ClientId, state: &WorldState) {
+        let capacity = self.capacity;
+        let unacked = self.unacked.entry(client).or_default();
+        unacked.push_back(Unacked {
+            first_frame: state.command_frame,
+            delta: None,
+            state: state.clone(),
+        });
+
+        if unacked.len() > capacity {
+            let oldest = unacked.pop_front().unwrap();
+            let next = unacked.pop_front().unwrap();
+            unacked.push_front(Unacked {
+                first_frame: oldest.first_frame,
+                delta: next.delta,
+                state: world::merge_states(oldest.state, next.state),
+            });
+        }
+    }
+
+    /// Records the state update of the client and returns the changes since its last
+    /// acknowledged state, with the `SerializedStateCache` filter of the result and the baseline
+    /// command frame to announce with a `ServerMessage::StateBaseline`.
+    pub(crate) fn delta(
+        &mut self,
+        client: ClientId,
+        state: WorldState,
+        filter: u64,
+    ) -> (WorldState, u64, CommandFrame) {
+        let command_frame = state.command_frame;
+        self.record(client, &state);
+
+        let unacked = self.unacked.get_mut(&client).unwrap();
+        for unacked in unacked.iter_mut() {
+            unacked.delta.get_or_insert(command_frame);
+        }
+
+        let baseline = unacked.front().unwrap().first_frame;
+
+        // Everything before this update was acknowledged, the payload can be shared.
+        if unacked.len() == 1 {
+            return (state, filter, baseline);
+        }
+
+        let delta = unacked
+            .iter()
+            .map(|unacked| unacked.state.clone())
+            .fold(None, |merged, state| match merged {
+                Some(merged) => Some(world::merge_states(merged, state)),
+                None => Some(state),
+            })
+            .unwrap();
+
+        // The merged state is only sent to this client, it can not share cached bytes.
+        let mut hasher = DefaultHasher::new();
+        (filter, client, baseline).hash(&mut hasher);
+
+        // Keeps clear of `SerializedStateCache::UNFILTERED` and the version filters.
+        (
+            delta,
+            (5 << 32) | (hasher.finish() & u32::MAX as u64),
+            baseline,
+        )
+    }
+}
+
+/// Client resource with the command frames of the applied state updates,
+/// enabled with `ClientWorldBuilder::with_state_acknowledgements`.
+///
+/// With acknowledgements the server merges the unacknowledged updates into every update,
+/// updates older than the last applied one are dropped. Updates of the same command frame are
+/// applied, the server sends e.g. the entities of a new region before the update of the frame.
+///
+/// The values of the changed components are kept from before every applied command frame. A
+/// merged update is applied onto the values at its baseline, the diffs of the frames the client
+/// applied already are not applied twice.
+#[derive(Debug, Default)]
+pub struct StateAcks {
+    applied: Option<CommandFrame>,
+    pending: Option<CommandFrame>,
+    // The announced baselines, by the command frame of the merged update.
+    baselines: BTreeMap<CommandFrame, CommandFrame>,
+    // The serialized components of (entity, component) before the updates of a command frame.
+    preimages: BTreeMap<CommandFrame, HashMap<(Uid, Uid), Vec<u8>>>,
+}
+
+impl StateAcks {
+    pub fn new() -> StateAcks {
+        StateAcks::default()
+    }
+
+    /// The command frame of the last applied state update.
+    pub fn applied(&self) -> Option<CommandFrame> {
+        self.applied
+    }
+
+    /// Removes the command frame to acknowledge, `None` if no merged update was applied since
+    /// the last call. Acknowledging the newest frame acknowledges the older ones.
+    ///
+    /// The client world sends the acknowledgement to the server as a `ClientMessage::StateAck`.
+    pub fn take_ack(&mut self) -> Option<CommandFrame> {
+        self.pending.take()
+    }
+
+    /// Whether the state update of the command frame builds on the applied state.
+    pub(crate) fn is_current(&self, command_frame: CommandFrame) -> bool {
+        self.applied.map_or(true, |applied| command_frame >= applied)
+    }
+
+    /// The next update of the command frame is merged since the baseline command frame.
+    pub(crate) fn set_baseline(&mut self, command_frame: CommandFrame, baseline: CommandFrame) {
+        self.baselines.insert(command_frame, baseline);
+    }
+
+    /// Takes the baseline of the merged update of the command frame, `None` if the update is
+    /// not a merged one. The baselines of older frames are dropped, like the values of the
+    /// frames before the baseline, the server does not merge them anymore.
+    pub(crate) fn take_baseline(&mut self, command_frame: CommandFrame) -> Option<CommandFrame> {
+        let baseline = self.baselines.remove(&command_frame);
+        self.baselines = self.baselines.split_off(&command_frame);
+
+        if let Some(baseline) = baseline {
+            self.preimages = self.preimages.split_off(&baseline);
+        }
+
+        baseline
+    }
+
+    /// Keeps the value of the component before the updates of the command frame, the value
+    /// before the first update of the frame is kept.
+    pub(crate) fn record_preimage(
+        &mut self,
+        command_frame: CommandFrame,
+        entity: Uid,
+        component: Uid,
+        value: impl FnOnce() -> Option<Vec<u8>>,
+    ) {
+        let preimages = self.preimages.entry(command_frame).or_default();
+
+        if !preimages.contains_key(&(entity, component)) {
+            if let Some(value) = value() {
+                preimages.insert((entity, component), value);
+            }
+        }
+    }
+
+    /// The value of the component before the first update since the baseline that changed it,
+    /// `None` if no applied update since the baseline changed it.
+    pub(crate) fn preimage(
+        &self,
+        baseline: CommandFrame,
+        entity: Uid,
+        component: Uid,
+    ) -> Option<&[u8]> {
+        self.preimages
+            .range(baseline..)
+            .find_map(|(_, preimages)| preimages.get(&(entity, component)))
+            .map(|value| value.as_slice())
+    }
+
+    /// The update of the command frame was applied, it is acknowledged when it was merged.
+    pub(crate) fn applied_update(&mut self, command_frame: CommandFrame, merged: bool) {
+        self.applied = Some(command_frame);
+
+        if merged {
+            self.pending = Some(command_frame);
+        }
+    }
+
+    /// An initial sync replaces the world, the next update is applied whatever its frame.
+    pub(crate) fn reset(&mut self) {
+        self.applied = None;
+        self.baselines.clear();
+        self.preimages.clear();
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use net_sync::synchronisation::WorldState;
+
+    use crate::resources::{SerializedStateCache, StateAcks, StateBaselines};
+
+    fn state(command_frame: u32, removed: u32) -> WorldState {
+        let mut state = WorldState::new(command_frame);
+        state.remove_entity(removed);
+        state
+    }
+
+    #[test]
+    fn unacked_updates_are_merged_test() {
+        let mut baselines = StateBaselines::new(4);
+        let unfiltered = SerializedStateCache::UNFILTERED;
+
+        let (delta, filter, baseline) = baselines.delta(7, state(1, 10), unfiltered);
+        assert_eq!((delta.removed.len(), filter, baseline), (1, unfiltered, 1));
+
+        // The first update was lost, the second brings its changes along.
+        let (delta, filter, baseline) = baselines.delta(7, state(2, 11), unfiltered);
+        assert_eq!((delta.command_frame, baseline), (2, 1));
+        assert_eq!(delta.removed.len(), 2);
+        assert_ne!(filter, unfiltered);
+
+        // An update sent after the merged update of its frame is not acknowledged with it.
+        baselines.record(7, &state(2, 12));
+
+        assert!(baselines.acknowledge(7, 2));
+        assert!(!baselines.acknowledge(7, 1));
+        assert_eq!(baselines.unacked(7), 1);
+
+        let (delta, _, baseline) = baselines.delta(7, state(3, 13), unfiltered);
+        assert_eq!((delta.removed.len(), baseline), (2, 2));
+
+        assert!(baselines.acknowledge(7, 3));
+        assert_eq!(baselines.unacked(7), 0);
+
+        let (delta, filter, baseline) = baselines.delta(7, state(4, 14), unfiltered);
+        assert_eq!((delta.removed.len(), filter, baseline), (1, unfiltered, 4));
+    }
+
+    #[test]
+    fn oldest_updates_are_merged_over_capacity_test() {
+        let mut baselines = StateBaselines::new(2);
+
+        for frame in 1..=4 {
+            let (_, _, baseline) =
+                baselines.delta(8, state(frame, frame), SerializedStateCache::UNFILTERED);
+            assert_eq!(baseline, 1);
+        }
+        assert_eq!(baselines.unacked(8), 2);
+
+        // The merged oldest update holds the update of frame 3, it is acknowledged with it.
+        assert!(baselines.acknowledge(8, 2));
+        assert_eq!(baselines.unacked(8), 2);
+        assert!(baselines.acknowledge(8, 3));
+        assert_eq!(baselines.unacked(8), 1);
+    }
+
+    #[test]
+    fn merged_updates_are_acknowledged_test() {
+        let mut acks = StateAcks::new();
+
+        acks.set_baseline(3, 2);
+        acks.set_baseline(4, 2);
+        acks.applied_update(3, false);
+        assert!(!acks.is_current(2));
+        assert_eq!(acks.take_ack(), None);
+
+        acks.applied_update(4, true);
+        assert_eq!(acks.take_ack(), Some(4));
+        assert_eq!(acks.take_ack(), None);
+
+        // The earliest value since the baseline is restored, the first value of a frame is kept.
+        acks.record_preimage(2, 1, 5, || Some(vec![2]));
+        acks.record_preimage(3, 1, 5, || Some(vec![3]));
+        acks.record_preimage(3, 1, 5, || Some(vec![4]));
+        assert_eq!(acks.preimage(2, 1, 5), Some(&[2][..]));
+        assert_eq!(acks.preimage(3, 1, 5), Some(&[3][..]));
+        assert_eq!(acks.preimage(4, 1, 5), None);
+
+        // Taking the baseline of frame 4 drops the one of frame 3 and the values before it.
+        assert_eq!(acks.take_baseline(4), Some(2));
+        assert_eq!(acks.take_baseline(3), None);
+
+        acks.set_baseline(5, 3);
+        assert_eq!(acks.take_baseline(5), Some(3));
+        assert_eq!(acks.preimage(2, 1, 5), Some(&[3][..]));
+    }
+}
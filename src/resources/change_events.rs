@@ -0,0 +1,185 @@
+use std::{any::TypeId, collections::HashSet};
+
+use bincode::Options;
+use crossbeam_channel::{unbounded, Receiver, Sender, TryIter};
+use legion::{storage::Component, Entity, World};
+use serde::de::DeserializeOwned;
+
+use net_sync::uid::Uid;
+
+use crate::{
+    error::ErrorKind, register::ComponentRegistration, tracking::re_exports::bincode,
+    world::default_options,
+};
+
+/// A component of a replicated entity that was changed by a server state update.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostApplyChange {
+    pub entity: Entity,
+    /// The uid of the entity.
+    pub uid: Uid,
+    pub component_uid: Uid,
+    /// The serialized component before the update was applied.
+    pub old_bytes: Vec<u8>,
+    /// The serialized component after the update was applied.
+    pub new_bytes: Vec<u8>,
+}
+
+impl PostApplyChange {
+    /// Decodes the component before the update was applied.
+    pub fn old<T: DeserializeOwned>(&self) -> Result<T, ErrorKind> {
+        decode(&self.old_bytes)
+    }
+
+    /// Decodes the component after the update was applied.
+    pub fn new<T: DeserializeOwned>(&self) -> Result<T, ErrorKind> {
+        decode(&self.new_bytes)
+    }
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, ErrorKind> {
+    default_options()
+        .deserialize(bytes)
+        .map_err(|e| ErrorKind::SerializationError(e.to_string()))
+}
+
+/// Client resource with a channel of the `PostApplyChange`s of the watched component types,
+/// enabled with `ClientWorldBuilder::with_change_events`.
+///
+/// Game code can react to a changed value, e.g. play a sound when the health drops,
+/// without keeping a copy of the previous value. Only the watched types are serialized
+/// before a change is applied, components with a transform are not reported.
+///
+/// ```ignore
+/// for change in change_events.try_iter() {
+///     let (old, new) = (change.old::<Health>()?, change.new::<Health>()?);
+/// }
+/// ```
+pub struct ChangeEvents {
+    watched: HashSet<TypeId>,
+    sender: Sender<PostApplyChange>,
+    receiver: Receiver<PostApplyChange>,
+}
+
+impl ChangeEvents {
+    pub fn new() -> ChangeEvents {
+        let (sender, receiver) = unbounded();
+        ChangeEvents {
+            watched: HashSet::new(),
+            sender,
+            receiver,
+        }
+    }
+
+    /// Reports the changes of component type `T`.
+    pub fn watch<T: Component>(&mut self) {
+        self.watched.insert(TypeId::of::<T>());
+    }
+
+    pub fn is_watched(&self, type_id: &TypeId) -> bool {
+        self.watched.contains(type_id)
+    }
+
+    /// The receiving end of the channel, it can be cloned to receive on another thread.
+    pub fn receiver(&self) -> &Receiver<PostApplyChange> {
+        &self.receiver
+    }
+
+    /// Receives the pending events.
+    pub fn try_iter(&self) -> TryIter<PostApplyChange> {
+        self.receiver.try_iter()
+    }
+
+    /// Serializes the component of the entity, `None` if its type is not watched.
+    pub(crate) fn snapshot(
+        &self,
+        registration: &ComponentRegistration,
+        world: &World,
+        entity: Entity,
+    ) -> Option<Vec<u8>> {
+        if !self.is_watched(&registration.ty()) {
+            return None;
+        }
+
+        let mut result = None;
+
+        registration.serialize_if_exists_in_world(world, entity, &mut |serialize| {
+            let mut buffer = Vec::new();
+            let serializer = &mut bincode::Serializer::new(&mut buffer, default_options());
+
+            if erased_serde::serialize(&serialize, serializer).is_ok() {
+                result = Some(buffer);
+            }
+        });
+
+        result
+    }
+
+    /// Sends the change of the component if its value before the update was taken.
+    pub(crate) fn record(
+        &self,
+        registration: &ComponentRegistration,
+        world: &World,
+        entity: Entity,
+        uid: Uid,
+        component_uid: Uid,
+        old_bytes: Option<Vec<u8>>,
+    ) {
+        let new_bytes = self.snapshot(registration, world, entity);
+
+        if let (Some(old_bytes), Some(new_bytes)) = (old_bytes, new_bytes) {
+            // The receiver is owned by this resource, the channel can not be disconnected.
+            let _ = self.sender.send(PostApplyChange {
+                entity,
+                uid,
+                component_uid,
+                old_bytes,
+                new_bytes,
+            });
+        }
+    }
+}
+
+impl Default for ChangeEvents {
+    fn default() -> Self {
+        ChangeEvents::new()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::any::TypeId;
+
+    use legion::World;
+
+    use crate::{
+        components::UidComponent,
+        resources::{ChangeEvents, RegisteredComponentsResource},
+    };
+
+    #[test]
+    fn old_and_new_value_are_sent_test() {
+        let registered = RegisteredComponentsResource::new();
+        let type_id = TypeId::of::<UidComponent>();
+        let component_uid = *registered.get_uid(&type_id).unwrap();
+        let registry_by_type = registered.by_type_id();
+        let registration = registry_by_type.get(&type_id).unwrap();
+
+        let mut world = World::default();
+        let entity = world.push((UidComponent::new(1),));
+
+        let mut events = ChangeEvents::new();
+        assert!(events.snapshot(registration, &world, entity).is_none());
+
+        events.watch::<UidComponent>();
+        let old_bytes = events.snapshot(registration, &world, entity);
+
+        world.entry(entity).unwrap().add_component(UidComponent::new(2));
+        events.record(registration, &world, entity, 1, component_uid, old_bytes);
+
+        let changes = events.try_iter().collect::<Vec<_>>();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].old::<UidComponent>().unwrap().uid(), 1);
+        assert_eq!(changes[0].new::<UidComponent>().unwrap().uid(), 2);
+    }
+}
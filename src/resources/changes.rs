@@ -0,0 +1,94 @@
+use std::collections::{HashSet, VecDeque};
+
+use legion::Entity;
+
+use net_sync::synchronisation::CommandFrame;
+
+/// Client resource with the entities that were changed by the last applied server state updates.
+///
+/// Combine it with a query to visit the entities changed since some command frame:
+///
+/// ```ignore
+/// for entity in changes.changed_since(frame) {
+///     if let Ok(position) = query.get(&world, entity) { .. }
+/// }
+/// ```
+pub struct ReplicatedChanges {
+    frames: VecDeque<(CommandFrame, HashSet<Entity>)>,
+    capacity: usize,
+}
+
+impl ReplicatedChanges {
+    /// Keeps the changes of the last `capacity` state updates.
+    pub fn with_capacity(capacity: usize) -> ReplicatedChanges {
+        ReplicatedChanges {
+            frames: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Marks the entity as changed by the state update of the given command frame.
+    pub fn record(&mut self, command_frame: CommandFrame, entity: Entity) {
+        match self.frames.back_mut() {
+            Some((frame, entities)) if *frame == command_frame => {
+                entities.insert(entity);
+            }
+            _ => {
+                if self.frames.len() == self.capacity {
+                    self.frames.pop_front();
+                }
+
+                let mut entities = HashSet::new();
+                entities.insert(entity);
+                self.frames.push_back((command_frame, entities));
+            }
+        }
+    }
+
+    /// Returns the entities changed by the state updates of command frames after `command_frame`.
+    pub fn changed_since(&self, command_frame: CommandFrame) -> HashSet<Entity> {
+        self.frames
+            .iter()
+            .filter(|(frame, _)| *frame > command_frame)
+            .flat_map(|(_, entities)| entities.iter().copied())
+            .collect()
+    }
+
+    /// Returns the command frame and the entities of the last state update that changed entities.
+    pub fn last_update(&self) -> Option<(CommandFrame, &HashSet<Entity>)> {
+        self.frames
+            .back()
+            .map(|(frame, entities)| (*frame, entities))
+    }
+}
+
+impl Default for ReplicatedChanges {
+    fn default() -> Self {
+        ReplicatedChanges::with_capacity(32)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use legion::World;
+
+    use crate::resources::ReplicatedChanges;
+
+    #[test]
+    fn changed_since_merges_frames_test() {
+        let mut world = World::default();
+        let entities = world.extend(vec![(), (), ()]).to_vec();
+
+        let mut changes = ReplicatedChanges::with_capacity(2);
+        changes.record(1, entities[0]);
+        changes.record(2, entities[1]);
+        changes.record(2, entities[1]);
+        changes.record(3, entities[2]);
+
+        assert_eq!(changes.changed_since(1).len(), 2);
+        assert!(changes.changed_since(2).contains(&entities[2]));
+        // Frame 1 was dropped because of the capacity.
+        assert_eq!(changes.changed_since(0).len(), 2);
+        assert_eq!(changes.last_update().unwrap().0, 3);
+    }
+}
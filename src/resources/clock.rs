@@ -0,0 +1,88 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Source of time used by the ticker and timeout logic.
+///
+/// Time is expressed as the duration since the clock was started,
+/// this way platforms without `std::time::Instant` (WASM) can implement their own clock.
+pub trait Clock: Send + Sync + 'static {
+    fn now(&self) -> Duration;
+}
+
+/// Clock backed by the monotonic system clock.
+pub struct RealClock {
+    start: Instant,
+}
+
+impl RealClock {
+    pub fn new() -> RealClock {
+        RealClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for RealClock {
+    fn default() -> Self {
+        RealClock::new()
+    }
+}
+
+impl Clock for RealClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// Clock that only moves when told to, used for deterministic tests and WASM shims.
+///
+/// The clock can be cloned, all clones share the same time.
+#[derive(Clone, Default)]
+pub struct ManualClock {
+    now: Arc<Mutex<Duration>>,
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock::default()
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+
+    pub fn set(&self, now: Duration) {
+        *self.now.lock().unwrap() = now;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// Resource containing the clock used by the synchronisation layer.
+pub struct ClockResource {
+    clock: Box<dyn Clock>,
+}
+
+impl ClockResource {
+    pub fn new<C: Clock>(clock: C) -> ClockResource {
+        ClockResource {
+            clock: Box::new(clock),
+        }
+    }
+
+    pub fn now(&self) -> Duration {
+        self.clock.now()
+    }
+}
+
+impl Default for ClockResource {
+    fn default() -> Self {
+        ClockResource::new(RealClock::new())
+    }
+}
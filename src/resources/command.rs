@@ -0,0 +1,75 @@
+use std::vec::Drain;
+
+use net_sync::{synchronisation::CommandFrame, transport::ClientId};
+
+use crate::protocol::{CommandOutcome, CommandResult};
+
+/// Server resource to which systems write the outcome of the commands they processed.
+///
+/// The server world sends every result only to the client that issued the command.
+pub struct CommandResultQueue<C> {
+    results: Vec<(ClientId, CommandResult<C>)>,
+}
+
+impl<C> CommandResultQueue<C> {
+    pub fn new() -> CommandResultQueue<C> {
+        CommandResultQueue {
+            results: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, client: ClientId, command_frame: CommandFrame, outcome: CommandOutcome<C>) {
+        self.results.push((
+            client,
+            CommandResult {
+                command_frame,
+                outcome,
+            },
+        ));
+    }
+
+    pub fn accept(&mut self, client: ClientId, command_frame: CommandFrame) {
+        self.push(client, command_frame, CommandOutcome::Accepted);
+    }
+
+    pub fn reject(&mut self, client: ClientId, command_frame: CommandFrame, reason: impl Into<String>) {
+        self.push(client, command_frame, CommandOutcome::Rejected(reason.into()));
+    }
+
+    pub fn transform(&mut self, client: ClientId, command_frame: CommandFrame, command: C) {
+        self.push(client, command_frame, CommandOutcome::Transformed(command));
+    }
+
+    pub(crate) fn drain(&mut self) -> Drain<'_, (ClientId, CommandResult<C>)> {
+        self.results.drain(..)
+    }
+}
+
+/// Client resource with the command results received from the server since they were last drained.
+pub struct CommandResultEvents<C> {
+    results: Vec<CommandResult<C>>,
+}
+
+impl<C> CommandResultEvents<C> {
+    pub fn new() -> CommandResultEvents<C> {
+        CommandResultEvents {
+            results: Vec::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, result: CommandResult<C>) {
+        self.results.push(result);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &CommandResult<C>> {
+        self.results.iter()
+    }
+
+    pub fn drain(&mut self) -> Drain<'_, CommandResult<C>> {
+        self.results.drain(..)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+}
@@ -2,7 +2,7 @@ use std::{
     any::TypeId,
     collections::{
         hash_map::{self},
-        HashMap,
+        HashMap, HashSet,
     },
     hash::Hash,
     slice,
@@ -11,9 +11,13 @@ use std::{
 
 use net_sync::uid::Uid;
 
-use crate::register::{ComponentRegister, ComponentRegistrationRef};
+use crate::{
+    components::{ReplicatedThisFrame, UidComponent},
+    filters::RegisteredFilter,
+    register::{ComponentRegister, ComponentRegistrationRef},
+};
 
-use legion::Registry;
+use legion::{storage::ComponentTypeId, world::Duplicate, Registry};
 
 unsafe impl Send for RegisteredComponentsResource {}
 unsafe impl Sync for RegisteredComponentsResource {}
@@ -27,9 +31,11 @@ pub struct RegisteredComponentsResource {
     registration_by_uid: Arc<Mutex<HashMap<Uid, ComponentRegistrationRef>>>,
     registration_by_type_id: Arc<Mutex<HashMap<TypeId, ComponentRegistrationRef>>>,
     uid_with_registration: Arc<Mutex<Vec<(Uid, ComponentRegistrationRef)>>>,
+    component_type_ids: Arc<HashSet<ComponentTypeId>>,
 
     pub(crate) legion_registry: legion::Registry<String>,
-    pub(crate) merger: Mutex<legion::world::Duplicate>,
+    /// Registers the components into a `Duplicate`, see `merger`.
+    merger_registrations: Arc<Vec<fn(&mut Duplicate)>>,
 }
 
 impl RegisteredComponentsResource {
@@ -39,6 +45,7 @@ impl RegisteredComponentsResource {
 
         let mut uid_with_type_id = HashMap::new();
         let mut type_id_with_uid = HashMap::new();
+        let mut component_type_ids = HashSet::new();
 
         let mut sorted_registry = ComponentRegister::by_unique_uid()
             .clone()
@@ -49,7 +56,7 @@ impl RegisteredComponentsResource {
         sorted_registry.sort_by(|a, b| a.1.ty().partial_cmp(&b.1.ty()).unwrap());
 
         let mut registry = legion::Registry::<String>::new();
-        let mut merger = legion::world::Duplicate::new();
+        let mut merger_registrations = Vec::<fn(&mut Duplicate)>::new();
 
         for entry in sorted_registry.iter() {
             by_uid.insert(entry.0, entry.1);
@@ -57,11 +64,19 @@ impl RegisteredComponentsResource {
 
             type_id_with_uid.insert(entry.1.ty(), entry.0);
             uid_with_type_id.insert(entry.0, entry.1.ty());
+            component_type_ids.insert(entry.1.component_type_id());
 
             entry.1.register_into_registry(&mut registry);
-            entry.1.register_into_merger(&mut merger);
+            merger_registrations.push(entry.1.register_into_merger);
         }
 
+        // The marker is not synchronized, but must not exclude entities from world snapshots.
+        component_type_ids.insert(ComponentTypeId::of::<ReplicatedThisFrame>());
+        registry.register::<ReplicatedThisFrame>(
+            std::any::type_name::<ReplicatedThisFrame>().to_string(),
+        );
+        merger_registrations.push(|merger| merger.register_clone::<ReplicatedThisFrame>());
+
         Self {
             type_id_with_uid,
             uid_with_type_id,
@@ -69,9 +84,10 @@ impl RegisteredComponentsResource {
             registration_by_uid: Arc::new(Mutex::new(by_uid)),
             registration_by_type_id: Arc::new(Mutex::new(by_type_id)),
             uid_with_registration: Arc::new(Mutex::new(sorted_registry)),
+            component_type_ids: Arc::new(component_type_ids),
 
             legion_registry: registry,
-            merger: Mutex::new(merger),
+            merger_registrations: Arc::new(merger_registrations),
         }
     }
 
@@ -103,8 +119,40 @@ impl RegisteredComponentsResource {
         &mut self.legion_registry
     }
 
-    pub fn legion_merger(&self) -> &Mutex<legion::world::Duplicate> {
-        &self.merger
+    /// Creates a merger that clones the registered components between worlds,
+    /// e.g. for `World::clone_from`.
+    ///
+    /// Every call gets its own merger, so initial syncs of concurrent client joins do not wait
+    /// for each other. The registrations are collected once, creating a merger only fills
+    /// its table.
+    pub fn merger(&self) -> Duplicate {
+        let mut merger = Duplicate::new();
+
+        for register in self.merger_registrations.iter() {
+            register(&mut merger);
+        }
+
+        merger
+    }
+
+    /// Like `merger`, but only with the components of which `include` accepts the uid. The
+    /// `UidComponent` is always registered, the entities are identified by it.
+    pub fn merger_of(&self, include: impl Fn(Uid) -> bool) -> Duplicate {
+        let uid_component = self.get_uid(&TypeId::of::<UidComponent>()).copied();
+        let mut merger = Duplicate::new();
+
+        for (uid, registration) in self.uid_with_registration.lock().unwrap().iter() {
+            if include(*uid) || Some(*uid) == uid_component {
+                registration.register_into_merger(&mut merger);
+            }
+        }
+
+        merger
+    }
+
+    /// Returns a layout filter matching archetypes of which all components are registered.
+    pub fn filter(&self) -> RegisteredFilter {
+        RegisteredFilter::new(self.component_type_ids.clone())
     }
 }
 
@@ -150,7 +198,12 @@ impl<'a> SliceRegistry<'a> {
 
 #[cfg(test)]
 pub mod test {
-    use crate::resources::RegisteredComponentsResource;
+    use legion::World;
+
+    use crate::{
+        components::{Frozen, UidComponent},
+        resources::RegisteredComponentsResource,
+    };
 
     #[test]
     fn register_should_have_same_components_test() {
@@ -176,4 +229,37 @@ pub mod test {
             assert!(registry.get_type(&entry.0).is_some());
         }
     }
+
+    #[test]
+    fn mergers_are_independent_test() {
+        let registry = RegisteredComponentsResource::new();
+        let mut source = World::default();
+        let entity = source.push((UidComponent::new(1),));
+
+        // Both mergers are alive at the same time, like during concurrent initial syncs.
+        let mut first = registry.merger();
+        let mut second = registry.merger();
+
+        let mut first_world = World::default();
+        let mut second_world = World::default();
+        first_world.clone_from_single(&source, entity, &mut first);
+        second_world.clone_from_single(&source, entity, &mut second);
+
+        assert_eq!(first_world.len(), 1);
+        assert_eq!(second_world.len(), 1);
+    }
+    #[test]
+    fn merger_of_only_clones_included_components_test() {
+        let registry = RegisteredComponentsResource::new();
+        let mut source = World::default();
+        let entity = source.push((UidComponent::new(1), Frozen::new(2)));
+
+        let mut merger = registry.merger_of(|_| false);
+        let mut world = World::default();
+        let cloned = world.clone_from_single(&source, entity, &mut merger);
+
+        let entry = world.entry_ref(cloned).unwrap();
+        assert!(entry.get_component::<UidComponent>().is_ok());
+        assert!(entry.get_component::<Frozen>().is_err());
+    }
 }
@@ -0,0 +1,145 @@
+use std::collections::VecDeque;
+
+use net_sync::synchronisation::CommandFrame;
+
+/// The state of the connection of the client with the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// No message was received from the server yet.
+    Connecting,
+    /// Messages are received from the server.
+    Connected,
+    /// The connection was lost, commands are handled with the `CommandBufferPolicy`.
+    Disconnected,
+    /// There is no server, commands are accepted locally.
+    Offline,
+}
+
+/// What the client does with its commands while it is disconnected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandBufferPolicy {
+    /// Commands issued while disconnected are dropped.
+    Drop,
+    /// Commands of the last `n` command frames are kept and sent on reconnect.
+    Buffer(u32),
+}
+
+impl Default for CommandBufferPolicy {
+    fn default() -> Self {
+        CommandBufferPolicy::Drop
+    }
+}
+
+/// Client resource tracking the connection state and the commands held back while disconnected.
+///
+/// The client world marks the connection as connected when a message from the server arrives,
+/// and as disconnected when the network thread stopped or the transport reported a disconnect
+/// in the `NetworkEventQueue`.
+pub struct ClientConnection<C> {
+    state: ConnectionState,
+    policy: CommandBufferPolicy,
+    held: VecDeque<(CommandFrame, C)>,
+}
+
+impl<C> ClientConnection<C> {
+    pub fn new(policy: CommandBufferPolicy) -> ClientConnection<C> {
+        ClientConnection {
+            state: ConnectionState::Connecting,
+            policy,
+            held: VecDeque::new(),
+        }
+    }
+
+    /// Creates a connection in offline mode, see `ConnectionState::Offline`.
+    pub fn offline() -> ClientConnection<C> {
+        ClientConnection {
+            state: ConnectionState::Offline,
+            policy: CommandBufferPolicy::Drop,
+            held: VecDeque::new(),
+        }
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    /// Sets the connection state, offline mode can not be left.
+    pub fn set_state(&mut self, state: ConnectionState) {
+        if self.state != ConnectionState::Offline {
+            self.state = state;
+        }
+    }
+
+    pub fn policy(&self) -> CommandBufferPolicy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: CommandBufferPolicy) {
+        self.policy = policy;
+
+        if policy == CommandBufferPolicy::Drop {
+            self.held.clear();
+        }
+    }
+
+    /// Holds back a command issued while disconnected, according to the policy.
+    pub(crate) fn hold(&mut self, command_frame: CommandFrame, command: C) {
+        if let CommandBufferPolicy::Buffer(frames) = self.policy {
+            self.held.push_back((command_frame, command));
+
+            while let Some((oldest, _)) = self.held.front() {
+                if command_frame.saturating_sub(*oldest) >= frames {
+                    self.held.pop_front();
+                } else {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Returns the held back commands, oldest first.
+    pub(crate) fn take_held(&mut self) -> Vec<(CommandFrame, C)> {
+        self.held.drain(..).collect()
+    }
+
+    /// The number of commands held back.
+    pub fn held_len(&self) -> usize {
+        self.held.len()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::resources::{ClientConnection, CommandBufferPolicy, ConnectionState};
+
+    #[test]
+    fn drop_policy_holds_nothing_test() {
+        let mut connection = ClientConnection::new(CommandBufferPolicy::Drop);
+        connection.set_state(ConnectionState::Disconnected);
+
+        connection.hold(1, ());
+
+        assert_eq!(connection.held_len(), 0);
+    }
+
+    #[test]
+    fn buffer_policy_keeps_last_frames_test() {
+        let mut connection = ClientConnection::new(CommandBufferPolicy::Buffer(2));
+
+        connection.hold(1, 1);
+        connection.hold(2, 2);
+        connection.hold(3, 3);
+
+        assert_eq!(connection.take_held(), vec![(2, 2), (3, 3)]);
+        assert_eq!(connection.held_len(), 0);
+    }
+
+    #[test]
+    fn offline_can_not_be_left_test() {
+        let mut connection = ClientConnection::<()>::offline();
+
+        connection.set_state(ConnectionState::Connected);
+
+        assert_eq!(connection.state(), ConnectionState::Offline);
+    }
+}
@@ -0,0 +1,91 @@
+use std::any::{type_name, TypeId};
+
+use legion::{storage::Component, Entity, World};
+
+struct Constraint {
+    component: TypeId,
+    type_name: &'static str,
+    is_valid: Box<dyn Fn(&World, Entity) -> bool + Send + Sync>,
+}
+
+/// Server resource with the value constraints of component types,
+/// registered with `ServerWorldBuilder::with_constraint`.
+///
+/// The constraints are checked when client-writable state enters the authoritative world,
+/// e.g. with `ServerWorld::merge_simulation_state`. A state that violates a constraint is
+/// rejected and logged. Game code that ingests client state of its own checks it with
+/// `violations`.
+///
+/// ```ignore
+/// ServerWorldBuilder::default().with_constraint(|health: &Health| health.0 <= MAX_HEALTH);
+/// ```
+#[derive(Default)]
+pub struct ComponentConstraints {
+    constraints: Vec<Constraint>,
+}
+
+impl ComponentConstraints {
+    pub fn new() -> ComponentConstraints {
+        ComponentConstraints::default()
+    }
+
+    /// Adds a constraint of component `T`, a type can have several constraints.
+    pub fn register_constraint<T: Component>(
+        &mut self,
+        is_valid: impl Fn(&T) -> bool + Send + Sync + 'static,
+    ) {
+        self.constraints.push(Constraint {
+            component: TypeId::of::<T>(),
+            type_name: type_name::<T>(),
+            is_valid: Box::new(move |world, entity| {
+                world.entry_ref(entity).map_or(true, |entry| {
+                    entry.get_component::<T>().map_or(true, &is_valid)
+                })
+            }),
+        });
+    }
+
+    /// Whether component type `T` has a constraint.
+    pub fn is_constrained<T: Component>(&self) -> bool {
+        self.constraints
+            .iter()
+            .any(|constraint| constraint.component == TypeId::of::<T>())
+    }
+
+    /// The type names of the components of the entity that violate a constraint.
+    pub fn violations(&self, world: &World, entity: Entity) -> Vec<&'static str> {
+        let mut violations = self
+            .constraints
+            .iter()
+            .filter(|constraint| !(constraint.is_valid)(world, entity))
+            .map(|constraint| constraint.type_name)
+            .collect::<Vec<_>>();
+        violations.dedup();
+        violations
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use legion::World;
+
+    use crate::{components::DynamicComponent, resources::ComponentConstraints};
+
+    #[test]
+    fn violating_components_are_reported_test() {
+        let mut constraints = ComponentConstraints::new();
+        constraints.register_constraint(|component: &DynamicComponent| {
+            component.value().as_i64().map_or(false, |value| value <= 100)
+        });
+        assert!(constraints.is_constrained::<DynamicComponent>());
+
+        let mut world = World::default();
+        let valid = world.push((DynamicComponent::new("health", serde_json::json!(80)),));
+        let invalid = world.push((DynamicComponent::new("health", serde_json::json!(500)),));
+        let unconstrained = world.push((0u32,));
+
+        assert!(constraints.violations(&world, valid).is_empty());
+        assert_eq!(constraints.violations(&world, invalid).len(), 1);
+        assert!(constraints.violations(&world, unconstrained).is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use legion::{
+    query::{IntoQuery, Read},
+    Entity, World,
+};
+
+use net_sync::synchronisation::CommandFrame;
+
+use crate::components::CorrelationId;
+
+/// Client resource with the local entities that represent a predicted effect of a command,
+/// e.g. the projectile of a "fire" command.
+///
+/// An ephemeral entity is removed when the server spawned entity with the same `CorrelationId`
+/// arrives, or when its time to live expired.
+#[derive(Debug, Default)]
+pub struct EphemeralEntities {
+    next_id: u32,
+    // Ephemeral entities by correlation id, with the command frame at which they expire.
+    entities: HashMap<u32, (Entity, CommandFrame)>,
+}
+
+impl EphemeralEntities {
+    pub fn new() -> EphemeralEntities {
+        EphemeralEntities::default()
+    }
+
+    /// Registers a predicted entity that lives until `expires_at` at most,
+    /// returns the correlation id to send with the command.
+    pub fn insert(&mut self, entity: Entity, expires_at: CommandFrame) -> CorrelationId {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        self.entities.insert(id, (entity, expires_at));
+        CorrelationId::new(id)
+    }
+
+    /// Returns the predicted entity of the correlation id, if it was not removed yet.
+    pub fn get(&self, correlation: CorrelationId) -> Option<Entity> {
+        self.entities
+            .get(&correlation.id())
+            .map(|(entity, _)| *entity)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Removes the ephemeral entities that are replaced by a server entity or that expired.
+    ///
+    /// Returns the replaced entities with the server entity that replaced them.
+    pub fn update(
+        &mut self,
+        world: &mut World,
+        command_frame: CommandFrame,
+    ) -> Vec<(Entity, Entity)> {
+        let mut replaced = Vec::new();
+
+        if self.entities.is_empty() {
+            return replaced;
+        }
+
+        let arrived = <(Entity, Read<CorrelationId>)>::query()
+            .iter(world)
+            .map(|(entity, correlation)| (*entity, correlation.id()))
+            .collect::<Vec<(Entity, u32)>>();
+
+        for (server_entity, id) in arrived {
+            if let Some((entity, _)) = self.entities.remove(&id) {
+                world.remove(entity);
+                replaced.push((entity, server_entity));
+            }
+        }
+
+        let expired = self
+            .entities
+            .iter()
+            .filter(|(_, (_, expires_at))| *expires_at <= command_frame)
+            .map(|(id, _)| *id)
+            .collect::<Vec<u32>>();
+
+        for id in expired {
+            if let Some((entity, _)) = self.entities.remove(&id) {
+                world.remove(entity);
+            }
+        }
+
+        replaced
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use legion::World;
+
+    use crate::{components::CorrelationId, resources::EphemeralEntities};
+
+    #[test]
+    fn ephemeral_removed_when_server_entity_arrives_test() {
+        let mut world = World::default();
+        let mut ephemeral = EphemeralEntities::new();
+
+        let predicted = world.push((1u8,));
+        let correlation = ephemeral.insert(predicted, 10);
+
+        ephemeral.update(&mut world, 1);
+        assert!(world.contains(predicted));
+
+        let server_entity = world.push((correlation,));
+        assert_eq!(ephemeral.update(&mut world, 2), vec![(predicted, server_entity)]);
+
+        assert!(!world.contains(predicted));
+        assert!(ephemeral.is_empty());
+    }
+
+    #[test]
+    fn ephemeral_removed_when_expired_test() {
+        let mut world = World::default();
+        let mut ephemeral = EphemeralEntities::new();
+
+        let predicted = world.push((1u8,));
+        ephemeral.insert(predicted, 5);
+        world.push((CorrelationId::new(7),));
+
+        ephemeral.update(&mut world, 4);
+        assert!(world.contains(predicted));
+
+        ephemeral.update(&mut world, 5);
+        assert!(!world.contains(predicted));
+    }
+}
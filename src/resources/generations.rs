@@ -0,0 +1,232 @@
+use std::collections::{HashMap, VecDeque};
+
+use net_sync::{
+    synchronisation::{CommandFrame, WorldState},
+    uid::Uid,
+};
+
+/// Server resource that hands out entity uids and withholds released uids for a number of
+/// command frames before they are reused.
+///
+/// A uid that is reused right away can alias the removed entity: a late state update or
+/// message of the old entity applies to the new one. With a reuse delay longer than the
+/// latency of the slowest client the old uid is out of flight before it is handed out again.
+#[derive(Debug)]
+pub struct UidRecycler {
+    next: Uid,
+    reuse_after: CommandFrame,
+    /// The released uids in the order they were released.
+    released: VecDeque<(Uid, CommandFrame)>,
+}
+
+impl UidRecycler {
+    /// Hands out uids from `first` and reuses released uids after `reuse_after` command frames.
+    pub fn new(first: Uid, reuse_after: CommandFrame) -> UidRecycler {
+        UidRecycler {
+            next: first,
+            reuse_after,
+            released: VecDeque::new(),
+        }
+    }
+
+    /// Returns a uid for an entity spawned at the command frame, assign it with `UidComponent`.
+    pub fn allocate(&mut self, command_frame: CommandFrame) -> Uid {
+        match self.released.front() {
+            Some((uid, released))
+                if command_frame.saturating_sub(*released) >= self.reuse_after =>
+            {
+                let uid = *uid;
+                self.released.pop_front();
+                uid
+            }
+            _ => {
+                let uid = self.next;
+                self.next += 1;
+                uid
+            }
+        }
+    }
+
+    /// Releases the uid of an entity removed at the command frame.
+    pub fn release(&mut self, uid: Uid, command_frame: CommandFrame) {
+        self.released.push_back((uid, command_frame));
+    }
+
+    /// The number of released uids that are not reused yet.
+    pub fn withheld(&self) -> usize {
+        self.released.len()
+    }
+}
+
+/// Client resource that drops the parts of state updates that belong to an earlier entity with
+/// the same uid, enabled with `ClientWorldBuilder::with_uid_generations`.
+///
+/// The command frame an uid is inserted at is the generation of its entity. Changes of a
+/// state update older than the generation, and inserts older than the last removal of the uid,
+/// are stale and left out before the update is applied.
+#[derive(Debug)]
+pub struct UidGenerations {
+    retention: CommandFrame,
+    inserted: HashMap<Uid, CommandFrame>,
+    removed: HashMap<Uid, CommandFrame>,
+    dropped: u64,
+}
+
+impl UidGenerations {
+    /// Remembers removed uids for `retention` command frames.
+    pub fn new(retention: CommandFrame) -> UidGenerations {
+        UidGenerations {
+            retention,
+            inserted: HashMap::new(),
+            removed: HashMap::new(),
+            dropped: 0,
+        }
+    }
+
+    /// The command frame the entity with the uid was inserted at,
+    /// `None` for entities of the initial sync.
+    pub fn generation(&self, uid: Uid) -> Option<CommandFrame> {
+        self.inserted.get(&uid).copied()
+    }
+
+    /// The number of stale changes that were dropped.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    fn is_stale(&self, uid: Uid, command_frame: CommandFrame) -> bool {
+        match (self.inserted.get(&uid), self.removed.get(&uid)) {
+            (Some(inserted), _) => command_frame < *inserted,
+            (None, Some(removed)) => command_frame <= *removed,
+            (None, None) => false,
+        }
+    }
+
+    /// An insert older than the current entity or than the removal of the previous one.
+    fn is_stale_insert(&self, uid: Uid, command_frame: CommandFrame) -> bool {
+        self.inserted
+            .get(&uid)
+            .into_iter()
+            .chain(self.removed.get(&uid))
+            .any(|frame| command_frame < *frame)
+    }
+
+    /// Drops the stale changes from the state and records its removals and inserts,
+    /// returns the number of dropped changes.
+    pub(crate) fn validate(&mut self, state: &mut WorldState) -> usize {
+        let command_frame = state.command_frame;
+        let mut dropped = 0;
+
+        let before = state.removed.len();
+        state
+            .removed
+            .retain(|uid| !self.is_stale(*uid, command_frame));
+        dropped += before - state.removed.len();
+
+        for uid in state.removed.iter() {
+            self.inserted.remove(uid);
+            self.removed.insert(*uid, command_frame);
+        }
+
+        let before = state.inserted.len();
+        let generations = &*self;
+        state
+            .inserted
+            .retain(|inserted| !generations.is_stale_insert(inserted.entity_id(), command_frame));
+        dropped += before - state.inserted.len();
+
+        for inserted in state.inserted.iter() {
+            // An insert of a known entity refreshes it, it keeps its generation.
+            self.removed.remove(&inserted.entity_id());
+            self.inserted
+                .entry(inserted.entity_id())
+                .or_insert(command_frame);
+        }
+
+        let before = state.component_removed.len()
+            + state.component_added.len()
+            + state.changed.len();
+        let generations = &*self;
+        let is_current = |uid: Uid| !generations.is_stale(uid, command_frame);
+
+        state
+            .component_removed
+            .retain(|removed| is_current(removed.entity_id()));
+        state
+            .component_added
+            .retain(|added| is_current(added.entity_id()));
+        state
+            .changed
+            .retain(|changed| is_current(changed.entity_id()));
+
+        dropped += before
+            - (state.component_removed.len() + state.component_added.len() + state.changed.len());
+
+        let oldest = command_frame.saturating_sub(self.retention);
+        self.removed.retain(|_, removed| *removed >= oldest);
+
+        if dropped > 0 {
+            state.recalculate_size();
+            self.dropped += dropped as u64;
+        }
+
+        dropped
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use net_sync::synchronisation::{ComponentData, WorldState};
+
+    use crate::resources::{UidGenerations, UidRecycler};
+
+    #[test]
+    fn released_uids_are_withheld_test() {
+        let mut recycler = UidRecycler::new(1, 10);
+
+        // Rapid create and destroy cycles do not reuse the uid within the delay.
+        for frame in 0..5 {
+            let uid = recycler.allocate(frame);
+            assert_eq!(uid, frame + 1);
+            recycler.release(uid, frame);
+        }
+        assert_eq!(recycler.withheld(), 5);
+
+        assert_eq!(recycler.allocate(10), 1);
+        assert_eq!(recycler.allocate(10), 6);
+        assert_eq!(recycler.allocate(11), 2);
+    }
+
+    #[test]
+    fn stale_changes_of_reused_uids_are_dropped_test() {
+        let mut generations = UidGenerations::new(100);
+
+        let mut state = WorldState::new(5);
+        state.insert_entity(1, Vec::new());
+        assert_eq!(generations.validate(&mut state), 0);
+
+        let mut state = WorldState::new(8);
+        state.remove_entity(1);
+        generations.validate(&mut state);
+
+        // The uid is reused, the new entity is inserted at frame 10.
+        let mut state = WorldState::new(10);
+        state.insert_entity(1, Vec::new());
+        generations.validate(&mut state);
+        assert_eq!(generations.generation(1), Some(10));
+
+        // A late update of the old entity.
+        let mut late = WorldState::new(7);
+        late.change(1, ComponentData::new(1, vec![1]));
+        late.insert_entity(1, Vec::new());
+        late.remove_entity(1);
+        assert_eq!(generations.validate(&mut late), 3);
+        assert!(late.is_empty());
+
+        let mut state = WorldState::new(11);
+        state.change(1, ComponentData::new(1, vec![2]));
+        assert_eq!(generations.validate(&mut state), 0);
+        assert_eq!(state.changed.len(), 1);
+        assert_eq!(generations.dropped(), 3);
+    }
+}
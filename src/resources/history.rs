@@ -0,0 +1,76 @@
+use std::collections::VecDeque;
+
+use net_sync::synchronisation::CommandFrame;
+
+/// Bounded history of serialized snapshots of the replicated client world, one per command frame.
+///
+/// Debug overlays can use this to scrub through recent frames, see `ClientWorld::world_at`.
+pub struct WorldHistory {
+    capacity: usize,
+    snapshots: VecDeque<(CommandFrame, Vec<u8>)>,
+}
+
+impl WorldHistory {
+    pub fn with_capacity(frames: usize) -> WorldHistory {
+        WorldHistory {
+            capacity: frames,
+            snapshots: VecDeque::with_capacity(frames),
+        }
+    }
+
+    /// Records the snapshot of the given frame, the oldest snapshot is dropped if the history is full.
+    pub fn record(&mut self, command_frame: CommandFrame, snapshot: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+
+        self.snapshots.push_back((command_frame, snapshot));
+    }
+
+    pub fn snapshot(&self, command_frame: CommandFrame) -> Option<&[u8]> {
+        self.snapshots
+            .iter()
+            .find(|(frame, _)| *frame == command_frame)
+            .map(|(_, snapshot)| snapshot.as_slice())
+    }
+
+    /// Returns the recorded frames from oldest to newest.
+    pub fn frames(&self) -> impl Iterator<Item = CommandFrame> + '_ {
+        self.snapshots.iter().map(|(frame, _)| *frame)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::resources::WorldHistory;
+
+    #[test]
+    fn history_drops_oldest_snapshot_test() {
+        let mut history = WorldHistory::with_capacity(2);
+
+        history.record(1, vec![1]);
+        history.record(2, vec![2]);
+        history.record(3, vec![3]);
+
+        assert_eq!(history.len(), 2);
+        assert!(history.snapshot(1).is_none());
+        assert_eq!(history.snapshot(3), Some(&[3][..]));
+        assert_eq!(history.frames().collect::<Vec<_>>(), vec![2, 3]);
+    }
+}
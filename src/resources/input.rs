@@ -0,0 +1,120 @@
+use std::{any::Any, collections::VecDeque};
+
+use net_sync::synchronisation::CommandFrame;
+
+/// Client resource that turns the raw input of the game into one command per command frame.
+///
+/// Game code records input continuously with `input_mut`, at the start of every command frame
+/// the client world calls the sample function once and sends the command to the server.
+/// The commands of the last frames are kept, prediction and resimulation code reads them with
+/// `command_at`. Predicted component changes are still recorded in the `ClientCommandBuffer`,
+/// their command is not sent again for frames that have a sampled command.
+///
+/// ```ignore
+/// let sampler = InputSampler::new(Keys::default(), |keys: &mut Keys| {
+///     let command = MoveCommand::from(&*keys);
+///     keys.clear_pressed();
+///     command
+/// });
+/// ClientWorldBuilder::default().with_input_sampler(sampler);
+/// ```
+pub struct InputSampler<C> {
+    input: Box<dyn Any + Send + Sync>,
+    sample: Box<dyn FnMut(&mut (dyn Any + Send + Sync)) -> C + Send + Sync>,
+    sampled: VecDeque<(CommandFrame, C)>,
+    capacity: usize,
+}
+
+impl<C> InputSampler<C> {
+    /// Creates a sampler with the initial raw input and the function that samples a command.
+    ///
+    /// The sample function may reset the input, e.g. accumulated mouse movement.
+    pub fn new<I, F>(input: I, mut sample: F) -> InputSampler<C>
+    where
+        I: Send + Sync + 'static,
+        F: FnMut(&mut I) -> C + Send + Sync + 'static,
+    {
+        InputSampler {
+            input: Box::new(input),
+            sample: Box::new(move |input: &mut (dyn Any + Send + Sync)| {
+                sample(input.downcast_mut::<I>().expect("Input type does not change."))
+            }),
+            sampled: VecDeque::new(),
+            capacity: 64,
+        }
+    }
+
+    /// Keeps the commands of the last `capacity` command frames.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Returns the raw input, `None` if it is not of type `I`.
+    pub fn input_mut<I: 'static>(&mut self) -> Option<&mut I> {
+        self.input.downcast_mut::<I>()
+    }
+
+    /// The command sampled for the given command frame.
+    pub fn command_at(&self, command_frame: CommandFrame) -> Option<&C> {
+        self.sampled
+            .iter()
+            .find(|(frame, _)| *frame == command_frame)
+            .map(|(_, command)| command)
+    }
+
+    /// The last sampled command and its command frame.
+    pub fn latest(&self) -> Option<(CommandFrame, &C)> {
+        self.sampled.back().map(|(frame, command)| (*frame, command))
+    }
+
+    /// Samples the command of the command frame.
+    ///
+    /// Returns `None` if the frame was already sampled, there is one command per frame.
+    pub(crate) fn sample(&mut self, command_frame: CommandFrame) -> Option<&C> {
+        if self
+            .latest()
+            .map_or(false, |(frame, _)| frame >= command_frame)
+        {
+            return None;
+        }
+
+        let command = (self.sample)(&mut *self.input);
+
+        if self.sampled.len() == self.capacity {
+            self.sampled.pop_front();
+        }
+        self.sampled.push_back((command_frame, command));
+
+        self.sampled.back().map(|(_, command)| command)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::resources::InputSampler;
+
+    #[test]
+    fn one_command_per_frame_test() {
+        let mut sampler = InputSampler::new(0u32, |presses: &mut u32| {
+            let command = *presses;
+            *presses = 0;
+            command
+        })
+        .with_capacity(2);
+
+        *sampler.input_mut::<u32>().unwrap() += 3;
+
+        assert_eq!(sampler.sample(1), Some(&3));
+        assert_eq!(sampler.sample(1), None);
+
+        *sampler.input_mut::<u32>().unwrap() += 1;
+        sampler.sample(2);
+        sampler.sample(3);
+
+        assert_eq!(sampler.command_at(1), None);
+        assert_eq!(sampler.command_at(2), Some(&1));
+        assert_eq!(sampler.latest(), Some((3, &0)));
+        assert!(sampler.input_mut::<u8>().is_none());
+    }
+}
@@ -0,0 +1,389 @@
+use std::collections::{HashMap, HashSet};
+
+use legion::{Entity, Resources};
+
+use net_sync::transport::ClientId;
+
+use crate::resources::ConnectionQuality;
+
+/// A change of the entities that are relevant to a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterestChange {
+    Relevant(ClientId, Entity),
+    Irrelevant(ClientId, Entity),
+}
+
+/// Server resource with the entities in the interest scope of each client.
+///
+/// Interest management code updates the scopes, the server world calls the `InterestHooks`
+/// for the entities that entered or left a scope at the end of the tick.
+#[derive(Debug, Default)]
+pub struct InterestScopes {
+    scopes: HashMap<ClientId, HashSet<Entity>>,
+    changes: Vec<InterestChange>,
+}
+
+impl InterestScopes {
+    pub fn new() -> InterestScopes {
+        InterestScopes::default()
+    }
+
+    /// Replaces the scope of the client by the given entities.
+    pub fn set_relevant(&mut self, client: ClientId, entities: impl IntoIterator<Item = Entity>) {
+        let relevant = entities.into_iter().collect::<HashSet<Entity>>();
+        let scope = self.scopes.entry(client).or_insert_with(HashSet::new);
+
+        for entity in scope.difference(&relevant) {
+            self.changes.push(InterestChange::Irrelevant(client, *entity));
+        }
+        for entity in relevant.difference(scope) {
+            self.changes.push(InterestChange::Relevant(client, *entity));
+        }
+
+        *scope = relevant;
+    }
+
+    /// Adds the entity to the scope of the client.
+    pub fn insert(&mut self, client: ClientId, entity: Entity) {
+        if self.scopes.entry(client).or_default().insert(entity) {
+            self.changes.push(InterestChange::Relevant(client, entity));
+        }
+    }
+
+    /// Removes the entity from the scope of the client.
+    pub fn remove(&mut self, client: ClientId, entity: Entity) {
+        if let Some(scope) = self.scopes.get_mut(&client) {
+            if scope.remove(&entity) {
+                self.changes.push(InterestChange::Irrelevant(client, entity));
+            }
+        }
+    }
+
+    /// Removes a despawned entity from the scopes of all clients.
+    pub fn remove_entity(&mut self, entity: Entity) {
+        for (client, scope) in self.scopes.iter_mut() {
+            if scope.remove(&entity) {
+                self.changes.push(InterestChange::Irrelevant(*client, entity));
+            }
+        }
+    }
+
+    /// Removes the scope of a disconnected client, all its entities become irrelevant.
+    pub fn remove_client(&mut self, client: ClientId) {
+        if let Some(scope) = self.scopes.remove(&client) {
+            self.changes.extend(
+                scope
+                    .into_iter()
+                    .map(|entity| InterestChange::Irrelevant(client, entity)),
+            );
+        }
+    }
+
+    /// Moves the scope to another client id without raising changes, see
+    /// `ServerWorld::migrate_client`.
+    pub fn rebind(&mut self, from: ClientId, to: ClientId) {
+        if let Some(scope) = self.scopes.remove(&from) {
+            self.scopes.insert(to, scope);
+        }
+    }
+
+    pub fn is_relevant(&self, client: ClientId, entity: Entity) -> bool {
+        self.scopes
+            .get(&client)
+            .map_or(false, |scope| scope.contains(&entity))
+    }
+
+    /// Returns the clients that have the entity in their scope.
+    pub fn clients_of(&self, entity: Entity) -> impl Iterator<Item = ClientId> + '_ {
+        self.scopes
+            .iter()
+            .filter(move |(_, scope)| scope.contains(&entity))
+            .map(|(client, _)| *client)
+    }
+
+    /// Returns the entities in the scope of the client.
+    pub fn relevant(&self, client: ClientId) -> impl Iterator<Item = Entity> + '_ {
+        self.scopes
+            .get(&client)
+            .into_iter()
+            .flat_map(|scope| scope.iter().copied())
+    }
+
+    /// Returns the changes since the last call, in the order they were made.
+    pub(crate) fn drain_changes(&mut self) -> Vec<InterestChange> {
+        self.changes.drain(..).collect()
+    }
+}
+
+/// Server hooks called when an entity enters or leaves the interest scope of a client,
+/// see `InterestScopes`.
+///
+/// Use them to load and unload heavier per client data (navigation data, voice channels)
+/// in lockstep with the replication scope.
+pub trait InterestHooks: Send + Sync + 'static {
+    fn on_entity_relevant(
+        &mut self,
+        _client: ClientId,
+        _entity: Entity,
+        _resources: &mut Resources,
+    ) {
+    }
+
+    fn on_entity_irrelevant(
+        &mut self,
+        _client: ClientId,
+        _entity: Entity,
+        _resources: &mut Resources,
+    ) {
+    }
+}
+
+/// Bandwidth budget of the adaptive interest radius, see `ServerConfig::interest_budget`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterestBudget {
+    /// Bytes of state updates a client may receive per command frame, on average.
+    pub bytes_per_frame: usize,
+    pub min_radius: f32,
+    pub max_radius: f32,
+    /// Factor by which the radius shrinks when a client is over budget.
+    pub shrink_factor: f32,
+    /// Factor by which the radius grows when a client has headroom.
+    pub grow_factor: f32,
+    /// The radius only grows when the usage is below this fraction of the budget.
+    pub headroom: f32,
+    /// Consecutive command frames a client has to be over budget or have headroom
+    /// before its radius changes.
+    pub frames: u32,
+    /// Factor of the maximum radius of a client with a degraded connection.
+    pub degraded_scale: f32,
+    /// Factor of the maximum radius of a client with a bad connection.
+    pub bad_scale: f32,
+}
+
+impl InterestBudget {
+    /// The maximum radius of a client with the given connection quality.
+    pub fn max_radius(&self, quality: ConnectionQuality) -> f32 {
+        let scale = match quality {
+            ConnectionQuality::Good => 1.,
+            ConnectionQuality::Degraded => self.degraded_scale,
+            ConnectionQuality::Bad => self.bad_scale,
+        };
+
+        (self.max_radius * scale).max(self.min_radius)
+    }
+}
+
+impl Default for InterestBudget {
+    fn default() -> Self {
+        InterestBudget {
+            bytes_per_frame: 1024,
+            min_radius: 16.,
+            max_radius: 128.,
+            shrink_factor: 0.8,
+            grow_factor: 1.1,
+            headroom: 0.7,
+            frames: 30,
+            degraded_scale: 0.75,
+            bad_scale: 0.5,
+        }
+    }
+}
+
+/// Server resource with the interest radius of each client, adapted to its bandwidth usage.
+///
+/// The radius of a client with a degraded or bad connection is capped, see
+/// `InterestBudget::max_radius`.
+/// Interest management code should use `radius` to fill the `InterestScopes`,
+/// the radius can equally be read as a scale of the maximum number of replicated entities.
+/// Changes are raised as `ServerEvent::InterestRadiusChanged`, e.g. to update the fog distance.
+#[derive(Debug)]
+pub struct InterestRadii {
+    budget: InterestBudget,
+    // Scales the bytes per frame of the budget, see `LoadShedding`.
+    budget_scale: f32,
+    clients: HashMap<ClientId, AdaptiveRadius>,
+}
+
+impl InterestRadii {
+    pub fn new(budget: InterestBudget) -> InterestRadii {
+        InterestRadii {
+            budget,
+            budget_scale: 1.,
+            clients: HashMap::new(),
+        }
+    }
+
+    pub fn budget(&self) -> &InterestBudget {
+        &self.budget
+    }
+
+    /// Scales the bytes per frame of the budget of all clients.
+    pub(crate) fn set_budget_scale(&mut self, scale: f32) {
+        self.budget_scale = scale;
+    }
+
+    /// Returns the interest radius of the client, unknown clients have the maximum radius.
+    pub fn radius(&self, client: ClientId) -> f32 {
+        self.clients
+            .get(&client)
+            .map_or(self.budget.max_radius, |adaptive| adaptive.radius)
+    }
+
+    /// Updates the radius of the client with the total bytes sent to it and the quality of its
+    /// connection, once per command frame.
+    ///
+    /// Returns the new radius if it changed.
+    pub(crate) fn update(
+        &mut self,
+        client: ClientId,
+        bytes_sent: usize,
+        quality: ConnectionQuality,
+    ) -> Option<f32> {
+        let budget = InterestBudget {
+            bytes_per_frame: (self.budget.bytes_per_frame as f32 * self.budget_scale) as usize,
+            max_radius: self.budget.max_radius(quality),
+            ..self.budget.clone()
+        };
+
+        self.clients
+            .entry(client)
+            .or_insert_with(|| AdaptiveRadius::new(budget.max_radius, bytes_sent))
+            .update(&budget, bytes_sent)
+    }
+
+    pub fn remove_client(&mut self, client: ClientId) {
+        self.clients.remove(&client);
+    }
+
+    /// Moves the radius to another client id, see `ServerWorld::migrate_client`.
+    pub fn rebind(&mut self, from: ClientId, to: ClientId) {
+        if let Some(adaptive) = self.clients.remove(&from) {
+            self.clients.insert(to, adaptive);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct AdaptiveRadius {
+    radius: f32,
+    last_bytes_sent: usize,
+    // Bytes per frame, smoothed with an exponential moving average.
+    average: f32,
+    over_budget: u32,
+    headroom: u32,
+}
+
+impl AdaptiveRadius {
+    fn new(radius: f32, bytes_sent: usize) -> AdaptiveRadius {
+        AdaptiveRadius {
+            radius,
+            last_bytes_sent: bytes_sent,
+            average: 0.,
+            over_budget: 0,
+            headroom: 0,
+        }
+    }
+
+    fn update(&mut self, budget: &InterestBudget, bytes_sent: usize) -> Option<f32> {
+        // The connection of the client got worse, the radius shrinks right away.
+        if self.radius > budget.max_radius {
+            self.last_bytes_sent = bytes_sent;
+            self.over_budget = 0;
+            self.headroom = 0;
+            self.radius = budget.max_radius;
+            return Some(self.radius);
+        }
+
+        let frame_bytes = bytes_sent.saturating_sub(self.last_bytes_sent);
+        self.last_bytes_sent = bytes_sent;
+        self.average = (self.average * 7. + frame_bytes as f32) / 8.;
+
+        let limit = budget.bytes_per_frame as f32;
+
+        if self.average > limit {
+            self.over_budget += 1;
+            self.headroom = 0;
+        } else if self.average < limit * budget.headroom {
+            self.headroom += 1;
+            self.over_budget = 0;
+        } else {
+            self.over_budget = 0;
+            self.headroom = 0;
+        }
+
+        let radius = if self.over_budget >= budget.frames {
+            self.radius * budget.shrink_factor
+        } else if self.headroom >= budget.frames {
+            self.radius * budget.grow_factor
+        } else {
+            return None;
+        };
+
+        self.over_budget = 0;
+        self.headroom = 0;
+
+        let radius = radius.max(budget.min_radius).min(budget.max_radius);
+
+        if radius == self.radius {
+            return None;
+        }
+
+        self.radius = radius;
+        Some(radius)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::resources::{
+        interest::AdaptiveRadius, ConnectionQuality, InterestBudget, InterestRadii,
+    };
+
+    #[test]
+    fn radius_adapts_to_bandwidth_test() {
+        let budget = InterestBudget {
+            bytes_per_frame: 100,
+            frames: 2,
+            ..InterestBudget::default()
+        };
+        let mut adaptive = AdaptiveRadius::new(budget.max_radius, 0);
+        let mut bytes_sent = 0;
+
+        // Over budget for two frames.
+        adaptive.average = 200.;
+        bytes_sent += 200;
+        assert_eq!(adaptive.update(&budget, bytes_sent), None);
+        bytes_sent += 200;
+        assert_eq!(adaptive.update(&budget, bytes_sent), Some(128. * 0.8));
+
+        // Headroom, the radius grows back to the maximum.
+        adaptive.average = 0.;
+        let mut radius = None;
+        for _ in 0..16 {
+            radius = adaptive.update(&budget, bytes_sent).or(radius);
+        }
+        assert_eq!(radius, Some(128.));
+        assert_eq!(adaptive.update(&budget, bytes_sent), None);
+    }
+
+    #[test]
+    fn radius_is_capped_by_quality_test() {
+        let budget = InterestBudget {
+            frames: 1,
+            ..InterestBudget::default()
+        };
+        let mut radii = InterestRadii::new(budget);
+
+        assert_eq!(radii.update(1, 0, ConnectionQuality::Good), None);
+        assert_eq!(radii.update(1, 0, ConnectionQuality::Bad), Some(64.));
+        assert_eq!(radii.radius(1), 64.);
+        assert_eq!(radii.update(1, 0, ConnectionQuality::Degraded), Some(64. * 1.1));
+
+        // The radius grows back once the connection recovered.
+        let mut radius = None;
+        for _ in 0..8 {
+            radius = radii.update(1, 0, ConnectionQuality::Good).or(radius);
+        }
+        assert_eq!(radius, Some(128.));
+    }
+}
@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+/// Client resource with an interpolation delay that adapts to the arrival jitter of the state
+/// updates, enabled with `ClientWorldBuilder::with_adaptive_interpolation`.
+///
+/// Interpolation code renders the replicated entities at `render_time`, the delay behind the
+/// clock. The buffer runs dry when no update arrives within the delay, this is counted
+/// as an underrun and the delay grows to the gap that caused it. Otherwise the delay slowly
+/// shrinks to the mean arrival interval plus four times its jitter, within the bounds.
+#[derive(Debug, Clone)]
+pub struct InterpolationDelay {
+    min: Duration,
+    max: Duration,
+    delay: Duration,
+    last_arrival: Option<Duration>,
+    // Seconds, smoothed with an exponential moving average.
+    interval: f64,
+    // Mean deviation of the interval in seconds.
+    jitter: f64,
+    underruns: u64,
+}
+
+impl InterpolationDelay {
+    /// Adapts the delay between `min` and `max`, it starts at `min`.
+    pub fn new(min: Duration, max: Duration) -> InterpolationDelay {
+        InterpolationDelay {
+            min,
+            max: max.max(min),
+            delay: min,
+            last_arrival: None,
+            interval: 0.,
+            jitter: 0.,
+            underruns: 0,
+        }
+    }
+
+    /// The current interpolation delay.
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    /// The time to render the replicated entities at, `now` of the client clock minus the delay.
+    pub fn render_time(&self, now: Duration) -> Duration {
+        now.checked_sub(self.delay).unwrap_or_default()
+    }
+
+    /// The smoothed interval between state updates.
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs_f64(self.interval)
+    }
+
+    /// The mean deviation of the interval between state updates.
+    pub fn jitter(&self) -> Duration {
+        Duration::from_secs_f64(self.jitter)
+    }
+
+    /// The number of state updates that arrived after the buffer ran dry.
+    pub fn underruns(&self) -> u64 {
+        self.underruns
+    }
+
+    /// Records the arrival of a state update at `now` of the client clock.
+    pub(crate) fn record_arrival(&mut self, now: Duration) {
+        let last_arrival = match self.last_arrival.replace(now) {
+            Some(last_arrival) => last_arrival,
+            None => return,
+        };
+
+        let gap = now.checked_sub(last_arrival).unwrap_or_default();
+        let seconds = gap.as_secs_f64();
+
+        // The first interval sets the delay, the buffer did not run yet.
+        if self.interval == 0. {
+            self.interval = seconds;
+            self.delay = gap.max(self.min).min(self.max);
+            return;
+        }
+
+        self.jitter += ((seconds - self.interval).abs() - self.jitter) / 16.;
+        self.interval += (seconds - self.interval) / 16.;
+
+        let target = Duration::from_secs_f64(self.interval + 4. * self.jitter)
+            .max(self.min)
+            .min(self.max);
+
+        if gap > self.delay {
+            self.underruns += 1;
+            self.delay = gap.max(target).min(self.max);
+        } else if self.delay > target {
+            self.delay -= (self.delay - target) / 16;
+        } else {
+            self.delay = target;
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::time::Duration;
+
+    use crate::resources::InterpolationDelay;
+
+    #[test]
+    fn delay_adapts_to_jitter_test() {
+        let mut delay =
+            InterpolationDelay::new(Duration::from_millis(20), Duration::from_millis(200));
+        let mut now = Duration::from_secs(1);
+
+        // Steady 50ms updates, the delay settles at the interval.
+        for _ in 0..64 {
+            delay.record_arrival(now);
+            now += Duration::from_millis(50);
+        }
+        let steady = delay.delay();
+        assert!(steady >= Duration::from_millis(45) && steady <= Duration::from_millis(60));
+        assert_eq!(delay.underruns(), 0);
+
+        // A late update underruns the buffer and grows the delay to the gap.
+        now += Duration::from_millis(100);
+        delay.record_arrival(now);
+        assert_eq!(delay.underruns(), 1);
+        assert_eq!(delay.delay(), Duration::from_millis(150));
+
+        // The delay shrinks back once the updates are steady again, and stays in its bounds.
+        for _ in 0..256 {
+            now += Duration::from_millis(50);
+            delay.record_arrival(now);
+        }
+        assert!(delay.delay() < Duration::from_millis(100));
+
+        now += Duration::from_secs(1);
+        delay.record_arrival(now);
+        assert_eq!(delay.delay(), Duration::from_millis(200));
+        assert_eq!(delay.render_time(Duration::from_millis(100)), Duration::from_millis(0));
+    }
+}
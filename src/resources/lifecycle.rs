@@ -0,0 +1,267 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use legion::{Resources, World};
+
+use net_sync::{transport::ClientId, uid::Uid};
+
+use crate::protocol::LocalPlayer;
+
+/// What the server does with the entities a client owns in the `PlayerOwnership` when its
+/// connection is lost, see `ServerConfig::disconnect_policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectPolicy {
+    /// The entities stay in the world without an owner.
+    Keep,
+    /// The entities are destroyed, following the policies of the `EntityReferences`.
+    Despawn,
+    /// The entities are kept for the duration, a client that reconnects with
+    /// `ServerWorld::reconnect` in time owns them again. They are destroyed afterwards.
+    Reserve(Duration),
+    /// `ConnectionHooks::on_client_disconnected` decides what happens with the entities.
+    Callback,
+}
+
+impl Default for DisconnectPolicy {
+    fn default() -> Self {
+        DisconnectPolicy::Keep
+    }
+}
+
+/// Server hooks called when clients connect, lose their connection and reconnect,
+/// see `ServerWorldBuilder::with_connection_hooks`.
+pub trait ConnectionHooks: Send + Sync + 'static {
+    fn on_client_connected(&mut self, _client: ClientId, _resources: &mut Resources) {}
+
+    /// Called with the entities the client owned, before the `DisconnectPolicy` applies.
+    fn on_client_disconnected(
+        &mut self,
+        _client: ClientId,
+        _owned: &[Uid],
+        _world: &mut World,
+        _resources: &mut Resources,
+    ) {
+    }
+
+    fn on_client_reconnected(
+        &mut self,
+        _from: ClientId,
+        _to: ClientId,
+        _resources: &mut Resources,
+    ) {
+    }
+}
+
+/// A connection change the transport reported in the `NetworkEventQueue`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TransportEvent {
+    Connected(ClientId),
+    Disconnected(ClientId),
+}
+
+/// A client whose entities are kept for a reconnect, see `DisconnectPolicy::Reserve`.
+#[derive(Debug)]
+struct Reservation {
+    expires: Duration,
+    owned: Vec<(Uid, LocalPlayer)>,
+}
+
+/// Server resource with the connected clients, enabled with
+/// `ServerWorldBuilder::with_connection_lifecycle`.
+///
+/// Every tick the connect and disconnect events of the `NetworkEventQueue` are consumed and the
+/// clients of the `ServerPostOffice` are compared with the last tick: new clients raise
+/// `ServerEvent::ClientConnected`, clients the transport disconnected or that are gone lost their
+/// connection, also when the transport keeps their postbox. Transports that report neither can
+/// call `ServerWorld::connection_lost`.
+/// A lost connection forgets the synchronisation state of the client like
+/// `ServerWorld::disconnect`, and applies the `ServerConfig::disconnect_policy` to its entities.
+#[derive(Debug, Default)]
+pub struct ConnectionLifecycle {
+    connected: HashSet<ClientId>,
+    // Disconnected clients whose postbox is still in the post office.
+    closed: HashSet<ClientId>,
+    reserved: HashMap<ClientId, Reservation>,
+}
+
+impl ConnectionLifecycle {
+    pub fn new() -> ConnectionLifecycle {
+        ConnectionLifecycle::default()
+    }
+
+    pub fn is_connected(&self, client: ClientId) -> bool {
+        self.connected.contains(&client)
+    }
+
+    /// The connected clients.
+    pub fn connected(&self) -> impl Iterator<Item = ClientId> + '_ {
+        self.connected.iter().copied()
+    }
+
+    /// Whether the entities of the disconnected client are kept for a reconnect.
+    pub fn is_reserved(&self, client: ClientId) -> bool {
+        self.reserved.contains_key(&client)
+    }
+
+    /// The disconnected clients whose entities are kept for a reconnect.
+    pub fn reserved(&self) -> impl Iterator<Item = ClientId> + '_ {
+        self.reserved.keys().copied()
+    }
+
+    /// Applies the events of the transport and compares the clients of the post office with the
+    /// known clients, returns the new clients and the clients that lost their connection.
+    pub(crate) fn update(
+        &mut self,
+        present: impl Iterator<Item = ClientId>,
+        events: impl IntoIterator<Item = TransportEvent>,
+    ) -> (Vec<ClientId>, Vec<ClientId>) {
+        let mut lost = Vec::new();
+
+        for event in events {
+            match event {
+                // A client id that is used again, e.g. by a transport that keeps the postbox.
+                TransportEvent::Connected(client) => {
+                    self.closed.remove(&client);
+                }
+                TransportEvent::Disconnected(client) => {
+                    if self.connected.remove(&client) {
+                        lost.push(client);
+                    }
+                    self.closed.insert(client);
+                }
+            }
+        }
+
+        let mut present = present.collect::<HashSet<_>>();
+        self.closed.retain(|client| present.contains(client));
+        present.retain(|client| !self.closed.contains(client));
+
+        let mut connected = present
+            .difference(&self.connected)
+            .copied()
+            .collect::<Vec<_>>();
+        lost.extend(self.connected.difference(&present).copied());
+        connected.sort();
+        lost.sort();
+
+        self.connected = present;
+        (connected, lost)
+    }
+
+    /// The server disconnected the client, it is not reported as lost or connected again
+    /// while its postbox is in the post office.
+    pub(crate) fn close(&mut self, client: ClientId) {
+        self.connected.remove(&client);
+        self.closed.insert(client);
+    }
+
+    /// Moves the connection of a client to a new client id, see `ServerWorld::migrate_client`.
+    /// The new id is not reported as a new client, the old one is not reported as lost.
+    pub fn rebind(&mut self, from: ClientId, to: ClientId) {
+        self.connected.remove(&from);
+        self.connected.insert(to);
+        self.closed.remove(&to);
+        // The postbox of the old connection may stay in the post office for a while.
+        self.closed.insert(from);
+
+        if let Some(reservation) = self.reserved.remove(&from) {
+            self.reserved.insert(to, reservation);
+        }
+    }
+
+    pub(crate) fn reserve(
+        &mut self,
+        client: ClientId,
+        expires: Duration,
+        owned: Vec<(Uid, LocalPlayer)>,
+    ) {
+        self.reserved.insert(client, Reservation { expires, owned });
+    }
+
+    /// Takes the reserved entities of the client with their local players.
+    pub(crate) fn take_reserved(&mut self, client: ClientId) -> Option<Vec<(Uid, LocalPlayer)>> {
+        self.reserved
+            .remove(&client)
+            .map(|reservation| reservation.owned)
+    }
+
+    /// Takes the reservations that expired at `now`.
+    pub(crate) fn expired(&mut self, now: Duration) -> Vec<(ClientId, Vec<Uid>)> {
+        let expired = self
+            .reserved
+            .iter()
+            .filter(|(_, reservation)| reservation.expires <= now)
+            .map(|(client, _)| *client)
+            .collect::<Vec<_>>();
+
+        expired
+            .into_iter()
+            .filter_map(|client| {
+                self.take_reserved(client)
+                    .map(|owned| (client, owned.into_iter().map(|(uid, _)| uid).collect()))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::time::Duration;
+
+    use crate::resources::{lifecycle::TransportEvent, ConnectionLifecycle};
+
+    #[test]
+    fn connections_are_diffed_test() {
+        let mut lifecycle = ConnectionLifecycle::new();
+
+        assert_eq!(lifecycle.update(vec![1, 2].into_iter(), None), (vec![1, 2], vec![]));
+        assert_eq!(lifecycle.update(vec![2, 3].into_iter(), None), (vec![3], vec![1]));
+        assert!(!lifecycle.is_connected(1));
+
+        lifecycle.close(2);
+        assert_eq!(lifecycle.update(vec![2, 3].into_iter(), None), (vec![], vec![]));
+        assert!(!lifecycle.is_connected(2));
+
+        lifecycle.reserve(1, Duration::from_secs(30), vec![(10, 0)]);
+        lifecycle.reserve(4, Duration::from_secs(10), vec![(11, 0)]);
+
+        assert_eq!(lifecycle.expired(Duration::from_secs(20)), vec![(4, vec![11])]);
+        assert!(lifecycle.is_reserved(1));
+        assert_eq!(lifecycle.take_reserved(1), Some(vec![(10, 0)]));
+        assert!(lifecycle.expired(Duration::from_secs(60)).is_empty());
+    }
+
+    #[test]
+    fn transport_events_are_consumed_test() {
+        let mut lifecycle = ConnectionLifecycle::new();
+        lifecycle.update(vec![1, 2].into_iter(), None);
+
+        // The transport keeps the postbox of the closed connection.
+        let events = vec![TransportEvent::Disconnected(1)];
+        assert_eq!(lifecycle.update(vec![1, 2].into_iter(), events), (vec![], vec![1]));
+        assert_eq!(lifecycle.update(vec![1, 2].into_iter(), None), (vec![], vec![]));
+
+        // A disconnect of an unknown client is not reported.
+        let events = vec![TransportEvent::Disconnected(3)];
+        assert_eq!(lifecycle.update(vec![1, 2].into_iter(), events), (vec![], vec![]));
+
+        let events = vec![TransportEvent::Connected(1)];
+        assert_eq!(lifecycle.update(vec![1, 2].into_iter(), events), (vec![1], vec![]));
+        assert!(lifecycle.is_connected(1));
+    }
+    #[test]
+    fn rebound_client_is_not_reported_test() {
+        let mut lifecycle = ConnectionLifecycle::new();
+        lifecycle.update(vec![1].into_iter(), None);
+
+        lifecycle.rebind(1, 2);
+        assert_eq!(lifecycle.update(vec![1, 2].into_iter(), None), (vec![], vec![]));
+        assert!(lifecycle.is_connected(2));
+        assert!(!lifecycle.is_connected(1));
+
+        // The old postbox is gone.
+        assert_eq!(lifecycle.update(vec![2].into_iter(), None), (vec![], vec![]));
+    }
+}
@@ -0,0 +1,56 @@
+/// Whether the TCP listener of the server accepts new connections, see
+/// `ServerWorld::close_listener`.
+///
+/// The listener stays bound while it is closed, connections the operating system queued in the
+/// meantime are accepted when it opens again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenerGate {
+    open: bool,
+}
+
+impl ListenerGate {
+    pub fn new() -> ListenerGate {
+        ListenerGate { open: true }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Stops accepting connections, returns `false` if the gate was already closed.
+    pub fn close(&mut self) -> bool {
+        let was_open = self.open;
+        self.open = false;
+        was_open
+    }
+
+    /// Accepts connections again, returns `false` if the gate was already open.
+    pub fn open(&mut self) -> bool {
+        let was_open = self.open;
+        self.open = true;
+        !was_open
+    }
+}
+
+impl Default for ListenerGate {
+    fn default() -> Self {
+        ListenerGate::new()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::resources::ListenerGate;
+
+    #[test]
+    fn gate_reports_changes_test() {
+        let mut gate = ListenerGate::new();
+
+        assert!(!gate.open());
+        assert!(gate.close());
+        assert!(!gate.close());
+        assert!(!gate.is_open());
+        assert!(gate.open());
+        assert!(gate.is_open());
+    }
+}
@@ -0,0 +1,608 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    time::Duration,
+};
+
+use net_sync::{synchronisation::CommandFrame, transport::ClientId};
+
+/// Quality of a client connection, see `ServerConfig::quality_thresholds`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectionQuality {
+    Good,
+    Degraded,
+    Bad,
+}
+
+/// Round trip thresholds at which a connection changes quality.
+#[derive(Debug, Clone)]
+pub struct QualityThresholds {
+    /// Round trip time from which a connection is degraded.
+    pub degraded_round_trip: Duration,
+    /// Round trip time from which a connection is bad.
+    pub bad_round_trip: Duration,
+    /// A connection only improves when its round trip time is this much below the threshold.
+    /// This prevents flipping between two states when the round trip time is near a threshold.
+    pub hysteresis: Duration,
+}
+
+impl Default for QualityThresholds {
+    fn default() -> Self {
+        QualityThresholds {
+            degraded_round_trip: Duration::from_millis(150),
+            bad_round_trip: Duration::from_millis(400),
+            hysteresis: Duration::from_millis(30),
+        }
+    }
+}
+
+impl QualityThresholds {
+    /// Returns the quality of a connection that currently has the given quality and round trip time.
+    pub fn evaluate(&self, current: ConnectionQuality, round_trip: Duration) -> ConnectionQuality {
+        let improved_below = |threshold: Duration| round_trip + self.hysteresis < threshold;
+
+        match current {
+            ConnectionQuality::Good if round_trip >= self.bad_round_trip => ConnectionQuality::Bad,
+            ConnectionQuality::Good if round_trip >= self.degraded_round_trip => {
+                ConnectionQuality::Degraded
+            }
+            ConnectionQuality::Degraded if round_trip >= self.bad_round_trip => {
+                ConnectionQuality::Bad
+            }
+            ConnectionQuality::Degraded if improved_below(self.degraded_round_trip) => {
+                ConnectionQuality::Good
+            }
+            ConnectionQuality::Bad if improved_below(self.degraded_round_trip) => {
+                ConnectionQuality::Good
+            }
+            ConnectionQuality::Bad if improved_below(self.bad_round_trip) => {
+                ConnectionQuality::Degraded
+            }
+            current => current,
+        }
+    }
+}
+
+/// The number of state update sizes kept per client, see `ClientMetrics::recent_state_sizes`.
+const RECENT_STATE_SIZES: usize = 16;
+
+/// Metrics of a single client connection.
+#[derive(Debug, Clone)]
+pub struct ClientMetrics {
+    round_trip_time: Option<Duration>,
+    quality: ConnectionQuality,
+    bytes_sent: usize,
+    state_updates_sent: u64,
+    last_state_frame: Option<CommandFrame>,
+    recent_state_sizes: VecDeque<usize>,
+}
+
+impl ClientMetrics {
+    fn new() -> ClientMetrics {
+        ClientMetrics {
+            round_trip_time: None,
+            quality: ConnectionQuality::Good,
+            bytes_sent: 0,
+            state_updates_sent: 0,
+            last_state_frame: None,
+            recent_state_sizes: VecDeque::with_capacity(RECENT_STATE_SIZES),
+        }
+    }
+
+    /// Returns the smoothed round trip time.
+    pub fn round_trip_time(&self) -> Option<Duration> {
+        self.round_trip_time
+    }
+
+    pub fn quality(&self) -> ConnectionQuality {
+        self.quality
+    }
+
+    pub fn bytes_sent(&self) -> usize {
+        self.bytes_sent
+    }
+
+    pub fn state_updates_sent(&self) -> u64 {
+        self.state_updates_sent
+    }
+
+    /// The command frame of the last state update that was sent to the client.
+    pub fn last_state_frame(&self) -> Option<CommandFrame> {
+        self.last_state_frame
+    }
+
+    /// The sizes in bytes of the last sent state updates, the oldest first.
+    pub fn recent_state_sizes(&self) -> impl Iterator<Item = usize> + '_ {
+        self.recent_state_sizes.iter().copied()
+    }
+}
+
+/// The number of frames of which the changes are averaged,
+/// see `ServerMetrics::average_changes_per_frame`.
+const RECENT_FRAMES: usize = 64;
+
+/// Per client connection metrics on the server.
+///
+/// Round trip times are measured with the `ServerMessage::Ping`s of the server world, see
+/// `ServerConfig::ping_interval`. Sent state updates are recorded by the server world.
+/// The server world also records the changes per frame and the sizes of the serialized components.
+pub struct ServerMetrics {
+    clients: HashMap<ClientId, ClientMetrics>,
+    component_sizes: HashMap<&'static str, usize>,
+    recent_changes: VecDeque<usize>,
+}
+
+impl ServerMetrics {
+    pub fn new() -> ServerMetrics {
+        ServerMetrics {
+            clients: HashMap::new(),
+            component_sizes: HashMap::new(),
+            recent_changes: VecDeque::with_capacity(RECENT_FRAMES),
+        }
+    }
+
+    pub fn client(&self, client: &ClientId) -> Option<&ClientMetrics> {
+        self.clients.get(client)
+    }
+
+    pub fn clients(&self) -> impl Iterator<Item = (&ClientId, &ClientMetrics)> {
+        self.clients.iter()
+    }
+
+    /// Returns the quality of the client connection, unknown clients are considered good.
+    pub fn quality(&self, client: &ClientId) -> ConnectionQuality {
+        self.clients
+            .get(client)
+            .map(|metrics| metrics.quality)
+            .unwrap_or(ConnectionQuality::Good)
+    }
+
+    /// Records a measured round trip, the round trip time is smoothed with an exponential moving average.
+    pub fn record_round_trip(&mut self, client: ClientId, round_trip: Duration) {
+        let metrics = self.client_mut(client);
+
+        metrics.round_trip_time = Some(match metrics.round_trip_time {
+            Some(smoothed) => (smoothed * 7 + round_trip) / 8,
+            None => round_trip,
+        });
+    }
+
+    pub fn record_state_update(
+        &mut self,
+        client: ClientId,
+        command_frame: CommandFrame,
+        bytes: usize,
+    ) {
+        let metrics = self.client_mut(client);
+        metrics.bytes_sent += bytes;
+        metrics.state_updates_sent += 1;
+        metrics.last_state_frame = Some(command_frame);
+
+        if metrics.recent_state_sizes.len() == RECENT_STATE_SIZES {
+            metrics.recent_state_sizes.pop_front();
+        }
+        metrics.recent_state_sizes.push_back(bytes);
+    }
+
+    /// Records the size of a serialized component, the last size of a type is its estimate.
+    pub fn record_component_size(&mut self, type_name: &'static str, bytes: usize) {
+        self.component_sizes.insert(type_name, bytes);
+    }
+
+    /// The size in bytes of the last serialized component of the type.
+    pub fn component_size(&self, type_name: &str) -> Option<usize> {
+        self.component_sizes.get(type_name).copied()
+    }
+
+    /// Records the number of entity and component changes in the state of a frame.
+    pub fn record_frame_changes(&mut self, changes: usize) {
+        if self.recent_changes.len() == RECENT_FRAMES {
+            self.recent_changes.pop_front();
+        }
+        self.recent_changes.push_back(changes);
+    }
+
+    /// The average number of changes per frame over the recent frames.
+    pub fn average_changes_per_frame(&self) -> f32 {
+        if self.recent_changes.is_empty() {
+            return 0.;
+        }
+
+        self.recent_changes.iter().sum::<usize>() as f32 / self.recent_changes.len() as f32
+    }
+
+    pub fn remove_client(&mut self, client: &ClientId) {
+        self.clients.remove(client);
+    }
+
+    /// Moves the metrics to another client id, see `ServerWorld::migrate_client`.
+    pub fn rebind(&mut self, from: ClientId, to: ClientId) {
+        if let Some(metrics) = self.clients.remove(&from) {
+            self.clients.insert(to, metrics);
+        }
+    }
+
+    /// Re-evaluates the quality of all clients and returns the clients of which the quality changed.
+    pub fn evaluate_quality(
+        &mut self,
+        thresholds: &QualityThresholds,
+    ) -> Vec<(ClientId, ConnectionQuality)> {
+        let mut changes = Vec::new();
+
+        for (client, metrics) in self.clients.iter_mut() {
+            if let Some(round_trip) = metrics.round_trip_time {
+                let quality = thresholds.evaluate(metrics.quality, round_trip);
+
+                if quality != metrics.quality {
+                    metrics.quality = quality;
+                    changes.push((*client, quality));
+                }
+            }
+        }
+
+        changes
+    }
+
+    fn client_mut(&mut self, client: ClientId) -> &mut ClientMetrics {
+        self.clients.entry(client).or_insert_with(ClientMetrics::new)
+    }
+}
+
+/// Prediction statistics of a single component type.
+#[derive(Debug, Clone, Default)]
+pub struct ComponentPredictionStats {
+    predictions: u64,
+    mispredictions: u64,
+    correction_bytes: u64,
+}
+
+impl ComponentPredictionStats {
+    /// The number of predicted changes that were compared with the server state.
+    pub fn predictions(&self) -> u64 {
+        self.predictions
+    }
+
+    pub fn mispredictions(&self) -> u64 {
+        self.mispredictions
+    }
+
+    /// The fraction of predictions that did not match the server state.
+    pub fn misprediction_rate(&self) -> f32 {
+        if self.predictions == 0 {
+            0.
+        } else {
+            self.mispredictions as f32 / self.predictions as f32
+        }
+    }
+
+    /// The average size in bytes of the server difference applied to correct a misprediction.
+    pub fn average_correction_size(&self) -> f32 {
+        if self.mispredictions == 0 {
+            0.
+        } else {
+            self.correction_bytes as f32 / self.mispredictions as f32
+        }
+    }
+}
+
+/// Client side prediction metrics, recorded while applying server state updates.
+///
+/// Use these to tune prediction code, e.g. a component type with a high misprediction rate
+/// diverges from the server simulation.
+#[derive(Debug, Clone, Default)]
+pub struct PredictionMetrics {
+    components: HashMap<&'static str, ComponentPredictionStats>,
+    // Number of resimulations by the number of command frames resimulated.
+    resimulation_depths: BTreeMap<u32, u64>,
+    deduplicated_inserts: u64,
+    skipped_resimulations: u64,
+}
+
+impl PredictionMetrics {
+    pub fn new() -> PredictionMetrics {
+        PredictionMetrics::default()
+    }
+
+    /// Returns the statistics of the component with the given type name.
+    pub fn component(&self, type_name: &str) -> Option<&ComponentPredictionStats> {
+        self.components.get(type_name)
+    }
+
+    pub fn components(&self) -> impl Iterator<Item = (&'static str, &ComponentPredictionStats)> {
+        self.components.iter().map(|(name, stats)| (*name, stats))
+    }
+
+    /// Histogram of the resimulation depths, ordered by depth.
+    pub fn resimulation_depths(&self) -> impl Iterator<Item = (u32, u64)> + '_ {
+        self.resimulation_depths
+            .iter()
+            .map(|(depth, count)| (*depth, *count))
+    }
+
+    pub fn resimulations(&self) -> u64 {
+        self.resimulation_depths.values().sum()
+    }
+
+    /// The number of mispredictions applied without resimulation because they were too deep.
+    pub fn skipped_resimulations(&self) -> u64 {
+        self.skipped_resimulations
+    }
+
+    pub fn mispredictions(&self) -> u64 {
+        self.components
+            .values()
+            .map(|stats| stats.mispredictions)
+            .sum()
+    }
+
+    /// The number of inserts of already known entities, applied as a refresh of the entity.
+    pub fn deduplicated_inserts(&self) -> u64 {
+        self.deduplicated_inserts
+    }
+
+    pub fn record_prediction(&mut self, type_name: &'static str) {
+        self.components.entry(type_name).or_default().predictions += 1;
+    }
+
+    pub fn record_misprediction(&mut self, type_name: &'static str, correction_bytes: usize) {
+        let stats = self.components.entry(type_name).or_default();
+        stats.predictions += 1;
+        stats.mispredictions += 1;
+        stats.correction_bytes += correction_bytes as u64;
+    }
+
+    pub fn record_resimulation(&mut self, depth: u32) {
+        *self.resimulation_depths.entry(depth).or_default() += 1;
+    }
+
+    pub fn record_skipped_resimulation(&mut self) {
+        self.skipped_resimulations += 1;
+    }
+
+    pub fn record_deduplicated_insert(&mut self) {
+        self.deduplicated_inserts += 1;
+    }
+
+    pub fn reset(&mut self) {
+        self.components.clear();
+        self.resimulation_depths.clear();
+        self.deduplicated_inserts = 0;
+        self.skipped_resimulations = 0;
+    }
+}
+
+/// Received state update bytes of a single component type.
+#[derive(Debug, Clone, Default)]
+pub struct ComponentBandwidthStats {
+    bytes: u64,
+    count: u64,
+    // Received bytes per command frame within the window, oldest first.
+    recent: VecDeque<(CommandFrame, u64)>,
+}
+
+impl ComponentBandwidthStats {
+    /// The total number of bytes received for this component type.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// The total number of received inserts, additions and changes of this component type.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The number of bytes received within the window of `BandwidthMetrics`.
+    pub fn recent_bytes(&self) -> u64 {
+        self.recent.iter().map(|(_, bytes)| bytes).sum()
+    }
+}
+
+/// Client side bandwidth metrics of the received state updates, per component type.
+///
+/// Use these to find the component types that take most of the traffic,
+/// e.g. `share("Transform")` returns `0.7` if transforms are 70% of the recent traffic.
+#[derive(Debug, Clone)]
+pub struct BandwidthMetrics {
+    components: HashMap<&'static str, ComponentBandwidthStats>,
+    // The number of command frames the recent byte counters cover.
+    window: u32,
+    last_frame: CommandFrame,
+}
+
+impl BandwidthMetrics {
+    pub fn new() -> BandwidthMetrics {
+        BandwidthMetrics::with_window(60)
+    }
+
+    /// Keeps the recent byte counters of the last `window` command frames.
+    pub fn with_window(window: u32) -> BandwidthMetrics {
+        BandwidthMetrics {
+            components: HashMap::new(),
+            window: window.max(1),
+            last_frame: 0,
+        }
+    }
+
+    /// Returns the statistics of the component with the given type name.
+    pub fn component(&self, type_name: &str) -> Option<&ComponentBandwidthStats> {
+        self.components.get(type_name)
+    }
+
+    pub fn components(&self) -> impl Iterator<Item = (&'static str, &ComponentBandwidthStats)> {
+        self.components.iter().map(|(name, stats)| (*name, stats))
+    }
+
+    /// The number of bytes received for all component types within the window.
+    pub fn recent_bytes(&self) -> u64 {
+        self.components
+            .values()
+            .map(|stats| stats.recent_bytes())
+            .sum()
+    }
+
+    /// The fraction of the recent bytes that were received for the given component type.
+    pub fn share(&self, type_name: &str) -> f32 {
+        let total = self.recent_bytes();
+
+        match self.components.get(type_name) {
+            Some(stats) if total != 0 => stats.recent_bytes() as f32 / total as f32,
+            _ => 0.,
+        }
+    }
+
+    pub fn record(&mut self, command_frame: CommandFrame, type_name: &'static str, bytes: usize) {
+        if command_frame != self.last_frame {
+            self.last_frame = command_frame;
+
+            // Drop the counters of the frames that left the window.
+            let oldest = command_frame.saturating_sub(self.window - 1);
+            for stats in self.components.values_mut() {
+                while let Some((frame, _)) = stats.recent.front() {
+                    if *frame < oldest {
+                        stats.recent.pop_front();
+                    } else {
+                        break;
+                    }
+                }
+            }
+        }
+
+        let stats = self.components.entry(type_name).or_default();
+        stats.bytes += bytes as u64;
+        stats.count += 1;
+
+        match stats.recent.back_mut() {
+            Some((frame, recent)) if *frame == command_frame => *recent += bytes as u64,
+            _ => stats.recent.push_back((command_frame, bytes as u64)),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.components.clear();
+    }
+}
+
+impl Default for BandwidthMetrics {
+    fn default() -> Self {
+        BandwidthMetrics::new()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::time::Duration;
+
+    use crate::resources::{
+        BandwidthMetrics, ConnectionQuality, PredictionMetrics, QualityThresholds, ServerMetrics,
+    };
+
+    fn thresholds() -> QualityThresholds {
+        QualityThresholds {
+            degraded_round_trip: Duration::from_millis(100),
+            bad_round_trip: Duration::from_millis(200),
+            hysteresis: Duration::from_millis(20),
+        }
+    }
+
+    #[test]
+    fn quality_degrades_at_threshold_test() {
+        let thresholds = thresholds();
+
+        assert_eq!(
+            thresholds.evaluate(ConnectionQuality::Good, Duration::from_millis(100)),
+            ConnectionQuality::Degraded
+        );
+        assert_eq!(
+            thresholds.evaluate(ConnectionQuality::Good, Duration::from_millis(250)),
+            ConnectionQuality::Bad
+        );
+    }
+
+    #[test]
+    fn quality_improves_with_hysteresis_test() {
+        let thresholds = thresholds();
+
+        // Below the threshold, but within the hysteresis margin.
+        assert_eq!(
+            thresholds.evaluate(ConnectionQuality::Degraded, Duration::from_millis(90)),
+            ConnectionQuality::Degraded
+        );
+        assert_eq!(
+            thresholds.evaluate(ConnectionQuality::Degraded, Duration::from_millis(70)),
+            ConnectionQuality::Good
+        );
+        assert_eq!(
+            thresholds.evaluate(ConnectionQuality::Bad, Duration::from_millis(150)),
+            ConnectionQuality::Degraded
+        );
+    }
+
+    #[test]
+    fn changes_are_averaged_over_recent_frames_test() {
+        let mut metrics = ServerMetrics::new();
+        assert_eq!(metrics.average_changes_per_frame(), 0.);
+
+        metrics.record_frame_changes(90);
+        for _ in 0..64 {
+            metrics.record_frame_changes(2);
+        }
+        assert_eq!(metrics.average_changes_per_frame(), 2.);
+
+        metrics.record_component_size("Position", 12);
+        metrics.record_component_size("Position", 16);
+        assert_eq!(metrics.component_size("Position"), Some(16));
+        assert_eq!(metrics.component_size("Health"), None);
+    }
+
+    #[test]
+    fn prediction_metrics_per_component_test() {
+        let mut metrics = PredictionMetrics::new();
+
+        metrics.record_prediction("Position");
+        metrics.record_misprediction("Position", 10);
+        metrics.record_misprediction("Position", 20);
+        metrics.record_prediction("Health");
+
+        let position = metrics.component("Position").unwrap();
+        assert_eq!(position.predictions(), 3);
+        assert_eq!(position.mispredictions(), 2);
+        assert_eq!(position.average_correction_size(), 15.);
+        assert_eq!(metrics.component("Health").unwrap().misprediction_rate(), 0.);
+        assert_eq!(metrics.mispredictions(), 2);
+    }
+
+    #[test]
+    fn resimulation_depth_histogram_test() {
+        let mut metrics = PredictionMetrics::new();
+
+        metrics.record_resimulation(3);
+        metrics.record_resimulation(1);
+        metrics.record_resimulation(3);
+
+        assert_eq!(
+            metrics.resimulation_depths().collect::<Vec<(u32, u64)>>(),
+            vec![(1, 1), (3, 2)]
+        );
+        assert_eq!(metrics.resimulations(), 3);
+    }
+
+    #[test]
+    fn bandwidth_share_per_component_test() {
+        let mut metrics = BandwidthMetrics::with_window(2);
+
+        metrics.record(1, "Transform", 70);
+        metrics.record(1, "Health", 30);
+
+        assert_eq!(metrics.share("Transform"), 0.7);
+        assert_eq!(metrics.component("Health").unwrap().count(), 1);
+
+        metrics.record(2, "Health", 30);
+        // Frame 1 leaves the window.
+        metrics.record(3, "Health", 40);
+
+        let transform = metrics.component("Transform").unwrap();
+        assert_eq!(transform.bytes(), 70);
+        assert_eq!(transform.recent_bytes(), 0);
+        assert_eq!(metrics.recent_bytes(), 70);
+        assert_eq!(metrics.share("Health"), 1.);
+    }
+}
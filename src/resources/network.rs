@@ -0,0 +1,215 @@
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+
+use net_sync::{
+    event::{NetworkEvent, NetworkEventQueue},
+    synchronisation::{NetworkCommand, NetworkMessage},
+    transport,
+    transport::PostBox,
+};
+
+use crate::resources::SocketOptions;
+
+/// Time the network thread sleeps between two pumps of the socket.
+const PUMP_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Size of the receive buffer of the network thread.
+const RECV_BUFFER_SIZE: usize = 5000;
+
+/// Resource that reads and writes the client socket on a dedicated thread.
+///
+/// The thread owns the TCP connection and exchanges messages with the world over channels,
+/// so a long simulation tick can not cause TCP backpressure or receive buffer overflows.
+/// The thread is stopped and joined when this resource is dropped.
+///
+/// The events of the transport are forwarded to the world, see `drain_events`. The thread stops
+/// on its own when the transport reports that the connection was lost, `is_alive` returns
+/// `false` from then on.
+pub struct ClientNetworkThread<
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+> {
+    incoming: Receiver<transport::ServerToClientMessage<ServerToClientMessage>>,
+    outgoing:
+        Sender<transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>>,
+    // Received messages that did not match the predicate of a previous `drain_inbox`.
+    inbox: Vec<transport::ServerToClientMessage<ServerToClientMessage>>,
+    events: Receiver<NetworkEvent>,
+    running: Arc<AtomicBool>,
+    alive: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+/// Clears the alive flag when the network thread exits, also when it panics.
+struct AliveGuard(Arc<AtomicBool>);
+
+impl Drop for AliveGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+impl<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    > ClientNetworkThread<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>
+{
+    /// Connects to the given address and starts pumping the connection on a new thread.
+    ///
+    /// Fails when the server can not be reached or the thread can not be spawned.
+    pub fn connect(
+        addr: SocketAddr,
+        options: &SocketOptions,
+    ) -> io::Result<
+        ClientNetworkThread<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>,
+    > {
+        let mut tcp_client = options.connect(addr)?;
+
+        let (incoming_tx, incoming) = unbounded();
+        let (outgoing, outgoing_rx) = unbounded();
+        let (events_tx, events_rx) = unbounded();
+        let running = Arc::new(AtomicBool::new(true));
+
+        let alive = Arc::new(AtomicBool::new(true));
+
+        let thread_running = running.clone();
+        let thread_alive = alive.clone();
+        let handle = thread::Builder::new()
+            .name("legion-sync-network".to_string())
+            .spawn(move || {
+                let mut postbox = PostBox::<
+                    transport::ServerToClientMessage<ServerToClientMessage>,
+                    transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+                >::new();
+                let mut events = NetworkEventQueue::new();
+                let mut recv_buffer = vec![0; RECV_BUFFER_SIZE];
+
+                let _alive = AliveGuard(thread_alive);
+
+                while thread_running.load(Ordering::Acquire) {
+                    net_sync::transport::tcp::tcp_client_receive_system(
+                        &mut tcp_client,
+                        &mut postbox,
+                        &mut events,
+                        &mut recv_buffer,
+                    );
+
+                    for message in postbox.drain_inbox(|_| true) {
+                        if incoming_tx.send(message).is_err() {
+                            // The world is gone, nothing to deliver to anymore.
+                            return;
+                        }
+                    }
+
+                    for message in outgoing_rx.try_iter() {
+                        postbox.send(message);
+                    }
+
+                    net_sync::transport::tcp::tcp_client_sent_system(
+                        &mut tcp_client,
+                        &mut postbox,
+                        &mut events,
+                    );
+
+                    let mut disconnected = false;
+                    for event in events.drain().collect::<Vec<_>>() {
+                        disconnected |= matches!(event, NetworkEvent::Disconnected(_));
+                        // A failed send means the world is gone, like for the messages above.
+                        let _ = events_tx.send(event);
+                    }
+
+                    if disconnected {
+                        // The guard clears the alive flag.
+                        return;
+                    }
+
+                    thread::sleep(PUMP_INTERVAL);
+                }
+            })?;
+
+        Ok(ClientNetworkThread {
+            incoming,
+            outgoing,
+            inbox: Vec::new(),
+            events: events_rx,
+            running,
+            alive,
+            handle: Some(handle),
+        })
+    }
+
+    /// Returns the received messages matching the predicate, like `PostBox::drain_inbox`.
+    pub fn drain_inbox(
+        &mut self,
+        predicate: impl Fn(&transport::ServerToClientMessage<ServerToClientMessage>) -> bool,
+    ) -> Vec<transport::ServerToClientMessage<ServerToClientMessage>> {
+        self.inbox.extend(self.incoming.try_iter());
+
+        let (drained, kept) = self.inbox.drain(..).partition(|message| predicate(message));
+        self.inbox = kept;
+
+        drained
+    }
+
+    /// Adds a message to the received ones, e.g. a user message that arrived in a bundle.
+    pub fn add_to_inbox(
+        &mut self,
+        message: transport::ServerToClientMessage<ServerToClientMessage>,
+    ) {
+        self.inbox.push(message);
+    }
+
+    /// Returns the events the transport raised on the network thread since the last call,
+    /// e.g. `NetworkEvent::Disconnected` when the connection was lost.
+    pub fn drain_events(&mut self) -> Vec<NetworkEvent> {
+        self.events.try_iter().collect()
+    }
+
+    /// Queues the message to be sent by the network thread.
+    pub fn send(
+        &self,
+        message: transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+    ) {
+        // If the thread has stopped the connection is lost, the message can be dropped.
+        let _ = self.outgoing.send(message);
+    }
+
+    /// Returns whether the network thread is still pumping the connection.
+    pub fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::Acquire)
+    }
+
+    /// Stops the network thread and waits for it to finish.
+    pub fn shutdown(&mut self) {
+        self.running.store(false, Ordering::Release);
+
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    > Drop
+    for ClientNetworkThread<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>
+{
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
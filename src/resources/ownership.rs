@@ -0,0 +1,85 @@
+use net_sync::{transport::ClientId, uid::Uid};
+
+use crate::resources::PlayerOwnership;
+
+/// Server resource that ties the commands of the clients to the entities they own in the
+/// `PlayerOwnership`, enabled with `ServerWorldBuilder::with_ownership_rules`.
+///
+/// `targets` returns the entities a command affects. `ServerWorld::drain_commands` drops the
+/// commands that affect an entity the sending client does not own and raises a
+/// `ServerEvent::CommandRejected`. The owners are replicated with the `Owner` component.
+pub struct OwnershipRules<C> {
+    targets: Box<dyn Fn(&C) -> Vec<Uid> + Send + Sync>,
+    allow_unowned: bool,
+    rejected: u64,
+}
+
+impl<C> OwnershipRules<C> {
+    pub fn new(targets: impl Fn(&C) -> Vec<Uid> + Send + Sync + 'static) -> OwnershipRules<C> {
+        OwnershipRules {
+            targets: Box::new(targets),
+            allow_unowned: false,
+            rejected: 0,
+        }
+    }
+
+    /// Accepts commands for entities without an owner, e.g. shared doors and pickups.
+    pub fn with_unowned(mut self) -> Self {
+        self.allow_unowned = true;
+        self
+    }
+
+    /// The number of rejected commands.
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    /// Whether the client may send the command, `Err` with the first entity it does not own.
+    pub fn authorize(
+        &mut self,
+        client: ClientId,
+        command: &C,
+        ownership: Option<&PlayerOwnership>,
+    ) -> Result<(), Uid> {
+        for uid in (self.targets)(command) {
+            let authorized = match ownership.and_then(|ownership| ownership.owner(uid)) {
+                Some((owner, _)) => owner == client,
+                None => self.allow_unowned,
+            };
+
+            if !authorized {
+                self.rejected += 1;
+                return Err(uid);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::resources::{OwnershipRules, PlayerOwnership};
+
+    #[test]
+    fn commands_only_affect_owned_entities_test() {
+        let mut ownership = PlayerOwnership::new();
+        ownership.set_owner(1, 7, 0);
+        ownership.set_owner(2, 8, 0);
+
+        let mut rules = OwnershipRules::new(|targets: &Vec<u32>| targets.clone());
+
+        assert_eq!(rules.authorize(7, &vec![1], Some(&ownership)), Ok(()));
+        assert_eq!(rules.authorize(7, &vec![1, 2], Some(&ownership)), Err(2));
+        assert_eq!(rules.authorize(7, &vec![3], Some(&ownership)), Err(3));
+        assert_eq!(rules.rejected(), 2);
+
+        let mut rules = rules.with_unowned();
+        assert_eq!(rules.authorize(7, &vec![3], Some(&ownership)), Ok(()));
+
+        // Moving the entity to another client moves the authority along.
+        ownership.set_owner(1, 8, 0);
+        assert_eq!(rules.authorize(7, &vec![1], Some(&ownership)), Err(1));
+        assert_eq!(ownership.drain_changes().len(), 3);
+    }
+}
@@ -0,0 +1,250 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    hash::Hash,
+};
+
+use net_sync::{synchronisation::CommandFrame, transport::ClientId, uid::Uid};
+
+use crate::protocol::{LocalPlayer, PlayerCommand};
+
+/// Client resource with the number of local players that share the connection,
+/// see `ClientWorldBuilder::with_local_players`.
+///
+/// The client sends `PlayerCommand`s, the local player index travels with every command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalPlayers {
+    count: LocalPlayer,
+}
+
+impl LocalPlayers {
+    pub fn new(count: LocalPlayer) -> LocalPlayers {
+        LocalPlayers {
+            count: count.max(1),
+        }
+    }
+
+    pub fn count(&self) -> LocalPlayer {
+        self.count
+    }
+
+    /// The indices of the local players.
+    pub fn iter(&self) -> impl Iterator<Item = LocalPlayer> {
+        0..self.count
+    }
+
+    /// Wraps the command of a local player, `None` if there is no player with the index.
+    pub fn command<C>(&self, player: LocalPlayer, command: C) -> Option<PlayerCommand<C>> {
+        if player < self.count {
+            Some(PlayerCommand::new(player, command))
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for LocalPlayers {
+    fn default() -> Self {
+        LocalPlayers::new(1)
+    }
+}
+
+/// Server resource with the command buffer of every local player of every client.
+///
+/// Game code pushes the received `PlayerCommand`s and drains them per player,
+/// so the commands of players that share a connection are never mixed up.
+pub struct PlayerCommands<C, K = ClientId> {
+    max_players: LocalPlayer,
+    buffers: HashMap<(K, LocalPlayer), VecDeque<(CommandFrame, C)>>,
+}
+
+impl<C, K: Hash + Eq + Copy> PlayerCommands<C, K> {
+    /// Accepts commands of at most `max_players` local players per client.
+    pub fn new(max_players: LocalPlayer) -> PlayerCommands<C, K> {
+        PlayerCommands {
+            max_players: max_players.max(1),
+            buffers: HashMap::new(),
+        }
+    }
+
+    /// Buffers a received command.
+    ///
+    /// Returns `false` and drops the command if its player index exceeds the maximum.
+    pub fn push(
+        &mut self,
+        client: K,
+        command_frame: CommandFrame,
+        command: PlayerCommand<C>,
+    ) -> bool {
+        if command.player >= self.max_players {
+            return false;
+        }
+
+        self.buffers
+            .entry((client, command.player))
+            .or_default()
+            .push_back((command_frame, command.command));
+        true
+    }
+
+    /// Takes the buffered commands of a local player in the order they were received.
+    pub fn drain(&mut self, client: K, player: LocalPlayer) -> Vec<(CommandFrame, C)> {
+        self.buffers
+            .get_mut(&(client, player))
+            .map_or_else(Vec::new, |buffer| buffer.drain(..).collect())
+    }
+
+    /// The number of buffered commands of a local player.
+    pub fn len(&self, client: K, player: LocalPlayer) -> usize {
+        self.buffers
+            .get(&(client, player))
+            .map_or(0, |buffer| buffer.len())
+    }
+
+    /// Removes the buffers of a disconnected client.
+    pub fn remove_client(&mut self, client: K) {
+        self.buffers.retain(|(buffered, _), _| *buffered != client);
+    }
+
+    /// Moves the buffers to another client id, see `ServerWorld::migrate_client`.
+    pub fn rebind(&mut self, from: K, to: K) {
+        let moved = self
+            .buffers
+            .keys()
+            .filter(|(client, _)| *client == from)
+            .copied()
+            .collect::<Vec<_>>();
+
+        for (client, player) in moved {
+            if let Some(buffer) = self.buffers.remove(&(client, player)) {
+                self.buffers.insert((to, player), buffer);
+            }
+        }
+    }
+}
+
+/// Server resource with the local player that owns an entity, e.g. its avatar.
+///
+/// With `OwnershipRules` the server only accepts the commands of a client for the entities it
+/// owns, and the owners are replicated with the `Owner` component.
+#[derive(Debug)]
+pub struct PlayerOwnership<K = ClientId> {
+    owners: HashMap<Uid, (K, LocalPlayer)>,
+    changed: HashSet<Uid>,
+}
+
+impl<K: Hash + Eq + Copy> PlayerOwnership<K> {
+    pub fn new() -> PlayerOwnership<K> {
+        PlayerOwnership {
+            owners: HashMap::new(),
+            changed: HashSet::new(),
+        }
+    }
+
+    /// Makes the local player the owner of the entity, replaces the previous owner.
+    pub fn set_owner(&mut self, uid: Uid, client: K, player: LocalPlayer) {
+        if self.owners.insert(uid, (client, player)) != Some((client, player)) {
+            self.changed.insert(uid);
+        }
+    }
+
+    pub fn remove_owner(&mut self, uid: Uid) -> Option<(K, LocalPlayer)> {
+        let owner = self.owners.remove(&uid);
+        if owner.is_some() {
+            self.changed.insert(uid);
+        }
+        owner
+    }
+
+    pub fn owner(&self, uid: Uid) -> Option<(K, LocalPlayer)> {
+        self.owners.get(&uid).copied()
+    }
+
+    pub fn is_owner(&self, uid: Uid, client: K, player: LocalPlayer) -> bool {
+        self.owner(uid) == Some((client, player))
+    }
+
+    /// The entities owned by the local player.
+    pub fn owned_by(&self, client: K, player: LocalPlayer) -> impl Iterator<Item = Uid> + '_ {
+        self.owners
+            .iter()
+            .filter(move |(_, owner)| **owner == (client, player))
+            .map(|(uid, _)| *uid)
+    }
+
+    /// The entities owned by the local players of the client.
+    pub fn owned_by_client(
+        &self,
+        client: K,
+    ) -> impl Iterator<Item = (Uid, LocalPlayer)> + '_ {
+        self.owners
+            .iter()
+            .filter(move |(_, (owner, _))| *owner == client)
+            .map(|(uid, (_, player))| (*uid, *player))
+    }
+
+    /// Removes the ownerships of a disconnected client.
+    pub fn remove_client(&mut self, client: K) {
+        let changed = &mut self.changed;
+
+        self.owners.retain(|uid, (owner, _)| {
+            if *owner == client {
+                changed.insert(*uid);
+            }
+            *owner != client
+        });
+    }
+
+    /// Moves the ownerships to another client id, see `ServerWorld::migrate_client`.
+    pub fn rebind(&mut self, from: K, to: K) {
+        for (uid, (owner, _)) in self.owners.iter_mut() {
+            if *owner == from {
+                *owner = to;
+                self.changed.insert(*uid);
+            }
+        }
+    }
+
+    /// Takes the entities whose owner changed since the last call.
+    pub(crate) fn drain_changes(&mut self) -> Vec<Uid> {
+        self.changed.drain().collect()
+    }
+}
+
+impl<K: Hash + Eq + Copy> Default for PlayerOwnership<K> {
+    fn default() -> Self {
+        PlayerOwnership::new()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::{
+        protocol::PlayerCommand,
+        resources::{LocalPlayers, PlayerCommands, PlayerOwnership},
+    };
+
+    #[test]
+    fn commands_per_local_player_test() {
+        let local_players = LocalPlayers::new(2);
+        let mut commands = PlayerCommands::<&str, u32>::new(2);
+        let mut ownership = PlayerOwnership::<u32>::new();
+
+        assert!(local_players.command(2, "jump").is_none());
+
+        assert!(commands.push(1, 5, local_players.command(0, "left").unwrap()));
+        assert!(commands.push(1, 5, local_players.command(1, "right").unwrap()));
+        assert!(!commands.push(1, 5, PlayerCommand::new(2, "jump")));
+
+        assert_eq!(commands.drain(1, 1), vec![(5, "right")]);
+        assert_eq!(commands.len(1, 0), 1);
+
+        ownership.set_owner(10, 1, 0);
+        ownership.set_owner(11, 1, 1);
+        commands.rebind(1, 7);
+        ownership.rebind(1, 7);
+
+        assert_eq!(commands.drain(7, 0), vec![(5, "left")]);
+        assert!(ownership.is_owner(11, 7, 1));
+        assert_eq!(ownership.owned_by(7, 0).collect::<Vec<_>>(), vec![10]);
+    }
+}
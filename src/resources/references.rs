@@ -0,0 +1,224 @@
+use std::mem;
+
+use legion::{
+    query::{IntoQuery, Write},
+    storage::Component,
+    Entity, World,
+};
+
+use net_sync::uid::Uid;
+
+use crate::{components::NetworkEntity, world};
+
+/// What happens with the entities that reference a destroyed entity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferencePolicy {
+    /// The referencing entities are destroyed too.
+    Cascade,
+    /// The references are set to null.
+    Nullify,
+    /// The entity is destroyed once no entity references it anymore.
+    Defer,
+}
+
+/// Resource with the component fields that reference other entities, see `NetworkEntity`.
+///
+/// Replicated entities are destroyed on the server with `destroy` instead of removing them,
+/// the policy of each field that references the entity decides what happens with the referencing
+/// entities, so clients never hold a reference to an entity they do not know.
+///
+/// Register the same fields on the server and the client. Nullified references are not sent,
+/// the client nullifies them itself when it removes the entity.
+///
+/// ```ignore
+/// ServerWorldBuilder::default()
+///     .with_entity_reference(ReferencePolicy::Nullify, |target: &mut Target| &mut target.entity);
+/// ```
+#[derive(Default)]
+pub struct EntityReferences {
+    fields: Vec<(ReferencePolicy, Box<dyn ErasedField>)>,
+    pending: Vec<Uid>,
+}
+
+impl EntityReferences {
+    pub fn new() -> EntityReferences {
+        EntityReferences::default()
+    }
+
+    /// Registers a field of component type `T` that references another entity.
+    pub fn register<T: Component>(
+        &mut self,
+        policy: ReferencePolicy,
+        field: fn(&mut T) -> &mut NetworkEntity,
+    ) {
+        self.fields.push((policy, Box::new(Field { field })));
+    }
+
+    /// Destroys the entity with the uid at the end of the server tick.
+    pub fn destroy(&mut self, uid: Uid) {
+        if !self.pending.contains(&uid) {
+            self.pending.push(uid);
+        }
+    }
+
+    /// The entities that are waiting to be destroyed, e.g. deferred by a `Defer` field.
+    pub fn pending(&self) -> impl Iterator<Item = Uid> + '_ {
+        self.pending.iter().copied()
+    }
+
+    /// Destroys the pending entities that are not deferred, returns the destroyed uids.
+    pub(crate) fn destroy_pending(&mut self, world: &mut World) -> Vec<Uid> {
+        let mut destroyed = Vec::new();
+        let mut deferred = Vec::new();
+        let mut queue = mem::replace(&mut self.pending, Vec::new());
+
+        while let Some(uid) = queue.pop() {
+            if destroyed.contains(&uid) {
+                continue;
+            }
+
+            let entity = match world::entity_by_uid(world, uid) {
+                Some(entity) => entity,
+                None => continue,
+            };
+
+            let is_deferred = self
+                .fields
+                .iter()
+                .filter(|(policy, _)| *policy == ReferencePolicy::Defer)
+                .any(|(_, field)| !field.referencing(world, uid).is_empty());
+
+            if is_deferred {
+                deferred.push(uid);
+                continue;
+            }
+
+            for (policy, field) in self.fields.iter() {
+                match policy {
+                    ReferencePolicy::Cascade => {
+                        for dependent in field.referencing(world, uid) {
+                            match world::uid_of(world, dependent) {
+                                Some(dependent_uid) => queue.push(dependent_uid),
+                                // Local entities are not replicated, no policy applies to them.
+                                None => {
+                                    world.remove(dependent);
+                                }
+                            }
+                        }
+                    }
+                    ReferencePolicy::Nullify => field.nullify(world, uid),
+                    ReferencePolicy::Defer => {}
+                }
+            }
+
+            world.remove(entity);
+            destroyed.push(uid);
+        }
+
+        self.pending = deferred;
+        destroyed
+    }
+
+    /// Nullifies the references of the `Nullify` fields to the removed entity,
+    /// used by the client that receives the removal.
+    pub(crate) fn nullify_references(&self, world: &mut World, uid: Uid) {
+        for (policy, field) in self.fields.iter() {
+            if *policy == ReferencePolicy::Nullify {
+                field.nullify(world, uid);
+            }
+        }
+    }
+}
+
+/// Type erased `Field`, so fields of different component types can be stored together.
+trait ErasedField: Send + Sync {
+    fn referencing(&self, world: &mut World, uid: Uid) -> Vec<Entity>;
+
+    fn nullify(&self, world: &mut World, uid: Uid);
+}
+
+struct Field<T> {
+    field: fn(&mut T) -> &mut NetworkEntity,
+}
+
+impl<T: Component> ErasedField for Field<T> {
+    fn referencing(&self, world: &mut World, uid: Uid) -> Vec<Entity> {
+        <(Entity, Write<T>)>::query()
+            .iter_mut(world)
+            .filter_map(|(entity, component)| {
+                if (self.field)(component).refers_to(uid) {
+                    Some(*entity)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn nullify(&self, world: &mut World, uid: Uid) {
+        for component in <Write<T>>::query().iter_mut(world) {
+            let reference = (self.field)(component);
+
+            if reference.refers_to(uid) {
+                reference.set_null();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use legion::{world::EntityStore, World};
+
+    use crate::{
+        components::{NetworkEntity, UidComponent},
+        resources::{EntityReferences, ReferencePolicy},
+    };
+
+    struct Owner(NetworkEntity);
+
+    struct Target(NetworkEntity);
+
+    #[test]
+    fn destroy_applies_policies_test() {
+        let mut world = World::default();
+        let mut references = EntityReferences::new();
+        references.register(ReferencePolicy::Cascade, |owner: &mut Owner| &mut owner.0);
+        references.register(ReferencePolicy::Nullify, |target: &mut Target| &mut target.0);
+
+        world.push((UidComponent::new(1),));
+        let owned = world.push((UidComponent::new(2), Owner(NetworkEntity::new(1))));
+        let targeting = world.push((UidComponent::new(3), Target(NetworkEntity::new(1))));
+
+        references.destroy(1);
+        let mut destroyed = references.destroy_pending(&mut world);
+        destroyed.sort();
+
+        assert_eq!(destroyed, vec![1, 2]);
+        assert!(!world.contains(owned));
+        assert!(world
+            .entry_ref(targeting)
+            .unwrap()
+            .get_component::<Target>()
+            .unwrap()
+            .0
+            .is_null());
+    }
+
+    #[test]
+    fn destroy_is_deferred_test() {
+        let mut world = World::default();
+        let mut references = EntityReferences::new();
+        references.register(ReferencePolicy::Defer, |owner: &mut Owner| &mut owner.0);
+
+        world.push((UidComponent::new(1),));
+        let owned = world.push((UidComponent::new(2), Owner(NetworkEntity::new(1))));
+
+        references.destroy(1);
+        assert!(references.destroy_pending(&mut world).is_empty());
+        assert_eq!(references.pending().collect::<Vec<_>>(), vec![1]);
+
+        world.remove(owned);
+        assert_eq!(references.destroy_pending(&mut world), vec![1]);
+    }
+}
@@ -0,0 +1,363 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    mem,
+};
+
+use legion::{
+    query::{IntoQuery, Read, TryRead},
+    Entity, World,
+};
+
+use net_sync::{
+    synchronisation::{CommandFrame, WorldState},
+    transport::ClientId,
+    uid::Uid,
+};
+
+use crate::{
+    components::{Region, UidComponent},
+    error::ErrorKind,
+    protocol::{RegionId, RegionManifest},
+    resources::{
+        InterestScopes, RegisteredComponentsResource, Relevancy, RelevancyOverrides,
+        SerializationResource,
+    },
+    world::{self, serialize_entity},
+};
+
+/// The entities and manifests a client receives when its resident regions changed.
+pub(crate) struct RegionTransition {
+    pub(crate) client: ClientId,
+    /// Inserts the entities that became visible and removes the ones that left.
+    pub(crate) state: WorldState,
+    pub(crate) manifests: Vec<RegionManifest>,
+}
+
+/// Server resource that keeps only the declared regions of a huge world resident on a client,
+/// enabled with `ServerWorldBuilder::with_region_streaming`.
+///
+/// Entities are assigned to a region with the `Region` component. Game code reports the regions
+/// a client wants with `set_resident`, e.g. from a message of its own. The client then receives
+/// the population of a region as inserted entities when it enters the region and as removed
+/// entities when it leaves, preceded by a `RegionManifest`. The state updates of the client only
+/// contain the entities of its resident regions and the entities without a region.
+///
+/// The streamed entities enter and leave the `InterestScopes` of the client,
+/// the `InterestHooks` are called for them in the next tick.
+/// Clients that did not declare regions receive the whole world.
+#[derive(Debug, Default)]
+pub struct RegionStreaming {
+    resident: HashMap<ClientId, HashSet<RegionId>>,
+    /// The regions the client received, clients without an entry have the whole world.
+    streamed: HashMap<ClientId, HashSet<RegionId>>,
+    /// The replicated entities with their region, as of the last update.
+    entities: HashMap<Uid, (Entity, Option<RegionId>)>,
+    previous: HashMap<Uid, (Entity, Option<RegionId>)>,
+    /// The entities a client received whole with the transition of this frame.
+    inserted: HashMap<ClientId, HashSet<Uid>>,
+}
+
+impl RegionStreaming {
+    pub fn new() -> RegionStreaming {
+        RegionStreaming::default()
+    }
+
+    /// Replaces the resident regions of the client, the change is streamed with the next frame.
+    pub fn set_resident(&mut self, client: ClientId, regions: impl IntoIterator<Item = RegionId>) {
+        self.resident.insert(client, regions.into_iter().collect());
+    }
+
+    /// The regions the client declared resident.
+    pub fn resident(&self, client: ClientId) -> impl Iterator<Item = RegionId> + '_ {
+        self.resident
+            .get(&client)
+            .into_iter()
+            .flat_map(|regions| regions.iter().copied())
+    }
+
+    /// Whether the client receives only its resident regions.
+    pub fn is_streaming(&self, client: ClientId) -> bool {
+        self.resident.contains_key(&client)
+    }
+
+    /// The region of the replicated entity as of the last frame.
+    pub fn region_of(&self, uid: Uid) -> Option<RegionId> {
+        self.entities.get(&uid).and_then(|(_, region)| *region)
+    }
+
+    /// The replicated entities of the region as of the last frame.
+    pub fn population(&self, region: RegionId) -> impl Iterator<Item = Uid> + '_ {
+        population(&self.entities, region).into_iter()
+    }
+
+    /// Removes the regions of a disconnected client.
+    pub fn remove_client(&mut self, client: ClientId) {
+        self.resident.remove(&client);
+        self.streamed.remove(&client);
+        self.inserted.remove(&client);
+    }
+
+    /// Moves the regions to another client id, see `ServerWorld::migrate_client`.
+    pub fn rebind(&mut self, from: ClientId, to: ClientId) {
+        if let Some(resident) = self.resident.remove(&from) {
+            self.resident.insert(to, resident);
+        }
+        if let Some(streamed) = self.streamed.remove(&from) {
+            self.streamed.insert(to, streamed);
+        }
+    }
+
+    /// The client received the whole world with an initial sync,
+    /// the next update removes the regions it does not have resident.
+    pub(crate) fn synced(&mut self, client: ClientId) {
+        self.streamed.remove(&client);
+    }
+
+    /// Updates the regions of the entities and returns the entities the clients have to insert
+    /// or remove because their resident regions changed or the entities moved.
+    ///
+    /// Entities that fail to serialize are left out, their errors are returned.
+    pub(crate) fn update(
+        &mut self,
+        world: &World,
+        registered: &RegisteredComponentsResource,
+        serialization: &SerializationResource,
+        quarantined: &HashSet<Uid>,
+        command_frame: CommandFrame,
+        mut scopes: Option<&mut InterestScopes>,
+        relevancy: Option<&RelevancyOverrides>,
+    ) -> (Vec<RegionTransition>, Vec<(Uid, ErrorKind)>) {
+        let entities = <(Entity, Read<UidComponent>, TryRead<Region>)>::query()
+            .iter(world)
+            .filter(|(_, uid, _)| !quarantined.contains(&uid.uid()))
+            .map(|(entity, uid, region)| (uid.uid(), (*entity, region.map(|region| region.id()))))
+            .collect::<HashMap<Uid, (Entity, Option<RegionId>)>>();
+
+        self.previous = mem::replace(&mut self.entities, entities);
+        self.inserted.clear();
+
+        let mut transitions = Vec::new();
+        let mut errors = Vec::new();
+
+        for (client, resident) in self.resident.iter() {
+            let streamed = self.streamed.get(client);
+            let was_visible = |region: Option<RegionId>| match (region, streamed) {
+                (Some(region), Some(streamed)) => streamed.contains(&region),
+                _ => true,
+            };
+            let is_visible = |region: Option<RegionId>| match region {
+                Some(region) => resident.contains(&region),
+                None => true,
+            };
+
+            let mut manifests = Vec::new();
+            let (entered, left) = match streamed {
+                Some(streamed) => (
+                    resident.difference(streamed).copied().collect::<Vec<_>>(),
+                    streamed.difference(resident).copied().collect::<Vec<_>>(),
+                ),
+                // The client has the whole world, it leaves every other region.
+                None => (
+                    resident.iter().copied().collect(),
+                    self.previous
+                        .values()
+                        .filter_map(|(_, region)| *region)
+                        .filter(|region| !resident.contains(region))
+                        .collect::<HashSet<_>>()
+                        .into_iter()
+                        .collect(),
+                ),
+            };
+
+            for region in entered {
+                manifests.push(RegionManifest {
+                    region,
+                    resident: true,
+                    entities: population(&self.entities, region),
+                });
+            }
+            for region in left {
+                manifests.push(RegionManifest {
+                    region,
+                    resident: false,
+                    entities: population(&self.previous, region),
+                });
+            }
+
+            let mut state = WorldState::new(command_frame);
+            let inserted = self.inserted.entry(*client).or_default();
+
+            // Entities spawned in this frame are inserted by the state update itself.
+            for (uid, (entity, region)) in self.entities.iter() {
+                let (_, previous_region) = match self.previous.get(uid) {
+                    Some(previous) => previous,
+                    None => continue,
+                };
+
+                // The relevancy overrides take precedence over the regions.
+                if relevancy.map_or(false, |relevancy| relevancy.get(*client, *uid).is_some()) {
+                    continue;
+                }
+
+                match (was_visible(*previous_region), is_visible(*region)) {
+                    (true, false) => {
+                        state.remove_entity(*uid);
+
+                        if let Some(scopes) = scopes.as_deref_mut() {
+                            scopes.remove(*client, *entity);
+                        }
+                    }
+                    (false, true) => {
+                        let components = serialize_entity(
+                            world,
+                            registered,
+                            serialization,
+                            *entity,
+                            *uid,
+                            &mut errors,
+                        );
+
+                        state.insert_entity(*uid, components);
+                        inserted.insert(*uid);
+
+                        if let Some(scopes) = scopes.as_deref_mut() {
+                            scopes.insert(*client, *entity);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            if !state.is_empty() || !manifests.is_empty() {
+                transitions.push(RegionTransition {
+                    client: *client,
+                    state,
+                    manifests,
+                });
+            }
+        }
+
+        self.streamed = self.resident.clone();
+
+        (transitions, errors)
+    }
+
+    /// Whether the entity is in a resident region of the client or has no region.
+    pub(crate) fn is_resident(&self, client: ClientId, uid: Uid) -> bool {
+        let region = match self.entities.get(&uid).or_else(|| self.previous.get(&uid)) {
+            Some((_, region)) => *region,
+            None => None,
+        };
+
+        match (region, self.resident.get(&client)) {
+            (Some(region), Some(resident)) => resident.contains(&region),
+            _ => true,
+        }
+    }
+
+    /// Returns the part of the state that concerns the resident regions of the client,
+    /// and the entities that are always relevant to it.
+    pub(crate) fn filter_state(
+        &self,
+        client: ClientId,
+        state: &WorldState,
+        relevancy: Option<&RelevancyOverrides>,
+    ) -> WorldState {
+        let inserted = self.inserted.get(&client);
+
+        world::filter_state(state, |uid| {
+            // The transition of this frame already sent the current components.
+            if inserted.map_or(false, |inserted| inserted.contains(&uid)) {
+                return false;
+            }
+
+            let always = relevancy.map_or(false, |relevancy| {
+                relevancy.get(client, uid) == Some(Relevancy::Always)
+            });
+
+            always || self.is_resident(client, uid)
+        })
+    }
+
+    /// The `SerializedStateCache` filter of the states filtered for the client,
+    /// clients with the same resident regions share their payloads.
+    pub(crate) fn cache_filter(&self, client: ClientId, filter: u64) -> u64 {
+        let mut regions = self.resident(client).collect::<Vec<_>>();
+        regions.sort();
+
+        let mut hasher = DefaultHasher::new();
+        (filter, regions).hash(&mut hasher);
+
+        // Keeps clear of `SerializedStateCache::UNFILTERED` and the version filters.
+        (2 << 32) | (hasher.finish() & u32::MAX as u64)
+    }
+}
+
+fn population(entities: &HashMap<Uid, (Entity, Option<RegionId>)>, region: RegionId) -> Vec<Uid> {
+    let mut uids = entities
+        .iter()
+        .filter(|(_, (_, entity_region))| *entity_region == Some(region))
+        .map(|(uid, _)| *uid)
+        .collect::<Vec<_>>();
+    uids.sort();
+    uids
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::collections::HashSet;
+
+    use legion::World;
+
+    use net_sync::synchronisation::{ComponentData, WorldState};
+
+    use crate::{
+        components::{Region, UidComponent},
+        resources::{RegionStreaming, RegisteredComponentsResource, SerializationResource},
+    };
+
+    #[test]
+    fn regions_are_streamed_on_enter_and_leave_test() {
+        let registered = RegisteredComponentsResource::new();
+        let serialization = SerializationResource::default();
+        let quarantined = HashSet::new();
+        let mut world = World::default();
+        world.push((UidComponent::new(1), Region::new(1)));
+        world.push((UidComponent::new(2), Region::new(2)));
+        world.push((UidComponent::new(3),));
+
+        let mut streaming = RegionStreaming::new();
+        streaming.update(&world, &registered, &serialization, &quarantined, 1, None, None);
+
+        // The client has the whole world from its initial sync.
+        streaming.set_resident(7, vec![1]);
+        let (transitions, _) =
+            streaming.update(&world, &registered, &serialization, &quarantined, 2, None, None);
+        assert_eq!(transitions.len(), 1);
+        assert_eq!(transitions[0].state.removed, vec![2]);
+        assert_eq!(transitions[0].manifests.len(), 2);
+
+        streaming.set_resident(7, vec![2]);
+        let (transitions, _) =
+            streaming.update(&world, &registered, &serialization, &quarantined, 3, None, None);
+        let transition = &transitions[0];
+        assert_eq!(transition.state.removed, vec![1]);
+        assert_eq!(transition.state.inserted[0].entity_id(), 2);
+
+        let manifest = transition.manifests.iter().find(|x| x.resident).unwrap();
+        assert_eq!((manifest.region, &manifest.entities[..]), (2, &[2][..]));
+
+        // Entity 2 was inserted whole, only the entity without a region is left.
+        let mut state = WorldState::new(3);
+        for uid in 1..=3 {
+            state.change(uid, ComponentData::new(1, vec![1]));
+        }
+        let filtered = streaming.filter_state(7, &state, None);
+        assert_eq!(filtered.changed.len(), 1);
+        assert_eq!(filtered.changed[0].entity_id(), 3);
+
+        // Clients that did not declare regions receive everything.
+        assert_eq!(streaming.filter_state(8, &state, None).changed.len(), 3);
+    }
+}
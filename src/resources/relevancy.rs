@@ -0,0 +1,225 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet},
+    hash::{Hash, Hasher},
+};
+
+use legion::Entity;
+
+use net_sync::{synchronisation::WorldState, transport::ClientId, uid::Uid};
+
+use crate::world;
+
+/// How an entity is replicated to a client regardless of the interest rules,
+/// see `RelevancyOverrides`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Relevancy {
+    /// The entity is always replicated to the client, e.g. its own character.
+    Always,
+    /// The entity is never replicated to the client, e.g. an enemy in stealth.
+    Never,
+}
+
+/// An override that changed since the last frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RelevancyChange {
+    pub(crate) client: ClientId,
+    pub(crate) uid: Uid,
+    pub(crate) entity: Entity,
+    pub(crate) relevancy: Option<Relevancy>,
+}
+
+/// Server resource with the entities game code made relevant or irrelevant to a client,
+/// set with `ServerWorld::always_relevant` and `ServerWorld::never_relevant`.
+///
+/// The overrides take precedence over the `RegionStreaming`: an always relevant entity is
+/// replicated outside of the resident regions of the client, a never relevant entity is not
+/// replicated at all. A changed override is applied with the next frame, the client receives
+/// the entity as inserted or removed entity. Like the regions, the initial sync carries the
+/// whole world, the never relevant entities are removed right after it.
+#[derive(Debug, Default)]
+pub struct RelevancyOverrides {
+    overrides: HashMap<ClientId, BTreeMap<Uid, Relevancy>>,
+    changes: Vec<RelevancyChange>,
+    /// The entities a client received whole with the changes of this frame.
+    inserted: HashMap<ClientId, HashSet<Uid>>,
+}
+
+impl RelevancyOverrides {
+    pub fn new() -> RelevancyOverrides {
+        RelevancyOverrides::default()
+    }
+
+    /// Overrides the relevancy of the entity for the client, `None` restores the interest rules.
+    pub fn set(
+        &mut self,
+        client: ClientId,
+        entity: Entity,
+        uid: Uid,
+        relevancy: Option<Relevancy>,
+    ) {
+        let overrides = self.overrides.entry(client).or_default();
+
+        let previous = match relevancy {
+            Some(relevancy) => overrides.insert(uid, relevancy),
+            None => overrides.remove(&uid),
+        };
+
+        if overrides.is_empty() {
+            self.overrides.remove(&client);
+        }
+
+        if previous != relevancy {
+            self.changes.push(RelevancyChange {
+                client,
+                uid,
+                entity,
+                relevancy,
+            });
+        }
+    }
+
+    pub fn get(&self, client: ClientId, uid: Uid) -> Option<Relevancy> {
+        self.overrides.get(&client)?.get(&uid).copied()
+    }
+
+    /// The overridden entities of the client.
+    pub fn overrides(&self, client: ClientId) -> impl Iterator<Item = (Uid, Relevancy)> + '_ {
+        self.overrides
+            .get(&client)
+            .into_iter()
+            .flat_map(|overrides| overrides.iter().map(|(uid, relevancy)| (*uid, *relevancy)))
+    }
+
+    /// The entities that are never relevant to the client.
+    pub fn never_relevant(&self, client: ClientId) -> impl Iterator<Item = Uid> + '_ {
+        self.overrides(client)
+            .filter(|(_, relevancy)| *relevancy == Relevancy::Never)
+            .map(|(uid, _)| uid)
+    }
+
+    /// Forgets the overrides of a removed entity.
+    pub fn remove_entity(&mut self, uid: Uid) {
+        for overrides in self.overrides.values_mut() {
+            overrides.remove(&uid);
+        }
+        self.overrides.retain(|_, overrides| !overrides.is_empty());
+        self.changes.retain(|change| change.uid != uid);
+    }
+
+    /// Forgets the overrides of a disconnected client.
+    pub fn remove_client(&mut self, client: ClientId) {
+        self.overrides.remove(&client);
+        self.inserted.remove(&client);
+        self.changes.retain(|change| change.client != client);
+    }
+
+    /// Moves the overrides to another client id, see `ServerWorld::migrate_client`.
+    pub fn rebind(&mut self, from: ClientId, to: ClientId) {
+        if let Some(overrides) = self.overrides.remove(&from) {
+            self.overrides.insert(to, overrides);
+        }
+        for change in self.changes.iter_mut().filter(|change| change.client == from) {
+            change.client = to;
+        }
+    }
+
+    /// Takes the overrides that changed since the last frame, the last change of each entity.
+    pub(crate) fn drain_changes(&mut self) -> Vec<RelevancyChange> {
+        self.inserted.clear();
+
+        let mut seen = HashSet::new();
+        let mut changes = self
+            .changes
+            .drain(..)
+            .rev()
+            .filter(|change| seen.insert((change.client, change.uid)))
+            .collect::<Vec<_>>();
+        changes.reverse();
+        changes
+    }
+
+    /// The entity was sent whole to the client with the changes of this frame.
+    pub(crate) fn inserted(&mut self, client: ClientId, uid: Uid) {
+        self.inserted.entry(client).or_default().insert(uid);
+    }
+
+    /// Whether the states of the client have to be filtered.
+    pub(crate) fn is_filtering(&self, client: ClientId) -> bool {
+        self.overrides.contains_key(&client) || self.inserted.contains_key(&client)
+    }
+
+    /// Returns the state without the entities that are never relevant to the client.
+    pub(crate) fn filter_state(&self, client: ClientId, state: &WorldState) -> WorldState {
+        let inserted = self.inserted.get(&client);
+
+        world::filter_state(state, |uid| {
+            // The changes of this frame already sent the current components.
+            !inserted.map_or(false, |inserted| inserted.contains(&uid))
+                && self.get(client, uid) != Some(Relevancy::Never)
+        })
+    }
+
+    /// The `SerializedStateCache` filter of the states filtered for the client,
+    /// clients with the same overrides share their payloads.
+    pub(crate) fn cache_filter(&self, client: ClientId, filter: u64) -> u64 {
+        let mut inserted = self
+            .inserted
+            .get(&client)
+            .into_iter()
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        inserted.sort();
+
+        let mut hasher = DefaultHasher::new();
+        (filter, self.overrides.get(&client), inserted).hash(&mut hasher);
+
+        // Keeps clear of `SerializedStateCache::UNFILTERED`, the version and the region filters.
+        (3 << 32) | (hasher.finish() & u32::MAX as u64)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use legion::World;
+
+    use net_sync::synchronisation::{ComponentData, WorldState};
+
+    use crate::resources::{Relevancy, RelevancyOverrides};
+
+    #[test]
+    fn never_relevant_entities_are_filtered_test() {
+        let mut world = World::default();
+        let character = world.push(());
+        let stealthed = world.push(());
+
+        let mut relevancy = RelevancyOverrides::new();
+        relevancy.set(1, character, 10, Some(Relevancy::Always));
+        relevancy.set(1, stealthed, 11, Some(Relevancy::Never));
+        relevancy.set(1, stealthed, 11, Some(Relevancy::Never));
+        assert_eq!(relevancy.drain_changes().len(), 2);
+
+        let mut state = WorldState::new(3);
+        state.change(10, ComponentData::new(1, Vec::new()));
+        state.change(11, ComponentData::new(1, Vec::new()));
+        state.change(12, ComponentData::new(1, Vec::new()));
+
+        assert!(relevancy.is_filtering(1));
+        let filtered = relevancy.filter_state(1, &state);
+        assert_eq!(filtered.changed.len(), 2);
+        assert!(filtered.changed.iter().all(|changed| changed.entity_id() != 11));
+
+        // Clients with the same overrides share the filtered payload.
+        relevancy.set(2, stealthed, 11, Some(Relevancy::Never));
+        assert_ne!(relevancy.cache_filter(1, 0), relevancy.cache_filter(2, 0));
+        relevancy.set(2, character, 10, Some(Relevancy::Always));
+        assert_eq!(relevancy.cache_filter(1, 0), relevancy.cache_filter(2, 0));
+
+        relevancy.set(1, stealthed, 11, None);
+        relevancy.set(1, character, 10, None);
+        relevancy.set(1, character, 10, Some(Relevancy::Never));
+        assert_eq!(relevancy.drain_changes().len(), 4);
+        assert_eq!(relevancy.get(1, 10), Some(Relevancy::Never));
+        assert!(!relevancy.is_filtering(3));
+    }
+}
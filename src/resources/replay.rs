@@ -0,0 +1,160 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+};
+
+use bincode::Options;
+use serde::Serialize;
+
+use net_sync::{synchronisation::CommandFrame, transport::ClientId};
+
+use crate::{tracking::re_exports::bincode, world::default_options};
+
+/// The default number of command frames in which duplicate commands are detected.
+const DEFAULT_WINDOW: CommandFrame = 64;
+
+/// The commands of a client that were accepted and dropped, see `CommandReplayGuard`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplayStats {
+    pub accepted: u64,
+    /// Commands that were received twice within the window.
+    pub duplicates: u64,
+    /// Commands of a command frame older than the window.
+    pub replayed: u64,
+}
+
+#[derive(Debug, Default)]
+struct ClientCommands {
+    latest: CommandFrame,
+    seen: HashSet<(CommandFrame, u64)>,
+    stats: ReplayStats,
+}
+
+/// Server resource that drops duplicate and replayed client commands,
+/// e.g. commands that are retransmitted after a reconnect.
+///
+/// The command frames of a client only move forward. A command is accepted once per command
+/// frame, commands of frames more than `window` frames before the latest frame of the client are
+/// dropped. `ServerWorld::drain_commands` passes the received commands through the guard,
+/// systems that read the post office themselves call `accept`.
+#[derive(Debug)]
+pub struct CommandReplayGuard {
+    window: CommandFrame,
+    clients: HashMap<ClientId, ClientCommands>,
+}
+
+impl CommandReplayGuard {
+    pub fn new() -> CommandReplayGuard {
+        CommandReplayGuard::with_window(DEFAULT_WINDOW)
+    }
+
+    /// Detects duplicates within the last `window` command frames of a client.
+    pub fn with_window(window: CommandFrame) -> CommandReplayGuard {
+        CommandReplayGuard {
+            window: window.max(1),
+            clients: HashMap::new(),
+        }
+    }
+
+    /// Returns whether the command should be executed, `false` for duplicates and replays.
+    pub fn accept<C: Serialize>(
+        &mut self,
+        client: ClientId,
+        command_frame: CommandFrame,
+        command: &C,
+    ) -> bool {
+        let window = self.window;
+        let commands = self.clients.entry(client).or_default();
+
+        if command_frame.saturating_add(window) < commands.latest {
+            commands.stats.replayed += 1;
+            return false;
+        }
+
+        if !commands.seen.insert((command_frame, fingerprint(command))) {
+            commands.stats.duplicates += 1;
+            return false;
+        }
+
+        if command_frame > commands.latest {
+            commands.latest = command_frame;
+
+            let oldest = command_frame.saturating_sub(window);
+            commands.seen.retain(|(frame, _)| *frame >= oldest);
+        }
+
+        commands.stats.accepted += 1;
+        true
+    }
+
+    pub fn stats(&self, client: ClientId) -> ReplayStats {
+        self.clients
+            .get(&client)
+            .map_or_else(ReplayStats::default, |commands| commands.stats)
+    }
+
+    /// The latest command frame the client sent a command for.
+    pub fn latest(&self, client: ClientId) -> Option<CommandFrame> {
+        self.clients.get(&client).map(|commands| commands.latest)
+    }
+
+    pub fn remove_client(&mut self, client: ClientId) {
+        self.clients.remove(&client);
+    }
+
+    /// Moves the received commands to another client id, see `ServerWorld::migrate_client`.
+    pub fn rebind(&mut self, from: ClientId, to: ClientId) {
+        if let Some(commands) = self.clients.remove(&from) {
+            self.clients.insert(to, commands);
+        }
+    }
+}
+
+impl Default for CommandReplayGuard {
+    fn default() -> Self {
+        CommandReplayGuard::new()
+    }
+}
+
+/// Hashes the serialized command, commands do not have to implement `Hash`.
+fn fingerprint<C: Serialize>(command: &C) -> u64 {
+    let mut hasher = DefaultHasher::new();
+
+    match default_options().serialize(command) {
+        Ok(bytes) => bytes.hash(&mut hasher),
+        Err(_) => 0.hash(&mut hasher),
+    }
+
+    hasher.finish()
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::resources::{CommandReplayGuard, ReplayStats};
+
+    #[test]
+    fn duplicates_and_replays_are_dropped_test() {
+        let mut guard = CommandReplayGuard::with_window(4);
+
+        assert!(guard.accept(1, 10, &"left"));
+        assert!(guard.accept(1, 10, &"jump"));
+        assert!(!guard.accept(1, 10, &"left"));
+        assert!(guard.accept(1, 8, &"right"));
+
+        assert!(guard.accept(1, 20, &"left"));
+        assert!(!guard.accept(1, 10, &"fire"));
+
+        // Another client has its own frames.
+        assert!(guard.accept(2, 10, &"left"));
+
+        assert_eq!(
+            guard.stats(1),
+            ReplayStats {
+                accepted: 4,
+                duplicates: 1,
+                replayed: 1,
+            }
+        );
+        assert_eq!(guard.latest(1), Some(20));
+    }
+}
@@ -0,0 +1,194 @@
+use std::ops::RangeInclusive;
+
+use itertools::Itertools;
+
+use net_sync::{
+    synchronisation::{ClientCommandBufferEntry, CommandFrame, NetworkCommand},
+    uid::Uid,
+};
+
+/// Who replays the mispredicted frames, see `ResimulationQueue`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResimulationExecutor {
+    /// The ranges are pushed to the `ResimulationBuffer` and replayed by the systems,
+    /// they are resolved once the systems ran.
+    BuiltIn,
+    /// Only the queue receives the ranges, game code replays them and calls `mark_resolved`.
+    Custom,
+}
+
+/// Identifier of a pending resimulation, see `ResimulationQueue::mark_resolved`.
+pub type ResimulationId = u64;
+
+/// The frames a mispredicted state update requires to replay.
+pub struct ResimulationRange<C: NetworkCommand> {
+    id: ResimulationId,
+    start: CommandFrame,
+    end: CommandFrame,
+    entries: Vec<ClientCommandBufferEntry<C>>,
+}
+
+impl<C: NetworkCommand> ResimulationRange<C> {
+    pub fn id(&self) -> ResimulationId {
+        self.id
+    }
+
+    /// The command frame of the state update, the world is corrected to this frame.
+    pub fn start(&self) -> CommandFrame {
+        self.start
+    }
+
+    /// The command frame of the client when the misprediction was detected.
+    pub fn end(&self) -> CommandFrame {
+        self.end
+    }
+
+    pub fn frames(&self) -> RangeInclusive<CommandFrame> {
+        self.start..=self.end
+    }
+
+    /// The uids of the mispredicted entities.
+    pub fn entities(&self) -> Vec<Uid> {
+        self.entries
+            .iter()
+            .map(|entry| entry.entity_id)
+            .sorted()
+            .dedup()
+            .collect()
+    }
+
+    /// The commands to replay, oldest first, one per command frame.
+    pub fn commands(&self) -> Vec<(CommandFrame, &C)> {
+        self.entries
+            .iter()
+            .map(|entry| (entry.command_frame, &entry.command))
+            .sorted_by_key(|(command_frame, _)| *command_frame)
+            .dedup_by(|a, b| a.0 == b.0)
+            .collect()
+    }
+
+    /// The command buffer entries of the mispredicted entities.
+    pub fn entries(&self) -> &[ClientCommandBufferEntry<C>] {
+        &self.entries
+    }
+}
+
+/// Client resource with the pending resimulations, in the order the mispredictions were found.
+///
+/// By default the `ResimulationBuffer` drives the replay and the queue is for inspection.
+/// Engines that replay the frames themselves opt out with
+/// `ClientWorldBuilder::with_custom_resimulation`, they iterate the `pending` ranges and
+/// mark them resolved when they are replayed.
+pub struct ResimulationQueue<C: NetworkCommand> {
+    executor: ResimulationExecutor,
+    next_id: ResimulationId,
+    pending: Vec<ResimulationRange<C>>,
+}
+
+impl<C: NetworkCommand> ResimulationQueue<C> {
+    pub fn new(executor: ResimulationExecutor) -> ResimulationQueue<C> {
+        ResimulationQueue {
+            executor,
+            next_id: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn executor(&self) -> ResimulationExecutor {
+        self.executor
+    }
+
+    /// The ranges that are not resolved yet, oldest first.
+    pub fn pending(&self) -> impl Iterator<Item = &ResimulationRange<C>> {
+        self.pending.iter()
+    }
+
+    /// Removes the range from the queue, returns `false` if it is not pending.
+    pub fn mark_resolved(&mut self, id: ResimulationId) -> bool {
+        let before = self.pending.len();
+        self.pending.retain(|range| range.id != id);
+        self.pending.len() != before
+    }
+
+    /// Takes all pending ranges, e.g. to replay them at once.
+    pub fn take_pending(&mut self) -> Vec<ResimulationRange<C>> {
+        self.pending.drain(..).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub(crate) fn push(
+        &mut self,
+        start: CommandFrame,
+        end: CommandFrame,
+        entries: Vec<ClientCommandBufferEntry<C>>,
+    ) -> ResimulationId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.pending.push(ResimulationRange {
+            id,
+            start,
+            end,
+            entries,
+        });
+
+        id
+    }
+
+    /// Called after the systems ran, the built-in executor replayed the ranges pushed before.
+    pub(crate) fn systems_executed(&mut self) {
+        if self.executor == ResimulationExecutor::BuiltIn {
+            self.pending.clear();
+        }
+    }
+}
+
+impl<C: NetworkCommand> Default for ResimulationQueue<C> {
+    fn default() -> Self {
+        ResimulationQueue::new(ResimulationExecutor::BuiltIn)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use serde::{Deserialize, Serialize};
+
+    use net_sync::synchronisation::NetworkCommand;
+
+    use crate::resources::{ResimulationExecutor, ResimulationQueue};
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    struct TestCommand;
+
+    impl NetworkCommand for TestCommand {}
+
+    #[test]
+    fn custom_executor_resolves_ranges_test() {
+        let mut queue = ResimulationQueue::<TestCommand>::new(ResimulationExecutor::Custom);
+        let first = queue.push(4, 9, Vec::new());
+        let second = queue.push(6, 10, Vec::new());
+
+        queue.systems_executed();
+        assert_eq!(queue.len(), 2);
+
+        let range = queue.pending().next().unwrap();
+        assert_eq!((range.id(), range.frames()), (first, 4..=9));
+
+        assert!(queue.mark_resolved(first));
+        assert!(!queue.mark_resolved(first));
+        assert_eq!(queue.take_pending()[0].id(), second);
+        assert!(queue.is_empty());
+
+        let mut built_in = ResimulationQueue::<TestCommand>::default();
+        built_in.push(4, 9, Vec::new());
+        built_in.systems_executed();
+        assert!(built_in.is_empty());
+    }
+}
@@ -0,0 +1,117 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+use net_sync::synchronisation::CommandFrame;
+
+/// Shared randomness between server and clients.
+///
+/// The seed is chosen by the server and replicated with the initial state sync, a
+/// `ServerMessage::Resync` re-seeds the clients with the current seed of the server.
+/// Every command frame gets its own random stream (see `for_frame`),
+/// client prediction and server simulation therefore draw identical values for the same frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncedRng {
+    seed: u64,
+}
+
+impl SyncedRng {
+    pub fn new(seed: u64) -> SyncedRng {
+        SyncedRng { seed }
+    }
+
+    /// Creates a rng with a seed from the randomly seeded std hasher.
+    pub fn from_entropy() -> SyncedRng {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u8(0);
+        SyncedRng::new(hasher.finish())
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    /// Returns the random stream of the given command frame.
+    pub fn for_frame(&self, command_frame: CommandFrame) -> FrameRng {
+        let mut state = self.seed ^ (command_frame as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        FrameRng {
+            state: split_mix(&mut state),
+        }
+    }
+}
+
+/// Random stream of a single command frame (SplitMix64).
+#[derive(Debug, Clone)]
+pub struct FrameRng {
+    state: u64,
+}
+
+impl FrameRng {
+    pub fn next_u64(&mut self) -> u64 {
+        split_mix(&mut self.state)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    /// Returns a value in the range `[0, 1)`.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    /// Returns a value in the range `[low, high)`.
+    pub fn range(&mut self, low: u32, high: u32) -> u32 {
+        assert!(low < high, "Range should not be empty.");
+        low + self.next_u32() % (high - low)
+    }
+}
+
+fn split_mix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::resources::SyncedRng;
+
+    #[test]
+    fn same_seed_and_frame_draw_same_values_test() {
+        let server = SyncedRng::new(42);
+        let client = SyncedRng::new(42);
+
+        let mut server_stream = server.for_frame(10);
+        let mut client_stream = client.for_frame(10);
+
+        for _ in 0..10 {
+            assert_eq!(server_stream.next_u64(), client_stream.next_u64());
+        }
+    }
+
+    #[test]
+    fn frames_have_different_streams_test() {
+        let rng = SyncedRng::new(42);
+
+        assert_ne!(rng.for_frame(1).next_u64(), rng.for_frame(2).next_u64());
+    }
+
+    #[test]
+    fn values_stay_in_range_test() {
+        let mut stream = SyncedRng::new(7).for_frame(0);
+
+        for _ in 0..100 {
+            let value = stream.range(5, 10);
+            assert!(value >= 5 && value < 10);
+            assert!(stream.next_f32() < 1.);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    any::{type_name, TypeId},
+    marker::PhantomData,
+};
+
+use legion::{systems::Resource, Resources};
+
+use net_sync::synchronisation::CommandFrame;
+
+/// A user resource that is rewound together with the replicated world,
+/// e.g. a physics world that systems consult during resimulation.
+///
+/// Register it with `ClientWorldBuilder::with_rollback_resource`.
+pub trait RollbackResource: Resource {
+    /// Saves the state of the resource at the end of the command frame.
+    fn save(&mut self, command_frame: CommandFrame);
+
+    /// Restores the state saved at the command frame, returns `false` if it is not saved anymore.
+    fn restore(&mut self, command_frame: CommandFrame) -> bool;
+}
+
+trait ErasedRollback: Send + Sync {
+    fn resource_type(&self) -> TypeId;
+
+    fn type_name(&self) -> &'static str;
+
+    fn save(&self, resources: &Resources, command_frame: CommandFrame);
+
+    fn restore(&self, resources: &Resources, command_frame: CommandFrame) -> bool;
+}
+
+struct Rollback<R>(PhantomData<fn() -> R>);
+
+impl<R: RollbackResource> ErasedRollback for Rollback<R> {
+    fn resource_type(&self) -> TypeId {
+        TypeId::of::<R>()
+    }
+
+    fn type_name(&self) -> &'static str {
+        type_name::<R>()
+    }
+
+    fn save(&self, resources: &Resources, command_frame: CommandFrame) {
+        if let Some(mut resource) = resources.get_mut::<R>() {
+            resource.save(command_frame);
+        }
+    }
+
+    fn restore(&self, resources: &Resources, command_frame: CommandFrame) -> bool {
+        resources
+            .get_mut::<R>()
+            .map_or(false, |mut resource| resource.restore(command_frame))
+    }
+}
+
+/// Client resource with the registered `RollbackResource`s.
+///
+/// The client world saves them after every command frame. When a state update mispredicted
+/// and the changed entities are pushed to the `ResimulationBuffer`, they are restored to the
+/// command frame of the update before the systems replay the commands.
+#[derive(Default)]
+pub struct RollbackResources {
+    resources: Vec<Box<dyn ErasedRollback>>,
+    pending_restore: Option<CommandFrame>,
+}
+
+impl RollbackResources {
+    pub fn new() -> RollbackResources {
+        RollbackResources::default()
+    }
+
+    /// Registers resource type `R`, a type is registered once.
+    pub fn register<R: RollbackResource>(&mut self) {
+        if !self
+            .resources
+            .iter()
+            .any(|resource| resource.resource_type() == TypeId::of::<R>())
+        {
+            self.resources.push(Box::new(Rollback::<R>(PhantomData)));
+        }
+    }
+
+    /// The type names of the registered resources.
+    pub fn registered(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.resources.iter().map(|resource| resource.type_name())
+    }
+
+    /// Restores the resources to the command frame before the systems run the next time,
+    /// the oldest requested frame is kept.
+    pub fn request_restore(&mut self, command_frame: CommandFrame) {
+        self.pending_restore = Some(
+            self.pending_restore
+                .map_or(command_frame, |pending| pending.min(command_frame)),
+        );
+    }
+
+    pub(crate) fn save(&self, resources: &Resources, command_frame: CommandFrame) {
+        for resource in self.resources.iter() {
+            resource.save(resources, command_frame);
+        }
+    }
+
+    /// Restores the requested frame, returns the resources that did not have it saved.
+    pub(crate) fn restore_pending(&mut self, resources: &Resources) -> Vec<&'static str> {
+        let command_frame = match self.pending_restore.take() {
+            Some(command_frame) => command_frame,
+            None => return Vec::new(),
+        };
+
+        self.resources
+            .iter()
+            .filter(|resource| !resource.restore(resources, command_frame))
+            .map(|resource| resource.type_name())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::collections::BTreeMap;
+
+    use legion::Resources;
+
+    use net_sync::synchronisation::CommandFrame;
+
+    use crate::resources::{RollbackResource, RollbackResources};
+
+    #[derive(Default)]
+    struct Physics {
+        time: u32,
+        saved: BTreeMap<CommandFrame, u32>,
+    }
+
+    impl RollbackResource for Physics {
+        fn save(&mut self, command_frame: CommandFrame) {
+            self.saved.insert(command_frame, self.time);
+        }
+
+        fn restore(&mut self, command_frame: CommandFrame) -> bool {
+            match self.saved.get(&command_frame) {
+                Some(time) => {
+                    self.time = *time;
+                    true
+                }
+                None => false,
+            }
+        }
+    }
+
+    #[test]
+    fn oldest_requested_frame_is_restored_test() {
+        let mut resources = Resources::default();
+        resources.insert(Physics::default());
+
+        let mut rollback = RollbackResources::new();
+        rollback.register::<Physics>();
+        rollback.register::<Physics>();
+        assert_eq!(rollback.registered().count(), 1);
+
+        for frame in 1..=3 {
+            resources.get_mut::<Physics>().unwrap().time = frame * 10;
+            rollback.save(&resources, frame);
+        }
+
+        rollback.request_restore(3);
+        rollback.request_restore(2);
+        assert!(rollback.restore_pending(&resources).is_empty());
+        assert_eq!(resources.get::<Physics>().unwrap().time, 20);
+
+        rollback.request_restore(7);
+        assert_eq!(rollback.restore_pending(&resources).len(), 1);
+        assert!(rollback.restore_pending(&resources).is_empty());
+    }
+}
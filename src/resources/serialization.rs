@@ -0,0 +1,177 @@
+use serde::{
+    de::{DeserializeOwned, DeserializeSeed},
+    Serialize,
+};
+
+use crate::{error::ErrorKind, tracking::re_exports::bincode, world::default_options};
+
+/// The format of the payloads the worlds serialize themselves: the initial state syncs of the
+/// world and of the replication contexts, the components and their differences in the state
+/// updates, and the archived and persisted components. The client and the server must use the
+/// same format.
+///
+/// The values recorded by the change tracker of net-sync, the unchanged components in the
+/// `ModifiedComponentsBuffer` and the `ClientCommandBuffer`, keep its bincode format.
+pub trait SerializationStrategy: Send + Sync + 'static {
+    /// Calls `serialize` with a serializer that writes the returned bytes.
+    fn serialize_with(
+        &self,
+        serialize: &mut dyn FnMut(&mut dyn erased_serde::Serializer) -> Result<(), ErrorKind>,
+    ) -> Result<Vec<u8>, ErrorKind>;
+
+    fn serialize(&self, value: &dyn erased_serde::Serialize) -> Result<Vec<u8>, ErrorKind> {
+        self.serialize_with(&mut |serializer| {
+            value
+                .erased_serialize(serializer)
+                .map(|_| ())
+                .map_err(serialization_error)
+        })
+    }
+
+    /// Calls `deserialize` with a deserializer of the bytes.
+    fn deserialize<'de>(
+        &self,
+        bytes: &'de [u8],
+        deserialize: &mut dyn FnMut(
+            &mut dyn erased_serde::Deserializer<'de>,
+        ) -> Result<(), ErrorKind>,
+    ) -> Result<(), ErrorKind>;
+}
+
+/// Bincode with fixed size integers, the default format.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bincode;
+
+impl SerializationStrategy for Bincode {
+    fn serialize_with(
+        &self,
+        serialize: &mut dyn FnMut(&mut dyn erased_serde::Serializer) -> Result<(), ErrorKind>,
+    ) -> Result<Vec<u8>, ErrorKind> {
+        let mut buffer = Vec::new();
+        let serializer = &mut bincode::Serializer::new(&mut buffer, default_options());
+
+        serialize(&mut erased_serde::Serializer::erase(serializer))?;
+        Ok(buffer)
+    }
+
+    fn deserialize<'de>(
+        &self,
+        bytes: &'de [u8],
+        deserialize: &mut dyn FnMut(
+            &mut dyn erased_serde::Deserializer<'de>,
+        ) -> Result<(), ErrorKind>,
+    ) -> Result<(), ErrorKind> {
+        let deserializer = &mut bincode::Deserializer::from_slice(bytes, default_options());
+        deserialize(&mut erased_serde::Deserializer::erase(deserializer))
+    }
+}
+
+/// Resource containing the serialization strategy, see `SerializationStrategy`.
+pub struct SerializationResource {
+    strategy: Box<dyn SerializationStrategy>,
+}
+
+impl SerializationResource {
+    pub fn new<S: SerializationStrategy>(strategy: S) -> SerializationResource {
+        SerializationResource {
+            strategy: Box::new(strategy),
+        }
+    }
+
+    pub fn serialize<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, ErrorKind> {
+        self.strategy.serialize(value)
+    }
+
+    /// Serializes with a serializer of the strategy, e.g. the difference of a component.
+    pub fn serialize_with(
+        &self,
+        serialize: impl FnOnce(&mut dyn erased_serde::Serializer) -> Result<(), ErrorKind>,
+    ) -> Result<Vec<u8>, ErrorKind> {
+        let mut serialize = Some(serialize);
+
+        self.strategy.serialize_with(&mut |serializer| {
+            let serialize = serialize
+                .take()
+                .ok_or_else(|| ErrorKind::SerializationError("serialized twice".to_string()))?;
+            serialize(serializer)
+        })
+    }
+
+    pub fn deserialize<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, ErrorKind> {
+        self.deserialize_seed(bytes, std::marker::PhantomData::<T>)
+    }
+
+    /// Deserializes the bytes with the seed, e.g. a legion world with its registry.
+    pub fn deserialize_seed<'de, T: DeserializeSeed<'de>>(
+        &self,
+        bytes: &'de [u8],
+        seed: T,
+    ) -> Result<T::Value, ErrorKind> {
+        let mut seed = Some(seed);
+        let mut value = None;
+
+        self.strategy.deserialize(bytes, &mut |deserializer| {
+            let seed = seed
+                .take()
+                .ok_or_else(|| ErrorKind::SerializationError("deserialized twice".to_string()))?;
+            value = Some(seed.deserialize(deserializer).map_err(serialization_error)?);
+            Ok(())
+        })?;
+
+        value.ok_or_else(|| ErrorKind::SerializationError("nothing deserialized".to_string()))
+    }
+
+    /// Deserializes with a deserializer of the strategy, e.g. a component into a world.
+    pub fn deserialize_with<'de, R>(
+        &self,
+        bytes: &'de [u8],
+        deserialize: impl FnOnce(&mut dyn erased_serde::Deserializer<'de>) -> R,
+    ) -> Result<R, ErrorKind> {
+        let mut deserialize = Some(deserialize);
+        let mut value = None;
+
+        self.strategy.deserialize(bytes, &mut |deserializer| {
+            let deserialize = deserialize
+                .take()
+                .ok_or_else(|| ErrorKind::SerializationError("deserialized twice".to_string()))?;
+            value = Some(deserialize(deserializer));
+            Ok(())
+        })?;
+
+        value.ok_or_else(|| ErrorKind::SerializationError("nothing deserialized".to_string()))
+    }
+}
+
+impl Default for SerializationResource {
+    fn default() -> Self {
+        SerializationResource::new(Bincode)
+    }
+}
+
+fn serialization_error(error: impl std::fmt::Display) -> ErrorKind {
+    ErrorKind::SerializationError(error.to_string())
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::{
+        protocol::InitialSync,
+        resources::SerializationResource,
+        tracking::re_exports::bincode,
+    };
+
+    #[test]
+    fn bincode_matches_the_default_encoding_test() {
+        let serialization = SerializationResource::default();
+        let initial_sync = InitialSync {
+            rng_seed: 7,
+            world: vec![1, 2, 3],
+        };
+
+        let bytes = serialization.serialize(&initial_sync).unwrap();
+        assert_eq!(bytes, bincode::serialize(&initial_sync).unwrap());
+
+        let decoded = serialization.deserialize::<InitialSync>(&bytes).unwrap();
+        assert_eq!(decoded, initial_sync);
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+};
+
+use net_sync::transport::ClientId;
+
+/// Secret of a client session, see `ServerWorld::session_token`.
+pub type SessionToken = u64;
+
+/// Server resource with the session tokens of the clients.
+///
+/// Game code sends the token of a client to it, e.g. in a user message. A client that continues
+/// its session from a new connection presents the token again, `ServerWorld::migrate_client`
+/// only moves the session when it matches.
+#[derive(Debug, Default)]
+pub struct SessionTokens {
+    tokens: HashMap<ClientId, SessionToken>,
+}
+
+impl SessionTokens {
+    pub fn new() -> SessionTokens {
+        SessionTokens::default()
+    }
+
+    /// The token of the client, issued on the first call.
+    pub fn issue(&mut self, client: ClientId) -> SessionToken {
+        *self.tokens.entry(client).or_insert_with(random_token)
+    }
+
+    pub fn token(&self, client: ClientId) -> Option<SessionToken> {
+        self.tokens.get(&client).copied()
+    }
+
+    /// Whether the token was issued to the client.
+    pub fn verify(&self, client: ClientId, token: SessionToken) -> bool {
+        self.tokens.get(&client) == Some(&token)
+    }
+
+    pub fn remove_client(&mut self, client: ClientId) {
+        self.tokens.remove(&client);
+    }
+
+    /// Moves the token to another client id, see `ServerWorld::migrate_client`.
+    pub fn rebind(&mut self, from: ClientId, to: ClientId) {
+        if let Some(token) = self.tokens.remove(&from) {
+            self.tokens.insert(to, token);
+        }
+    }
+}
+
+/// A token from the randomly seeded std hasher, like `SyncedRng::from_entropy`.
+fn random_token() -> SessionToken {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u8(0);
+    hasher.finish()
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::resources::SessionTokens;
+
+    #[test]
+    fn tokens_are_verified_per_client_test() {
+        let mut tokens = SessionTokens::new();
+        let token = tokens.issue(1);
+
+        assert_eq!(tokens.issue(1), token);
+        assert_ne!(tokens.issue(2), token);
+        assert!(tokens.verify(1, token));
+        assert!(!tokens.verify(2, token));
+
+        tokens.rebind(1, 3);
+        assert!(!tokens.verify(1, token));
+        assert!(tokens.verify(3, token));
+
+        tokens.remove_client(3);
+        assert_eq!(tokens.token(3), None);
+    }
+}
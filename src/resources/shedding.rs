@@ -0,0 +1,217 @@
+use std::{any::TypeId, collections::HashSet, time::Duration};
+
+use legion::storage::Component;
+
+/// How much replication work the server does, see `LoadShedding`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Fidelity {
+    /// Nothing is shed.
+    Full,
+    /// State updates are sent less often and the client budgets shrink.
+    Reduced,
+    /// Like `Reduced`, further, and the low-priority components are not diffed.
+    Minimal,
+}
+
+impl Fidelity {
+    // The number of steps below full fidelity.
+    fn steps(self) -> u32 {
+        match self {
+            Fidelity::Full => 0,
+            Fidelity::Reduced => 1,
+            Fidelity::Minimal => 2,
+        }
+    }
+
+    fn lower(self) -> Fidelity {
+        match self {
+            Fidelity::Full => Fidelity::Reduced,
+            _ => Fidelity::Minimal,
+        }
+    }
+
+    fn raise(self) -> Fidelity {
+        match self {
+            Fidelity::Minimal => Fidelity::Reduced,
+            _ => Fidelity::Full,
+        }
+    }
+}
+
+/// When and how much replication work `LoadShedding` sheds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SheddingConfig {
+    /// The time a tick may take, usually the frame duration of the `CommandFrameTicker`.
+    pub tick_budget: Duration,
+    /// Consecutive command frames over the tick budget before the fidelity is lowered a step.
+    pub overrun_frames: u32,
+    /// Consecutive command frames under `recovery_ratio` of the tick budget before the fidelity
+    /// is raised a step.
+    pub recovery_frames: u32,
+    pub recovery_ratio: f32,
+    /// Every step below full fidelity multiplies the state update interval of the clients.
+    pub interval_factor: u32,
+    /// Every step below full fidelity multiplies the `InterestBudget` of the clients.
+    pub budget_factor: f32,
+}
+
+impl Default for SheddingConfig {
+    fn default() -> Self {
+        SheddingConfig {
+            tick_budget: Duration::from_millis(16),
+            overrun_frames: 30,
+            recovery_frames: 120,
+            recovery_ratio: 0.7,
+            interval_factor: 2,
+            budget_factor: 0.5,
+        }
+    }
+}
+
+/// Server resource that degrades the replication fidelity under sustained tick overruns,
+/// enabled with `ServerWorldBuilder::with_load_shedding`.
+///
+/// The duration of every tick that replicated a command frame is recorded. When the ticks
+/// overrun the budget for `overrun_frames` frames the fidelity is lowered a step, and raised
+/// again after `recovery_frames` frames with headroom, so short spikes do not flap it. Changes
+/// are raised as `ServerEvent::FidelityChanged`. Changes of the low-priority components, e.g.
+/// cosmetics, are kept while the fidelity is minimal and sent once it is raised again.
+#[derive(Debug, Clone)]
+pub struct LoadShedding {
+    config: SheddingConfig,
+    low_priority: HashSet<TypeId>,
+    fidelity: Fidelity,
+    overruns: u32,
+    headroom: u32,
+    last_tick: Duration,
+}
+
+impl LoadShedding {
+    pub fn new(config: SheddingConfig) -> LoadShedding {
+        LoadShedding {
+            config,
+            low_priority: HashSet::new(),
+            fidelity: Fidelity::Full,
+            overruns: 0,
+            headroom: 0,
+            last_tick: Duration::default(),
+        }
+    }
+
+    /// Marks the component as low-priority, it is not diffed at minimal fidelity.
+    pub fn with_low_priority<T: Component>(mut self) -> Self {
+        self.low_priority.insert(TypeId::of::<T>());
+        self
+    }
+
+    pub fn config(&self) -> &SheddingConfig {
+        &self.config
+    }
+
+    pub fn fidelity(&self) -> Fidelity {
+        self.fidelity
+    }
+
+    /// The duration of the last recorded tick.
+    pub fn last_tick(&self) -> Duration {
+        self.last_tick
+    }
+
+    /// Records the duration of a tick, returns the new fidelity if it changed.
+    pub fn record_tick(&mut self, duration: Duration) -> Option<Fidelity> {
+        self.last_tick = duration;
+
+        let budget = self.config.tick_budget;
+
+        if duration > budget {
+            self.overruns += 1;
+            self.headroom = 0;
+        } else if duration.as_secs_f32() < budget.as_secs_f32() * self.config.recovery_ratio {
+            self.headroom += 1;
+            self.overruns = 0;
+        } else {
+            self.overruns = 0;
+            self.headroom = 0;
+        }
+
+        let fidelity = if self.overruns >= self.config.overrun_frames {
+            self.fidelity.lower()
+        } else if self.headroom >= self.config.recovery_frames {
+            self.fidelity.raise()
+        } else {
+            return None;
+        };
+
+        self.overruns = 0;
+        self.headroom = 0;
+
+        if fidelity == self.fidelity {
+            return None;
+        }
+
+        self.fidelity = fidelity;
+        Some(fidelity)
+    }
+
+    /// Multiplies the state update interval of a client at the current fidelity.
+    pub fn update_interval(&self, interval: u32) -> u32 {
+        interval * self.config.interval_factor.max(1).pow(self.fidelity.steps())
+    }
+
+    /// The factor of the `InterestBudget` of the clients at the current fidelity.
+    pub fn budget_scale(&self) -> f32 {
+        self.config.budget_factor.powi(self.fidelity.steps() as i32)
+    }
+
+    /// The components that are not diffed at the current fidelity.
+    pub(crate) fn deferred(&self) -> Option<&HashSet<TypeId>> {
+        match self.fidelity {
+            Fidelity::Minimal => Some(&self.low_priority),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::time::Duration;
+
+    use crate::resources::{Fidelity, LoadShedding, SheddingConfig};
+
+    struct Cosmetic;
+
+    #[test]
+    fn fidelity_follows_sustained_load_test() {
+        let mut shedding = LoadShedding::new(SheddingConfig {
+            tick_budget: Duration::from_millis(10),
+            overrun_frames: 2,
+            recovery_frames: 3,
+            ..SheddingConfig::default()
+        })
+        .with_low_priority::<Cosmetic>();
+
+        let overrun = Duration::from_millis(12);
+        let idle = Duration::from_millis(2);
+
+        // A single spike does not lower the fidelity.
+        assert_eq!(shedding.record_tick(overrun), None);
+        assert_eq!(shedding.record_tick(idle), None);
+        assert_eq!(shedding.record_tick(overrun), None);
+        assert_eq!(shedding.record_tick(overrun), Some(Fidelity::Reduced));
+        assert_eq!(shedding.update_interval(2), 4);
+        assert!(shedding.deferred().is_none());
+
+        shedding.record_tick(overrun);
+        assert_eq!(shedding.record_tick(overrun), Some(Fidelity::Minimal));
+        assert_eq!(shedding.budget_scale(), 0.25);
+        assert_eq!(shedding.deferred().unwrap().len(), 1);
+
+        // Ticks between the recovery ratio and the budget keep the fidelity.
+        shedding.record_tick(idle);
+        shedding.record_tick(idle);
+        assert_eq!(shedding.record_tick(Duration::from_millis(9)), None);
+        shedding.record_tick(idle);
+        shedding.record_tick(idle);
+        assert_eq!(shedding.record_tick(idle), Some(Fidelity::Reduced));
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashSet;
+
+use net_sync::{transport::ClientId, uid::Uid};
+
+use crate::resources::PlayerOwnership;
+
+/// Why the state a simulator submitted for an entity was not merged,
+/// see `ServerWorld::merge_simulation_state`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimulationRejection {
+    /// The client is not a trusted simulator.
+    NotTrusted,
+    /// The simulator does not own the entity in the `PlayerOwnership`.
+    NotOwned,
+    /// The entity does not exist or is quarantined.
+    UnknownEntity,
+    /// Simulators change components, the server inserts and removes the entities.
+    EntityLifecycle,
+    /// The merged entity violated a constraint of the `ComponentConstraints`, it was restored.
+    ConstraintViolation,
+}
+
+/// The entities of a submitted simulation state that were merged and rejected.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimulationMerge {
+    pub merged: Vec<Uid>,
+    pub rejected: Vec<(Uid, SimulationRejection)>,
+}
+
+/// Server resource with the clients that are trusted to submit authoritative state,
+/// enabled with `ServerWorldBuilder::with_trusted_simulators`.
+///
+/// A simulator is a headless client, e.g. an off-process physics node, that simulates a subset of
+/// the entities for the server. Game code authenticates the node when it connects, e.g. with a
+/// handshake message of its own, and trusts it with `trust`. The node then submits the changes of
+/// its simulation with a message of its own, the server merges them with
+/// `ServerWorld::merge_simulation_state` and sends them to the players with the next state update.
+///
+/// The state of an entity is only accepted from the client that owns it in the `PlayerOwnership`,
+/// so the server decides per entity which simulator is authoritative.
+#[derive(Debug, Default)]
+pub struct TrustedSimulators {
+    clients: HashSet<ClientId>,
+}
+
+impl TrustedSimulators {
+    pub fn new() -> TrustedSimulators {
+        TrustedSimulators::default()
+    }
+
+    /// Trusts the client to submit the state of the entities it owns.
+    pub fn trust(&mut self, client: ClientId) {
+        self.clients.insert(client);
+    }
+
+    /// Stops accepting state from the client, returns whether it was trusted.
+    pub fn revoke(&mut self, client: ClientId) -> bool {
+        self.clients.remove(&client)
+    }
+
+    pub fn is_trusted(&self, client: ClientId) -> bool {
+        self.clients.contains(&client)
+    }
+
+    /// The trusted clients.
+    pub fn clients(&self) -> impl Iterator<Item = ClientId> + '_ {
+        self.clients.iter().copied()
+    }
+
+    /// Whether the client may submit the state of the entity.
+    pub fn authorize(
+        &self,
+        client: ClientId,
+        uid: Uid,
+        ownership: Option<&PlayerOwnership>,
+    ) -> Result<(), SimulationRejection> {
+        if !self.is_trusted(client) {
+            return Err(SimulationRejection::NotTrusted);
+        }
+
+        match ownership.and_then(|ownership| ownership.owner(uid)) {
+            Some((owner, _)) if owner == client => Ok(()),
+            _ => Err(SimulationRejection::NotOwned),
+        }
+    }
+
+    /// Removes the trust of a disconnected client.
+    pub fn remove_client(&mut self, client: ClientId) {
+        self.clients.remove(&client);
+    }
+
+    /// Moves the trust to another client id, see `ServerWorld::migrate_client`.
+    pub fn rebind(&mut self, from: ClientId, to: ClientId) {
+        if self.clients.remove(&from) {
+            self.clients.insert(to);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::resources::{PlayerOwnership, SimulationRejection, TrustedSimulators};
+
+    #[test]
+    fn only_owned_entities_of_trusted_clients_are_authorized_test() {
+        let mut ownership = PlayerOwnership::new();
+        ownership.set_owner(1, 7, 0);
+        ownership.set_owner(2, 8, 0);
+
+        let mut simulators = TrustedSimulators::new();
+        assert_eq!(
+            simulators.authorize(7, 1, Some(&ownership)),
+            Err(SimulationRejection::NotTrusted)
+        );
+
+        simulators.trust(7);
+        assert_eq!(simulators.authorize(7, 1, Some(&ownership)), Ok(()));
+        assert_eq!(
+            simulators.authorize(7, 2, Some(&ownership)),
+            Err(SimulationRejection::NotOwned)
+        );
+        assert_eq!(
+            simulators.authorize(7, 1, None),
+            Err(SimulationRejection::NotOwned)
+        );
+
+        simulators.rebind(7, 9);
+        ownership.rebind(7, 9);
+        assert!(!simulators.is_trusted(7));
+        assert_eq!(simulators.authorize(9, 1, Some(&ownership)), Ok(()));
+    }
+}
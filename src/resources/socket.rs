@@ -0,0 +1,136 @@
+use std::{
+    io,
+    net::{SocketAddr, TcpListener, TcpStream},
+    time::Duration,
+};
+
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+
+use net_sync::transport::tcp::TcpClientResource;
+
+/// The backlog of the listeners bound with `SocketOptions::bind_listener`.
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Socket tuning of the TCP transport, set with `ServerWorldBuilder::with_socket_options` and
+/// `ClientWorldBuilder::with_socket_options`.
+///
+/// Options that are `None` keep the operating system defaults. The defaults are sized for
+/// interactive traffic, state streaming to many entities usually needs larger buffers.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SocketOptions {
+    /// `SO_RCVBUF` in bytes.
+    pub recv_buffer_size: Option<usize>,
+    /// `SO_SNDBUF` in bytes.
+    pub send_buffer_size: Option<usize>,
+    /// Enables TCP keepalive, the idle time before the first probe.
+    pub keepalive_time: Option<Duration>,
+    /// The time between keepalive probes, only used with `keepalive_time`.
+    pub keepalive_interval: Option<Duration>,
+    /// How long closing the socket blocks to send the queued data,
+    /// `Some(Duration::from_secs(0))` resets the connection instead.
+    pub linger: Option<Duration>,
+    /// `SO_REUSEADDR` on listeners bound with `bind_listener`, so a restarted server can bind
+    /// the port while the connections of the previous one are in `TIME_WAIT`.
+    pub reuse_address: bool,
+}
+
+impl SocketOptions {
+    /// Binds a non-blocking listener with the options, e.g. for `ServerWorldBuilder::with_tcp`.
+    pub fn bind_listener(&self, addr: SocketAddr) -> io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        socket.set_reuse_address(self.reuse_address)?;
+        self.apply(SockRef::from(&socket))?;
+        socket.bind(&addr.into())?;
+        socket.listen(LISTEN_BACKLOG)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(socket.into())
+    }
+
+    /// Connects the TCP client of a client world to the address, with the options.
+    pub(crate) fn connect(&self, addr: SocketAddr) -> io::Result<TcpClientResource> {
+        let client = TcpClientResource::new(addr)?;
+        self.apply_to_stream(client.stream())?;
+
+        Ok(client)
+    }
+
+    pub(crate) fn apply_to_stream(&self, stream: &TcpStream) -> io::Result<()> {
+        self.apply(SockRef::from(stream))
+    }
+
+    /// Accepted streams inherit the options of the listener.
+    pub(crate) fn apply_to_listener(&self, listener: &TcpListener) -> io::Result<()> {
+        self.apply(SockRef::from(listener))
+    }
+
+    fn apply(&self, socket: SockRef<'_>) -> io::Result<()> {
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+
+        if let Some(time) = self.keepalive_time {
+            let mut keepalive = TcpKeepalive::new().with_time(time);
+
+            if let Some(interval) = self.keepalive_interval {
+                keepalive = keepalive.with_interval(interval);
+            }
+
+            socket.set_tcp_keepalive(&keepalive)?;
+        }
+
+        if self.linger.is_some() {
+            socket.set_linger(self.linger)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::{net::TcpListener, time::Duration};
+
+    use socket2::SockRef;
+
+    use crate::resources::SocketOptions;
+
+    #[test]
+    fn options_are_applied_to_the_listener_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let options = SocketOptions {
+            recv_buffer_size: Some(1 << 16),
+            keepalive_time: Some(Duration::from_secs(10)),
+            linger: Some(Duration::from_secs(1)),
+            ..SocketOptions::default()
+        };
+
+        options.apply_to_listener(&listener).unwrap();
+
+        let socket = SockRef::from(&listener);
+        assert!(socket.keepalive().unwrap());
+        assert_eq!(socket.linger().unwrap(), Some(Duration::from_secs(1)));
+        // The kernel may round the size, e.g. Linux doubles it.
+        assert!(socket.recv_buffer_size().unwrap() >= 1 << 16);
+    }
+
+    #[test]
+    fn listener_rebinds_a_reused_address_test() {
+        let options = SocketOptions {
+            reuse_address: true,
+            ..SocketOptions::default()
+        };
+
+        let listener = options.bind_listener("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        assert!(SockRef::from(&listener).reuse_address().unwrap());
+
+        drop(listener);
+        let listener = options.bind_listener(addr).unwrap();
+        assert_eq!(listener.local_addr().unwrap(), addr);
+    }
+}
@@ -0,0 +1,203 @@
+use std::{
+    any::TypeId,
+    collections::{HashMap, HashSet},
+};
+
+use legion::{
+    query::{IntoQuery, Read},
+    storage::Component,
+    Entity, World,
+};
+
+use net_sync::{synchronisation::CommandFrame, uid::Uid};
+
+use crate::components::UidComponent;
+
+/// What the client does with an entity the server did not update for a while.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StaleAction {
+    /// Raise `ClientEvent::PossiblyStale`.
+    Report,
+    /// Raise the event and request a resync of the entity, see `StaleSweep::take_resync_requests`.
+    /// The server removes the entity if the client should not have it anymore.
+    Resync,
+}
+
+/// When an entity is considered stale, see `StaleSweep`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StalePolicy {
+    /// The number of command frames without a state update of the entity.
+    pub frames: CommandFrame,
+    pub action: StaleAction,
+}
+
+impl StalePolicy {
+    pub fn new(frames: CommandFrame, action: StaleAction) -> StalePolicy {
+        StalePolicy { frames, action }
+    }
+}
+
+/// An entity that was not updated by the server within the frames of its policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct StaleEntity {
+    pub(crate) entity: Entity,
+    pub(crate) uid: Uid,
+    pub(crate) last_updated: CommandFrame,
+}
+
+struct Sweep {
+    component: TypeId,
+    has_component: fn(&World, Entity) -> bool,
+    policy: StalePolicy,
+}
+
+fn has_component<T: Component>(world: &World, entity: Entity) -> bool {
+    world
+        .entry_ref(entity)
+        .map_or(false, |entry| entry.get_component::<T>().is_ok())
+}
+
+/// Client resource that finds the replicated entities the server stopped updating,
+/// enabled with `ClientWorldBuilder::with_stale_sweep`.
+///
+/// Lost packets or interest management can leave entities on the client that the server does
+/// not send anymore. The sweep is opt-in per kind of entity: entities with a watched component,
+/// e.g. the marker of a prefab, that were not part of a state update for the frames of the
+/// policy raise `ClientEvent::PossiblyStale` once.
+///
+/// With `StaleAction::Resync` the uids are queued as resync requests. Game code sends them to
+/// the server with a message of its own, the server answers with `ServerWorld::resync_entities`:
+/// entities that still exist are sent again, the others are removed from the client.
+#[derive(Default)]
+pub struct StaleSweep {
+    sweeps: Vec<Sweep>,
+    last_updated: HashMap<Entity, CommandFrame>,
+    reported: HashSet<Entity>,
+    resync_requests: Vec<Uid>,
+}
+
+impl StaleSweep {
+    pub fn new() -> StaleSweep {
+        StaleSweep::default()
+    }
+
+    /// Sweeps the entities with component `T`, replaces an earlier policy of `T`.
+    ///
+    /// An entity with several watched components uses the policy that was watched first.
+    pub fn watch<T: Component>(&mut self, policy: StalePolicy) {
+        match self
+            .sweeps
+            .iter_mut()
+            .find(|sweep| sweep.component == TypeId::of::<T>())
+        {
+            Some(sweep) => sweep.policy = policy,
+            None => self.sweeps.push(Sweep {
+                component: TypeId::of::<T>(),
+                has_component: has_component::<T>,
+                policy,
+            }),
+        }
+    }
+
+    /// The policy of the entity, `None` if it is not swept.
+    pub fn policy(&self, world: &World, entity: Entity) -> Option<StalePolicy> {
+        self.sweeps
+            .iter()
+            .find(|sweep| (sweep.has_component)(world, entity))
+            .map(|sweep| sweep.policy)
+    }
+
+    /// The command frame of the last state update of a swept entity.
+    pub fn last_updated(&self, entity: Entity) -> Option<CommandFrame> {
+        self.last_updated.get(&entity).copied()
+    }
+
+    /// Removes the uids of the stale entities the server should send again.
+    pub fn take_resync_requests(&mut self) -> Vec<Uid> {
+        self.resync_requests.drain(..).collect()
+    }
+
+    /// Marks the entity as updated by the server, it can be reported again.
+    pub(crate) fn updated(&mut self, entity: Entity, command_frame: CommandFrame) {
+        self.last_updated.insert(entity, command_frame);
+        self.reported.remove(&entity);
+    }
+
+    /// Returns the swept entities that became stale since the last sweep.
+    ///
+    /// Entities are tracked from the first sweep they are seen in.
+    pub(crate) fn sweep(&mut self, world: &World, command_frame: CommandFrame) -> Vec<StaleEntity> {
+        let entities = <(Entity, Read<UidComponent>)>::query()
+            .iter(world)
+            .map(|(entity, uid)| (*entity, uid.uid()))
+            .collect::<Vec<(Entity, Uid)>>();
+
+        let mut swept = HashSet::new();
+        let mut stale = Vec::new();
+
+        for (entity, uid) in entities {
+            let policy = match self.policy(world, entity) {
+                Some(policy) => policy,
+                None => continue,
+            };
+            swept.insert(entity);
+
+            let last_updated = *self.last_updated.entry(entity).or_insert(command_frame);
+
+            if command_frame.saturating_sub(last_updated) < policy.frames
+                || !self.reported.insert(entity)
+            {
+                continue;
+            }
+
+            if policy.action == StaleAction::Resync {
+                self.resync_requests.push(uid);
+            }
+
+            stale.push(StaleEntity {
+                entity,
+                uid,
+                last_updated,
+            });
+        }
+
+        // Forgets the removed entities.
+        self.last_updated.retain(|entity, _| swept.contains(entity));
+        self.reported.retain(|entity| swept.contains(entity));
+
+        stale
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use legion::World;
+
+    use crate::{
+        components::{DynamicComponent, UidComponent},
+        resources::{StaleAction, StalePolicy, StaleSweep},
+    };
+
+    #[test]
+    fn entities_without_updates_are_reported_once_test() {
+        let mut world = World::default();
+        let swept = world.push((UidComponent::new(1), DynamicComponent::default()));
+        world.push((UidComponent::new(2),));
+
+        let mut sweep = StaleSweep::new();
+        sweep.watch::<DynamicComponent>(StalePolicy::new(5, StaleAction::Resync));
+
+        assert!(sweep.sweep(&world, 10).is_empty());
+        assert!(sweep.sweep(&world, 14).is_empty());
+
+        let stale = sweep.sweep(&world, 15);
+        assert_eq!(stale.len(), 1);
+        assert_eq!((stale[0].uid, stale[0].last_updated), (1, 10));
+        assert!(sweep.sweep(&world, 16).is_empty());
+        assert_eq!(sweep.take_resync_requests(), vec![1]);
+
+        sweep.updated(swept, 16);
+        assert!(sweep.sweep(&world, 20).is_empty());
+        assert_eq!(sweep.sweep(&world, 21).len(), 1);
+    }
+}
@@ -0,0 +1,118 @@
+use std::{collections::HashMap, sync::Arc};
+
+use net_sync::synchronisation::CommandFrame;
+
+/// Server resource with the serialized state updates that are shared between clients.
+///
+/// Without interest filtering every client receives the same state of a command frame,
+/// it is serialized once and the bytes are reference counted by the clients that use them.
+/// Payloads are keyed by their command frame and the hash of the filter applied to the state,
+/// `UNFILTERED` for the complete state.
+#[derive(Debug, Default)]
+pub struct SerializedStateCache {
+    entries: HashMap<(CommandFrame, u64), Arc<Vec<u8>>>,
+    serializations: u64,
+    hits: u64,
+}
+
+impl SerializedStateCache {
+    /// The filter hash of a state that is sent as it is.
+    pub const UNFILTERED: u64 = 0;
+
+    pub fn new() -> SerializedStateCache {
+        SerializedStateCache::default()
+    }
+
+    /// Returns the cached bytes of the payload, serializes it if it is not cached yet.
+    ///
+    /// A payload that fails to serialize is not cached.
+    pub fn get_or_serialize<E>(
+        &mut self,
+        command_frame: CommandFrame,
+        filter: u64,
+        serialize: impl FnOnce() -> Result<Vec<u8>, E>,
+    ) -> Result<Arc<Vec<u8>>, E> {
+        if let Some(bytes) = self.entries.get(&(command_frame, filter)) {
+            self.hits += 1;
+            return Ok(bytes.clone());
+        }
+
+        self.serializations += 1;
+
+        let bytes = Arc::new(serialize()?);
+        self.entries.insert((command_frame, filter), bytes.clone());
+        Ok(bytes)
+    }
+
+    pub fn get(&self, command_frame: CommandFrame, filter: u64) -> Option<Arc<Vec<u8>>> {
+        self.entries.get(&(command_frame, filter)).cloned()
+    }
+
+    /// Drops the payloads of the command frames before `command_frame`.
+    ///
+    /// The bytes stay alive for as long as they are referenced elsewhere.
+    pub fn evict_before(&mut self, command_frame: CommandFrame) {
+        self.entries
+            .retain(|(cached_frame, _), _| *cached_frame >= command_frame);
+    }
+
+    /// The number of payloads that were serialized.
+    pub fn serializations(&self) -> u64 {
+        self.serializations
+    }
+
+    /// The number of times a payload was reused instead of serialized again.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::sync::Arc;
+
+    use crate::resources::SerializedStateCache;
+
+    #[test]
+    fn payload_serialized_once_test() {
+        let mut cache = SerializedStateCache::new();
+
+        let first = cache.get_or_serialize(1, SerializedStateCache::UNFILTERED, || ok(vec![1, 2]));
+        let second = cache.get_or_serialize(1, SerializedStateCache::UNFILTERED, || ok(vec![3]));
+
+        assert!(Arc::ptr_eq(&first.unwrap(), &second.unwrap()));
+        assert_eq!(cache.serializations(), 1);
+        assert_eq!(cache.hits(), 1);
+
+        cache
+            .get_or_serialize(2, SerializedStateCache::UNFILTERED, || ok(vec![3]))
+            .unwrap();
+        cache.evict_before(2);
+
+        assert!(cache.get(1, SerializedStateCache::UNFILTERED).is_none());
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn failed_payload_is_not_cached_test() {
+        let mut cache = SerializedStateCache::new();
+
+        assert!(cache
+            .get_or_serialize(1, SerializedStateCache::UNFILTERED, || Err(()))
+            .is_err());
+        assert!(cache.get(1, SerializedStateCache::UNFILTERED).is_none());
+        assert!(cache.is_empty());
+    }
+
+    fn ok(bytes: Vec<u8>) -> Result<Vec<u8>, ()> {
+        Ok(bytes)
+    }
+}
@@ -0,0 +1,311 @@
+use std::fmt::{self, Display, Formatter, Write};
+
+use legion::World;
+
+use net_sync::{
+    synchronisation::{CommandFrame, WorldState},
+    uid::Uid,
+};
+
+use crate::{
+    resources::RegisteredComponentsResource, tracking::re_exports::bincode,
+    world::default_options,
+};
+
+/// The number of offending bytes that are included in the hexdump of a violation.
+const HEXDUMP_LIMIT: usize = 256;
+
+/// What was wrong with the received data, see `StrictSchema`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ViolationKind {
+    /// The component uid is not registered on this side.
+    UnknownComponent(Uid),
+    /// The value was decoded without consuming all its bytes.
+    TrailingBytes {
+        component: Option<&'static str>,
+        trailing: usize,
+    },
+    /// A message that is not expected in the current state of the client.
+    UnexpectedMessage(&'static str),
+}
+
+/// Received data that the lenient mode would skip, with the context to debug it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaViolation {
+    pub command_frame: CommandFrame,
+    pub entity: Option<Uid>,
+    pub kind: ViolationKind,
+    /// The offending bytes, e.g. of the component.
+    pub bytes: Vec<u8>,
+}
+
+impl Display for SchemaViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ViolationKind::UnknownComponent(uid) => write!(f, "unknown component uid {}", uid)?,
+            ViolationKind::TrailingBytes {
+                component,
+                trailing,
+            } => write!(
+                f,
+                "{} trailing bytes after {}",
+                trailing,
+                component.unwrap_or("the packet")
+            )?,
+            ViolationKind::UnexpectedMessage(message) => write!(f, "unexpected {}", message)?,
+        }
+
+        write!(f, " at command frame {}", self.command_frame)?;
+        if let Some(entity) = self.entity {
+            write!(f, " of entity {}", entity)?;
+        }
+        writeln!(f, ", {} bytes:", self.bytes.len())?;
+
+        f.write_str(&hexdump(&self.bytes))
+    }
+}
+
+/// Client resource that turns received data the client would skip into an immediate error,
+/// enabled with `ClientWorldBuilder::with_strict_schema`.
+///
+/// Meant for development builds: an unknown component uid, trailing bytes after a decoded value
+/// or an unexpected message drop the whole message. They are raised as
+/// `ClientEvent::SchemaViolation`, with the frame, the entity and a hexdump of the offending
+/// bytes. Validating costs a decode of every value, the mode can be
+/// switched off at runtime with `set_enabled` so production builds stay lenient.
+///
+/// The values are decoded as bincode, the format of the default `SerializationStrategy`.
+#[derive(Debug, Clone)]
+pub struct StrictSchema {
+    enabled: bool,
+}
+
+impl StrictSchema {
+    pub fn new() -> StrictSchema {
+        StrictSchema { enabled: true }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Checks that all components of the update are registered and that the values decode
+    /// without trailing bytes. Changed components are diffs of a local value, only their
+    /// uid is checked.
+    pub fn validate_update(
+        &self,
+        update: &WorldState,
+        registered: &RegisteredComponentsResource,
+    ) -> Result<(), SchemaViolation> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let registry_by_uid = registered.by_uid();
+        let violation = |entity, kind, bytes: &[u8]| SchemaViolation {
+            command_frame: update.command_frame,
+            entity: Some(entity),
+            kind,
+            bytes: bytes.to_vec(),
+        };
+
+        let values = update
+            .inserted
+            .iter()
+            .flat_map(|inserted| {
+                inserted
+                    .components()
+                    .iter()
+                    .map(move |component| (inserted.entity_id(), component))
+            })
+            .chain(
+                update
+                    .component_added
+                    .iter()
+                    .map(|added| (added.entity_id(), added.component_data())),
+            );
+
+        // Values are decoded into a scratch world, the registrations only decode into worlds.
+        let mut scratch = World::default();
+
+        for (entity, component) in values {
+            let registration = registry_by_uid.get(&component.component_id()).ok_or_else(|| {
+                violation(
+                    entity,
+                    ViolationKind::UnknownComponent(component.component_id()),
+                    component.data(),
+                )
+            })?;
+
+            let mut remaining = component.data();
+            let scratch_entity = scratch.push(());
+            {
+                let deserializer =
+                    &mut bincode::Deserializer::with_reader(&mut remaining, default_options());
+                let data = &mut erased_serde::Deserializer::erase(deserializer);
+                registration.add_component(&mut scratch, scratch_entity, data);
+            }
+
+            if !remaining.is_empty() {
+                return Err(violation(
+                    entity,
+                    ViolationKind::TrailingBytes {
+                        component: Some(registration.type_name()),
+                        trailing: remaining.len(),
+                    },
+                    component.data(),
+                ));
+            }
+        }
+
+        let uids = update
+            .component_removed
+            .iter()
+            .map(|removed| (removed.entity_id(), removed.component_id(), &[][..]))
+            .chain(update.changed.iter().map(|changed| {
+                let component = changed.component_data();
+                (changed.entity_id(), component.component_id(), component.data())
+            }));
+
+        for (entity, component_uid, bytes) in uids {
+            if registry_by_uid.get(&component_uid).is_none() {
+                return Err(violation(
+                    entity,
+                    ViolationKind::UnknownComponent(component_uid),
+                    bytes,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that a packet, e.g. the initial sync, was decoded without trailing bytes.
+    pub fn validate_packet(
+        &self,
+        command_frame: CommandFrame,
+        packet: &[u8],
+        consumed: usize,
+    ) -> Result<(), SchemaViolation> {
+        if !self.enabled || consumed >= packet.len() {
+            return Ok(());
+        }
+
+        Err(SchemaViolation {
+            command_frame,
+            entity: None,
+            kind: ViolationKind::TrailingBytes {
+                component: None,
+                trailing: packet.len() - consumed,
+            },
+            bytes: packet.to_vec(),
+        })
+    }
+
+    /// Reports a message that is not expected in the current state of the client.
+    pub fn unexpected(
+        &self,
+        command_frame: CommandFrame,
+        message: &'static str,
+    ) -> Result<(), SchemaViolation> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        Err(SchemaViolation {
+            command_frame,
+            entity: None,
+            kind: ViolationKind::UnexpectedMessage(message),
+            bytes: Vec::new(),
+        })
+    }
+}
+
+impl Default for StrictSchema {
+    fn default() -> Self {
+        StrictSchema::new()
+    }
+}
+
+/// Formats the bytes as offset, 16 hex bytes and their ascii per line.
+fn hexdump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+
+    for (line, chunk) in bytes[..bytes.len().min(HEXDUMP_LIMIT)].chunks(16).enumerate() {
+        let _ = write!(dump, "{:08x} ", line * 16);
+
+        for index in 0..16 {
+            match chunk.get(index) {
+                Some(byte) => {
+                    let _ = write!(dump, " {:02x}", byte);
+                }
+                None => dump.push_str("   "),
+            }
+        }
+
+        dump.push_str("  |");
+        dump.extend(chunk.iter().map(|byte| match *byte {
+            0x20..=0x7e => *byte as char,
+            _ => '.',
+        }));
+        dump.push_str("|\n");
+    }
+
+    if bytes.len() > HEXDUMP_LIMIT {
+        let _ = writeln!(dump, "... {} more bytes", bytes.len() - HEXDUMP_LIMIT);
+    }
+
+    dump
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::any::TypeId;
+
+    use net_sync::synchronisation::{ComponentData, WorldState};
+
+    use crate::{
+        components::Region,
+        resources::{RegisteredComponentsResource, StrictSchema, ViolationKind},
+        tracking::re_exports::bincode,
+        world::default_options,
+    };
+    use bincode::Options;
+
+    #[test]
+    fn unknown_components_and_trailing_bytes_are_violations_test() {
+        let registered = RegisteredComponentsResource::new();
+        let region_uid = *registered.get_uid(&TypeId::of::<Region>()).unwrap();
+        let mut strict = StrictSchema::new();
+
+        let mut bytes = default_options().serialize(&Region::new(3)).unwrap();
+        let mut update = WorldState::new(4);
+        update.add_component(1, ComponentData::new(region_uid, bytes.clone()));
+        assert_eq!(strict.validate_update(&update, &registered), Ok(()));
+
+        bytes.push(0xff);
+        let mut update = WorldState::new(4);
+        update.add_component(1, ComponentData::new(region_uid, bytes));
+        let violation = strict.validate_update(&update, &registered).unwrap_err();
+        assert_eq!(violation.entity, Some(1));
+        assert!(matches!(
+            violation.kind,
+            ViolationKind::TrailingBytes { trailing: 1, .. }
+        ));
+        assert!(violation.to_string().contains("00000000  03 00 00 00 ff"));
+
+        let mut update = WorldState::new(5);
+        update.change(2, ComponentData::new(u32::MAX, Vec::new()));
+        assert_eq!(
+            strict.validate_update(&update, &registered).unwrap_err().kind,
+            ViolationKind::UnknownComponent(u32::MAX)
+        );
+
+        strict.set_enabled(false);
+        assert_eq!(strict.validate_update(&update, &registered), Ok(()));
+    }
+}
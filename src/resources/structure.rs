@@ -0,0 +1,143 @@
+use net_sync::{
+    synchronisation::{CommandFrame, WorldState},
+    uid::Uid,
+};
+
+/// A component the client added or removed ahead of the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StructuralChange {
+    Added,
+    /// The serialized value of the removed component, it is restored on a misprediction.
+    Removed { unchanged: Vec<u8> },
+}
+
+/// A predicted addition or removal of a component.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StructuralPrediction {
+    pub command_frame: CommandFrame,
+    pub entity_id: Uid,
+    pub component_uid: Uid,
+    pub change: StructuralChange,
+}
+
+impl StructuralPrediction {
+    /// Whether the update of the server made the same structural change.
+    pub fn is_confirmed_by(&self, update: &WorldState) -> bool {
+        match self.change {
+            StructuralChange::Added => {
+                update.component_added.iter().any(|added| {
+                    added.entity_id() == self.entity_id
+                        && added.component_data().component_id() == self.component_uid
+                }) || update.inserted.iter().any(|inserted| {
+                    inserted.entity_id() == self.entity_id
+                        && inserted
+                            .components()
+                            .iter()
+                            .any(|component| component.component_id() == self.component_uid)
+                })
+            }
+            StructuralChange::Removed { .. } => {
+                update.removed.contains(&self.entity_id)
+                    || update.component_removed.iter().any(|removed| {
+                        removed.entity_id() == self.entity_id
+                            && removed.component_id() == self.component_uid
+                    })
+            }
+        }
+    }
+}
+
+/// Client resource with the predicted additions and removals of components, enabled with
+/// `ClientWorldBuilder::with_structural_prediction`.
+///
+/// The `ClientCommandBuffer` holds the predicted component values, this resource the predicted
+/// structure, e.g. the `Carried` component added when picking up an item. Game code predicts
+/// them with `ClientWorld::predict_add_component` and `ClientWorld::predict_remove_component`.
+/// A state update validates the predictions up to its command frame: changes the server did not
+/// make are undone and the entity is resimulated.
+#[derive(Debug, Clone, Default)]
+pub struct StructuralPredictions {
+    predictions: Vec<StructuralPrediction>,
+}
+
+impl StructuralPredictions {
+    pub fn new() -> StructuralPredictions {
+        StructuralPredictions::default()
+    }
+
+    pub fn record(&mut self, prediction: StructuralPrediction) {
+        self.predictions.push(prediction);
+    }
+
+    /// The predictions that were not validated yet, oldest first.
+    pub fn pending(&self) -> impl Iterator<Item = &StructuralPrediction> + '_ {
+        self.predictions.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.predictions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.predictions.is_empty()
+    }
+
+    /// Takes the predictions up to the command frame, newest first so undoing them in order
+    /// restores the oldest state.
+    pub(crate) fn take_until(&mut self, command_frame: CommandFrame) -> Vec<StructuralPrediction> {
+        let (mut due, pending) = self
+            .predictions
+            .drain(..)
+            .partition::<Vec<_>, _>(|prediction| prediction.command_frame <= command_frame);
+
+        self.predictions = pending;
+        due.reverse();
+        due
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use net_sync::synchronisation::{ComponentData, WorldState};
+
+    use crate::resources::{StructuralChange, StructuralPrediction, StructuralPredictions};
+
+    #[test]
+    fn predictions_are_confirmed_by_the_update_of_their_frame_test() {
+        let mut predictions = StructuralPredictions::new();
+        predictions.record(StructuralPrediction {
+            command_frame: 4,
+            entity_id: 1,
+            component_uid: 7,
+            change: StructuralChange::Added,
+        });
+        predictions.record(StructuralPrediction {
+            command_frame: 5,
+            entity_id: 1,
+            component_uid: 8,
+            change: StructuralChange::Removed {
+                unchanged: Vec::new(),
+            },
+        });
+        predictions.record(StructuralPrediction {
+            command_frame: 9,
+            entity_id: 2,
+            component_uid: 7,
+            change: StructuralChange::Added,
+        });
+
+        let mut update = WorldState::new(5);
+        update.add_component(1, ComponentData::new(7, Vec::new()));
+
+        let due = predictions.take_until(5);
+        assert_eq!(due.len(), 2);
+        assert_eq!(predictions.len(), 1);
+
+        // Newest first.
+        assert!(!due[0].is_confirmed_by(&update));
+        assert!(due[1].is_confirmed_by(&update));
+
+        update.remove_component(1, 8);
+        assert!(due[0].is_confirmed_by(&update));
+    }
+}
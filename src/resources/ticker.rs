@@ -0,0 +1,319 @@
+use std::{time::Duration, vec::Drain};
+
+use net_sync::synchronisation::CommandFrame;
+
+/// The default maximum number of frames the ticker catches up after a stall.
+const DEFAULT_MAX_CATCH_UP: u32 = 5;
+
+/// What the ticker does when more than `max_catch_up` frames are owed,
+/// e.g. after a debugger pause or a hitch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StallPolicy {
+    /// Ticks `max_catch_up` owed frames, one per `try_tick`, the other frames are dropped.
+    CatchUp,
+    /// Jumps the command frame over the owed frames without simulating them, so the command
+    /// frame keeps up with the time. The server tells its clients to resync their command frame.
+    SkipFrames,
+    /// Drops the owed frames, the simulation continues as if the stall did not happen.
+    Pause,
+}
+
+impl Default for StallPolicy {
+    fn default() -> Self {
+        StallPolicy::CatchUp
+    }
+}
+
+/// Events emitted by the `CommandFrameTicker`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TickerEvent {
+    /// More frames were owed than the ticker is allowed to catch up, the rest was skipped.
+    FramesSkipped {
+        command_frame: CommandFrame,
+        skipped: u32,
+    },
+    /// The command frame jumped over frames that were not simulated, see `StallPolicy::SkipFrames`.
+    FramesJumped {
+        from: CommandFrame,
+        to: CommandFrame,
+    },
+    /// The owed frames were dropped, see `StallPolicy::Pause`.
+    Paused {
+        command_frame: CommandFrame,
+        dropped: u32,
+    },
+}
+
+/// Ticks command frames at a fixed simulation speed (frames per second).
+///
+/// The ticker does not read time itself, the current time is passed in from the `ClockResource`.
+/// Elapsed time is accumulated in nanoseconds, so the remainder of a frame carries over to the next
+/// and the tick rate does not drift. After a stall at most `max_catch_up` owed frames are ticked,
+/// one per `try_tick`, the other frames are skipped and reported as `TickerEvent::FramesSkipped`.
+/// Other `StallPolicy`s skip or drop all owed frames instead.
+pub struct CommandFrameTicker {
+    default_simulation_speed: f32,
+    simulation_speed: f32,
+    frame_duration: u64,
+    command_frame: CommandFrame,
+    last_update: Option<Duration>,
+    accumulator: u64,
+    max_catch_up: u32,
+    stall_policy: StallPolicy,
+    events: Vec<TickerEvent>,
+}
+
+impl CommandFrameTicker {
+    pub fn new(simulation_speed: f32) -> CommandFrameTicker {
+        CommandFrameTicker {
+            default_simulation_speed: simulation_speed,
+            simulation_speed,
+            frame_duration: frame_duration(simulation_speed),
+            command_frame: 0,
+            last_update: None,
+            accumulator: 0,
+            max_catch_up: DEFAULT_MAX_CATCH_UP,
+            stall_policy: StallPolicy::default(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Sets the maximum number of owed frames that are ticked after a stall.
+    pub fn with_max_catch_up(mut self, max_catch_up: u32) -> Self {
+        self.max_catch_up = max_catch_up.max(1);
+        self
+    }
+
+    /// Sets what happens when more than `max_catch_up` frames are owed.
+    pub fn with_stall_policy(mut self, policy: StallPolicy) -> Self {
+        self.stall_policy = policy;
+        self
+    }
+
+    /// Advances the command frame if a frame duration of time is accumulated.
+    pub fn try_tick(&mut self, now: Duration) -> bool {
+        let last_update = match self.last_update {
+            Some(last_update) => last_update,
+            None => {
+                // The first call starts the simulation.
+                self.last_update = Some(now);
+                self.command_frame += 1;
+                return true;
+            }
+        };
+
+        let elapsed = now.checked_sub(last_update).unwrap_or_default();
+        self.last_update = Some(now.max(last_update));
+        self.accumulator += elapsed.as_nanos() as u64;
+
+        let owed = (self.accumulator / self.frame_duration) as u32;
+
+        if owed > self.max_catch_up {
+            // Under every policy the frame of this call is still ticked.
+            let skipped = match self.stall_policy {
+                StallPolicy::CatchUp => owed - self.max_catch_up,
+                StallPolicy::SkipFrames | StallPolicy::Pause => owed - 1,
+            };
+            self.accumulator -= skipped as u64 * self.frame_duration;
+
+            self.events.push(match self.stall_policy {
+                StallPolicy::CatchUp => TickerEvent::FramesSkipped {
+                    command_frame: self.command_frame,
+                    skipped,
+                },
+                StallPolicy::SkipFrames => {
+                    let from = self.command_frame;
+                    self.command_frame += skipped;
+                    TickerEvent::FramesJumped {
+                        from,
+                        to: self.command_frame,
+                    }
+                }
+                StallPolicy::Pause => TickerEvent::Paused {
+                    command_frame: self.command_frame,
+                    dropped: skipped,
+                },
+            });
+        }
+
+        if self.accumulator >= self.frame_duration {
+            self.accumulator -= self.frame_duration;
+            self.command_frame += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn command_frame(&self) -> CommandFrame {
+        self.command_frame
+    }
+
+    pub fn set_command_frame(&mut self, command_frame: CommandFrame) {
+        self.command_frame = command_frame;
+    }
+
+    /// The duration of a command frame at the current simulation speed.
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_nanos(self.frame_duration)
+    }
+
+    pub fn default_simulation_speed(&self) -> f32 {
+        self.default_simulation_speed
+    }
+
+    pub fn simulation_speed(&self) -> f32 {
+        self.simulation_speed
+    }
+
+    /// Sets the simulation speed in frames per second.
+    ///
+    /// The accumulated time is kept, only the duration of the following frames changes.
+    pub fn adjust_simulation(&mut self, simulation_speed: f32) {
+        self.simulation_speed = simulation_speed;
+        self.frame_duration = frame_duration(simulation_speed);
+    }
+
+    pub fn max_catch_up(&self) -> u32 {
+        self.max_catch_up
+    }
+
+    pub fn stall_policy(&self) -> StallPolicy {
+        self.stall_policy
+    }
+
+    pub fn set_stall_policy(&mut self, policy: StallPolicy) {
+        self.stall_policy = policy;
+    }
+
+    /// Returns the events emitted since they were last drained.
+    pub fn drain_events(&mut self) -> Drain<'_, TickerEvent> {
+        self.events.drain(..)
+    }
+}
+
+/// The duration of a frame in nanoseconds.
+fn frame_duration(simulation_speed: f32) -> u64 {
+    ((1_000_000_000. / simulation_speed as f64).round() as u64).max(1)
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::time::Duration;
+
+    use crate::resources::{Clock, CommandFrameTicker, ManualClock, StallPolicy, TickerEvent};
+
+    #[test]
+    fn ticks_once_per_frame_duration_test() {
+        let clock = ManualClock::new();
+        let mut ticker = CommandFrameTicker::new(10.);
+
+        assert!(ticker.try_tick(clock.now()));
+        assert!(!ticker.try_tick(clock.now()));
+
+        clock.advance(Duration::from_millis(50));
+        assert!(!ticker.try_tick(clock.now()));
+
+        clock.advance(Duration::from_millis(50));
+        assert!(ticker.try_tick(clock.now()));
+        assert_eq!(ticker.command_frame(), 2);
+    }
+
+    #[test]
+    fn adjusted_speed_changes_frame_duration_test() {
+        let clock = ManualClock::new();
+        let mut ticker = CommandFrameTicker::new(10.);
+        ticker.adjust_simulation(20.);
+
+        assert!(ticker.try_tick(clock.now()));
+        clock.advance(Duration::from_millis(50));
+        assert!(ticker.try_tick(clock.now()));
+        assert_eq!(ticker.default_simulation_speed(), 10.);
+    }
+
+    #[test]
+    fn remainder_carries_over_without_drift_test() {
+        let clock = ManualClock::new();
+        let mut ticker = CommandFrameTicker::new(30.);
+        let mut ticks = 0;
+
+        for _ in 0..=1000 {
+            if ticker.try_tick(clock.now()) {
+                ticks += 1;
+            }
+            clock.advance(Duration::from_millis(1));
+        }
+
+        // The first tick at 0ms plus 30 ticks for one second.
+        assert_eq!(ticks, 31);
+    }
+
+    #[test]
+    fn stall_catches_up_limited_frames_test() {
+        let clock = ManualClock::new();
+        let mut ticker = CommandFrameTicker::new(10.).with_max_catch_up(3);
+
+        assert!(ticker.try_tick(clock.now()));
+        clock.advance(Duration::from_secs(1));
+
+        assert!(ticker.try_tick(clock.now()));
+        assert!(ticker.try_tick(clock.now()));
+        assert!(ticker.try_tick(clock.now()));
+        assert!(!ticker.try_tick(clock.now()));
+        assert_eq!(ticker.command_frame(), 4);
+
+        assert_eq!(
+            ticker.drain_events().collect::<Vec<TickerEvent>>(),
+            vec![TickerEvent::FramesSkipped {
+                command_frame: 1,
+                skipped: 7
+            }]
+        );
+    }
+
+    #[test]
+    fn stall_policies_test() {
+        let clock = ManualClock::new();
+        let mut skipping = CommandFrameTicker::new(10.)
+            .with_max_catch_up(3)
+            .with_stall_policy(StallPolicy::SkipFrames);
+        let mut pausing = CommandFrameTicker::new(10.)
+            .with_max_catch_up(3)
+            .with_stall_policy(StallPolicy::Pause);
+
+        assert!(skipping.try_tick(clock.now()));
+        assert!(pausing.try_tick(clock.now()));
+        clock.advance(Duration::from_secs(1));
+
+        assert!(skipping.try_tick(clock.now()));
+        assert!(!skipping.try_tick(clock.now()));
+        assert_eq!(skipping.command_frame(), 11);
+        assert_eq!(
+            skipping.drain_events().collect::<Vec<TickerEvent>>(),
+            vec![TickerEvent::FramesJumped { from: 1, to: 10 }]
+        );
+
+        assert!(pausing.try_tick(clock.now()));
+        assert!(!pausing.try_tick(clock.now()));
+        assert_eq!(pausing.command_frame(), 2);
+        assert_eq!(
+            pausing.drain_events().collect::<Vec<TickerEvent>>(),
+            vec![TickerEvent::Paused {
+                command_frame: 1,
+                dropped: 9
+            }]
+        );
+    }
+
+    #[test]
+    fn clock_going_backwards_does_not_tick_test() {
+        let clock = ManualClock::new();
+        clock.set(Duration::from_secs(1));
+        let mut ticker = CommandFrameTicker::new(10.);
+
+        assert!(ticker.try_tick(clock.now()));
+        clock.set(Duration::from_millis(500));
+        assert!(!ticker.try_tick(clock.now()));
+        assert_eq!(ticker.drain_events().count(), 0);
+    }
+}
@@ -0,0 +1,312 @@
+use std::{any::TypeId, collections::HashMap};
+
+use legion::{query::IntoQuery, storage::Component, Entity, World};
+use serde::Deserialize;
+
+use net_sync::track_attr::serde_diff::SerdeDiff;
+
+use crate::register::{self, DiffPolicy};
+
+/// Client resource with the conversions of server component types into client component types.
+///
+/// A server component type with a transform is not added to the client world,
+/// its converted client type is added instead, e.g. a server `PhysicsBody` as a `RenderTransform`.
+/// The server type has to be registered on the client too, its uid and bytes are used to decode it.
+///
+/// ```ignore
+/// ClientWorldBuilder::default()
+///     .with_component_transform(|body: &PhysicsBody| RenderTransform::from(body.position));
+/// ```
+///
+/// World snapshots (`ClientWorldBuilder::with_history`) only include registered component types.
+#[derive(Default)]
+pub struct ComponentTransforms {
+    transforms: HashMap<TypeId, Box<dyn ErasedTransform>>,
+}
+
+impl ComponentTransforms {
+    pub fn new() -> ComponentTransforms {
+        ComponentTransforms::default()
+    }
+
+    /// Registers the conversion of server type `S` into client type `T`.
+    pub fn register<S, T>(&mut self, convert: fn(&S) -> T)
+    where
+        S: Clone + SerdeDiff + for<'de> Deserialize<'de> + Send + Sync + 'static,
+        T: Component,
+    {
+        self.transforms.insert(
+            TypeId::of::<S>(),
+            Box::new(Transform {
+                convert,
+                shadows: HashMap::new(),
+            }),
+        );
+    }
+
+    /// Returns whether the server type has a transform.
+    pub fn contains(&self, server_type: &TypeId) -> bool {
+        self.transforms.contains_key(server_type)
+    }
+
+    /// Adds the converted client component of the serialized server component to the entity.
+    ///
+    /// Returns `false` if the server type has no transform.
+    pub fn add_component(
+        &mut self,
+        server_type: &TypeId,
+        world: &mut World,
+        entity: Entity,
+        data: &mut dyn erased_serde::Deserializer,
+    ) -> bool {
+        match self.transforms.get_mut(server_type) {
+            Some(transform) => {
+                transform.add_component(world, entity, data);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Applies the serialized server difference and converts the changed server component again.
+    ///
+    /// Returns `false` if the server type has no transform.
+    pub fn apply_changes(
+        &mut self,
+        server_type: &TypeId,
+        world: &mut World,
+        entity: Entity,
+        data: &mut dyn erased_serde::Deserializer,
+        policy: DiffPolicy,
+    ) -> bool {
+        match self.transforms.get_mut(server_type) {
+            Some(transform) => {
+                transform.apply_changes(world, entity, data, policy);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes the converted client component from the entity.
+    ///
+    /// Returns `false` if the server type has no transform.
+    pub fn remove_component(
+        &mut self,
+        server_type: &TypeId,
+        world: &mut World,
+        entity: Entity,
+    ) -> bool {
+        match self.transforms.get_mut(server_type) {
+            Some(transform) => {
+                transform.remove_component(world, entity);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forgets the server components of a removed entity.
+    pub fn remove_entity(&mut self, entity: Entity) {
+        for transform in self.transforms.values_mut() {
+            transform.forget(entity);
+        }
+    }
+
+    /// Replaces the server components in the world by their client components,
+    /// used after the initial state sync was merged into the world.
+    pub fn convert_world(&mut self, world: &mut World) {
+        for transform in self.transforms.values_mut() {
+            transform.convert_world(world);
+        }
+    }
+}
+
+/// Type erased `Transform`, so transforms of different types can be stored together.
+trait ErasedTransform: Send + Sync {
+    fn add_component(
+        &mut self,
+        world: &mut World,
+        entity: Entity,
+        data: &mut dyn erased_serde::Deserializer,
+    );
+
+    fn apply_changes(
+        &mut self,
+        world: &mut World,
+        entity: Entity,
+        data: &mut dyn erased_serde::Deserializer,
+        policy: DiffPolicy,
+    );
+
+    fn remove_component(&mut self, world: &mut World, entity: Entity);
+
+    fn forget(&mut self, entity: Entity);
+
+    fn convert_world(&mut self, world: &mut World);
+}
+
+struct Transform<S, T> {
+    convert: fn(&S) -> T,
+    // The last received server component of each entity, differences are applied to it.
+    shadows: HashMap<Entity, S>,
+}
+
+impl<S, T> Transform<S, T>
+where
+    S: Clone + SerdeDiff + for<'de> Deserialize<'de> + Send + Sync + 'static,
+    T: Component,
+{
+    fn set(&mut self, world: &mut World, entity: Entity, server: S) {
+        let client = (self.convert)(&server);
+
+        if let Some(mut entry) = world.entry(entity) {
+            entry.add_component(client);
+            self.shadows.insert(entity, server);
+        }
+    }
+}
+
+impl<S, T> ErasedTransform for Transform<S, T>
+where
+    S: Clone + SerdeDiff + for<'de> Deserialize<'de> + Send + Sync + 'static,
+    T: Component,
+{
+    fn add_component(
+        &mut self,
+        world: &mut World,
+        entity: Entity,
+        data: &mut dyn erased_serde::Deserializer,
+    ) {
+        let server = erased_serde::deserialize::<S>(data).expect("failed to deserialize component");
+        self.set(world, entity, server);
+    }
+
+    fn apply_changes(
+        &mut self,
+        world: &mut World,
+        entity: Entity,
+        data: &mut dyn erased_serde::Deserializer,
+        policy: DiffPolicy,
+    ) {
+        let mut server = match self.shadows.remove(&entity) {
+            Some(server) => server,
+            None => return,
+        };
+
+        register::apply_changes(&mut server, data, policy);
+
+        self.set(world, entity, server);
+    }
+
+    fn remove_component(&mut self, world: &mut World, entity: Entity) {
+        self.shadows.remove(&entity);
+
+        if let Some(mut entry) = world.entry(entity) {
+            entry.remove_component::<T>();
+        }
+    }
+
+    fn forget(&mut self, entity: Entity) {
+        self.shadows.remove(&entity);
+    }
+
+    fn convert_world(&mut self, world: &mut World) {
+        let synced = <(Entity, &S)>::query()
+            .iter(world)
+            .map(|(entity, server)| (*entity, server.clone()))
+            .collect::<Vec<(Entity, S)>>();
+
+        for (entity, server) in synced {
+            if let Some(mut entry) = world.entry(entity) {
+                entry.remove_component::<S>();
+            }
+
+            self.set(world, entity, server);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::any::TypeId;
+
+    use legion::{world::EntityStore, Entity, World};
+
+    use net_sync::track_attr::serde_diff::{Config, FieldPathMode};
+
+    use crate::{
+        register::DiffPolicy,
+        resources::ComponentTransforms,
+        tracking::{re_exports::serde_diff::*, track_attr::*},
+        world::default_options,
+    };
+
+    #[derive(Clone, Default, Debug, Serialize, Deserialize, SerdeDiff, PartialEq)]
+    struct PhysicsBody {
+        position: i32,
+        velocity: i32,
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct RenderTransform(i32);
+
+    fn transforms() -> ComponentTransforms {
+        let mut transforms = ComponentTransforms::new();
+        transforms.register(|body: &PhysicsBody| RenderTransform(body.position));
+        transforms
+    }
+
+    #[test]
+    fn transform_converts_world_and_changes_test() {
+        let mut transforms = transforms();
+        let mut world = World::default();
+        let entity = world.push((PhysicsBody {
+            position: 1,
+            velocity: 2,
+        },));
+
+        transforms.convert_world(&mut world);
+
+        let render = |world: &World, entity: Entity| {
+            world
+                .entry_ref(entity)
+                .unwrap()
+                .get_component::<RenderTransform>()
+                .map(|transform| transform.0)
+                .ok()
+        };
+        assert_eq!(render(&world, entity), Some(1));
+        assert!(world
+            .entry_ref(entity)
+            .unwrap()
+            .get_component::<PhysicsBody>()
+            .is_err());
+
+        let changed = PhysicsBody {
+            position: 5,
+            velocity: 2,
+        };
+        let diff = Config::new()
+            .with_field_path_mode(FieldPathMode::Index)
+            .serializable_diff(
+                &PhysicsBody {
+                    position: 1,
+                    velocity: 2,
+                },
+                &changed,
+            );
+        let bytes = bincode::serialize(&diff).unwrap();
+
+        let mut deserializer = bincode::Deserializer::from_slice(&bytes, default_options());
+        assert!(transforms.apply_changes(
+            &TypeId::of::<PhysicsBody>(),
+            &mut world,
+            entity,
+            &mut erased_serde::Deserializer::erase(&mut deserializer),
+            DiffPolicy::Diff,
+        ));
+
+        assert_eq!(render(&world, entity), Some(5));
+    }
+}
@@ -0,0 +1,632 @@
+use std::{
+    collections::HashMap,
+    io, mem,
+    net::{SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use net_sync::transport::{self, ClientId};
+
+use crate::tracking::re_exports::bincode;
+
+/// Payload bytes of one fragment, a datagram stays below the common internet MTU.
+const MAX_FRAGMENT_SIZE: usize = 1100;
+/// Acknowledgements carried by one datagram.
+const MAX_ACKS: usize = 64;
+/// Incomplete unreliable messages this many sequences behind the newest fragment are dropped.
+const PARTIAL_WINDOW: u32 = 64;
+
+/// The delivery guarantee of a message sent over the UDP transport.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Reliability {
+    /// Sent once, may be lost or arrive out of order.
+    Unreliable,
+    /// Sent once, may be lost, a message older than the last delivered one is dropped.
+    UnreliableSequenced,
+    /// Sent until acknowledged, delivered once and in order.
+    Reliable,
+}
+
+/// Configuration of the UDP transport, set with `ServerWorldBuilder::with_udp_config` and
+/// `ClientWorldBuilder::with_udp_config`.
+///
+/// Every reliability has its own sequence, a lost unreliable state update does not hold back
+/// the reliable messages like it would on TCP. State updates are reliable by default:
+/// they contain the inserts, removals and field diffs since the previous update, which are lost
+/// with an unreliable update. Make them unreliable only when the synchronized components
+/// use `DiffPolicy::Whole` and the entities are not inserted and removed while replicated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UdpConfig {
+    /// The reliability of the state updates of the server.
+    pub state_updates: Reliability,
+    /// The reliability of the initial state sync, the client waits for it.
+    pub initial_sync: Reliability,
+    /// The reliability of the user messages, in both directions.
+    pub messages: Reliability,
+    /// The reliability of the client commands.
+    pub commands: Reliability,
+    /// Time after which an unacknowledged reliable fragment is sent again.
+    pub resend_after: Duration,
+    /// Time after which an empty datagram is sent when there is nothing else to send.
+    pub keepalive: Duration,
+    /// Time without datagrams after which the server drops the client.
+    pub timeout: Duration,
+}
+
+impl Default for UdpConfig {
+    fn default() -> Self {
+        UdpConfig {
+            state_updates: Reliability::Reliable,
+            initial_sync: Reliability::Reliable,
+            messages: Reliability::Reliable,
+            commands: Reliability::Reliable,
+            resend_after: Duration::from_millis(100),
+            keepalive: Duration::from_secs(1),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl UdpConfig {
+    pub(crate) fn server_reliability<ServerToClientMessage>(
+        &self,
+        message: &transport::ServerToClientMessage<ServerToClientMessage>,
+    ) -> Reliability {
+        match message {
+            transport::ServerToClientMessage::Message(_) => self.messages,
+            transport::ServerToClientMessage::StateUpdate(_) => self.state_updates,
+            transport::ServerToClientMessage::InitialStateSync(_) => self.initial_sync,
+        }
+    }
+
+    pub(crate) fn client_reliability<ClientToServerMessage, ClientToServerCommand>(
+        &self,
+        message: &transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+    ) -> Reliability {
+        match message {
+            transport::ClientToServerMessage::Message(_) => self.messages,
+            transport::ClientToServerMessage::Command(_, _) => self.commands,
+        }
+    }
+}
+
+/// The reliable fragments are acknowledged by their sequence and index.
+type FragmentId = (u32, u16);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Fragment {
+    reliability: Reliability,
+    sequence: u32,
+    index: u16,
+    count: u16,
+    payload: Vec<u8>,
+}
+
+/// A datagram without fragment and acknowledgements is a keepalive.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Datagram {
+    acks: Vec<FragmentId>,
+    fragment: Option<Fragment>,
+}
+
+/// Returns whether sequence `a` is newer than `b`, also when the sequence wrapped around.
+fn is_newer(a: u32, b: u32) -> bool {
+    a != b && a.wrapping_sub(b) < u32::MAX / 2
+}
+
+/// The reliability layer of one peer of the UDP transport.
+///
+/// Messages are split in fragments that fit in a datagram. Reliable fragments are sent again
+/// until they are acknowledged, the acknowledgements are carried by the outgoing datagrams.
+pub(crate) struct UdpConnection {
+    next_sequence: HashMap<Reliability, u32>,
+    queued: Vec<Fragment>,
+    unacked: HashMap<FragmentId, (Fragment, Duration)>,
+    acks: Vec<FragmentId>,
+    partial: HashMap<(Reliability, u32), Vec<Option<Vec<u8>>>>,
+    last_sequenced: Option<u32>,
+    next_reliable: u32,
+    reliable_ready: HashMap<u32, Vec<u8>>,
+    last_sent: Option<Duration>,
+    last_received: Duration,
+}
+
+impl UdpConnection {
+    pub(crate) fn new(now: Duration) -> UdpConnection {
+        UdpConnection {
+            next_sequence: HashMap::new(),
+            queued: Vec::new(),
+            unacked: HashMap::new(),
+            acks: Vec::new(),
+            partial: HashMap::new(),
+            last_sequenced: None,
+            next_reliable: 0,
+            reliable_ready: HashMap::new(),
+            last_sent: None,
+            last_received: now,
+        }
+    }
+
+    pub(crate) fn last_received(&self) -> Duration {
+        self.last_received
+    }
+
+    /// Queues the message, it is sent by the next `poll`.
+    pub(crate) fn queue(&mut self, reliability: Reliability, payload: &[u8], now: Duration) {
+        let sequence = self.next_sequence.entry(reliability).or_insert(0);
+
+        let chunks = if payload.is_empty() {
+            vec![payload]
+        } else {
+            payload.chunks(MAX_FRAGMENT_SIZE).collect()
+        };
+        let count = chunks.len() as u16;
+
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            let fragment = Fragment {
+                reliability,
+                sequence: *sequence,
+                index: index as u16,
+                count,
+                payload: chunk.to_vec(),
+            };
+
+            if reliability == Reliability::Reliable {
+                self.unacked
+                    .insert((fragment.sequence, fragment.index), (fragment.clone(), now));
+            }
+            self.queued.push(fragment);
+        }
+
+        *sequence = sequence.wrapping_add(1);
+    }
+
+    /// Returns the datagrams to send: the queued fragments, the reliable fragments that were not
+    /// acknowledged within `resend_after`, the acknowledgements and a keepalive when idle.
+    pub(crate) fn poll(
+        &mut self,
+        now: Duration,
+        resend_after: Duration,
+        keepalive: Duration,
+    ) -> Vec<Vec<u8>> {
+        let mut fragments = mem::take(&mut self.queued);
+
+        for (fragment, sent_at) in self.unacked.values_mut() {
+            if now.saturating_sub(*sent_at) >= resend_after {
+                *sent_at = now;
+                fragments.push(fragment.clone());
+            }
+        }
+
+        let mut datagrams = fragments
+            .into_iter()
+            .map(|fragment| Datagram {
+                acks: Vec::new(),
+                fragment: Some(fragment),
+            })
+            .collect::<Vec<Datagram>>();
+
+        for (i, acks) in mem::take(&mut self.acks).chunks(MAX_ACKS).enumerate() {
+            match datagrams.get_mut(i) {
+                Some(datagram) => datagram.acks = acks.to_vec(),
+                None => datagrams.push(Datagram {
+                    acks: acks.to_vec(),
+                    fragment: None,
+                }),
+            }
+        }
+
+        let idle = self
+            .last_sent
+            .map_or(true, |last_sent| now.saturating_sub(last_sent) >= keepalive);
+        if datagrams.is_empty() && idle {
+            datagrams.push(Datagram::default());
+        }
+
+        if !datagrams.is_empty() {
+            self.last_sent = Some(now);
+        }
+
+        datagrams
+            .iter()
+            .map(|datagram| bincode::serialize(datagram).expect("Datagram is serializable."))
+            .collect()
+    }
+
+    /// Handles a received datagram, returns the messages that are complete, in delivery order.
+    pub(crate) fn receive(&mut self, bytes: &[u8], now: Duration) -> Vec<Vec<u8>> {
+        let datagram = match bincode::deserialize::<Datagram>(bytes) {
+            Ok(datagram) => datagram,
+            Err(e) => {
+                log::warn!("Dropped malformed datagram: {}", e);
+                return Vec::new();
+            }
+        };
+
+        self.last_received = now;
+
+        for ack in datagram.acks {
+            self.unacked.remove(&ack);
+        }
+
+        let fragment = match datagram.fragment {
+            Some(fragment) => fragment,
+            None => return Vec::new(),
+        };
+        let reliability = fragment.reliability;
+        let sequence = fragment.sequence;
+
+        match reliability {
+            Reliability::Unreliable => {}
+            Reliability::UnreliableSequenced => {
+                if let Some(last) = self.last_sequenced {
+                    if !is_newer(sequence, last) {
+                        return Vec::new();
+                    }
+                }
+            }
+            Reliability::Reliable => {
+                // Acknowledged again when the acknowledgement was lost.
+                self.acks.push((sequence, fragment.index));
+
+                if is_newer(self.next_reliable, sequence)
+                    || self.reliable_ready.contains_key(&sequence)
+                {
+                    return Vec::new();
+                }
+            }
+        }
+
+        let payload = match self.assemble(fragment) {
+            Some(payload) => payload,
+            None => return Vec::new(),
+        };
+
+        match reliability {
+            Reliability::Unreliable => vec![payload],
+            Reliability::UnreliableSequenced => {
+                self.last_sequenced = Some(sequence);
+                let last = sequence;
+                self.partial.retain(|(reliability, sequence), _| {
+                    *reliability != Reliability::UnreliableSequenced || is_newer(*sequence, last)
+                });
+                vec![payload]
+            }
+            Reliability::Reliable => {
+                self.reliable_ready.insert(sequence, payload);
+
+                let mut delivered = Vec::new();
+                while let Some(payload) = self.reliable_ready.remove(&self.next_reliable) {
+                    delivered.push(payload);
+                    self.next_reliable = self.next_reliable.wrapping_add(1);
+                }
+                delivered
+            }
+        }
+    }
+
+    fn assemble(&mut self, fragment: Fragment) -> Option<Vec<u8>> {
+        if fragment.count == 1 {
+            return Some(fragment.payload);
+        }
+
+        // Unreliable messages with a lost fragment are never completed.
+        if fragment.reliability != Reliability::Reliable {
+            let (channel, newest) = (fragment.reliability, fragment.sequence);
+            self.partial.retain(|(reliability, sequence), _| {
+                *reliability != channel || is_newer(sequence.wrapping_add(PARTIAL_WINDOW), newest)
+            });
+        }
+
+        let key = (fragment.reliability, fragment.sequence);
+        let parts = self
+            .partial
+            .entry(key)
+            .or_insert_with(|| vec![None; fragment.count as usize]);
+
+        if let Some(part) = parts.get_mut(fragment.index as usize) {
+            *part = Some(fragment.payload);
+        }
+
+        if parts.iter().all(Option::is_some) {
+            let parts = self.partial.remove(&key).unwrap();
+            Some(parts.into_iter().flatten().flatten().collect())
+        } else {
+            None
+        }
+    }
+}
+
+/// Client resource of the UDP transport, the socket is connected to the server.
+pub struct UdpClientResource {
+    socket: UdpSocket,
+    connection: UdpConnection,
+    config: UdpConfig,
+}
+
+impl UdpClientResource {
+    /// Binds a socket on an ephemeral port and connects it to the server,
+    /// `now` is the time of the `ClockResource`.
+    pub fn new(
+        addr: SocketAddr,
+        config: UdpConfig,
+        now: Duration,
+    ) -> io::Result<UdpClientResource> {
+        let local: SocketAddr = if addr.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(UdpClientResource {
+            socket,
+            connection: UdpConnection::new(now),
+            config,
+        })
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    pub fn config(&self) -> &UdpConfig {
+        &self.config
+    }
+
+    /// Queues the message for the server, it is sent by `flush`.
+    pub fn send<T: Serialize>(&mut self, reliability: Reliability, message: &T, now: Duration) {
+        match bincode::serialize(message) {
+            Ok(payload) => self.connection.queue(reliability, &payload, now),
+            Err(e) => log::error!("Failed to serialize message: {}", e),
+        }
+    }
+
+    /// Sends the queued messages, resends and acknowledgements.
+    pub fn flush(&mut self, now: Duration) {
+        let datagrams = self
+            .connection
+            .poll(now, self.config.resend_after, self.config.keepalive);
+
+        for datagram in datagrams {
+            if let Err(e) = self.socket.send(&datagram) {
+                if e.kind() != io::ErrorKind::WouldBlock {
+                    log::error!("Failed to send datagram: {}", e);
+                }
+                // Reliable fragments are sent again, the others are lost like on the network.
+                break;
+            }
+        }
+    }
+
+    /// Reads the pending datagrams, returns the messages that are complete.
+    pub fn receive<T: DeserializeOwned>(
+        &mut self,
+        recv_buffer: &mut [u8],
+        now: Duration,
+    ) -> Vec<T> {
+        let mut messages = Vec::new();
+
+        loop {
+            let len = match self.socket.recv(recv_buffer) {
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::error!("Failed to receive datagram: {}", e);
+                    break;
+                }
+            };
+
+            for payload in self.connection.receive(&recv_buffer[..len], now) {
+                match bincode::deserialize(&payload) {
+                    Ok(message) => messages.push(message),
+                    Err(e) => log::error!("Failed to deserialize message: {}", e),
+                }
+            }
+        }
+
+        messages
+    }
+}
+
+/// Server resource of the UDP transport.
+///
+/// A client is accepted on its first datagram and dropped after `UdpConfig::timeout`
+/// without datagrams.
+pub struct UdpServerResource {
+    socket: UdpSocket,
+    clients: HashMap<SocketAddr, ClientId>,
+    connections: HashMap<ClientId, (SocketAddr, UdpConnection)>,
+    config: UdpConfig,
+}
+
+impl UdpServerResource {
+    pub fn new(socket: UdpSocket, config: UdpConfig) -> UdpServerResource {
+        UdpServerResource {
+            socket,
+            clients: HashMap::new(),
+            connections: HashMap::new(),
+            config,
+        }
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    pub fn config(&self) -> &UdpConfig {
+        &self.config
+    }
+
+    /// Returns the client that sends from the address.
+    pub fn client(&self, addr: &SocketAddr) -> Option<ClientId> {
+        self.clients.get(addr).copied()
+    }
+
+    /// Binds the address to the client, the datagrams from the address are now from the client.
+    pub fn accept(&mut self, addr: SocketAddr, client: ClientId, now: Duration) {
+        self.clients.insert(addr, client);
+        self.connections
+            .insert(client, (addr, UdpConnection::new(now)));
+    }
+
+    /// Queues the message for the client, it is sent by `flush`.
+    pub fn send<T: Serialize>(
+        &mut self,
+        client: ClientId,
+        reliability: Reliability,
+        message: &T,
+        now: Duration,
+    ) {
+        let connection = match self.connections.get_mut(&client) {
+            Some((_, connection)) => connection,
+            None => return,
+        };
+
+        match bincode::serialize(message) {
+            Ok(payload) => connection.queue(reliability, &payload, now),
+            Err(e) => log::error!("Failed to serialize message: {}", e),
+        }
+    }
+
+    /// Sends the queued messages, resends and acknowledgements of all clients.
+    pub fn flush(&mut self, now: Duration) {
+        for (addr, connection) in self.connections.values_mut() {
+            for datagram in connection.poll(now, self.config.resend_after, self.config.keepalive) {
+                if let Err(e) = self.socket.send_to(&datagram, *addr) {
+                    if e.kind() != io::ErrorKind::WouldBlock {
+                        log::error!("Failed to send datagram to {}: {}", addr, e);
+                    }
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Reads the pending datagrams, returns the messages that are complete with the address
+    /// they were sent from. Datagrams from addresses that are not accepted are returned as
+    /// `None`, accept the address to receive its messages.
+    pub fn receive<T: DeserializeOwned>(
+        &mut self,
+        recv_buffer: &mut [u8],
+        now: Duration,
+    ) -> Vec<(SocketAddr, Option<T>)> {
+        let mut messages = Vec::new();
+
+        loop {
+            let (len, addr) = match self.socket.recv_from(recv_buffer) {
+                Ok(received) => received,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::error!("Failed to receive datagram: {}", e);
+                    break;
+                }
+            };
+
+            let connection = match self.clients.get(&addr) {
+                Some(client) => &mut self.connections.get_mut(client).unwrap().1,
+                None => {
+                    // The first datagram is a keepalive or resent later.
+                    messages.push((addr, None));
+                    continue;
+                }
+            };
+
+            for payload in connection.receive(&recv_buffer[..len], now) {
+                match bincode::deserialize(&payload) {
+                    Ok(message) => messages.push((addr, Some(message))),
+                    Err(e) => log::error!("Failed to deserialize message from {}: {}", addr, e),
+                }
+            }
+        }
+
+        messages
+    }
+
+    /// Forgets the clients that did not send a datagram within `UdpConfig::timeout`
+    /// and returns them.
+    pub fn drop_timed_out(&mut self, now: Duration) -> Vec<ClientId> {
+        let timeout = self.config.timeout;
+
+        let timed_out = self
+            .connections
+            .iter()
+            .filter(|(_, (_, connection))| {
+                now.saturating_sub(connection.last_received()) >= timeout
+            })
+            .map(|(client, _)| *client)
+            .collect::<Vec<ClientId>>();
+
+        for client in timed_out.iter() {
+            if let Some((addr, _)) = self.connections.remove(client) {
+                self.clients.remove(&addr);
+            }
+        }
+
+        timed_out
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::time::Duration;
+
+    use crate::resources::udp::{Reliability, UdpConnection, MAX_FRAGMENT_SIZE};
+
+    const RESEND: Duration = Duration::from_millis(100);
+    const KEEPALIVE: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn lost_reliable_fragments_are_resent_and_delivered_in_order_test() {
+        let now = Duration::from_secs(1);
+        let mut sender = UdpConnection::new(now);
+        let mut receiver = UdpConnection::new(now);
+
+        let big = vec![7; MAX_FRAGMENT_SIZE * 2 + 1];
+        sender.queue(Reliability::Reliable, &big, now);
+        sender.queue(Reliability::Reliable, &[1], now);
+
+        // The second fragment of the first message is lost.
+        let datagrams = sender.poll(now, RESEND, KEEPALIVE);
+        assert_eq!(datagrams.len(), 4);
+        assert!(receiver.receive(&datagrams[0], now).is_empty());
+        assert!(receiver.receive(&datagrams[2], now).is_empty());
+        assert!(receiver.receive(&datagrams[3], now).is_empty());
+
+        for datagram in receiver.poll(now, RESEND, KEEPALIVE) {
+            sender.receive(&datagram, now);
+        }
+
+        let later = now + RESEND;
+        let resent = sender.poll(later, RESEND, KEEPALIVE);
+        assert_eq!(resent.len(), 1);
+        assert_eq!(receiver.receive(&resent[0], later), vec![big, vec![1]]);
+
+        // Duplicates of delivered messages are dropped.
+        assert!(receiver.receive(&datagrams[3], later).is_empty());
+    }
+
+    #[test]
+    fn sequenced_messages_drop_older_ones_test() {
+        let now = Duration::from_secs(1);
+        let mut sender = UdpConnection::new(now);
+        let mut receiver = UdpConnection::new(now);
+
+        sender.queue(Reliability::UnreliableSequenced, &[1], now);
+        sender.queue(Reliability::UnreliableSequenced, &[2], now);
+        sender.queue(Reliability::Unreliable, &[3], now);
+
+        let datagrams = sender.poll(now, RESEND, KEEPALIVE);
+        assert_eq!(receiver.receive(&datagrams[1], now), vec![vec![2]]);
+        assert!(receiver.receive(&datagrams[0], now).is_empty());
+        assert_eq!(receiver.receive(&datagrams[2], now), vec![vec![3]]);
+
+        // Unreliable messages are not resent.
+        assert!(sender.poll(now + RESEND, RESEND, KEEPALIVE).is_empty());
+    }
+}
@@ -0,0 +1,58 @@
+use crossbeam_channel::{unbounded, Receiver, Sender, TryIter};
+use legion::Entity;
+
+use net_sync::uid::Uid;
+
+/// A change in the lifecycle of the uid of a replicated entity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UidEvent {
+    /// The uid was assigned to an entity that is inserted by the server.
+    Allocated { uid: Uid, entity: Entity },
+    /// The entity with the uid is removed by the server, the uid is free again.
+    Deallocated { uid: Uid, entity: Entity },
+    /// The predicted local entity of a command was replaced by the server entity with the uid,
+    /// see `ClientWorld::spawn_ephemeral_predicted`.
+    Remapped {
+        predicted: Entity,
+        uid: Uid,
+        entity: Entity,
+    },
+}
+
+/// Client resource with a channel of the `UidEvent`s of the replicated world,
+/// enabled with `ClientWorldBuilder::with_uid_events`.
+///
+/// Debugging tools and game code, e.g. a minimap keyed by uid, can react to the uid lifecycle
+/// without polling the allocator. Events are kept until they are received.
+pub struct UidEvents {
+    sender: Sender<UidEvent>,
+    receiver: Receiver<UidEvent>,
+}
+
+impl UidEvents {
+    pub fn new() -> UidEvents {
+        let (sender, receiver) = unbounded();
+        UidEvents { sender, receiver }
+    }
+
+    /// The receiving end of the channel, it can be cloned to receive on another thread.
+    pub fn receiver(&self) -> &Receiver<UidEvent> {
+        &self.receiver
+    }
+
+    /// Receives the pending events.
+    pub fn try_iter(&self) -> TryIter<UidEvent> {
+        self.receiver.try_iter()
+    }
+
+    pub(crate) fn send(&self, event: UidEvent) {
+        // The receiver is owned by this resource, the channel can not be disconnected.
+        let _ = self.sender.send(event);
+    }
+}
+
+impl Default for UidEvents {
+    fn default() -> Self {
+        UidEvents::new()
+    }
+}
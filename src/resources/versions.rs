@@ -0,0 +1,297 @@
+use std::{
+    any::TypeId,
+    collections::{HashMap, HashSet},
+};
+
+use legion::{
+    query::{IntoQuery, Read},
+    Entity, World,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use net_sync::{
+    synchronisation::{CommandFrame, ComponentData, WorldState},
+    transport::ClientId,
+    uid::Uid,
+};
+
+use crate::{
+    components::UidComponent,
+    error::ErrorKind,
+    resources::{RegisteredComponentsResource, SerializationResource},
+    world,
+};
+
+/// Version of the component schema, raised by the game with every deploy that changes the
+/// layout of a synchronized component.
+pub type SchemaVersion = u16;
+
+type Downgrade =
+    Box<dyn Fn(&[u8], &SerializationResource) -> Result<Vec<u8>, ErrorKind> + Send + Sync>;
+
+/// Server resource that translates outgoing component data into the schema version of
+/// the client, so the server can be deployed before all clients are updated.
+///
+/// Game code negotiates the version with a message of its own and reports it with
+/// `set_client_version`. For clients with a lower version the server serializes the
+/// initial sync as inserted entities and sends changed components of downgraded types
+/// as removed and added component, diffs can not be translated between schemas.
+///
+/// ```ignore
+/// let mut versions = ComponentVersions::new(3);
+/// // Clients up to version 2 know `Health` without its shield.
+/// versions.register_downgrade(2, |health: Health| HealthV2 { value: health.value });
+/// ServerWorldBuilder::default().with_component_versions(versions);
+/// ```
+pub struct ComponentVersions {
+    current: SchemaVersion,
+    clients: HashMap<ClientId, SchemaVersion>,
+    downgrades: HashMap<TypeId, Vec<(SchemaVersion, Downgrade)>>,
+}
+
+impl ComponentVersions {
+    /// Creates the versions with the schema version of this server.
+    pub fn new(current: SchemaVersion) -> ComponentVersions {
+        ComponentVersions {
+            current,
+            clients: HashMap::new(),
+            downgrades: HashMap::new(),
+        }
+    }
+
+    pub fn current(&self) -> SchemaVersion {
+        self.current
+    }
+
+    /// Registers the conversion of component `T` into its layout up to schema `version`.
+    ///
+    /// A client receives the conversion with the lowest version that is not lower than its own,
+    /// and the component as it is if there is none. The conversion replaces a previous one
+    /// of the same version.
+    pub fn register_downgrade<T, Old>(&mut self, version: SchemaVersion, downgrade: fn(T) -> Old)
+    where
+        T: DeserializeOwned + 'static,
+        Old: Serialize + 'static,
+    {
+        let convert: Downgrade = Box::new(
+            move |data: &[u8], serialization: &SerializationResource| {
+                let component = serialization.deserialize::<T>(data)?;
+                serialization.serialize(&downgrade(component))
+            },
+        );
+
+        let downgrades = self.downgrades.entry(TypeId::of::<T>()).or_default();
+        downgrades.retain(|(registered, _)| *registered != version);
+        downgrades.push((version, convert));
+        downgrades.sort_by_key(|(registered, _)| *registered);
+    }
+
+    /// Sets the schema version the client negotiated.
+    pub fn set_client_version(&mut self, client: ClientId, version: SchemaVersion) {
+        self.clients.insert(client, version);
+    }
+
+    /// The schema version of the client, clients that did not negotiate use the current one.
+    pub fn client_version(&self, client: ClientId) -> SchemaVersion {
+        self.clients.get(&client).copied().unwrap_or(self.current)
+    }
+
+    pub fn remove_client(&mut self, client: ClientId) {
+        self.clients.remove(&client);
+    }
+
+    /// Moves the version to another client id, see `ServerWorld::migrate_client`.
+    pub fn rebind(&mut self, from: ClientId, to: ClientId) {
+        if let Some(version) = self.clients.remove(&from) {
+            self.clients.insert(to, version);
+        }
+    }
+
+    /// The version of the client if its data has to be downgraded.
+    pub(crate) fn outdated(&self, client: ClientId) -> Option<SchemaVersion> {
+        let version = self.client_version(client);
+
+        if version < self.current && !self.downgrades.is_empty() {
+            Some(version)
+        } else {
+            None
+        }
+    }
+
+    /// The `SerializedStateCache` filter of the states downgraded to the version.
+    pub(crate) fn cache_filter(version: SchemaVersion) -> u64 {
+        // Keeps clear of `SerializedStateCache::UNFILTERED`.
+        (1 << 32) | version as u64
+    }
+
+    fn downgrade(&self, type_id: TypeId, version: SchemaVersion) -> Option<&Downgrade> {
+        self.downgrades
+            .get(&type_id)?
+            .iter()
+            .find(|(registered, _)| *registered >= version)
+            .map(|(_, downgrade)| downgrade)
+    }
+
+    /// Translates the state into the schema version.
+    ///
+    /// Components that fail to convert are left out, their errors are returned.
+    pub(crate) fn downgrade_state(
+        &self,
+        version: SchemaVersion,
+        state: &WorldState,
+        world: &World,
+        registered: &RegisteredComponentsResource,
+        serialization: &SerializationResource,
+    ) -> (WorldState, Vec<(Uid, ErrorKind)>) {
+        let mut result = WorldState::new(state.command_frame);
+        result.command_frame_offset = state.command_frame_offset;
+        let mut errors = Vec::new();
+
+        let convert = |data: &ComponentData| -> Result<ComponentData, ErrorKind> {
+            let downgrade = registered
+                .get_type(&data.component_id())
+                .and_then(|type_id| self.downgrade(*type_id, version));
+
+            match downgrade {
+                Some(downgrade) => Ok(ComponentData::new(
+                    data.component_id(),
+                    downgrade(data.data(), serialization)?,
+                )),
+                None => Ok(data.clone()),
+            }
+        };
+
+        for removed in state.removed.iter() {
+            result.remove_entity(*removed);
+        }
+
+        for inserted in state.inserted.iter() {
+            let mut components = Vec::new();
+
+            for data in inserted.components().iter() {
+                match convert(data) {
+                    Ok(data) => components.push(data),
+                    Err(error) => errors.push((inserted.entity_id(), error)),
+                }
+            }
+
+            result.insert_entity(inserted.entity_id(), components);
+        }
+
+        for removed in state.component_removed.iter() {
+            result.remove_component(removed.entity_id(), removed.component_id());
+        }
+
+        for added in state.component_added.iter() {
+            match convert(added.component_data()) {
+                Ok(data) => result.add_component(added.entity_id(), data),
+                Err(error) => errors.push((added.entity_id(), error)),
+            }
+        }
+
+        for changed in state.changed.iter() {
+            let data = changed.component_data();
+            let type_id = registered.get_type(&data.component_id());
+
+            if type_id.map_or(true, |type_id| self.downgrade(*type_id, version).is_none()) {
+                result.change(changed.entity_id(), data.clone());
+                continue;
+            }
+
+            // The diff is of the current schema, the client receives the whole current value.
+            let current = world::entity_by_uid(world, changed.entity_id()).and_then(|entity| {
+                world::serialize_component(
+                    world,
+                    registered,
+                    serialization,
+                    entity,
+                    data.component_id(),
+                )
+            });
+
+            match current.map(|current| current.and_then(|current| convert(&current))) {
+                Some(Ok(current)) => {
+                    result.remove_component(changed.entity_id(), data.component_id());
+                    result.add_component(changed.entity_id(), current);
+                }
+                Some(Err(error)) => errors.push((changed.entity_id(), error)),
+                // The entity was removed since, the client receives the removal.
+                None => {}
+            }
+        }
+
+        (result, errors)
+    }
+
+    /// Returns a state in the schema version that inserts all replicated entities,
+    /// the initial sync of outdated clients.
+    pub(crate) fn initial_state(
+        &self,
+        version: SchemaVersion,
+        world: &World,
+        registered: &RegisteredComponentsResource,
+        serialization: &SerializationResource,
+        quarantined: &HashSet<Uid>,
+        command_frame: CommandFrame,
+    ) -> (WorldState, Vec<(Uid, ErrorKind)>) {
+        let mut state = WorldState::new(command_frame);
+        let mut errors = Vec::new();
+
+        let entities = <(Entity, Read<UidComponent>)>::query()
+            .iter(world)
+            .map(|(entity, uid)| (*entity, uid.uid()))
+            .filter(|(_, uid)| !quarantined.contains(uid))
+            .collect::<Vec<(Entity, Uid)>>();
+
+        for (entity, uid) in entities {
+            let components =
+                world::serialize_entity(world, registered, serialization, entity, uid, &mut errors);
+            state.insert_entity(uid, components);
+        }
+
+        let (state, downgrade_errors) =
+            self.downgrade_state(version, &state, world, registered, serialization);
+        errors.extend(downgrade_errors);
+
+        (state, errors)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::any::TypeId;
+
+    use legion::World;
+
+    use net_sync::synchronisation::{ComponentData, WorldState};
+
+    use crate::{
+        components::UidComponent,
+        resources::{ComponentVersions, RegisteredComponentsResource, SerializationResource},
+    };
+
+    #[test]
+    fn state_is_downgraded_test() {
+        let registered = RegisteredComponentsResource::new();
+        let serialization = SerializationResource::default();
+        let component_uid = *registered.get_uid(&TypeId::of::<UidComponent>()).unwrap();
+        let world = World::default();
+
+        let mut versions = ComponentVersions::new(3);
+        versions.register_downgrade(1, |uid: UidComponent| uid.uid() as u8);
+
+        let mut state = WorldState::new(5);
+        let data = serialization.serialize(&UidComponent::new(7)).unwrap();
+        state.insert_entity(7, vec![ComponentData::new(component_uid, data.clone())]);
+
+        // Version 2 has the current layout.
+        let (downgraded, errors) =
+            versions.downgrade_state(2, &state, &world, &registered, &serialization);
+        assert!(errors.is_empty());
+        assert_eq!(downgraded.inserted[0].components()[0].data(), &data[..]);
+
+        let (downgraded, _) =
+            versions.downgrade_state(0, &state, &world, &registered, &serialization);
+        assert_eq!(downgraded.inserted[0].components()[0].data(), &[7]);
+    }
+}
@@ -0,0 +1,87 @@
+//! Export of the registered components so that other tools (decoders, scripting layers) can
+//! interpret the synchronized data without linking against the game binary.
+
+use serde::{Deserialize, Serialize};
+
+use net_sync::uid::Uid;
+
+use crate::{components::type_uid, resources::RegisteredComponentsResource};
+
+/// A registered rust component.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ComponentSchema {
+    pub uid: Uid,
+    pub type_name: String,
+}
+
+/// A scripted type replicated by the [DynamicComponent](../components/struct.DynamicComponent.html).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DynamicComponentSchema {
+    pub type_uid: Uid,
+    pub type_name: String,
+}
+
+/// Describes all data that can be synchronized between server and client.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Schema {
+    pub components: Vec<ComponentSchema>,
+    pub dynamic_components: Vec<DynamicComponentSchema>,
+}
+
+impl Schema {
+    /// Creates a schema from all components registered with the `sync` attribute.
+    pub fn from_registered(registered: &RegisteredComponentsResource) -> Schema {
+        let mut components = registered
+            .slice_with_uid()
+            .iter()
+            .map(|(uid, registration)| ComponentSchema {
+                uid: *uid,
+                type_name: registration.type_name().to_string(),
+            })
+            .collect::<Vec<ComponentSchema>>();
+
+        components.sort_by_key(|component| component.uid);
+
+        Schema {
+            components,
+            dynamic_components: Vec::new(),
+        }
+    }
+
+    /// Declares interest in a dynamic type by name.
+    pub fn with_dynamic_type(mut self, type_name: &str) -> Schema {
+        let type_uid = type_uid(type_name);
+
+        if self.dynamic_component(type_uid).is_none() {
+            self.dynamic_components.push(DynamicComponentSchema {
+                type_uid,
+                type_name: type_name.to_string(),
+            });
+        }
+
+        self
+    }
+
+    pub fn component(&self, uid: Uid) -> Option<&ComponentSchema> {
+        self.components.iter().find(|component| component.uid == uid)
+    }
+
+    pub fn dynamic_component(&self, type_uid: Uid) -> Option<&DynamicComponentSchema> {
+        self.dynamic_components
+            .iter()
+            .find(|component| component.type_uid == type_uid)
+    }
+
+    /// Returns true if the dynamic type with the given uid is part of this schema.
+    pub fn is_interested_in(&self, type_uid: Uid) -> bool {
+        self.dynamic_component(type_uid).is_some()
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn from_json(json: &str) -> serde_json::Result<Schema> {
+        serde_json::from_str(json)
+    }
+}
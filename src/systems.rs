@@ -1,15 +1,21 @@
 //! A number of systems that can be used to synchronize and trace components.
 
-use legion::systems::{Builder, SystemBuilder};
+use legion::{
+    query::component,
+    systems::{Builder, SystemBuilder},
+    Entity, IntoQuery,
+};
 
 use net_sync::synchronisation::{NetworkCommand, NetworkMessage};
 
 use crate::{
+    components::ReplicatedThisFrame,
     resources::RegisteredComponentsResource,
     systems::tcp::{tcp_client_receive_system, tcp_client_sent_system},
 };
 
 pub mod tcp;
+pub mod udp;
 
 pub trait BuilderExt {
     fn add_server_systems(self) -> Builder;
@@ -30,6 +36,20 @@ pub trait BuilderExt {
     >(
         self,
     ) -> Builder;
+    fn add_udp_server_systems<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        self,
+    ) -> Builder;
+    fn add_udp_client_systems<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        self,
+    ) -> Builder;
 }
 
 impl BuilderExt for Builder {
@@ -96,6 +116,63 @@ impl BuilderExt for Builder {
 
         builder
     }
+
+    fn add_udp_server_systems<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        self,
+    ) -> Builder {
+        let builder = udp::udp_server_receive_system::<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >(self);
+
+        let builder = udp::udp_server_sent_system::<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >(builder);
+
+        builder
+    }
+
+    fn add_udp_client_systems<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        self,
+    ) -> Builder {
+        let builder = udp::udp_client_sent_system::<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >(self);
+
+        let builder = udp::udp_client_receive_system::<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >(builder);
+
+        builder
+    }
+}
+
+/// Removes the `ReplicatedThisFrame` markers, added at the end of the client schedule.
+pub fn clear_replicated_markers_system(builder: Builder) -> Builder {
+    builder.add_system(
+        SystemBuilder::new("clear_replicated_markers_system")
+            .with_query(<Entity>::query().filter(component::<ReplicatedThisFrame>()))
+            .build(|command_buffer, world, _, query| {
+                for entity in query.iter(world) {
+                    command_buffer.remove_component::<ReplicatedThisFrame>(*entity);
+                }
+            }),
+    )
 }
 
 pub trait SystemBuilderExt {
@@ -1,7 +1,7 @@
 use legion::systems::{Builder, SystemBuilder};
 
 use net_sync::{
-    synchronisation::{CommandFrameTicker, NetworkCommand, NetworkMessage},
+    synchronisation::{NetworkCommand, NetworkMessage},
     transport,
     transport::{
         tcp::{TcpClientResource, TcpListenerResource},
@@ -9,7 +9,7 @@ use net_sync::{
     },
 };
 
-use crate::resources::BufferResource;
+use crate::resources::{BufferResource, CommandFrameTicker, ListenerGate};
 use net_sync::event::NetworkEventQueue;
 
 pub fn tcp_connection_listener<
@@ -23,8 +23,12 @@ pub fn tcp_connection_listener<
         .write_resource::<TcpListenerResource>()
         .write_resource::<PostOffice<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>>()
         .write_resource::<NetworkEventQueue>()
+        .read_resource::<ListenerGate>()
         .build(|_, _, resources, _| {
-            net_sync::transport::tcp::tcp_connection_listener(&mut resources.0, &mut resources.1, &mut resources.2);
+            // See `ServerWorld::close_listener`.
+            if resources.3.is_open() {
+                net_sync::transport::tcp::tcp_connection_listener(&mut resources.0, &mut resources.1, &mut resources.2);
+            }
         }))
 }
 
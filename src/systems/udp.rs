@@ -0,0 +1,145 @@
+use legion::systems::{Builder, SystemBuilder};
+
+use net_sync::{
+    synchronisation::{NetworkCommand, NetworkMessage},
+    transport,
+    transport::{PostBox, PostOffice},
+};
+
+use crate::resources::{BufferResource, ClockResource, UdpClientResource, UdpServerResource};
+
+pub fn udp_client_receive_system<
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    builder: Builder,
+) -> Builder {
+    builder.add_system(
+        SystemBuilder::new("udp_client_receive_system")
+            .write_resource::<UdpClientResource>()
+            .write_resource::<PostBox<
+                transport::ServerToClientMessage<ServerToClientMessage>,
+                transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+            >>()
+            .write_resource::<BufferResource>()
+            .read_resource::<ClockResource>()
+            .build(|_, _, resources, _| {
+                let messages = resources
+                    .0
+                    .receive::<transport::ServerToClientMessage<ServerToClientMessage>>(
+                        &mut resources.2.recv_buffer,
+                        resources.3.now(),
+                    );
+
+                for message in messages {
+                    resources.1.add_to_inbox(message);
+                }
+            }),
+    )
+}
+
+pub fn udp_client_sent_system<
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    builder: Builder,
+) -> Builder {
+    builder.add_system(
+        SystemBuilder::new("udp_client_sent_system")
+            .write_resource::<UdpClientResource>()
+            .write_resource::<PostBox<
+                transport::ServerToClientMessage<ServerToClientMessage>,
+                transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+            >>()
+            .read_resource::<ClockResource>()
+            .build(|_, _, resources, _| {
+                let client = &mut resources.0;
+                let now = resources.2.now();
+
+                for message in resources.1.drain_outgoing(|_| true) {
+                    let reliability = client.config().client_reliability(&message);
+                    client.send(reliability, &message, now);
+                }
+
+                client.flush(now);
+            }),
+    )
+}
+
+pub fn udp_server_receive_system<
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    builder: Builder,
+) -> Builder {
+    builder.add_system(SystemBuilder::new("udp_server_receive_system")
+        .write_resource::<UdpServerResource>()
+        .write_resource::<PostOffice<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>>()
+        .write_resource::<BufferResource>()
+        .read_resource::<ClockResource>()
+        .build(|_, _, resources, _| {
+            let (server, postoffice, buffer, clock) = resources;
+            let now = clock.now();
+
+            for client in server.drop_timed_out(now) {
+                postoffice.remove_client(client);
+            }
+
+            let messages = server.receive::<transport::ClientToServerMessage<
+                ClientToServerMessage,
+                ClientToServerCommand,
+            >>(&mut buffer.recv_buffer, now);
+
+            for (addr, message) in messages {
+                let message = match message {
+                    Some(message) => message,
+                    None => {
+                        // The first datagram of a new client.
+                        if server.client(&addr).is_none() {
+                            let client = postoffice.add_client();
+                            server.accept(addr, client, now);
+                        }
+                        continue;
+                    }
+                };
+
+                let client = match server.client(&addr) {
+                    Some(client) => client,
+                    None => continue,
+                };
+
+                if let Some((_, connection)) = postoffice.clients_mut().find(|x| *x.0 == client) {
+                    connection.postbox_mut().add_to_inbox(message);
+                }
+            }
+        }))
+}
+
+pub fn udp_server_sent_system<
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    builder: Builder,
+) -> Builder {
+    builder.add_system(SystemBuilder::new("udp_server_sent_system")
+        .write_resource::<UdpServerResource>()
+        .write_resource::<PostOffice<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>>()
+        .read_resource::<ClockResource>()
+        .build(|_, _, resources, _| {
+            let (server, postoffice, clock) = resources;
+            let now = clock.now();
+
+            for (client, connection) in postoffice.clients_mut() {
+                for message in connection.postbox_mut().drain_outgoing(|_| true) {
+                    let reliability = server.config().server_reliability(&message);
+                    server.send(*client, reliability, &message, now);
+                }
+            }
+
+            server.flush(now);
+        }))
+}
@@ -1,13 +1,81 @@
+use std::{
+    any::TypeId,
+    collections::{HashMap, HashSet},
+    fmt::{self, Display, Formatter},
+    io,
+};
+
 use legion::systems::{Builder, Resource};
 
-use crate::register::ComponentRegistration;
-use legion::{world::SubWorld, Entity, World};
-use net_sync::compression::CompressionStrategy;
+use crate::{
+    components::{CorrelationId, DynamicComponent, Frozen, Region, UidComponent},
+    error::ErrorKind,
+    protocol,
+    register::{ComponentRegister, ComponentRegistration},
+    resources::{RegisteredComponentsResource, SerializationResource},
+    tracking::re_exports::bincode,
+};
+#[cfg(feature = "debug-names")]
+use crate::components::DebugName;
+use bincode::Options;
+use legion::{
+    query::{component, IntoQuery, Read},
+    world::{EntityStore, SubWorld},
+    Entity, World,
+};
+use net_sync::{
+    compression::CompressionStrategy,
+    synchronisation::{ComponentData, WorldState},
+    uid::Uid,
+};
+
+/// Returns the names of the resource types that are present.
+macro_rules! present_resources {
+    ($resources:expr, $($resource:ty),* $(,)?) => {{
+        let mut present = Vec::new();
+        $(
+            if $resources.contains::<$resource>() {
+                present.push(stringify!($resource));
+            }
+        )*
+        present
+    }};
+}
 
+pub mod archive;
 pub mod client;
+pub mod context;
+pub mod diff;
+pub mod fanout;
+pub mod merge;
+pub(crate) mod pacing;
+pub mod persistence;
+pub mod populate;
 pub mod server;
+pub mod tracker;
 pub mod world_instance;
 
+/// The name of the system group added by `WorldBuilder::default_systems` of the server.
+pub const SERVER_SYSTEMS: &str = "server systems";
+/// The name of the system group added by `WorldBuilder::default_systems` of the client.
+pub const CLIENT_SYSTEMS: &str = "client systems";
+/// The name of the system groups added by `WorldBuilder::register_systems`.
+pub const USER_SYSTEMS: &str = "user systems";
+/// The name of the system group added by `ServerWorldBuilder::with_tcp`.
+pub const TCP_SERVER_SYSTEMS: &str = "tcp server systems";
+/// The name of the system group added by `ClientWorldBuilder::with_tcp`,
+/// unless the network thread is enabled.
+pub const TCP_CLIENT_SYSTEMS: &str = "tcp client systems";
+/// The name of the system group added by `ServerWorldBuilder::with_udp`.
+pub const UDP_SERVER_SYSTEMS: &str = "udp server systems";
+/// The name of the system group added by `ClientWorldBuilder::with_udp`.
+pub const UDP_CLIENT_SYSTEMS: &str = "udp client systems";
+/// The name of the system group that removes the `ReplicatedThisFrame` markers on the client.
+pub const REPLICATED_MARKER_CLEANUP: &str = "replicated marker cleanup";
+
+/// A named group of systems, added to the schedule on build.
+pub(crate) type SystemGroup = (&'static str, fn(Builder) -> Builder);
+
 pub trait WorldBuilder {
     type BuildResult;
 
@@ -19,7 +87,116 @@ pub trait WorldBuilder {
 
     fn register_systems(self, user_system_builder: fn(Builder) -> Builder) -> Self;
 
-    fn build(self) -> Self::BuildResult;
+    /// Leaves the system groups with the given name out of the schedule,
+    /// also the ones that are added after this call. See `SERVER_SYSTEMS` and friends.
+    fn without_system(self, name: &'static str) -> Self;
+
+    /// Replaces a resource, for instance a default resource by a mock in tests.
+    /// The previous resource of the type is dropped.
+    fn replace_resource<R: Resource>(self, resource: R) -> Self;
+
+    /// Validates the setup and builds the world, see `BuildError`.
+    fn build(self) -> Result<Self::BuildResult, BuildError>;
+
+    /// Reports the configured systems, resources and components without building the world.
+    fn dry_run(&self) -> BuildReport;
+}
+
+/// A setup with which a world can not synchronize, returned by `WorldBuilder::build`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildError {
+    /// No component type is registered with the `sync` attribute, there is nothing to synchronize.
+    NoRegisteredComponents,
+    /// No transport is configured, use `with_tcp`, `with_custom_transport` or offline mode.
+    NoTransport,
+    /// The client could not connect to the server, with the kind of the io error.
+    ConnectionFailed(io::ErrorKind),
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::NoRegisteredComponents => {
+                f.write_str("No component type is registered with the `sync` attribute.")
+            }
+            BuildError::NoTransport => {
+                f.write_str("No transport is configured to synchronize over.")
+            }
+            BuildError::ConnectionFailed(kind) => {
+                write!(f, "Could not connect to the server: {:?}.", kind)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// The setup of a world builder, see `WorldBuilder::dry_run`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildReport {
+    /// The system groups in the order they run.
+    pub systems: Vec<&'static str>,
+    /// The synchronisation resources that are present.
+    pub resources: Vec<&'static str>,
+    /// The type names of the registered components.
+    pub components: Vec<&'static str>,
+    /// The errors of the setup, `build` returns the first one.
+    pub errors: Vec<BuildError>,
+}
+
+impl Display for BuildReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let sections = [
+            ("systems", &self.systems),
+            ("resources", &self.resources),
+            ("components", &self.components),
+        ];
+
+        for (name, entries) in sections.iter() {
+            writeln!(f, "{}:", name)?;
+            for entry in entries.iter() {
+                writeln!(f, "  {}", entry)?;
+            }
+        }
+
+        writeln!(f, "errors:")?;
+        for error in self.errors.iter() {
+            writeln!(f, "  {}", error)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the system groups that are not left out, in the order they were added.
+pub(crate) fn enabled_systems<'a>(
+    groups: &'a [SystemGroup],
+    without: &'a [&'static str],
+) -> impl Iterator<Item = &'a SystemGroup> {
+    groups.iter().filter(move |(name, _)| !without.contains(name))
+}
+
+/// Returns the type names of the registered components,
+/// and whether any of them is not a component of this crate.
+pub(crate) fn registered_components() -> (Vec<&'static str>, bool) {
+    let internal = [
+        TypeId::of::<UidComponent>(),
+        TypeId::of::<DynamicComponent>(),
+        TypeId::of::<CorrelationId>(),
+        TypeId::of::<Frozen>(),
+        TypeId::of::<Region>(),
+    ];
+
+    let mut names = Vec::new();
+    let mut has_game_components = false;
+
+    for registration in ComponentRegister.iter() {
+        names.push(registration.type_name());
+        has_game_components |= !internal.contains(&registration.ty());
+    }
+
+    names.sort();
+    (names, has_game_components)
 }
 
 pub trait WorldAbstraction {
@@ -37,3 +214,270 @@ impl<'a> WorldAbstraction for SubWorld<'a> {
         component.exists_in_subworld(&self, entity)
     }
 }
+
+/// Bincode options used for all synchronized data.
+pub(crate) fn default_options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+}
+
+/// Returns the replicated entity with the given uid.
+pub(crate) fn entity_by_uid(world: &World, uid: Uid) -> Option<Entity> {
+    <(Entity, Read<UidComponent>)>::query()
+        .iter(world)
+        .find(|(_, component)| component.uid() == uid)
+        .map(|(entity, _)| *entity)
+}
+
+/// Returns the replicated entities by uid, for lookups of many uids at once.
+pub(crate) fn entities_by_uid(world: &World) -> HashMap<Uid, Entity> {
+    <(Entity, Read<UidComponent>)>::query()
+        .iter(world)
+        .map(|(entity, component)| (component.uid(), *entity))
+        .collect()
+}
+
+/// Returns the first replicated entity with the given debug name.
+#[cfg(feature = "debug-names")]
+pub(crate) fn entity_by_name(world: &World, name: &str) -> Option<Entity> {
+    <(Entity, Read<UidComponent>, Read<DebugName>)>::query()
+        .iter(world)
+        .find(|(_, _, debug_name)| debug_name.name() == name)
+        .map(|(entity, _, _)| *entity)
+}
+
+/// Returns the debug name of the entity, `None` for entities without a name.
+#[cfg(feature = "debug-names")]
+pub(crate) fn name_of(world: &World, entity: Entity) -> Option<String> {
+    world
+        .entry_ref(entity)?
+        .get_component::<DebugName>()
+        .ok()
+        .map(|debug_name| debug_name.name().to_string())
+}
+
+/// Returns the uid of the entity, `None` for entities that are not replicated.
+pub(crate) fn uid_of(world: &World, entity: Entity) -> Option<Uid> {
+    world
+        .entry_ref(entity)?
+        .get_component::<UidComponent>()
+        .ok()
+        .map(|component| component.uid())
+}
+
+/// Serializes the registered components of the entity, the failed components are left out.
+pub(crate) fn serialize_entity(
+    world: &World,
+    registered: &RegisteredComponentsResource,
+    serialization: &SerializationResource,
+    entity: Entity,
+    uid: Uid,
+    errors: &mut Vec<(Uid, ErrorKind)>,
+) -> Vec<ComponentData> {
+    let mut components = Vec::new();
+
+    for (component_uid, _) in registered.slice_with_uid().iter() {
+        match serialize_component(world, registered, serialization, entity, *component_uid) {
+            Some(Ok(data)) => components.push(data),
+            Some(Err(error)) => errors.push((uid, error)),
+            None => {}
+        }
+    }
+
+    components
+}
+
+/// Serializes the component of the entity, `None` if the entity does not have it.
+pub(crate) fn serialize_component(
+    world: &World,
+    registered: &RegisteredComponentsResource,
+    serialization: &SerializationResource,
+    entity: Entity,
+    component_uid: Uid,
+) -> Option<Result<ComponentData, ErrorKind>> {
+    let registry_by_uid = registered.by_uid();
+    let registration = registry_by_uid.get(&component_uid)?;
+    let mut result = None;
+
+    registration.serialize_if_exists_in_world(world, entity, &mut |serialize| {
+        result = Some(
+            serialization
+                .serialize(&serialize)
+                .map(|buffer| ComponentData::new(component_uid, buffer)),
+        );
+    });
+
+    result
+}
+
+/// Copies the replicated entities with their registered components into the world of an initial
+/// sync, returns it with the uids of the copied entities.
+///
+/// The archetypes with an `UidComponent` are copied chunk by chunk with the merger of the
+/// registered components. Components that are not registered, e.g. server-only state, stay
+/// behind while their entities are synced. The excluded entities, e.g. the quarantined ones,
+/// are left out.
+pub(crate) fn initial_sync_world(
+    world: &World,
+    registered: &RegisteredComponentsResource,
+    excluded: &HashSet<Uid>,
+) -> (World, Vec<Uid>) {
+    let mut synced = World::default();
+    synced.clone_from(
+        world,
+        &component::<UidComponent>(),
+        &mut registered.merger(),
+    );
+
+    let excluded = <(Entity, Read<UidComponent>)>::query()
+        .iter(&synced)
+        .filter(|(_, uid)| excluded.contains(&uid.uid()))
+        .map(|(entity, _)| *entity)
+        .collect::<Vec<_>>();
+
+    for entity in excluded {
+        synced.remove(entity);
+    }
+
+    let uids = <Read<UidComponent>>::query()
+        .iter(&synced)
+        .map(|uid| uid.uid())
+        .collect();
+
+    (synced, uids)
+}
+
+/// Returns the part of the state that concerns the visible entities.
+pub(crate) fn filter_state(state: &WorldState, visible: impl Fn(Uid) -> bool) -> WorldState {
+    let mut result = WorldState::new(state.command_frame);
+    result.command_frame_offset = state.command_frame_offset;
+
+    for removed in state.removed.iter().filter(|uid| visible(**uid)) {
+        result.remove_entity(*removed);
+    }
+
+    for inserted in state.inserted.iter().filter(|x| visible(x.entity_id())) {
+        result.insert_entity(inserted.entity_id(), inserted.components().to_vec());
+    }
+
+    for removed in state.component_removed.iter().filter(|x| visible(x.entity_id())) {
+        result.remove_component(removed.entity_id(), removed.component_id());
+    }
+
+    for added in state.component_added.iter().filter(|x| visible(x.entity_id())) {
+        result.add_component(added.entity_id(), added.component_data().clone());
+    }
+
+    for changed in state.changed.iter().filter(|x| visible(x.entity_id())) {
+        result.change(changed.entity_id(), changed.component_data().clone());
+    }
+
+    result
+}
+
+/// Merges the changes of the newer state into the older state, see `WorldState::merge_newer`.
+/// The result has the command frame of the newer state.
+pub(crate) fn merge_states(older: WorldState, newer: WorldState) -> WorldState {
+    let mut merged = protocol::WorldState::from(&older);
+    merged.merge_newer(protocol::WorldState::from(&newer));
+    merged.command_frame = newer.command_frame;
+    merged.command_frame_offset = newer.command_frame_offset;
+    merged.into()
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::collections::HashSet;
+
+    use legion::{IntoQuery, Read, Universe, World};
+    use serde::de::DeserializeSeed;
+
+    use crate::{
+        components::UidComponent,
+        resources::RegisteredComponentsResource,
+        tracking::re_exports::bincode,
+        world::{
+            default_options, entity_by_uid, initial_sync_world, registered_components, uid_of,
+        },
+    };
+    use bincode::Options;
+
+    struct Unregistered;
+
+    #[test]
+    fn uid_lookups_test() {
+        let mut world = World::default();
+        let replicated = world.push((UidComponent::new(4),));
+        let local = world.push((0u8,));
+
+        assert_eq!(entity_by_uid(&world, 4), Some(replicated));
+        assert_eq!(entity_by_uid(&world, 5), None);
+        assert_eq!(uid_of(&world, replicated), Some(4));
+        assert_eq!(uid_of(&world, local), None);
+    }
+
+    #[cfg(feature = "debug-names")]
+    #[test]
+    fn name_lookups_test() {
+        use crate::{
+            components::DebugName,
+            world::{entity_by_name, name_of},
+        };
+
+        let mut world = World::default();
+        let named = world.push((UidComponent::new(4), DebugName::new("player")));
+        let unnamed = world.push((UidComponent::new(5),));
+
+        assert_eq!(entity_by_name(&world, "player"), Some(named));
+        assert_eq!(entity_by_name(&world, "enemy"), None);
+        assert_eq!(name_of(&world, named), Some("player".to_string()));
+        assert_eq!(name_of(&world, unnamed), None);
+    }
+
+    #[test]
+    fn initial_sync_skips_unregistered_components_test() {
+        let registered = RegisteredComponentsResource::new();
+
+        let mut world = World::default();
+        world.push((UidComponent::new(1),));
+        world.push((UidComponent::new(2), Unregistered));
+        world.push((UidComponent::new(3),));
+        world.push((Unregistered,));
+
+        // A quarantined entity is left out.
+        let excluded = [3].iter().copied().collect::<HashSet<_>>();
+        let (synced, mut uids) = initial_sync_world(&world, &registered, &excluded);
+        uids.sort_unstable();
+        assert_eq!(uids, vec![1, 2]);
+
+        let bytes = default_options()
+            .serialize(&synced.as_serializable(registered.filter(), registered.legion_registry()))
+            .expect("Initial sync should serialize with unregistered components in the world.");
+
+        let universe = Universe::new();
+        let mut deserializer = bincode::Deserializer::from_slice(&bytes, default_options());
+        let deserialized: World = registered
+            .legion_registry()
+            .as_deserialize(&universe)
+            .deserialize(&mut deserializer)
+            .unwrap();
+
+        // The entity with the unregistered component arrives with its registered components.
+        let mut uids = <Read<UidComponent>>::query()
+            .iter(&deserialized)
+            .map(|uid| uid.uid())
+            .collect::<Vec<_>>();
+        uids.sort_unstable();
+        assert_eq!(uids, vec![1, 2]);
+    }
+
+    #[test]
+    fn registered_components_test() {
+        let (components, has_game_components) = registered_components();
+
+        // The test component of the register module is a game component.
+        assert!(has_game_components);
+        assert!(components.contains(&std::any::type_name::<UidComponent>()));
+    }
+}
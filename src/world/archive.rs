@@ -0,0 +1,246 @@
+//! Offloading of inactive entities from the live world of persistent-world servers.
+//!
+//! Entities tagged with `Inactive` are serialized with the component registry and the
+//! `SerializationStrategy` into an `ArchiveStore` and removed from the world, clients receive
+//! them as removed entities.
+//! `ServerWorld::rehydrate` brings an archived entity back with its original uid.
+
+use std::collections::HashMap;
+
+use legion::{
+    query::{component, IntoQuery, Read},
+    Entity, World,
+};
+
+use net_sync::uid::{Uid, UidAllocator};
+
+use crate::{
+    components::UidComponent,
+    error::ErrorKind,
+    protocol::ComponentData,
+    resources::{RegisteredComponentsResource, SerializationResource},
+};
+
+/// Marks an entity to be archived by the `ServerWorld` at the end of the tick.
+///
+/// The marker itself is not replicated.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Inactive;
+
+/// Storage of archived entities, e.g. a directory or key-value database.
+///
+/// A record contains the registered components of an entity,
+/// components that are not registered are not archived.
+pub trait ArchiveStore: Send + Sync + 'static {
+    fn store(&mut self, uid: Uid, record: Vec<u8>) -> Result<(), ErrorKind>;
+
+    /// Removes and returns the record of the entity, `None` if it is not archived.
+    fn take(&mut self, uid: Uid) -> Result<Option<Vec<u8>>, ErrorKind>;
+}
+
+/// An `ArchiveStore` that keeps the records in memory.
+#[derive(Debug, Default)]
+pub struct MemoryArchiveStore {
+    records: HashMap<Uid, Vec<u8>>,
+}
+
+impl MemoryArchiveStore {
+    pub fn new() -> MemoryArchiveStore {
+        MemoryArchiveStore::default()
+    }
+
+    pub fn contains(&self, uid: Uid) -> bool {
+        self.records.contains_key(&uid)
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+impl ArchiveStore for MemoryArchiveStore {
+    fn store(&mut self, uid: Uid, record: Vec<u8>) -> Result<(), ErrorKind> {
+        self.records.insert(uid, record);
+        Ok(())
+    }
+
+    fn take(&mut self, uid: Uid) -> Result<Option<Vec<u8>>, ErrorKind> {
+        Ok(self.records.remove(&uid))
+    }
+}
+
+/// Archives the entities tagged with `Inactive` and removes them from the world.
+///
+/// Returns the archived uids, and the errors of the entities that stay in the world.
+/// Their `Inactive` marker is removed so they are not archived again every tick.
+pub(crate) fn archive_inactive(
+    world: &mut World,
+    registered: &RegisteredComponentsResource,
+    serialization: &SerializationResource,
+    store: &mut dyn ArchiveStore,
+) -> (Vec<Uid>, Vec<(Uid, ErrorKind)>) {
+    let inactive = <(Entity, Read<UidComponent>)>::query()
+        .filter(component::<Inactive>())
+        .iter(world)
+        .map(|(entity, uid)| (*entity, uid.uid()))
+        .collect::<Vec<(Entity, Uid)>>();
+
+    let mut archived = Vec::new();
+    let mut errors = Vec::new();
+
+    for (entity, uid) in inactive {
+        let result = serialize_entity(world, registered, serialization, entity)
+            .and_then(|record| store.store(uid, record));
+
+        match result {
+            Ok(()) => {
+                world.remove(entity);
+                archived.push(uid);
+            }
+            Err(error) => {
+                if let Some(mut entry) = world.entry(entity) {
+                    entry.remove_component::<Inactive>();
+                }
+                errors.push((uid, error));
+            }
+        }
+    }
+
+    (archived, errors)
+}
+
+/// Inserts the archived entity back into the world under its original uid.
+///
+/// Returns `None` if the entity is not archived.
+/// Call it after the state update of the tick that archived the entity was sent.
+pub(crate) fn rehydrate(
+    world: &mut World,
+    registered: &RegisteredComponentsResource,
+    serialization: &SerializationResource,
+    allocator: &mut UidAllocator<Entity>,
+    store: &mut dyn ArchiveStore,
+    uid: Uid,
+) -> Result<Option<Entity>, ErrorKind> {
+    let record = match store.take(uid)? {
+        Some(record) => record,
+        None => return Ok(None),
+    };
+
+    let components = serialization.deserialize::<Vec<ComponentData>>(&record)?;
+
+    let registry_by_uid = registered.by_uid();
+    let entity = world.extend(vec![()])[0];
+
+    for component in components {
+        let registration = registry_by_uid
+            .get(&component.component_id())
+            .ok_or_else(|| {
+                ErrorKind::SerializationError(format!(
+                    "Archived component {} of entity {} is not registered",
+                    component.component_id(),
+                    uid
+                ))
+            })?;
+
+        serialization.deserialize_with(component.data(), |data| {
+            registration.add_component(world, entity, data)
+        })?;
+    }
+
+    // The removed entity still holds the uid in the allocator.
+    let removed = *allocator.get_by_val(&uid);
+    let _ = allocator.deallocate(removed);
+    allocator.allocate(entity, Some(uid));
+
+    Ok(Some(entity))
+}
+
+fn serialize_entity(
+    world: &World,
+    registered: &RegisteredComponentsResource,
+    serialization: &SerializationResource,
+    entity: Entity,
+) -> Result<Vec<u8>, ErrorKind> {
+    let mut components = Vec::new();
+    let mut error = None;
+
+    for (component_uid, registration) in registered.slice_with_uid().iter() {
+        registration.serialize_if_exists_in_world(world, entity, &mut |serialize| {
+            match serialization.serialize(&serialize) {
+                Ok(buffer) => components.push(ComponentData::new(*component_uid, buffer)),
+                Err(e) => error = Some(e),
+            }
+        });
+    }
+
+    if let Some(error) = error {
+        return Err(error);
+    }
+
+    serialization.serialize(&components)
+}
+
+#[cfg(test)]
+pub mod test {
+    use legion::{world::EntityStore, Entity, World};
+
+    use net_sync::uid::UidAllocator;
+
+    use crate::{
+        components::{DynamicComponent, UidComponent},
+        resources::{RegisteredComponentsResource, SerializationResource},
+        world::{
+            archive::{archive_inactive, rehydrate, Inactive, MemoryArchiveStore},
+            entity_by_uid,
+        },
+    };
+
+    #[test]
+    fn archive_and_rehydrate_test() {
+        let registered = RegisteredComponentsResource::new();
+        let serialization = SerializationResource::default();
+        let mut allocator = UidAllocator::<Entity>::new();
+        let mut store = MemoryArchiveStore::new();
+        let mut world = World::default();
+
+        let entity = world.push((
+            UidComponent::new(3),
+            DynamicComponent::new("health", serde_json::json!(10)),
+            Inactive,
+        ));
+        allocator.allocate(entity, Some(3));
+        world.push((UidComponent::new(4),));
+
+        let (archived, errors) =
+            archive_inactive(&mut world, &registered, &serialization, &mut store);
+
+        assert_eq!(archived, vec![3]);
+        assert!(errors.is_empty());
+        assert_eq!(world.len(), 1);
+        assert!(store.contains(3));
+
+        let rehydrated = rehydrate(
+            &mut world,
+            &registered,
+            &serialization,
+            &mut allocator,
+            &mut store,
+            3,
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(entity_by_uid(&world, 3), Some(rehydrated));
+        assert_eq!(*allocator.get_by_val(&3), rehydrated);
+        assert!(world
+            .entry_ref(rehydrated)
+            .unwrap()
+            .get_component::<DynamicComponent>()
+            .is_ok());
+        assert!(store.is_empty());
+    }
+}
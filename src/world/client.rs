@@ -1,8 +1,16 @@
-use std::{marker::PhantomData, net::SocketAddr};
+use std::{
+    any::TypeId,
+    collections::{HashMap, HashSet},
+    marker::PhantomData,
+    mem,
+    net::SocketAddr,
+    time::Duration,
+};
 
 use itertools::Itertools;
 use legion::{
-    any,
+    query::{IntoQuery, Read, TryRead},
+    storage::{Component, IntoComponentSource},
     systems::{Builder, Resource},
     world::{Entity, Universe, World},
     Resources,
@@ -10,25 +18,66 @@ use legion::{
 
 use net_sync::{
     compression::{self, lz4::Lz4},
+    event::{NetworkEvent, NetworkEventQueue},
     synchronisation::{
-        ClientCommandBuffer, ClientCommandBufferEntry, CommandFrame, CommandFrameTicker,
-        ComponentChanged, ComponentData, NetworkCommand, NetworkMessage, ResimulationBuffer,
-        WorldState,
+        ClientCommandBuffer, ClientCommandBufferEntry, CommandFrame, ComponentChanged,
+        ComponentData, NetworkCommand, NetworkMessage, ResimulationBuffer, WorldState,
     },
     transport,
+    track_attr::serde_diff::SerdeDiff,
     transport::PostBox,
-    uid::UidAllocator,
+    uid::{Uid, UidAllocator},
 };
 
+#[cfg(feature = "chat")]
+use crate::chat::ChatInbox;
 use crate::{
-    resources::{EventResource, RegisteredComponentsResource, ResourcesExt},
-    systems::BuilderExt,
+    components::{
+        CorrelationId, EntityTags, Frozen, NetworkEntity, ReplicatedThisFrame, UidComponent,
+    },
+    event::{ClientEvent, ClientEvents},
+    protocol::{
+        order_state_updates, ClientAction, ClientMessage, ClientProtocol, CommandOutcome,
+        CommandResult, ContextId, InitialSync, LocalPlayer, ServerMessage, ServerToClient,
+    },
+    register::DiffPolicy,
+    resources::{
+        AuthorityAudit, AuthorityOverwrite, BandwidthMetrics, ChangeEvents, ClientConnection,
+        ClientNetworkThread, Clock, ClockResource, CommandBufferPolicy, CommandFrameTicker,
+        CommandResultEvents, ComponentTransforms, ConnectionState, EntityReferences,
+        EphemeralEntities, EventResource, InputSampler, InterpolationDelay, LocalPlayers,
+        OverwriteReason, PredictionMetrics, ReferencePolicy, RegisteredComponentsResource,
+        ReplicatedChanges, ResimulationExecutor, ResimulationQueue, ResourcesExt, RollbackResource,
+        RollbackResources, SchemaViolation, SerializationResource, SerializationStrategy,
+        SocketOptions, StalePolicy, StaleSweep, StateAcks, StrictSchema, StructuralChange,
+        StructuralPrediction, StructuralPredictions, SyncedRng, UdpConfig, UidEvent, UidEvents,
+        UidGenerations, WorldHistory,
+    },
+    systems::{clear_replicated_markers_system, BuilderExt},
     tracking::re_exports::bincode,
-    world::{world_instance::WorldInstance, WorldBuilder},
+    world::{
+        self,
+        context::ClientContext,
+        default_options,
+        merge::{merge_initial_sync, MergeResult, SlicedMerge},
+        world_instance::WorldInstance,
+        BuildError, BuildReport, SystemGroup, WorldBuilder,
+    },
 };
-use bincode::Options;
-use serde::de::DeserializeSeed;
-use std::{borrow::BorrowMut, ops::DerefMut};
+use serde::Deserialize;
+
+/// The post box resource of the client world.
+///
+/// User messages from the server are wrapped in `ServerMessage::User`, user messages sent to the
+/// server have to be wrapped in `ClientMessage::User`.
+pub type ClientPostBox<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand> =
+    PostBox<
+        transport::ServerToClientMessage<ServerMessage<ServerToClientMessage, ClientToServerCommand>>,
+        transport::ClientToServerMessage<
+            ClientMessage<ClientToServerMessage, ClientToServerCommand>,
+            ClientToServerCommand,
+        >,
+    >;
 
 pub struct ClientWorldBuilder<
     ServerToClientMessage: NetworkMessage,
@@ -37,7 +86,21 @@ pub struct ClientWorldBuilder<
     CompressionStrategy: compression::CompressionStrategy,
 > {
     resources: Resources,
-    system_builder: Builder,
+    systems: Vec<SystemGroup>,
+    without_systems: Vec<&'static str>,
+    state_applier: Box<dyn StateApplier<ClientToServerCommand>>,
+    max_resimulation_frames: Option<CommandFrame>,
+    prune_acked_commands: bool,
+    coalesce_state_updates: bool,
+    bundle_messages: bool,
+    initial_sync_slicing: Option<(usize, Duration)>,
+    tcp_addr: Option<SocketAddr>,
+    socket_options: SocketOptions,
+    udp_addr: Option<SocketAddr>,
+    udp_config: UdpConfig,
+    network_thread: bool,
+    offline: bool,
+    custom_transport: bool,
 
     cs: PhantomData<CompressionStrategy>,
     stcm: PhantomData<ServerToClientMessage>,
@@ -59,17 +122,9 @@ impl<
     >
 {
     fn default() -> Self {
-        ClientWorldBuilder {
-            resources: Default::default(),
-            system_builder: Builder::default(),
-
-            cs: PhantomData,
-            stcm: PhantomData,
-            ctsm: PhantomData,
-            ctsc: PhantomData,
-        }
-        .default_resources::<Lz4>()
-        .default_systems()
+        ClientWorldBuilder::bare()
+            .default_resources::<Lz4>()
+            .default_systems()
     }
 }
 
@@ -102,7 +157,7 @@ impl<
 
     fn default_systems(self) -> Self {
         let mut s = self;
-        s.system_builder = s.system_builder.add_client_systems();
+        s.systems.push((world::CLIENT_SYSTEMS, <Builder as BuilderExt>::add_client_systems));
         s
     }
 
@@ -114,21 +169,132 @@ impl<
 
     fn register_systems(self, user_system_builder: fn(Builder) -> Builder) -> Self {
         let mut s = self;
-        s.system_builder = user_system_builder(s.system_builder);
+        s.systems.push((world::USER_SYSTEMS, user_system_builder));
+        s
+    }
+
+    fn without_system(self, name: &'static str) -> Self {
+        let mut s = self;
+        s.without_systems.push(name);
         s
     }
 
-    fn build(self) -> Self::BuildResult {
+    fn replace_resource<R: Resource>(self, resource: R) -> Self {
         let mut s = self;
+        s.resources.insert(resource);
+        s
+    }
+
+    fn build(self) -> Result<Self::BuildResult, BuildError> {
+        if let Some(error) = self.dry_run().errors.first() {
+            return Err(*error);
+        }
+
+        let mut s = self;
+
+        if let Some(addr) = s.tcp_addr {
+            let connected = if s.network_thread {
+                ClientNetworkThread::<
+                    ServerMessage<ServerToClientMessage, ClientToServerCommand>,
+                    ClientMessage<ClientToServerMessage, ClientToServerCommand>,
+                    ClientToServerCommand,
+                >::connect(addr, &s.socket_options)
+                .map(|network_thread| s.resources.insert(network_thread))
+            } else {
+                s.resources.insert_tcp_client_resources::<ServerMessage<ServerToClientMessage, ClientToServerCommand>, ClientMessage<ClientToServerMessage, ClientToServerCommand>, ClientToServerCommand>(addr, &s.socket_options)
+            };
+
+            if let Err(e) = connected {
+                log::error!("Failed to connect to {}: {}", addr, e);
+                return Err(BuildError::ConnectionFailed(e.kind()));
+            }
+        }
+
+        if let Some(addr) = s.udp_addr {
+            s.resources.insert_udp_client_resources::<
+                ServerMessage<ServerToClientMessage, ClientToServerCommand>,
+                ClientMessage<ClientToServerMessage, ClientToServerCommand>,
+                ClientToServerCommand,
+            >(addr, s.udp_config.clone());
+        }
+
         let universe = Universe::new();
         let mut main_world = universe.create_world();
 
         s.resources.insert(EventResource::new(&mut main_world));
         s.resources.insert(universe);
 
-        let main_world = WorldInstance::new(main_world, s.system_builder.build());
+        let systems = s.system_groups();
+        let system_builder = world::enabled_systems(&systems, &s.without_systems)
+            .fold(Builder::default(), |builder, (_, add_systems)| add_systems(builder));
+
+        let main_world = WorldInstance::new(main_world, system_builder.build());
+
+        let mut client = ClientWorld::new(s.resources, main_world);
+        client.state_applier = s.state_applier;
+        client.max_resimulation_frames = s.max_resimulation_frames;
+        client.prune_acked_commands = s.prune_acked_commands;
+        client.coalesce_state_updates = s.coalesce_state_updates;
+        client.bundle_messages = s.bundle_messages;
+        client.initial_sync_slicing = s.initial_sync_slicing;
+        Ok(client)
+    }
+
+    fn dry_run(&self) -> BuildReport {
+        let systems = self.system_groups();
+
+        #[allow(unused_mut)]
+        let mut resources = present_resources!(
+            self.resources,
+            ClientEvents,
+            WorldHistory,
+            ComponentTransforms,
+            PredictionMetrics,
+            BandwidthMetrics,
+            EphemeralEntities,
+            ReplicatedChanges,
+            UidEvents,
+            UidGenerations,
+            InterpolationDelay,
+            StrictSchema,
+            StructuralPredictions,
+            ChangeEvents,
+            RollbackResources,
+            StaleSweep,
+            StateAcks,
+            AuthorityAudit,
+            LocalPlayers,
+            SyncedRng,
+            ClockResource,
+            SerializationResource,
+            CommandFrameTicker,
+            RegisteredComponentsResource,
+        );
+        #[cfg(feature = "chat")]
+        resources.extend(present_resources!(self.resources, ChatInbox));
+
+        let (components, has_game_components) = world::registered_components();
+
+        let mut errors = Vec::new();
+        if !has_game_components {
+            errors.push(BuildError::NoRegisteredComponents);
+        }
+        if self.tcp_addr.is_none()
+            && self.udp_addr.is_none()
+            && !self.offline
+            && !self.custom_transport
+        {
+            errors.push(BuildError::NoTransport);
+        }
 
-        ClientWorld::new(s.resources, main_world)
+        BuildReport {
+            systems: world::enabled_systems(&systems, &self.without_systems)
+                .map(|(name, _)| *name)
+                .collect(),
+            resources,
+            components,
+            errors,
+        }
     }
 }
 
@@ -145,11 +311,381 @@ impl<
         CompressionStrategy,
     >
 {
+    /// Creates a builder without the default systems and resources.
+    ///
+    /// Call `default_resources` with the compression of choice, or insert all resources yourself.
+    pub fn bare() -> Self {
+        ClientWorldBuilder {
+            resources: Default::default(),
+            systems: Vec::new(),
+            without_systems: Vec::new(),
+            state_applier: Box::new(DefaultStateApplier),
+            max_resimulation_frames: None,
+            prune_acked_commands: false,
+            coalesce_state_updates: false,
+            bundle_messages: false,
+            initial_sync_slicing: None,
+            tcp_addr: None,
+            socket_options: SocketOptions::default(),
+            udp_addr: None,
+            udp_config: UdpConfig::default(),
+            network_thread: false,
+            offline: false,
+            custom_transport: false,
+
+            cs: PhantomData,
+            stcm: PhantomData,
+            ctsm: PhantomData,
+            ctsc: PhantomData,
+        }
+    }
+
+    /// Returns the configured system groups and the ones that are added on build.
+    fn system_groups(&self) -> Vec<SystemGroup> {
+        let mut systems = self.systems.clone();
+
+        if self.tcp_addr.is_some() && !self.network_thread {
+            systems.push((
+                world::TCP_CLIENT_SYSTEMS,
+                <Builder as BuilderExt>::add_tcp_client_systems::<
+                    ServerMessage<ServerToClientMessage, ClientToServerCommand>,
+                    ClientMessage<ClientToServerMessage, ClientToServerCommand>,
+                    ClientToServerCommand,
+                >,
+            ));
+        }
+
+        if self.udp_addr.is_some() {
+            systems.push((
+                world::UDP_CLIENT_SYSTEMS,
+                <Builder as BuilderExt>::add_udp_client_systems::<
+                    ServerMessage<ServerToClientMessage, ClientToServerCommand>,
+                    ClientMessage<ClientToServerMessage, ClientToServerCommand>,
+                    ClientToServerCommand,
+                >,
+            ));
+        }
+
+        // Runs after the user systems, so they see the markers of the last applied state update.
+        systems.push((world::REPLICATED_MARKER_CLEANUP, clear_replicated_markers_system));
+        systems
+    }
+
+    /// Connects to the server over TCP when the world is built, `build` fails with
+    /// `BuildError::ConnectionFailed` if the server can not be reached.
     pub fn with_tcp(mut self, addr: SocketAddr) -> Self {
-        self.system_builder = self.system_builder.add_tcp_client_systems::<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>();
-        self.resources.insert_tcp_client_resources::<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>(addr);
+        if !self.offline {
+            self.tcp_addr = Some(addr);
+            self.udp_addr = None;
+        }
+        self
+    }
+
+    /// Connects to the server over UDP instead of TCP, see `UdpConfig` for the reliability
+    /// of the messages.
+    ///
+    /// The UDP transport always runs in the world schedule, `with_network_thread` is ignored.
+    pub fn with_udp(mut self, addr: SocketAddr) -> Self {
+        if !self.offline {
+            self.udp_addr = Some(addr);
+            self.tcp_addr = None;
+        }
+        self
+    }
+
+    /// Sets the reliability of the messages and the timing of the UDP transport.
+    pub fn with_udp_config(mut self, config: UdpConfig) -> Self {
+        self.udp_config = config;
+        self
+    }
+
+    /// Tunes the TCP socket of the connection to the server, see `SocketOptions`.
+    pub fn with_socket_options(mut self, options: SocketOptions) -> Self {
+        self.socket_options = options;
+        self
+    }
+
+    /// Marks the transport as set up by the user, who moves the messages
+    /// between the `ClientPostBox` and the server with their own systems or resources.
+    pub fn with_custom_transport(mut self) -> Self {
+        self.custom_transport = true;
+        self
+    }
+
+    /// Sets what happens with the commands issued while the client is disconnected.
+    pub fn with_command_buffer_policy(mut self, policy: CommandBufferPolicy) -> Self {
+        self.resources
+            .insert(ClientConnection::<ClientToServerCommand>::new(policy));
+        self
+    }
+
+    /// Runs the client without server, commands are accepted locally.
+    ///
+    /// No transport is set up, `with_tcp` and `with_udp` are ignored.
+    pub fn with_offline_mode(mut self) -> Self {
+        self.tcp_addr = None;
+        self.udp_addr = None;
+        self.offline = true;
+        self.resources
+            .insert(ClientConnection::<ClientToServerCommand>::offline());
+        self
+    }
+
+    /// Reads and writes the TCP connection on a dedicated thread instead of in the world schedule.
+    ///
+    /// The `ClientNetworkThread` resource then replaces the `ClientPostBox` resource, it forwards
+    /// the events of the transport to the `NetworkEventQueue`. Only the client has a network
+    /// thread, the server world pumps its TCP listener in its tick.
+    pub fn with_network_thread(mut self, enabled: bool) -> Self {
+        self.network_thread = enabled;
+        self
+    }
+
+    /// Registers a component field that references another entity, see `EntityReferences`.
+    pub fn with_entity_reference<T: Component>(
+        mut self,
+        policy: ReferencePolicy,
+        field: fn(&mut T) -> &mut NetworkEntity,
+    ) -> Self {
+        if !self.resources.contains::<EntityReferences>() {
+            self.resources.insert(EntityReferences::new());
+        }
+        self.resources
+            .get_mut::<EntityReferences>()
+            .unwrap()
+            .register(policy, field);
+        self
+    }
+
+    /// Samples one command per command frame from the raw input, see `InputSampler`.
+    pub fn with_input_sampler(mut self, sampler: InputSampler<ClientToServerCommand>) -> Self {
+        self.resources.insert(sampler);
+        self
+    }
+
+    /// Shares the connection between `count` local players, e.g. for split-screen.
+    ///
+    /// The commands carry the local player index, use `LocalPlayers::command` to create them
+    /// and a `PlayerCommand` as client command type.
+    pub fn with_local_players(mut self, count: LocalPlayer) -> Self {
+        self.resources.insert(LocalPlayers::new(count));
+        self
+    }
+
+    /// Sends the uid lifecycle of the replicated entities to the `UidEvents` resource.
+    pub fn with_uid_events(mut self) -> Self {
+        self.resources.insert(UidEvents::new());
+        self
+    }
+
+    /// Replays mispredictions with game code instead of the `ResimulationBuffer`, the ranges
+    /// are queued in the `ResimulationQueue` until they are marked resolved.
+    pub fn with_custom_resimulation(mut self) -> Self {
+        self.resources.insert(ResimulationQueue::<ClientToServerCommand>::new(
+            ResimulationExecutor::Custom,
+        ));
         self
     }
+
+    /// Adapts the interpolation delay between `min` and `max` to the arrival jitter of the state
+    /// updates, see `InterpolationDelay`.
+    pub fn with_adaptive_interpolation(mut self, min: Duration, max: Duration) -> Self {
+        self.resources.insert(InterpolationDelay::new(min, max));
+        self
+    }
+
+    /// Keeps the last `capacity` received chat messages, see `ChatInbox`.
+    #[cfg(feature = "chat")]
+    pub fn with_chat_inbox(mut self, capacity: usize) -> Self {
+        self.resources.insert(ChatInbox::new(capacity));
+        self
+    }
+
+    /// Drops the late changes of removed entities, so they are not applied to a new entity with
+    /// the same uid. Removed uids are remembered for `retention` command frames,
+    /// see `UidGenerations`.
+    pub fn with_uid_generations(mut self, retention: CommandFrame) -> Self {
+        self.resources.insert(UidGenerations::new(retention));
+        self
+    }
+
+    /// Sends the old and new value of changed `T` components to the `ChangeEvents` resource.
+    ///
+    /// Call it for every reported type, other types are not serialized before a change.
+    pub fn with_change_events<T: Component>(mut self) -> Self {
+        if !self.resources.contains::<ChangeEvents>() {
+            self.resources.insert(ChangeEvents::new());
+        }
+        self.resources
+            .get_mut::<ChangeEvents>()
+            .unwrap()
+            .watch::<T>();
+        self
+    }
+
+    /// Reports the entities with component `T` that the server did not update within the frames
+    /// of the policy, see `StaleSweep`.
+    pub fn with_stale_sweep<T: Component>(mut self, policy: StalePolicy) -> Self {
+        if !self.resources.contains::<StaleSweep>() {
+            self.resources.insert(StaleSweep::new());
+        }
+        self.resources
+            .get_mut::<StaleSweep>()
+            .unwrap()
+            .watch::<T>(policy);
+        self
+    }
+
+    /// Acknowledges the applied state updates, for a server with
+    /// `ServerWorldBuilder::with_state_acknowledgements`. See `StateAcks`.
+    pub fn with_state_acknowledgements(mut self) -> Self {
+        self.resources.insert(StateAcks::new());
+        self
+    }
+
+    /// Deserializes the initial state syncs with the strategy, the server must use the same one.
+    /// See `SerializationStrategy`.
+    pub fn with_serialization<S: SerializationStrategy>(mut self, strategy: S) -> Self {
+        self.resources.insert(SerializationResource::new(strategy));
+        self
+    }
+
+    /// Logs the last `capacity` local changes that the server state overwrote,
+    /// see `AuthorityAudit`.
+    pub fn with_authority_audit(mut self, capacity: usize) -> Self {
+        self.resources.insert(AuthorityAudit::new(capacity));
+        self
+    }
+
+    /// Keeps a snapshot of the replicated world for the last `frames` command frames.
+    /// See `ClientWorld::world_at`.
+    pub fn with_history(mut self, frames: usize) -> Self {
+        self.resources.insert(WorldHistory::with_capacity(frames));
+        self
+    }
+
+    /// Replaces the default `RealClock` by the given clock.
+    pub fn with_clock<C: Clock>(mut self, clock: C) -> Self {
+        self.resources.insert(ClockResource::new(clock));
+        self
+    }
+
+    /// Converts the server component type `S` into the client component type `T` on apply.
+    /// See `ComponentTransforms`.
+    pub fn with_component_transform<S, T>(mut self, convert: fn(&S) -> T) -> Self
+    where
+        S: Clone + SerdeDiff + for<'de> Deserialize<'de> + Send + Sync + 'static,
+        T: Component,
+    {
+        if !self.resources.contains::<ComponentTransforms>() {
+            self.resources.insert(ComponentTransforms::new());
+        }
+        self.resources
+            .get_mut::<ComponentTransforms>()
+            .unwrap()
+            .register(convert);
+        self
+    }
+
+    /// Inserts a user resource that is saved every command frame and restored before
+    /// a resimulation, see `RollbackResources`.
+    pub fn with_rollback_resource<R: RollbackResource>(mut self, resource: R) -> Self {
+        self.resources.insert(resource);
+        if !self.resources.contains::<RollbackResources>() {
+            self.resources.insert(RollbackResources::new());
+        }
+        self.resources
+            .get_mut::<RollbackResources>()
+            .unwrap()
+            .register::<R>();
+        self
+    }
+
+    /// Replaces the `DefaultStateApplier` that applies the received state updates to the world.
+    pub fn with_state_applier<A: StateApplier<ClientToServerCommand>>(
+        mut self,
+        applier: A,
+    ) -> Self {
+        self.state_applier = Box::new(applier);
+        self
+    }
+
+    /// Bounds the frame time of a misprediction: a state update that requires replaying more
+    /// than `frames` command frames is applied as it is, without resimulation.
+    ///
+    /// The skipped replays raise `ClientEvent::ResimulationSkipped`.
+    pub fn with_max_resimulation_frames(mut self, frames: CommandFrame) -> Self {
+        self.max_resimulation_frames = Some(frames);
+        self
+    }
+
+    /// Removes the predictions of the `ClientCommandBuffer` up to the command frame of each
+    /// applied state update, the buffer then holds about a round trip of frames.
+    ///
+    /// The capacity of the buffer stays the upper bound, e.g. while no updates arrive.
+    pub fn with_acked_command_pruning(mut self) -> Self {
+        self.prune_acked_commands = true;
+        self
+    }
+
+    /// Validates the components that game code predicts to add or remove against the server,
+    /// see `StructuralPredictions`.
+    pub fn with_structural_prediction(mut self) -> Self {
+        self.resources.insert(StructuralPredictions::new());
+        self
+    }
+
+    /// Drops received data that would be skipped partially and reports it, see `StrictSchema`.
+    pub fn with_strict_schema(mut self) -> Self {
+        self.resources.insert(StrictSchema::new());
+        self
+    }
+
+    /// Skips the changes of `DiffPolicy::Whole` components that are overwritten by a later state
+    /// update of the same tick, e.g. after a hitch. Inserts and removals are always applied.
+    pub fn with_state_update_coalescing(mut self) -> Self {
+        self.coalesce_state_updates = true;
+        self
+    }
+
+    /// Sends the pong, the state acknowledgement and the commands of a tick in one
+    /// `ClientMessage::Bundle` instead of a transport frame each.
+    ///
+    /// The server world unpacks the bundles, see `ServerConfig::bundle_messages` for the other
+    /// direction.
+    pub fn with_message_bundling(mut self) -> Self {
+        self.bundle_messages = true;
+        self
+    }
+
+    /// Merges the initial sync over several ticks instead of at once, so joining a large world
+    /// does not hitch a frame. Each command frame merges up to `entities_per_tick` entities and
+    /// stops early once the merge took `budget`. State updates are held back and applied in
+    /// order once the whole initial sync is merged.
+    pub fn with_time_sliced_initial_sync(
+        mut self,
+        entities_per_tick: usize,
+        budget: Duration,
+    ) -> Self {
+        self.initial_sync_slicing = Some((entities_per_tick, budget));
+        self
+    }
+}
+
+/// Read-only view of the client world as it was at some command frame.
+pub struct WorldView {
+    command_frame: CommandFrame,
+    world: World,
+}
+
+impl WorldView {
+    pub fn command_frame(&self) -> CommandFrame {
+        self.command_frame
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
 }
 
 pub struct ClientWorld<
@@ -160,8 +696,22 @@ pub struct ClientWorld<
 > {
     pub(crate) world: WorldInstance,
     pub(crate) resources: Resources,
-    // TODO: HACK, REMOVE!
-    has_received_first_message: bool,
+    protocol: ClientProtocol,
+    contexts: HashMap<ContextId, ClientContext<ClientToServerCommand>>,
+    state_applier: Box<dyn StateApplier<ClientToServerCommand>>,
+    max_resimulation_frames: Option<CommandFrame>,
+    prune_acked_commands: bool,
+    coalesce_state_updates: bool,
+    bundle_messages: bool,
+    initial_sync_slicing: Option<(usize, Duration)>,
+    // The initial sync that is being merged and the state updates held back until it completes.
+    pending_sync: Option<SlicedMerge>,
+    held_updates: Vec<WorldState>,
+    injected: Vec<
+        transport::ServerToClientMessage<
+            ServerMessage<ServerToClientMessage, ClientToServerCommand>,
+        >,
+    >,
 
     c: PhantomData<CompressionStrategy>,
     stcm: PhantomData<ServerToClientMessage>,
@@ -194,7 +744,17 @@ impl<
         ClientWorld {
             world,
             resources,
-            has_received_first_message: false,
+            protocol: ClientProtocol::new(),
+            contexts: HashMap::new(),
+            state_applier: Box::new(DefaultStateApplier),
+            max_resimulation_frames: None,
+            prune_acked_commands: false,
+            coalesce_state_updates: false,
+            bundle_messages: false,
+            initial_sync_slicing: None,
+            pending_sync: None,
+            held_updates: Vec::new(),
+            injected: Vec::new(),
 
             c: PhantomData,
             stcm: PhantomData,
@@ -207,24 +767,43 @@ impl<
         &mut self.world.world
     }
 
+    /// The number of merged and total entities of the initial sync that is being merged,
+    /// see `ClientWorldBuilder::with_time_sliced_initial_sync`.
+    pub fn initial_sync_progress(&self) -> Option<(usize, usize)> {
+        self.pending_sync
+            .as_ref()
+            .map(|pending| (pending.total() - pending.remaining(), pending.total()))
+    }
+
     pub fn tick(&mut self) {
         let resources = &mut self.resources;
 
         self.world.execute(resources);
 
-        let mut command_ticker = resources.get_mut::<CommandFrameTicker>().unwrap();
+        if let Some(mut queue) = resources.get_mut::<ResimulationQueue<ClientToServerCommand>>() {
+            queue.systems_executed();
+        }
 
-        if command_ticker.try_tick() {
-            let mut postbox = resources
-                .get_mut::<PostBox<
-                    transport::ServerToClientMessage<ServerToClientMessage>,
-                    transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
-                >>()
-                .unwrap();
+        let mut command_ticker = resources.get_mut::<CommandFrameTicker>().unwrap();
+        let clock = resources.get::<ClockResource>().unwrap();
+
+        if command_ticker.try_tick(clock.now()) {
+            // Without network thread the post box is pumped by the transport systems in the schedule.
+            let mut postbox = resources.get_mut::<ClientPostBox<
+                ServerToClientMessage,
+                ClientToServerMessage,
+                ClientToServerCommand,
+            >>();
+            let mut network_thread = resources.get_mut::<ClientNetworkThread<
+                ServerMessage<ServerToClientMessage, ClientToServerCommand>,
+                ClientMessage<ClientToServerMessage, ClientToServerCommand>,
+                ClientToServerCommand,
+            >>();
 
             let mut uid_allocator = resources.get_mut::<UidAllocator<Entity>>().unwrap();
             let registered = resources.get_mut::<RegisteredComponentsResource>().unwrap();
             let universe = resources.get_mut::<Universe>().unwrap();
+            let serialization = resources.get::<SerializationResource>().unwrap();
 
             let mut client_buffer = resources
                 .get_mut::<ClientCommandBuffer<ClientToServerCommand>>()
@@ -233,101 +812,979 @@ impl<
                 .get_mut::<ResimulationBuffer<ClientToServerCommand>>()
                 .unwrap();
 
-            let inbox = postbox.drain_inbox(|m| match m {
-                transport::ServerToClientMessage::StateUpdate(_) => true,
-                transport::ServerToClientMessage::InitialStateSync(_) => true,
-                _ => false,
+            let mut command_results = resources
+                .get_mut::<CommandResultEvents<ClientToServerCommand>>()
+                .unwrap();
+            let mut client_events = resources.get_mut::<ClientEvents>().unwrap();
+            let mut prediction_metrics = resources.get_mut::<PredictionMetrics>().unwrap();
+            let mut bandwidth_metrics = resources.get_mut::<BandwidthMetrics>().unwrap();
+            let mut transforms = resources.get_mut::<ComponentTransforms>().unwrap();
+            let mut replicated_changes = resources.get_mut::<ReplicatedChanges>().unwrap();
+            let uid_events = resources.get::<UidEvents>();
+            let change_events = resources.get::<ChangeEvents>();
+            let mut rollback = resources.get_mut::<RollbackResources>();
+            let mut generations = resources.get_mut::<UidGenerations>();
+            let mut interpolation = resources.get_mut::<InterpolationDelay>();
+            let strict = resources.get::<StrictSchema>();
+            let mut structural = resources.get_mut::<StructuralPredictions>();
+            let mut acks = resources.get_mut::<StateAcks>();
+            let mut audit = resources.get_mut::<AuthorityAudit>();
+            let mut resimulation_queue =
+                resources.get_mut::<ResimulationQueue<ClientToServerCommand>>();
+            let references = resources.get::<EntityReferences>();
+
+            let mut inbox = match (&mut network_thread, &mut postbox) {
+                (Some(network_thread), _) => network_thread.drain_inbox(is_sync_message),
+                (None, Some(postbox)) => postbox.drain_inbox(is_sync_message),
+                (None, None) => Vec::new(),
+            };
+
+            // Injected user messages wait in the inbox with the received ones.
+            let (injected, user): (Vec<_>, Vec<_>) =
+                self.injected.drain(..).partition(is_sync_message);
+            inbox.extend(injected);
+
+            for message in user {
+                match (&mut network_thread, &mut postbox) {
+                    (Some(network_thread), _) => network_thread.add_to_inbox(message),
+                    (None, Some(postbox)) => postbox.add_to_inbox(message),
+                    (None, None) => {}
+                }
+            }
+
+            let mut handled = Vec::new();
+            for packet in inbox {
+                let packet = ServerToClient::<_, WorldState>::from(packet);
+
+                for action in self.protocol.handle(packet) {
+                    match action {
+                        // The shared state updates are serialized with the format of the worlds.
+                        ClientAction::DecodeStateUpdate(bytes) => {
+                            match serialization.deserialize::<WorldState>(&bytes) {
+                                Ok(state) => {
+                                    handled.extend(self.protocol.decoded_state_update(state))
+                                }
+                                Err(e) => {
+                                    log::error!("Failed to deserialize a state update: {}", e)
+                                }
+                            }
+                        }
+                        action => handled.push(action),
+                    }
+                }
+            }
+
+            // Several state updates arrive in one tick after a hitch, they are applied in order.
+            let mut actions = order_state_updates(handled);
+
+            if self.coalesce_state_updates {
+                coalesce_whole_changes(&mut actions, &registered);
+            }
+
+            // The held state updates build on the initial sync, they go before the received ones.
+            if let (Some(pending), Some((entities_per_tick, budget))) =
+                (self.pending_sync.as_mut(), self.initial_sync_slicing)
+            {
+                let merged = pending.merge_slice(
+                    &mut self.world.world,
+                    &registered,
+                    &serialization,
+                    entities_per_tick,
+                    budget,
+                    &clock,
+                );
+                allocate_merged(&merged, &mut uid_allocator, uid_events.as_deref());
+                transforms.convert_world(&mut self.world.world);
+
+                if pending.is_complete() {
+                    self.pending_sync = None;
+                    actions.splice(
+                        0..0,
+                        self.held_updates.drain(..).map(ClientAction::ApplyStateUpdate),
+                    );
+                }
+            }
+
+            let mut connection = resources
+                .get_mut::<ClientConnection<ClientToServerCommand>>()
+                .unwrap();
+
+            // The transport systems report a lost connection in the `NetworkEventQueue`,
+            // the network thread forwards the events of its transport.
+            let mut network_events = resources.get_mut::<NetworkEventQueue>();
+            if let (Some(queue), Some(network_thread)) =
+                (network_events.as_deref_mut(), network_thread.as_mut())
+            {
+                for event in network_thread.drain_events() {
+                    queue.push(event);
+                }
+            }
+            let transport_disconnected = network_events
+                .as_deref_mut()
+                .map_or(false, drain_disconnects);
+            let thread_stopped = network_thread
+                .as_ref()
+                .map_or(false, |thread| !thread.is_alive());
+
+            if transport_disconnected || thread_stopped {
+                connection.set_state(ConnectionState::Disconnected);
+            } else if !actions.is_empty() {
+                connection.set_state(ConnectionState::Connected);
+            }
+
+            let mut pings = Vec::new();
+
+            for action in actions {
+                match action {
+                    ClientAction::AdjustSimulation {
+                        command_frame,
+                        offset,
+                    } => adjust_simulation_speed(offset, command_frame, &mut command_ticker),
+                    ClientAction::SetCommandFrame(command_frame) => {
+                        command_ticker.set_command_frame(command_frame)
+                    }
+                    ClientAction::ApplyStateUpdate(update) if self.pending_sync.is_some() => {
+                        self.held_updates.push(update)
+                    }
+                    ClientAction::ApplyStateUpdate(update)
+                        if acks
+                            .as_deref()
+                            .map_or(false, |acks| !acks.is_current(update.command_frame)) =>
+                    {
+                        // A newer update that was applied already carried its changes.
+                    }
+                    ClientAction::ApplyStateUpdate(mut update) => {
+                        if let Some(strict) = strict.as_deref() {
+                            let result = strict.validate_update(&update, &registered);
+
+                            if !check_schema(result, &mut client_events) {
+                                continue;
+                            }
+                        }
+
+                        record_bandwidth(&mut bandwidth_metrics, &registered, &update);
+                        let update_frame = update.command_frame;
+
+                        if let Some(interpolation) = interpolation.as_deref_mut() {
+                            interpolation.record_arrival(clock.now());
+                        }
+
+                        let is_merged = match acks.as_deref_mut() {
+                            Some(acks) => restore_baseline(
+                                acks,
+                                &mut self.world.world,
+                                &registered,
+                                &serialization,
+                                &update,
+                            ),
+                            None => false,
+                        };
+
+                        let mut state_updater = StateUpdater::new(
+                            &mut uid_allocator,
+                            &mut self.world.world,
+                            &registered,
+                            &serialization,
+                            &mut update,
+                            &mut client_buffer,
+                            &mut resimulation_buffer,
+                            command_ticker.command_frame(),
+                            Lz4,
+                        )
+                        .with_prediction_metrics(&mut prediction_metrics)
+                        .with_replicated_changes(&mut replicated_changes)
+                        .with_component_transforms(&mut transforms)
+                        .with_client_events(&mut client_events);
+
+                        if let Some(events) = uid_events.as_deref() {
+                            state_updater = state_updater.with_uid_events(events);
+                        }
+                        if let Some(references) = references.as_deref() {
+                            state_updater = state_updater.with_entity_references(references);
+                        }
+                        if let Some(events) = change_events.as_deref() {
+                            state_updater = state_updater.with_change_events(events);
+                        }
+                        if let Some(rollback) = rollback.as_deref_mut() {
+                            state_updater = state_updater.with_rollback_resources(rollback);
+                        }
+                        if let Some(queue) = resimulation_queue.as_deref_mut() {
+                            state_updater = state_updater.with_resimulation_queue(queue);
+                        }
+                        if let Some(generations) = generations.as_deref_mut() {
+                            state_updater = state_updater.with_uid_generations(generations);
+                        }
+                        if let Some(structural) = structural.as_deref_mut() {
+                            state_updater = state_updater.with_structural_predictions(structural);
+                        }
+                        if let Some(audit) = audit.as_deref_mut() {
+                            state_updater = state_updater.with_authority_audit(audit);
+                        }
+                        if let Some(frames) = self.max_resimulation_frames {
+                            state_updater = state_updater.with_max_resimulation_frames(frames);
+                        }
+                        if self.prune_acked_commands {
+                            state_updater = state_updater.with_acked_command_pruning();
+                        }
+
+                        self.state_applier.apply(state_updater);
+
+                        if let Some(acks) = acks.as_deref_mut() {
+                            acks.applied_update(update_frame, is_merged);
+                        }
+                    }
+                    ClientAction::ApplyInitialSync(initial_sync_bytes) => {
+                        let initial_sync =
+                            match serialization.deserialize::<InitialSync>(&initial_sync_bytes) {
+                                Ok(initial_sync) => initial_sync,
+                                Err(e) => {
+                                    log::error!("Failed to deserialize the initial sync: {}", e);
+                                    client_events
+                                        .push(ClientEvent::InitialSyncFailed(e.to_string()));
+                                    continue;
+                                }
+                            };
+
+                        if let Some(strict) = strict.as_deref() {
+                            let consumed = serialization
+                                .serialize(&initial_sync)
+                                .map_or(initial_sync_bytes.len(), |bytes| bytes.len());
+
+                            let result = strict.validate_packet(
+                                command_ticker.command_frame(),
+                                &initial_sync_bytes,
+                                consumed,
+                            );
+
+                            if !check_schema(result, &mut client_events) {
+                                continue;
+                            }
+                        }
+
+                        if let Some(acks) = acks.as_deref_mut() {
+                            acks.reset();
+                        }
+
+                        // (Re)seed the rng, the server might have chosen a new one since the last sync.
+                        resources
+                            .get_mut::<SyncedRng>()
+                            .unwrap()
+                            .reseed(initial_sync.rng_seed);
+
+                        let registry = registered.legion_registry();
+                        match serialization
+                            .deserialize_seed(&initial_sync.world, registry.as_deserialize(&universe))
+                        {
+                            Ok(world) if self.initial_sync_slicing.is_some() => {
+                                // Updates held for a previous initial sync are outdated.
+                                self.pending_sync =
+                                    Some(SlicedMerge::new(&self.world.world, world));
+                                self.held_updates.clear();
+                            }
+                            Ok(world) => {
+                                apply_initial_sync(
+                                    &mut self.world.world,
+                                    &world,
+                                    &registered,
+                                    &serialization,
+                                    &mut uid_allocator,
+                                    uid_events.as_deref(),
+                                );
+                                transforms.convert_world(&mut self.world.world);
+                            }
+                            Err(e) => {
+                                log::error!("Failed to deserialize the initial sync world: {}", e);
+                                client_events.push(ClientEvent::InitialSyncFailed(e.to_string()));
+                            }
+                        }
+                    }
+                    ClientAction::CommandResult(result) => command_results.push(result),
+                    ClientAction::ApplyContextInitialSync(id, initial_sync) => {
+                        let synced = serialization
+                            .deserialize::<InitialSync>(&initial_sync)
+                            .and_then(|initial_sync| {
+                                serialization.deserialize_seed(
+                                    &initial_sync.world,
+                                    registered.legion_registry().as_deserialize(&universe),
+                                )
+                            });
+                        let synced = match synced {
+                            Ok(synced) => synced,
+                            Err(e) => {
+                                log::error!(
+                                    "Failed to deserialize the sync of context {}: {}",
+                                    id,
+                                    e
+                                );
+                                client_events.push(ClientEvent::ContextSyncFailed {
+                                    context: id,
+                                    error: e.to_string(),
+                                });
+                                continue;
+                            }
+                        };
+
+                        let context = self.contexts.entry(id).or_insert_with(ClientContext::new);
+
+                        apply_initial_sync(
+                            &mut context.world,
+                            &synced,
+                            &registered,
+                            &serialization,
+                            &mut context.allocator,
+                            None,
+                        );
+                    }
+                    ClientAction::ApplyContextStateUpdate(id, update) => {
+                        if let Some(strict) = strict.as_deref() {
+                            if !self.contexts.contains_key(&id) {
+                                let result = strict.unexpected(
+                                    update.command_frame,
+                                    "state update of a context that was not synced",
+                                );
+
+                                if !check_schema(result, &mut client_events) {
+                                    continue;
+                                }
+                            }
+                        }
+
+                        let context = self.contexts.entry(id).or_insert_with(ClientContext::new);
+                        let mut update = WorldState::from(update);
+
+                        let state_updater = StateUpdater::new(
+                            &mut context.allocator,
+                            &mut context.world,
+                            &registered,
+                            &serialization,
+                            &mut update,
+                            &mut context.client_buffer,
+                            &mut context.resimulation_buffer,
+                            command_ticker.command_frame(),
+                            Lz4,
+                        );
+
+                        self.state_applier.apply(state_updater);
+                    }
+                    ClientAction::Disconnected(reason) => {
+                        connection.set_state(ConnectionState::Disconnected);
+                        client_events.push(ClientEvent::Disconnected(reason));
+                    }
+                    ClientAction::RegionManifest(manifest) => {
+                        client_events.push(ClientEvent::RegionManifest(manifest))
+                    }
+                    ClientAction::Pong(sent) => pings.push(sent),
+                    ClientAction::StateBaseline {
+                        command_frame,
+                        baseline,
+                    } => {
+                        if let Some(acks) = acks.as_deref_mut() {
+                            acks.set_baseline(command_frame, baseline);
+                        }
+                    }
+                    // Decoded before the state updates were ordered.
+                    ClientAction::DecodeStateUpdate(_) => {}
+                    ClientAction::Reseed(seed) => {
+                        resources.get_mut::<SyncedRng>().unwrap().reseed(seed)
+                    }
+                    // The user messages of bundles are delivered with the ones received on their
+                    // own, those are not drained from the inbox, see `is_sync_message`.
+                    ClientAction::User(message) => {
+                        let message =
+                            transport::ServerToClientMessage::Message(ServerMessage::User(message));
+
+                        match (&mut network_thread, &mut postbox) {
+                            (Some(network_thread), _) => network_thread.add_to_inbox(message),
+                            (None, Some(postbox)) => postbox.add_to_inbox(message),
+                            (None, None) => {}
+                        }
+                    }
+                }
+            }
+
+            let replaced = resources
+                .get_mut::<EphemeralEntities>()
+                .unwrap()
+                .update(&mut self.world.world, command_ticker.command_frame());
+
+            if let Some(events) = uid_events.as_deref() {
+                for (predicted, entity) in replaced {
+                    if let Some(uid) = world::uid_of(&self.world.world, entity) {
+                        events.send(UidEvent::Remapped {
+                            predicted,
+                            uid,
+                            entity,
+                        });
+                    }
+                }
+            }
+
+            // The entities updated in this tick carry the `ReplicatedThisFrame` marker.
+            if let Some(mut sweep) = resources.get_mut::<StaleSweep>() {
+                let command_frame = command_ticker.command_frame();
+
+                for (entity, _) in <(Entity, Read<ReplicatedThisFrame>)>::query()
+                    .iter(&self.world.world)
+                {
+                    sweep.updated(*entity, command_frame);
+                }
+
+                for stale in sweep.sweep(&self.world.world, command_frame) {
+                    client_events.push(ClientEvent::PossiblyStale {
+                        entity: stale.entity,
+                        uid: stale.uid,
+                        last_updated: stale.last_updated,
+                    });
+                }
+            }
+
+            if let Some(mut history) = resources.get_mut::<WorldHistory>() {
+                let command_frame = command_ticker.command_frame();
+                let snapshot = serialization.serialize(
+                    &self
+                        .world
+                        .world
+                        .as_serializable(registered.filter(), registered.legion_registry()),
+                );
+
+                // The history misses the frame, like a frame that was not recorded.
+                match snapshot {
+                    Ok(snapshot) => history.record(command_frame, snapshot),
+                    Err(e) => log::error!(
+                        "Failed to serialize the world snapshot of frame {}: {}",
+                        command_frame,
+                        e
+                    ),
+                }
+            }
+
+            // Saved before the restore, the frame is replayed from the restored state.
+            if let Some(rollback) = rollback.as_deref_mut() {
+                rollback.save(resources, command_ticker.command_frame());
+
+                for resource in rollback.restore_pending(resources) {
+                    log::warn!("{} is not rewound, its frame was not saved anymore.", resource);
+                }
+            }
+
+            let mut outgoing = Vec::new();
+
+            // The server measures the round trip time with the pings.
+            for sent in pings {
+                outgoing.push(transport::ClientToServerMessage::Message(
+                    ClientMessage::Pong(sent),
+                ));
+            }
+
+            // The server merges its updates since the last acknowledged one.
+            if let Some(command_frame) = acks.as_deref_mut().and_then(|acks| acks.take_ack()) {
+                outgoing.push(transport::ClientToServerMessage::Message(
+                    ClientMessage::StateAck(command_frame),
+                ));
+            }
+
+            // Replay the commands that were held back while disconnected.
+            if connection.state() == ConnectionState::Connected {
+                for (command_frame, command) in connection.take_held() {
+                    outgoing.push(transport::ClientToServerMessage::Command(
+                        command_frame,
+                        command,
+                    ));
+                }
+            }
+
+            let mut commands = Vec::new();
+            let mut sampler = resources.get_mut::<InputSampler<ClientToServerCommand>>();
+
+            if let Some(sampler) = sampler.as_mut() {
+                if let Some(command) = sampler.sample(command_ticker.command_frame()) {
+                    commands.push((command_ticker.command_frame(), command.clone()));
+                }
+            }
+
+            for entry in client_buffer.iter_history(1) {
+                // There is one command per frame, the sampled command was already sent.
+                let is_sampled = sampler
+                    .as_ref()
+                    .map_or(false, |sampler| sampler.command_at(entry.command_frame).is_some());
+
+                if !is_sampled {
+                    commands.push((entry.command_frame, entry.command.clone()));
+                }
+
+                entry.is_sent = true;
+            }
+
+            // Sent commands to server
+            for (command_frame, command) in commands {
+                match connection.state() {
+                    ConnectionState::Connecting | ConnectionState::Connected => outgoing.push(
+                        transport::ClientToServerMessage::Command(command_frame, command),
+                    ),
+                    ConnectionState::Disconnected => connection.hold(command_frame, command),
+                    ConnectionState::Offline => command_results.push(CommandResult {
+                        command_frame,
+                        outcome: CommandOutcome::Accepted,
+                    }),
+                }
+            }
+
+            // The messages of the tick are sent in one transport frame.
+            if self.bundle_messages && outgoing.len() > 1 {
+                let messages = outgoing
+                    .drain(..)
+                    .map(|message| match message {
+                        transport::ClientToServerMessage::Message(message) => message,
+                        transport::ClientToServerMessage::Command(command_frame, command) => {
+                            ClientMessage::Command(command_frame, command)
+                        }
+                    })
+                    .collect();
+
+                outgoing.extend(
+                    ClientMessage::bundle(messages).map(transport::ClientToServerMessage::Message),
+                );
+            }
+
+            for message in outgoing {
+                match (&network_thread, &mut postbox) {
+                    (Some(network_thread), _) => network_thread.send(message),
+                    (None, Some(postbox)) => postbox.send(message),
+                    (None, None) => {}
+                }
+            }
+        }
+    }
+
+    /// Spawns a local entity representing the predicted effect of a command, e.g. a projectile.
+    ///
+    /// Send the returned id with the command, the server adds it as component to the entity it
+    /// spawns. The local entity is removed when that entity arrives or after `ttl_frames`.
+    pub fn spawn_ephemeral_predicted<T>(&mut self, components: T, ttl_frames: u32) -> CorrelationId
+    where
+        Option<T>: IntoComponentSource,
+    {
+        let expires_at = self
+            .resources
+            .get::<CommandFrameTicker>()
+            .unwrap()
+            .command_frame()
+            + ttl_frames;
+        let entity = self.world.world.push(components);
+
+        self.resources
+            .get_mut::<EphemeralEntities>()
+            .unwrap()
+            .insert(entity, expires_at)
+    }
+
+    /// Handles the message on the next command frame as if it was received from the server,
+    /// e.g. to replay a capture. User messages are put in the inbox of the transport, where game
+    /// code drains them like the received ones.
+    pub fn inject(
+        &mut self,
+        message: transport::ServerToClientMessage<
+            ServerMessage<ServerToClientMessage, ClientToServerCommand>,
+        >,
+    ) {
+        self.injected.push(message);
+    }
+
+    pub fn connection_state(&self) -> ConnectionState {
+        self.resources
+            .get::<ClientConnection<ClientToServerCommand>>()
+            .unwrap()
+            .state()
+    }
+
+    /// Reports a connection state change detected by the transport, e.g. a disconnect.
+    pub fn set_connection_state(&mut self, state: ConnectionState) {
+        self.resources
+            .get_mut::<ClientConnection<ClientToServerCommand>>()
+            .unwrap()
+            .set_state(state);
+    }
+
+    /// Returns the replication context with the given id, if the server has synced it.
+    pub fn context(&self, id: ContextId) -> Option<&ClientContext<ClientToServerCommand>> {
+        self.contexts.get(&id)
+    }
+
+    pub fn context_mut(
+        &mut self,
+        id: ContextId,
+    ) -> Option<&mut ClientContext<ClientToServerCommand>> {
+        self.contexts.get_mut(&id)
+    }
+
+    /// Returns the replicated world as it was at the given command frame.
+    ///
+    /// Returns `None` if history is disabled (see `ClientWorldBuilder::with_history`)
+    /// or the frame is no longer in the history.
+    pub fn world_at(&self, command_frame: CommandFrame) -> Option<WorldView> {
+        let history = self.resources.get::<WorldHistory>()?;
+        let snapshot = history.snapshot(command_frame)?;
+
+        let registered = self.resources.get::<RegisteredComponentsResource>()?;
+        let serialization = self.resources.get::<SerializationResource>()?;
+        let universe = self.resources.get::<Universe>()?;
+
+        let world = serialization
+            .deserialize_seed(snapshot, registered.legion_registry().as_deserialize(&universe))
+            .ok()?;
+
+        Some(WorldView {
+            command_frame,
+            world,
+        })
+    }
+
+    /// Returns the replicated entity with the given uid.
+    pub fn entity_by_uid(&self, uid: Uid) -> Option<Entity> {
+        world::entity_by_uid(&self.world.world, uid)
+    }
+
+    /// Returns the uid of the entity, `None` for entities that are not replicated.
+    pub fn uid_of(&self, entity: Entity) -> Option<Uid> {
+        world::uid_of(&self.world.world, entity)
+    }
+
+    /// Returns the first replicated entity with the given `DebugName`.
+    #[cfg(feature = "debug-names")]
+    pub fn entity_by_name(&self, name: &str) -> Option<Entity> {
+        world::entity_by_name(&self.world.world, name)
+    }
+
+    /// Returns the `DebugName` of the entity, `None` for entities without a name.
+    #[cfg(feature = "debug-names")]
+    pub fn name_of(&self, entity: Entity) -> Option<String> {
+        world::name_of(&self.world.world, entity)
+    }
+
+    /// Returns the entities that have any of the tags of the mask, see `EntityTags`.
+    pub fn tagged(&self, mask: u32) -> Vec<Entity> {
+        EntityTags::tagged(&self.world.world, mask)
+    }
+
+    /// Returns the replicated entities that have none of the tags of the mask,
+    /// e.g. all but the debug entities.
+    pub fn without_tags(&self, mask: u32) -> Vec<Entity> {
+        <(Entity, Read<UidComponent>, TryRead<EntityTags>)>::query()
+            .iter(&self.world.world)
+            .filter(|(_, _, tags)| tags.map_or(true, |tags| !tags.intersects(mask)))
+            .map(|(entity, _, _)| *entity)
+            .collect()
+    }
+
+    /// Adds the component ahead of the server, e.g. when picking up an item,
+    /// see `ClientWorldBuilder::with_structural_prediction`.
+    ///
+    /// Returns `false` if the entity is not replicated.
+    pub fn predict_add_component<T: Component>(&mut self, entity: Entity, component: T) -> bool {
+        let entity_id = match world::uid_of(&self.world.world, entity) {
+            Some(uid) => uid,
+            None => return false,
+        };
+
+        match self.world.world.entry(entity) {
+            Some(mut entry) => entry.add_component(component),
+            None => return false,
+        }
+
+        self.record_structural_prediction::<T>(entity_id, StructuralChange::Added);
+        true
+    }
+
+    /// Removes the component ahead of the server, its value is restored if the server keeps it,
+    /// see `ClientWorldBuilder::with_structural_prediction`.
+    ///
+    /// Returns `false` if the entity is not replicated or does not have the component.
+    pub fn predict_remove_component<T: Component>(&mut self, entity: Entity) -> bool {
+        let entity_id = match world::uid_of(&self.world.world, entity) {
+            Some(uid) => uid,
+            None => return false,
+        };
+
+        let mut unchanged = None;
+        {
+            let registered = self.resources.get::<RegisteredComponentsResource>().unwrap();
+            let serialization = self.resources.get::<SerializationResource>().unwrap();
+            let registry_by_type = registered.by_type_id();
+
+            if let Some(registration) = registry_by_type.get(&TypeId::of::<T>()) {
+                registration.serialize_if_exists_in_world(
+                    &self.world.world,
+                    entity,
+                    &mut |serialize| unchanged = serialization.serialize(&serialize).ok(),
+                );
+            }
+        }
+
+        let unchanged = match unchanged {
+            Some(unchanged) => unchanged,
+            None => return false,
+        };
+
+        if let Some(mut entry) = self.world.world.entry(entity) {
+            entry.remove_component::<T>();
+        }
+
+        self.record_structural_prediction::<T>(entity_id, StructuralChange::Removed { unchanged });
+        true
+    }
+
+    fn record_structural_prediction<T: Component>(
+        &mut self,
+        entity_id: Uid,
+        change: StructuralChange,
+    ) {
+        let command_frame = self
+            .resources
+            .get::<CommandFrameTicker>()
+            .unwrap()
+            .command_frame();
+        let component_uid = self
+            .resources
+            .get::<RegisteredComponentsResource>()
+            .unwrap()
+            .get_uid(&TypeId::of::<T>())
+            .copied();
+
+        if let (Some(component_uid), Some(mut predictions)) = (
+            component_uid,
+            self.resources.get_mut::<StructuralPredictions>(),
+        ) {
+            predictions.record(StructuralPrediction {
+                command_frame,
+                entity_id,
+                component_uid,
+                change,
             });
+        }
+    }
 
-            for packet in inbox {
-                match packet {
-                    transport::ServerToClientMessage::StateUpdate(mut update) => {
-                        adjust_simulation_speed(
-                            update.command_frame_offset,
-                            update.command_frame,
-                            &mut command_ticker,
-                        );
+    pub fn resources(&self) -> &Resources {
+        &self.resources
+    }
 
-                        if !self.has_received_first_message {
-                            self.has_received_first_message = true;
-                            command_ticker.set_command_frame(update.command_frame + 3);
-                        }
+    pub fn resources_mut(&mut self) -> &mut Resources {
+        &mut self.resources
+    }
+}
 
-                        let mut state_updater = StateUpdater::new(
-                            &mut uid_allocator,
-                            &mut self.world.world,
-                            &registered,
-                            &mut update,
-                            &mut client_buffer,
-                            &mut resimulation_buffer,
-                            command_ticker.command_frame(),
-                            Lz4,
-                        );
+/// Merges the world of the initial state sync into the client world,
+/// and registers the uids of the inserted entities so that following state updates can resolve them.
+/// The uids of the removed entities are free again.
+fn apply_initial_sync(
+    world: &mut World,
+    synced: &World,
+    registered: &RegisteredComponentsResource,
+    serialization: &SerializationResource,
+    allocator: &mut UidAllocator<Entity>,
+    uid_events: Option<&UidEvents>,
+) -> MergeResult {
+    let merge_result = merge_initial_sync(world, synced, registered, serialization);
+    allocate_merged(&merge_result, allocator, uid_events);
+    merge_result
+}
 
-                        state_updater.apply_entity_removals();
-                        state_updater.apply_entity_inserts();
-                        state_updater.apply_removed_components();
-                        state_updater.apply_added_components();
-                        state_updater.apply_changed_components();
-                    }
-                    transport::ServerToClientMessage::InitialStateSync(world_state) => {
-                        let registry = registered.legion_registry();
-                        match registry.as_deserialize(&universe).deserialize(
-                            &mut bincode::Deserializer::from_slice(
-                                &world_state,
-                                bincode::DefaultOptions::new()
-                                    .with_fixint_encoding()
-                                    .allow_trailing_bytes(),
-                            ),
-                        ) {
-                            Ok(world) => {
-                                let mutex = registered.legion_merger();
-                                let mut merger = mutex.lock().unwrap();
-                                let merge_result = self
-                                    .world
-                                    .world
-                                    .clone_from(&world, &any(), merger.deref_mut())
-                                    .expect("Should have merged");
-
-                                for merge in merge_result.iter() {
-                                    let entry = self.world.world.entry(*merge.1).unwrap();
-                                    let uid = entry.get_component::<net_sync::uid::Uid>();
-                                }
+// Updated entities are already known by the allocator, removed ones are deallocated.
+fn allocate_merged(
+    merge_result: &MergeResult,
+    allocator: &mut UidAllocator<Entity>,
+    uid_events: Option<&UidEvents>,
+) {
+    for (uid, entity) in merge_result.inserted.iter() {
+        allocator.allocate(*entity, Some(*uid));
 
-                                let serialized = serde_json::to_value(
-                                    self.world
-                                        .world
-                                        .as_serializable(any(), registered.legion_registry()),
-                                )
-                                .unwrap();
-                            }
-                            Err(e) => {
-                                panic!("{:?}", e);
-                            }
-                        }
+        if let Some(events) = uid_events {
+            events.send(UidEvent::Allocated {
+                uid: *uid,
+                entity: *entity,
+            });
+        }
+    }
+
+    for (uid, entity) in merge_result.removed.iter() {
+        let _ = allocator.deallocate(*entity);
+
+        if let Some(events) = uid_events {
+            events.send(UidEvent::Deallocated {
+                uid: *uid,
+                entity: *entity,
+            });
+        }
+    }
+}
+
+/// Removes the changes of `DiffPolicy::Whole` components that a later state update of the actions
+/// overwrites. Diffs build on each other and are kept, like inserted, added and removed components.
+fn coalesce_whole_changes<M, C>(
+    actions: &mut [ClientAction<M, C, WorldState>],
+    registered: &RegisteredComponentsResource,
+) {
+    let registry_by_uid = registered.by_uid();
+    let is_whole = |component_uid: &Uid| {
+        registry_by_uid
+            .get(component_uid)
+            .map_or(false, |registration| registration.diff_policy() == DiffPolicy::Whole)
+    };
+
+    let mut overwritten = HashSet::new();
+
+    for action in actions.iter_mut().rev() {
+        match action {
+            ClientAction::ApplyStateUpdate(update) => {
+                update.changed.retain(|changed| {
+                    let key = (changed.entity_id(), changed.component_data().component_id());
+                    !overwritten.contains(&key)
+                });
+
+                for changed in update.changed.iter() {
+                    let component_uid = changed.component_data().component_id();
+
+                    if is_whole(&component_uid) {
+                        overwritten.insert((changed.entity_id(), component_uid));
                     }
-                    _ => {}
                 }
             }
+            // The updates before a new baseline are not overwritten by the updates after it.
+            ClientAction::ApplyInitialSync(_) | ClientAction::Disconnected(_) => {
+                overwritten.clear()
+            }
+            _ => {}
+        }
+    }
+}
 
-            // Sent commands to server
-            for command in client_buffer.iter_history(1) {
-                postbox.send(transport::ClientToServerMessage::Command(
-                    command.command_frame.clone(),
-                    command.command.clone(),
-                ));
+/// Keeps the values of the components the update changes, and restores them to their values at
+/// the baseline when the update is merged since a baseline. Returns whether it is merged.
+///
+/// A merged update repeats the diffs of the frames since the baseline, the diffs of the frames
+/// that were applied already would be applied twice otherwise.
+fn restore_baseline(
+    acks: &mut StateAcks,
+    world: &mut World,
+    registered: &RegisteredComponentsResource,
+    serialization: &SerializationResource,
+    update: &WorldState,
+) -> bool {
+    let command_frame = update.command_frame;
+    let entities = world::entities_by_uid(world);
+    let changed = update
+        .changed
+        .iter()
+        .map(|changed| (changed.entity_id(), changed.component_data().component_id()))
+        .filter_map(|(uid, component_uid)| Some((uid, *entities.get(&uid)?, component_uid)))
+        .unique()
+        .collect_vec();
+
+    for (uid, entity, component_uid) in changed.iter().copied() {
+        acks.record_preimage(command_frame, uid, component_uid, || {
+            world::serialize_component(world, registered, serialization, entity, component_uid)?
+                .ok()
+                .map(|data| data.data().to_vec())
+        });
+    }
 
-                command.is_sent = true;
-            }
+    let baseline = match acks.take_baseline(command_frame) {
+        Some(baseline) => baseline,
+        None => return false,
+    };
+
+    let registry_by_uid = registered.by_uid();
+
+    for (uid, entity, component_uid) in changed {
+        // The components no applied update since the baseline changed have their baseline value.
+        let preimage = match acks.preimage(baseline, uid, component_uid) {
+            Some(preimage) => preimage,
+            None => continue,
+        };
+        let registration = registry_by_uid
+            .get(&component_uid)
+            .expect("Component should be registered.");
+
+        let result = serialization.deserialize_with(preimage, |data| {
+            registration.add_component(world, entity, data)
+        });
+
+        if let Err(e) = result {
+            log::error!(
+                "Failed to restore {} of entity {}: {}",
+                registration.type_name(),
+                uid,
+                e
+            );
         }
     }
 
-    pub fn resources(&self) -> &Resources {
-        &self.resources
+    true
+}
+
+/// The serialized component of the entity, `None` if the entity does not have it.
+///
+/// The bytes are in the bincode format of the change tracker, like the predicted values the
+/// `AuthorityAudit` compares them with.
+fn component_bytes(
+    world: &World,
+    registered: &RegisteredComponentsResource,
+    entity: Entity,
+    component_uid: Uid,
+) -> Option<Vec<u8>> {
+    let registry_by_uid = registered.by_uid();
+    let registration = registry_by_uid.get(&component_uid)?;
+    let mut result = None;
+
+    registration.serialize_if_exists_in_world(world, entity, &mut |serialize| {
+        let mut buffer = Vec::new();
+        let serializer = &mut bincode::Serializer::new(&mut buffer, default_options());
+
+        if erased_serde::serialize(&serialize, serializer).is_ok() {
+            result = Some(buffer);
+        }
+    });
+
+    result
+}
+
+/// Raises the violation of the `StrictSchema` as `ClientEvent::SchemaViolation`, returns whether
+/// the received data is valid.
+fn check_schema(result: Result<(), SchemaViolation>, events: &mut ClientEvents) -> bool {
+    match result {
+        Ok(()) => true,
+        Err(violation) => {
+            log::error!("Strict schema violation: {}", violation);
+            events.push(ClientEvent::SchemaViolation(violation));
+            false
+        }
     }
+}
 
-    pub fn resources_mut(&mut self) -> &mut Resources {
-        &mut self.resources
+/// Records the received component bytes of the update per component type.
+///
+/// The bytes are recorded before the update is applied, so correctly predicted changes count too.
+fn record_bandwidth(
+    metrics: &mut BandwidthMetrics,
+    registered: &RegisteredComponentsResource,
+    update: &WorldState,
+) {
+    let registry_by_uid = registered.by_uid();
+
+    let components = update
+        .inserted
+        .iter()
+        .flat_map(|inserted| inserted.components().iter())
+        .chain(update.component_added.iter().map(|added| added.component_data()))
+        .chain(update.changed.iter().map(|changed| changed.component_data()));
+
+    for component in components {
+        if let Some(registration) = registry_by_uid.get(&component.component_id()) {
+            metrics.record(
+                update.command_frame,
+                registration.type_name(),
+                component.data().len(),
+            );
+        }
     }
 }
 
@@ -373,7 +1830,69 @@ fn adjust_simulation_speed(
     current_command_frame.adjust_simulation(new_rate);
 }
 
-struct StateUpdater<
+/// Consumes the disconnect events of the transport, returns whether the connection was lost.
+fn drain_disconnects(queue: &mut NetworkEventQueue) -> bool {
+    let mut disconnected = false;
+
+    for event in queue.drain().collect::<Vec<_>>() {
+        match event {
+            NetworkEvent::Disconnected(_) => disconnected = true,
+            event => queue.push(event),
+        }
+    }
+
+    disconnected
+}
+
+/// Returns whether the message is handled by the client world itself.
+fn is_sync_message<ServerToClientMessage, ClientToServerCommand>(
+    message: &transport::ServerToClientMessage<
+        ServerMessage<ServerToClientMessage, ClientToServerCommand>,
+    >,
+) -> bool {
+    match message {
+        transport::ServerToClientMessage::StateUpdate(_) => true,
+        transport::ServerToClientMessage::InitialStateSync(_) => true,
+        transport::ServerToClientMessage::Message(ServerMessage::CommandResult(_)) => true,
+        transport::ServerToClientMessage::Message(ServerMessage::ContextInitialSync(..)) => true,
+        transport::ServerToClientMessage::Message(ServerMessage::ContextStateUpdate(..)) => true,
+        transport::ServerToClientMessage::Message(ServerMessage::StateUpdatePart(_)) => true,
+        transport::ServerToClientMessage::Message(ServerMessage::Disconnect(_)) => true,
+        transport::ServerToClientMessage::Message(ServerMessage::Resync(_)) => true,
+        transport::ServerToClientMessage::Message(ServerMessage::RegionManifest(_)) => true,
+        transport::ServerToClientMessage::Message(ServerMessage::Bundle(_)) => true,
+        transport::ServerToClientMessage::Message(ServerMessage::Ping(_)) => true,
+        transport::ServerToClientMessage::Message(ServerMessage::StateBaseline(..)) => true,
+        transport::ServerToClientMessage::Message(ServerMessage::SerializedStateUpdate(_)) => true,
+        _ => false,
+    }
+}
+
+/// Applies the state updates received from the server to the client world.
+///
+/// Implement this to take over how updates are applied,
+/// e.g. to route them through your own command queue or to defer them to a render-sync point.
+/// See `ClientWorldBuilder::with_state_applier`.
+pub trait StateApplier<C: NetworkCommand>: Send + Sync + 'static {
+    fn apply(&mut self, updater: StateUpdater<C>);
+}
+
+/// Applies the steps of the `StateUpdater` in order, this is what the client uses by default.
+pub struct DefaultStateApplier;
+
+impl<C: NetworkCommand> StateApplier<C> for DefaultStateApplier {
+    fn apply(&mut self, mut updater: StateUpdater<C>) {
+        updater.apply_entity_removals();
+        updater.apply_entity_inserts();
+        updater.apply_removed_components();
+        updater.apply_added_components();
+        updater.reconcile_structural_predictions();
+        updater.apply_changed_components();
+    }
+}
+
+/// Applies a single state update to a client world.
+pub struct StateUpdater<
     'a,
     C: NetworkCommand,
     CompressionStrategy: compression::CompressionStrategy = Lz4,
@@ -381,10 +1900,26 @@ struct StateUpdater<
     allocator: &'a mut UidAllocator<Entity>,
     world: &'a mut World,
     registry: &'a RegisteredComponentsResource,
+    serialization: &'a SerializationResource,
     update: &'a mut WorldState,
     client_buffer: &'a mut ClientCommandBuffer<C>,
     resimmulation_buffer: &'a mut ResimulationBuffer<C>,
     current_command_frame: CommandFrame,
+    prediction_metrics: Option<&'a mut PredictionMetrics>,
+    changes: Option<&'a mut ReplicatedChanges>,
+    transforms: Option<&'a mut ComponentTransforms>,
+    uid_events: Option<&'a UidEvents>,
+    change_events: Option<&'a ChangeEvents>,
+    rollback: Option<&'a mut RollbackResources>,
+    references: Option<&'a EntityReferences>,
+    client_events: Option<&'a mut ClientEvents>,
+    max_resimulation_frames: Option<CommandFrame>,
+    resimulation_queue: Option<&'a mut ResimulationQueue<C>>,
+    structural: Option<&'a mut StructuralPredictions>,
+    audit: Option<&'a mut AuthorityAudit>,
+    // Entities with a mispredicted structure, resimulated by `apply_changed_components`.
+    mispredicted: Vec<Uid>,
+    prune_acked_commands: bool,
 
     phantom: PhantomData<CompressionStrategy>,
 }
@@ -396,6 +1931,7 @@ impl<'a, C: NetworkCommand, CompressionStrategy: compression::CompressionStrateg
         allocator: &'a mut UidAllocator<Entity>,
         world: &'a mut World,
         registry: &'a RegisteredComponentsResource,
+        serialization: &'a SerializationResource,
         update: &'a mut WorldState,
         client_buffer: &'a mut ClientCommandBuffer<C>,
         resimmulation_buffer: &'a mut ResimulationBuffer<C>,
@@ -406,53 +1942,257 @@ impl<'a, C: NetworkCommand, CompressionStrategy: compression::CompressionStrateg
             allocator,
             world,
             registry,
+            serialization,
             update,
             client_buffer,
             current_command_frame,
             resimmulation_buffer,
+            prediction_metrics: None,
+            changes: None,
+            transforms: None,
+            uid_events: None,
+            change_events: None,
+            rollback: None,
+            references: None,
+            client_events: None,
+            max_resimulation_frames: None,
+            resimulation_queue: None,
+            structural: None,
+            audit: None,
+            mispredicted: Vec::new(),
+            prune_acked_commands: false,
             phantom: PhantomData,
         }
     }
 
+    /// Records the entities changed by the update into the given resource.
+    pub fn with_replicated_changes(mut self, changes: &'a mut ReplicatedChanges) -> Self {
+        self.changes = Some(changes);
+        self
+    }
+
+    /// Tags the entity with `ReplicatedThisFrame` and records it in the replicated changes.
+    fn mark_changed(
+        world: &mut World,
+        changes: &mut Option<&'a mut ReplicatedChanges>,
+        command_frame: CommandFrame,
+        entity: Entity,
+    ) {
+        if let Some(mut entry) = world.entry(entity) {
+            if entry.get_component::<ReplicatedThisFrame>().is_err() {
+                entry.add_component(ReplicatedThisFrame);
+            }
+        }
+
+        if let Some(changes) = changes {
+            changes.record(command_frame, entity);
+        }
+    }
+
+    /// Sends the changes of the watched component types to the given resource.
+    pub fn with_change_events(mut self, events: &'a ChangeEvents) -> Self {
+        self.change_events = Some(events);
+        self
+    }
+
+    /// Requests the restore of the rollback resources when a misprediction is resimulated.
+    pub fn with_rollback_resources(mut self, rollback: &'a mut RollbackResources) -> Self {
+        self.rollback = Some(rollback);
+        self
+    }
+
+    /// Converts the server components that have a transform into their client components.
+    pub fn with_component_transforms(mut self, transforms: &'a mut ComponentTransforms) -> Self {
+        self.transforms = Some(transforms);
+        self
+    }
+
+    /// Sends the allocations and deallocations of uids to the given channel.
+    pub fn with_uid_events(mut self, events: &'a UidEvents) -> Self {
+        self.uid_events = Some(events);
+        self
+    }
+
+    /// Nullifies the references to removed entities, see `EntityReferences`.
+    pub fn with_entity_references(mut self, references: &'a EntityReferences) -> Self {
+        self.references = Some(references);
+        self
+    }
+
+    /// Leaves out the changes of the update that belong to an earlier entity with the same uid,
+    /// see `UidGenerations`.
+    pub fn with_uid_generations(self, generations: &mut UidGenerations) -> Self {
+        generations.validate(self.update);
+        self
+    }
+
+    /// Raises the events of the update, e.g. `ClientEvent::ResimulationSkipped`.
+    pub fn with_client_events(mut self, events: &'a mut ClientEvents) -> Self {
+        self.client_events = Some(events);
+        self
+    }
+
+    /// Applies mispredictions that would replay more than `frames` command frames without
+    /// resimulation, see `ClientWorldBuilder::with_max_resimulation_frames`.
+    pub fn with_max_resimulation_frames(mut self, frames: CommandFrame) -> Self {
+        self.max_resimulation_frames = Some(frames);
+        self
+    }
+
+    /// Removes the predictions up to the command frame of the update from the client buffer
+    /// once `apply_changed_components` compared them, see
+    /// `ClientWorldBuilder::with_acked_command_pruning`.
+    pub fn with_acked_command_pruning(mut self) -> Self {
+        self.prune_acked_commands = true;
+        self
+    }
+
+    /// Records the mispredicted ranges into the queue, see `ResimulationQueue`.
+    ///
+    /// With `ResimulationExecutor::Custom` they are not pushed to the `ResimulationBuffer`.
+    pub fn with_resimulation_queue(mut self, queue: &'a mut ResimulationQueue<C>) -> Self {
+        self.resimulation_queue = Some(queue);
+        self
+    }
+
+    /// Validates the predicted additions and removals of components, see `StructuralPredictions`.
+    pub fn with_structural_predictions(
+        mut self,
+        predictions: &'a mut StructuralPredictions,
+    ) -> Self {
+        self.structural = Some(predictions);
+        self
+    }
+
+    /// Logs the local changes that the server state overwrites, see `AuthorityAudit`.
+    pub fn with_authority_audit(mut self, audit: &'a mut AuthorityAudit) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Records the outcome of the client predictions into the given metrics.
+    pub fn with_prediction_metrics(mut self, metrics: &'a mut PredictionMetrics) -> Self {
+        self.prediction_metrics = Some(metrics);
+        self
+    }
+
+    /// The received update, `apply_changed_components` removes the correctly predicted changes from it.
+    pub fn update(&self) -> &WorldState {
+        self.update
+    }
+
+    pub fn world(&self) -> &World {
+        self.world
+    }
+
+    pub fn current_command_frame(&self) -> CommandFrame {
+        self.current_command_frame
+    }
+
     // Handle remove events, and clear mappings to prevent merge of removed entities and delete entity from worlds.
-    fn apply_entity_removals(&mut self) {
+    pub fn apply_entity_removals(&mut self) {
         for to_remove_entity in self.update.removed.iter() {
             let entity = self.allocator.get_by_val(to_remove_entity).clone();
 
             self.world.remove(entity);
 
+            if let Some(references) = self.references {
+                references.nullify_references(self.world, *to_remove_entity);
+            }
+
+            if let Some(transforms) = self.transforms.as_mut() {
+                transforms.remove_entity(entity);
+            }
+
             self.allocator
                 .deallocate(entity)
                 .expect("Entity should be allocated.");
+
+            if let Some(events) = self.uid_events {
+                events.send(UidEvent::Deallocated {
+                    uid: *to_remove_entity,
+                    entity,
+                });
+            }
         }
     }
 
-    fn apply_entity_inserts(&mut self) {
+    /// Inserts the new entities of the update.
+    ///
+    /// An insert of an entity the client already knows, after a resync, replay or redundant send,
+    /// refreshes the components of the existing entity instead of creating a duplicate.
+    pub fn apply_entity_inserts(&mut self) {
         let registry_by_id = self.registry.by_uid();
 
+        if self.update.inserted.is_empty() {
+            return;
+        }
+
+        // One pass over the world instead of a query per inserted entity.
+        let mut replicated = world::entities_by_uid(self.world);
+
         for to_insert_entity in self.update.inserted.iter() {
-            let entity = self.world.extend(vec![()])[0].clone();
+            let known = replicated.get(&to_insert_entity.entity_id()).copied();
+
+            let entity = match known {
+                Some(entity) => {
+                    if let Some(metrics) = self.prediction_metrics.as_mut() {
+                        metrics.record_deduplicated_insert();
+                    }
+                    entity
+                }
+                None => self.world.extend(vec![()])[0].clone(),
+            };
 
             for component in to_insert_entity.components() {
                 let component_registration = registry_by_id
                     .get(&component.component_id())
                     .expect("Component should be registered.");
 
-                let deserializer =
-                    &mut bincode::Deserializer::from_slice(component.data(), default_options());
-                component_registration.add_component(
-                    &mut self.world,
-                    entity,
-                    &mut erased_serde::Deserializer::erase(deserializer),
-                );
+                let transforms = &mut self.transforms;
+                let world = &mut *self.world;
+
+                let result = self.serialization.deserialize_with(component.data(), |data| {
+                    let transformed = match transforms.as_mut() {
+                        Some(transforms) => transforms.add_component(
+                            &component_registration.ty(),
+                            world,
+                            entity,
+                            data,
+                        ),
+                        None => false,
+                    };
+
+                    if !transformed {
+                        component_registration.add_component(world, entity, data);
+                    }
+                });
+
+                if let Err(e) = result {
+                    log::error!(
+                        "Failed to insert {} of entity {}: {}",
+                        component_registration.type_name(),
+                        to_insert_entity.entity_id(),
+                        e
+                    );
+                }
             }
 
-            self.allocator
-                .allocate(entity, Some(to_insert_entity.entity_id()));
+            if known.is_none() {
+                let uid = to_insert_entity.entity_id();
+                self.allocator.allocate(entity, Some(uid));
+                replicated.insert(uid, entity);
+
+                if let Some(events) = self.uid_events {
+                    events.send(UidEvent::Allocated { uid, entity });
+                }
+            }
+
+            Self::mark_changed(self.world, &mut self.changes, self.update.command_frame, entity);
         }
     }
 
-    fn apply_removed_components(&mut self) {
+    pub fn apply_removed_components(&mut self) {
         let registry_by_id = self.registry.by_uid();
 
         for to_remove_component in self.update.component_removed.iter() {
@@ -460,11 +2200,23 @@ impl<'a, C: NetworkCommand, CompressionStrategy: compression::CompressionStrateg
             let component_registration = registry_by_id
                 .get(&to_remove_component.component_id())
                 .expect("Component should be registered.");
-            component_registration.remove_component(self.world, *entity);
+
+            let transformed = match self.transforms.as_mut() {
+                Some(transforms) => {
+                    transforms.remove_component(&component_registration.ty(), self.world, *entity)
+                }
+                None => false,
+            };
+
+            if !transformed {
+                component_registration.remove_component(self.world, *entity);
+            }
+
+            Self::mark_changed(self.world, &mut self.changes, self.update.command_frame, *entity);
         }
     }
 
-    fn apply_added_components(&mut self) {
+    pub fn apply_added_components(&mut self) {
         let registry_by_id = self.registry.by_uid();
 
         for to_add_component in self.update.component_added.iter() {
@@ -474,20 +2226,132 @@ impl<'a, C: NetworkCommand, CompressionStrategy: compression::CompressionStrateg
                 .get(&component_data.component_id())
                 .expect("Component should be registered.");
 
-            let deserializer =
-                &mut bincode::Deserializer::from_slice(component_data.data(), default_options());
+            let transforms = &mut self.transforms;
+            let world = &mut *self.world;
+
+            let result = self.serialization.deserialize_with(component_data.data(), |data| {
+                let transformed = match transforms.as_mut() {
+                    Some(transforms) => transforms.add_component(
+                        &component_registration.ty(),
+                        world,
+                        *entity,
+                        data,
+                    ),
+                    None => false,
+                };
+
+                if !transformed {
+                    component_registration.add_component(world, *entity, data);
+                }
+            });
 
-            component_registration.add_component(
-                self.world,
-                *entity,
-                &mut erased_serde::Deserializer::erase(deserializer),
-            );
+            if let Err(e) = result {
+                log::error!(
+                    "Failed to add {} to entity {}: {}",
+                    component_registration.type_name(),
+                    to_add_component.entity_id(),
+                    e
+                );
+            }
+
+            Self::mark_changed(self.world, &mut self.changes, self.update.command_frame, *entity);
+        }
+    }
+
+    /// Validates the predicted additions and removals up to the command frame of the update.
+    ///
+    /// Changes the server did not make are undone: predicted components are removed again and
+    /// removed components are restored. The entity is resimulated by `apply_changed_components`.
+    /// Call it after the structural changes of the update are applied.
+    pub fn reconcile_structural_predictions(&mut self) {
+        let predictions = match self.structural.as_mut() {
+            Some(structural) => structural.take_until(self.update.command_frame),
+            None => return,
+        };
+
+        let registry_by_uid = self.registry.by_uid();
+
+        for prediction in predictions {
+            let registration = match registry_by_uid.get(&prediction.component_uid) {
+                Some(registration) => registration,
+                None => continue,
+            };
+
+            if prediction.is_confirmed_by(self.update) {
+                if let Some(metrics) = self.prediction_metrics.as_mut() {
+                    metrics.record_prediction(registration.type_name());
+                }
+                continue;
+            }
+
+            let entity = match world::entity_by_uid(self.world, prediction.entity_id) {
+                Some(entity) => entity,
+                None => continue,
+            };
+
+            let (reason, local_bytes) = match &prediction.change {
+                StructuralChange::Added => {
+                    let local_bytes = match self.audit {
+                        Some(_) => component_bytes(
+                            self.world,
+                            self.registry,
+                            entity,
+                            prediction.component_uid,
+                        ),
+                        None => None,
+                    };
+                    registration.remove_component(self.world, entity);
+
+                    (OverwriteReason::AdditionUndone, local_bytes)
+                }
+                StructuralChange::Removed { unchanged } => {
+                    let world = &mut *self.world;
+                    let result = self.serialization.deserialize_with(unchanged, |data| {
+                        registration.add_component(world, entity, data)
+                    });
+
+                    if let Err(e) = result {
+                        log::error!(
+                            "Failed to restore {} of entity {}: {}",
+                            registration.type_name(),
+                            prediction.entity_id,
+                            e
+                        );
+                    }
+
+                    (OverwriteReason::RemovalUndone, None)
+                }
+            };
+
+            if let Some(audit) = self.audit.as_mut() {
+                audit.record(AuthorityOverwrite {
+                    command_frame: self.update.command_frame,
+                    entity,
+                    uid: prediction.entity_id,
+                    component: registration.type_name(),
+                    reason,
+                    local_bytes,
+                    server_bytes: component_bytes(
+                        self.world,
+                        self.registry,
+                        entity,
+                        prediction.component_uid,
+                    ),
+                });
+            }
+
+            if let Some(metrics) = self.prediction_metrics.as_mut() {
+                metrics.record_misprediction(registration.type_name(), 0);
+            }
+
+            Self::mark_changed(self.world, &mut self.changes, self.update.command_frame, entity);
+            self.mispredicted.push(prediction.entity_id);
         }
     }
 
-    fn apply_changed_components(&mut self) {
+    pub fn apply_changed_components(&mut self) {
         // In this buffer the wrong client predicted state is stored.
-        let mut to_resimmulate = Vec::new();
+        let mut to_resimmulate = mem::take(&mut self.mispredicted);
 
         let registry_by_type = self.registry.by_type_id();
 
@@ -516,11 +2380,21 @@ impl<'a, C: NetworkCommand, CompressionStrategy: compression::CompressionStrateg
             // Get allocated entity id.
             let entity = self.allocator.get_by_val(&grouped_entity_id);
 
+            // The server moves frozen entities alone, their changes are applied as they are below.
+            if self
+                .world
+                .entry_ref(*entity)
+                .map_or(false, |entry| entry.get_component::<Frozen>().is_ok())
+            {
+                continue;
+            }
+
             // Now find the component registration needed for (se/dese)rializing.
             let registration = registry_by_type
                 .get(&oldest_change.component_type)
                 .expect("Should exist");
 
+            // The recorded values are in the bincode format of the change tracker.
             // Create deserializer of the oldest changed component.
             let oldest_change_deserializer = &mut bincode::Deserializer::from_slice(
                 &oldest_change.unchanged_data,
@@ -535,19 +2409,20 @@ impl<'a, C: NetworkCommand, CompressionStrategy: compression::CompressionStrateg
 
             // Those deserializers are used to find the difference between the the oldest unchanged and latest changed data.
             // This difference should be the same as calculated on the server.
+            let mut changed = false;
+
+            let difference = self.serialization.serialize_with(|serializer| {
+                changed = registration.serialize_difference(
+                    &mut erased_serde::Deserializer::erase(latest_change_deserializer),
+                    &mut erased_serde::Deserializer::erase(oldest_change_deserializer),
+                    serializer,
+                )?;
+                Ok(())
+            });
 
-            let mut buffer = Vec::new();
-
-            let mut bincode = bincode::Serializer::new(&mut buffer, default_options());
-            let serialized = &mut erased_serde::Serializer::erase(&mut bincode);
-
-            match registration.serialize_difference(
-                &mut erased_serde::Deserializer::erase(latest_change_deserializer),
-                &mut erased_serde::Deserializer::erase(oldest_change_deserializer),
-                serialized.borrow_mut(),
-            ) {
+            match difference {
                 // There is a difference, lets figure out if this is the same as on the server.
-                Ok(true) => {
+                Ok(buffer) if changed => {
                     // Create entry, when hashed, should also be in the server authority sate.
                     let client_state = ComponentData::new(
                         *self
@@ -563,7 +2438,11 @@ impl<'a, C: NetworkCommand, CompressionStrategy: compression::CompressionStrateg
                         .changed
                         .remove(&ComponentChanged(oldest_change.entity_id, client_state));
 
-                    if !client_state_match {
+                    if client_state_match {
+                        if let Some(metrics) = self.prediction_metrics.as_mut() {
+                            metrics.record_prediction(registration.type_name());
+                        }
+                    } else {
                         // There is a wrong client-perdition.
 
                         // Take the authoritative server state
@@ -574,28 +2453,70 @@ impl<'a, C: NetworkCommand, CompressionStrategy: compression::CompressionStrateg
                             .find(|val| val.0 == oldest_change.entity_id)
                             .expect("");
 
+                        if let Some(metrics) = self.prediction_metrics.as_mut() {
+                            metrics.record_misprediction(
+                                registration.type_name(),
+                                server_difference.1.data().len(),
+                            );
+                        }
+
                         // Add the oldest state change entry to the resimmulation buffer.
                         // The client should resimmulate the world state from this state.
                         to_resimmulate.push(oldest_change.entity_id);
 
-                        let mut bincode = bincode::Deserializer::from_slice(
-                            &mut server_difference.1.data(),
-                            default_options(),
+                        let old_bytes = self
+                            .change_events
+                            .and_then(|events| events.snapshot(registration, self.world, *entity));
+
+                        // Now apply the authoritative server-differences.
+                        let world = &mut *self.world;
+                        let result = self.serialization.deserialize_with(
+                            server_difference.1.data(),
+                            |server_difference| {
+                                registration.apply_changes(world, *entity, server_difference)
+                            },
                         );
 
-                        // Create deserializer of the server-difference.
-                        let mut server_difference_deserializer =
-                            erased_serde::Deserializer::erase(&mut bincode);
+                        if let Err(e) = result {
+                            log::error!(
+                                "Failed to apply the changes to entity {}: {}",
+                                oldest_change.entity_id,
+                                e
+                            );
+                        }
 
-                        // Now apply the authoritative server-differences.
-                        registration.apply_changes(
-                            self.world,
-                            *entity,
-                            &mut server_difference_deserializer,
-                        )
+                        if let Some(events) = self.change_events {
+                            events.record(
+                                registration,
+                                self.world,
+                                *entity,
+                                oldest_change.entity_id,
+                                server_difference.1.component_id(),
+                                old_bytes,
+                            );
+                        }
+
+                        if let Some(audit) = self.audit.as_mut() {
+                            audit.record(AuthorityOverwrite {
+                                command_frame,
+                                entity: *entity,
+                                uid: oldest_change.entity_id,
+                                component: registration.type_name(),
+                                reason: OverwriteReason::Mispredicted,
+                                local_bytes: Some(latest_change.changed_data.clone()),
+                                server_bytes: component_bytes(
+                                    self.world,
+                                    self.registry,
+                                    *entity,
+                                    server_difference.1.component_id(),
+                                ),
+                            });
+                        }
+
+                        Self::mark_changed(self.world, &mut self.changes, command_frame, *entity);
                     }
                 }
-                Ok(false) => {}
+                Ok(_) => {}
                 Err(e) => panic!("{:?}", e),
             }
         }
@@ -608,37 +2529,420 @@ impl<'a, C: NetworkCommand, CompressionStrategy: compression::CompressionStrateg
                 // Get allocated entity id.
                 let entity = self.allocator.get_by_val(&change.entity_id());
 
-                let mut bincode =
-                    bincode::Deserializer::from_slice(&mut change.1.data(), default_options());
+                let transforms = &mut self.transforms;
+                let change_events = self.change_events;
+                let world = &mut *self.world;
+
+                let result = self.serialization.deserialize_with(
+                    change.1.data(),
+                    |server_difference| {
+                        let transformed = match transforms.as_mut() {
+                            Some(transforms) => transforms.apply_changes(
+                                &registration.ty(),
+                                world,
+                                *entity,
+                                server_difference,
+                                registration.diff_policy(),
+                            ),
+                            None => false,
+                        };
+
+                        // Now apply the authoritative server-differences.
+                        if !transformed {
+                            let old_bytes = change_events
+                                .and_then(|events| events.snapshot(registration, world, *entity));
+
+                            registration.apply_changes(world, *entity, server_difference);
+
+                            if let Some(events) = change_events {
+                                events.record(
+                                    registration,
+                                    world,
+                                    *entity,
+                                    change.entity_id(),
+                                    change.component_data().component_id(),
+                                    old_bytes,
+                                );
+                            }
+                        }
+                    },
+                );
 
-                // Create deserializer of the server-difference.
-                let mut server_difference_deserializer =
-                    erased_serde::Deserializer::erase(&mut bincode);
+                if let Err(e) = result {
+                    log::error!(
+                        "Failed to apply the changes to entity {}: {}",
+                        change.entity_id(),
+                        e
+                    );
+                }
 
-                // Now apply the authoritative server-differences.
-                registration.apply_changes(self.world, *entity, &mut server_difference_deserializer)
+                Self::mark_changed(self.world, &mut self.changes, command_frame, *entity);
             }
         }
 
-        if to_resimmulate.len() != 0 {
+        let depth = self.current_command_frame - self.update.command_frame;
+
+        // The authoritative state is applied above, the predicted frames since are dropped.
+        if to_resimmulate.len() != 0
+            && self
+                .max_resimulation_frames
+                .map_or(false, |max_frames| depth > max_frames)
+        {
+            if let Some(metrics) = self.prediction_metrics.as_mut() {
+                metrics.record_skipped_resimulation();
+            }
+
+            if let Some(events) = self.client_events.as_mut() {
+                to_resimmulate.sort();
+                to_resimmulate.dedup();
+
+                events.push(ClientEvent::ResimulationSkipped {
+                    command_frame: self.update.command_frame,
+                    depth,
+                    entities: to_resimmulate,
+                });
+            }
+        } else if to_resimmulate.len() != 0 {
             let to_resimulate = self
                 .client_buffer
-                .iter_history(self.current_command_frame - self.update.command_frame)
+                .iter_history(depth)
                 .filter(|val| to_resimmulate.contains(&val.entity_id))
                 .map(|val| val.clone())
                 .collect::<Vec<ClientCommandBufferEntry<C>>>();
 
-            self.resimmulation_buffer.push(
-                self.update.command_frame,
-                self.current_command_frame,
-                to_resimulate,
-            );
+            let built_in = match self.resimulation_queue.as_mut() {
+                Some(queue) => {
+                    queue.push(
+                        self.update.command_frame,
+                        self.current_command_frame,
+                        to_resimulate.clone(),
+                    );
+                    queue.executor() == ResimulationExecutor::BuiltIn
+                }
+                None => true,
+            };
+
+            if built_in {
+                self.resimmulation_buffer.push(
+                    self.update.command_frame,
+                    self.current_command_frame,
+                    to_resimulate,
+                );
+            }
+
+            if let Some(metrics) = self.prediction_metrics.as_mut() {
+                metrics.record_resimulation(depth);
+            }
+
+            if let Some(rollback) = self.rollback.as_mut() {
+                rollback.request_restore(self.update.command_frame);
+            }
+        }
+
+        // The predictions of the frame are validated and the replays are copied out above.
+        if self.prune_acked_commands {
+            self.client_buffer
+                .retain(|entry| entry.command_frame > command_frame);
         }
     }
 }
 
-fn default_options() -> impl Options {
-    bincode::DefaultOptions::new()
-        .with_fixint_encoding()
-        .allow_trailing_bytes()
+#[cfg(test)]
+pub mod test {
+    use std::any::TypeId;
+
+    use bincode::Options;
+    use legion::{world::EntityStore, Entity, World};
+    use serde::{Deserialize, Serialize};
+
+    use net_sync::{
+        compression::lz4::Lz4,
+        synchronisation::{
+            ClientCommandBuffer, ComponentData, NetworkCommand, ResimulationBuffer, WorldState,
+        },
+        uid::UidAllocator,
+    };
+
+    use crate::{
+        components::{DynamicComponent, Region, ReplicatedThisFrame, UidComponent},
+        protocol::{order_state_updates, ClientAction},
+        resources::{
+            PredictionMetrics, RegisteredComponentsResource, ReplicatedChanges,
+            SerializationResource, UidEvent, UidEvents,
+        },
+        tracking::re_exports::bincode,
+        world::{
+            client::{apply_initial_sync, coalesce_whole_changes, StateUpdater},
+            default_options,
+        },
+    };
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    struct TestCommand;
+
+    impl NetworkCommand for TestCommand {}
+
+    #[test]
+    fn state_update_after_initial_sync_test() {
+        let registered = RegisteredComponentsResource::new();
+        let serialization = SerializationResource::default();
+        let mut allocator = UidAllocator::<Entity>::new();
+        let mut world = World::default();
+
+        let mut synced = World::default();
+        synced.push((
+            UidComponent::new(1),
+            DynamicComponent::new("health", serde_json::json!(10)),
+        ));
+        synced.push((UidComponent::new(2),));
+
+        apply_initial_sync(
+            &mut world,
+            &synced,
+            &registered,
+            &serialization,
+            &mut allocator,
+            None,
+        );
+
+        let dynamic_uid = *registered
+            .get_uid(&TypeId::of::<DynamicComponent>())
+            .unwrap();
+
+        let mut update = WorldState::new(1);
+        update.remove_component(1, dynamic_uid);
+        update.remove_entity(2);
+
+        let mut client_buffer = ClientCommandBuffer::<TestCommand>::with_capacity(10);
+        let mut resimulation_buffer = ResimulationBuffer::<TestCommand>::new();
+
+        let mut state_updater = StateUpdater::new(
+            &mut allocator,
+            &mut world,
+            &registered,
+            &serialization,
+            &mut update,
+            &mut client_buffer,
+            &mut resimulation_buffer,
+            1,
+            Lz4,
+        );
+
+        state_updater.apply_entity_removals();
+        state_updater.apply_removed_components();
+
+        assert_eq!(world.len(), 1);
+
+        let entity = *allocator.get_by_val(&1);
+        assert!(world
+            .entry_ref(entity)
+            .unwrap()
+            .get_component::<DynamicComponent>()
+            .is_err());
+    }
+
+    #[test]
+    fn overwritten_whole_changes_are_coalesced_test() {
+        let registered = RegisteredComponentsResource::new();
+        let region_uid = *registered.get_uid(&TypeId::of::<Region>()).unwrap();
+        let dynamic_uid = *registered
+            .get_uid(&TypeId::of::<DynamicComponent>())
+            .unwrap();
+
+        let update = |command_frame: u32| {
+            let mut update = WorldState::new(command_frame);
+            update.change(1, ComponentData::new(region_uid, vec![command_frame as u8]));
+            update.change(1, ComponentData::new(dynamic_uid, Vec::new()));
+            ClientAction::ApplyStateUpdate(update)
+        };
+
+        // A burst that arrived out of order.
+        let mut actions: Vec<ClientAction<(), TestCommand>> =
+            order_state_updates(vec![update(3), update(1), update(2)]);
+        coalesce_whole_changes(&mut actions, &registered);
+
+        let changed = actions
+            .iter()
+            .map(|action| match action {
+                ClientAction::ApplyStateUpdate(update) => {
+                    (update.command_frame, update.changed.len())
+                }
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+
+        // Only the last region value is applied, the diffs of the dynamic component are kept.
+        assert_eq!(changed, vec![(1, 1), (2, 1), (3, 2)]);
+    }
+
+    #[test]
+    fn touched_entities_are_marked_test() {
+        let registered = RegisteredComponentsResource::new();
+        let serialization = SerializationResource::default();
+        let mut allocator = UidAllocator::<Entity>::new();
+        let mut world = World::default();
+        let mut changes = ReplicatedChanges::default();
+
+        let mut synced = World::default();
+        synced.push((
+            UidComponent::new(1),
+            DynamicComponent::new("health", serde_json::json!(10)),
+        ));
+        synced.push((UidComponent::new(2),));
+
+        apply_initial_sync(
+            &mut world,
+            &synced,
+            &registered,
+            &serialization,
+            &mut allocator,
+            None,
+        );
+
+        let dynamic_uid = *registered
+            .get_uid(&TypeId::of::<DynamicComponent>())
+            .unwrap();
+
+        let mut update = WorldState::new(1);
+        update.remove_component(1, dynamic_uid);
+
+        let mut client_buffer = ClientCommandBuffer::<TestCommand>::with_capacity(10);
+        let mut resimulation_buffer = ResimulationBuffer::<TestCommand>::new();
+
+        let mut state_updater = StateUpdater::new(
+            &mut allocator,
+            &mut world,
+            &registered,
+            &serialization,
+            &mut update,
+            &mut client_buffer,
+            &mut resimulation_buffer,
+            1,
+            Lz4,
+        )
+        .with_replicated_changes(&mut changes);
+
+        state_updater.apply_removed_components();
+
+        let touched = *allocator.get_by_val(&1);
+        let untouched = *allocator.get_by_val(&2);
+
+        let has_marker = |entity| {
+            world
+                .entry_ref(entity)
+                .unwrap()
+                .get_component::<ReplicatedThisFrame>()
+                .is_ok()
+        };
+        assert!(has_marker(touched));
+        assert!(!has_marker(untouched));
+        assert_eq!(changes.changed_since(0).len(), 1);
+    }
+
+    #[test]
+    fn known_entity_insert_is_deduplicated_test() {
+        let registered = RegisteredComponentsResource::new();
+        let serialization = SerializationResource::default();
+        let mut allocator = UidAllocator::<Entity>::new();
+        let mut world = World::default();
+        let mut metrics = PredictionMetrics::new();
+
+        let mut synced = World::default();
+        synced.push((UidComponent::new(1),));
+
+        apply_initial_sync(
+            &mut world,
+            &synced,
+            &registered,
+            &serialization,
+            &mut allocator,
+            None,
+        );
+
+        let uid_component = *registered.get_uid(&TypeId::of::<UidComponent>()).unwrap();
+        let data = default_options().serialize(&UidComponent::new(1)).unwrap();
+
+        let mut update = WorldState::new(1);
+        update.insert_entity(1, vec![ComponentData::new(uid_component, data)]);
+
+        let mut client_buffer = ClientCommandBuffer::<TestCommand>::with_capacity(10);
+        let mut resimulation_buffer = ResimulationBuffer::<TestCommand>::new();
+
+        let mut state_updater = StateUpdater::new(
+            &mut allocator,
+            &mut world,
+            &registered,
+            &serialization,
+            &mut update,
+            &mut client_buffer,
+            &mut resimulation_buffer,
+            1,
+            Lz4,
+        )
+        .with_prediction_metrics(&mut metrics);
+
+        state_updater.apply_entity_inserts();
+
+        assert_eq!(world.len(), 1);
+        assert_eq!(metrics.deduplicated_inserts(), 1);
+    }
+
+    #[test]
+    fn uid_lifecycle_is_sent_test() {
+        let registered = RegisteredComponentsResource::new();
+        let serialization = SerializationResource::default();
+        let mut allocator = UidAllocator::<Entity>::new();
+        let mut world = World::default();
+        let events = UidEvents::new();
+
+        let mut synced = World::default();
+        synced.push((UidComponent::new(1),));
+
+        apply_initial_sync(
+            &mut world,
+            &synced,
+            &registered,
+            &serialization,
+            &mut allocator,
+            Some(&events),
+        );
+
+        let synced_entity = *allocator.get_by_val(&1);
+        assert_eq!(
+            events.try_iter().collect::<Vec<UidEvent>>(),
+            vec![UidEvent::Allocated {
+                uid: 1,
+                entity: synced_entity
+            }]
+        );
+
+        let mut update = WorldState::new(1);
+        update.remove_entity(1);
+
+        let mut client_buffer = ClientCommandBuffer::<TestCommand>::with_capacity(10);
+        let mut resimulation_buffer = ResimulationBuffer::<TestCommand>::new();
+
+        let mut state_updater = StateUpdater::new(
+            &mut allocator,
+            &mut world,
+            &registered,
+            &serialization,
+            &mut update,
+            &mut client_buffer,
+            &mut resimulation_buffer,
+            1,
+            Lz4,
+        )
+        .with_uid_events(&events);
+
+        state_updater.apply_entity_removals();
+
+        assert_eq!(
+            events.try_iter().collect::<Vec<UidEvent>>(),
+            vec![UidEvent::Deallocated {
+                uid: 1,
+                entity: synced_entity
+            }]
+        );
+    }
 }
@@ -0,0 +1,178 @@
+//! Additional replication contexts that share the connection of the server and client world.
+//!
+//! A context is an independent world with its own uid space and component subset,
+//! e.g. a lobby world that stays alive next to the match world.
+//! The default context (`0`) is the world of the `ServerWorld` and `ClientWorld` itself.
+
+use std::collections::{HashMap, HashSet};
+
+use legion::{Entity, World};
+
+use net_sync::{
+    synchronisation::{
+        ClientCommandBuffer, CommandFrame, ModifiedComponentsBuffer, NetworkCommand,
+        ResimulationBuffer, WorldState,
+    },
+    transport::ClientId,
+    uid::{Uid, UidAllocator},
+};
+
+use crate::{
+    error::ErrorKind,
+    protocol::{self, ContextId},
+    resources::{EventResource, RegisteredComponentsResource, SerializationResource},
+    world::server::{add_differences_to_state, handle_world_events},
+};
+
+/// A replication context on the server.
+pub struct ReplicationContext {
+    id: ContextId,
+    world: World,
+    allocator: UidAllocator<Entity>,
+    modified_buffer: ModifiedComponentsBuffer,
+    events: EventResource,
+    components: Option<HashSet<Uid>>,
+    pub(crate) synced_clients: HashSet<ClientId>,
+}
+
+impl ReplicationContext {
+    pub fn new(id: ContextId) -> ReplicationContext {
+        assert_ne!(id, 0, "Context 0 is reserved for the default context.");
+
+        let mut world = World::default();
+        let events = EventResource::new(&mut world);
+
+        ReplicationContext {
+            id,
+            world,
+            allocator: UidAllocator::new(),
+            modified_buffer: ModifiedComponentsBuffer::new(),
+            events,
+            components: None,
+            synced_clients: HashSet::new(),
+        }
+    }
+
+    /// Only replicates the components with the given registration uids.
+    pub fn with_components(mut self, components: impl IntoIterator<Item = Uid>) -> Self {
+        self.components = Some(components.into_iter().collect());
+        self
+    }
+
+    pub fn id(&self) -> ContextId {
+        self.id
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.world
+    }
+
+    /// Allocator of the uid space of this context, inserted entities should be allocated with it.
+    pub fn allocator_mut(&mut self) -> &mut UidAllocator<Entity> {
+        &mut self.allocator
+    }
+
+    /// Buffer in which the component modifications of this context should be tracked.
+    pub fn modified_buffer_mut(&mut self) -> &mut ModifiedComponentsBuffer {
+        &mut self.modified_buffer
+    }
+
+    pub(crate) fn state_update(
+        &mut self,
+        command_frame: CommandFrame,
+        registered: &RegisteredComponentsResource,
+        serialization: &SerializationResource,
+    ) -> protocol::WorldState {
+        let mut world_state = WorldState::new(command_frame);
+
+        let mut failures = add_differences_to_state(
+            registered,
+            serialization,
+            &mut world_state,
+            &mut self.modified_buffer,
+            &mut HashMap::new(),
+            &self.world,
+            &self.allocator,
+            &HashSet::new(),
+            None,
+        );
+
+        failures.extend(handle_world_events(
+            &self.world,
+            &mut self.allocator,
+            registered,
+            serialization,
+            &self.events,
+            &mut world_state,
+            &HashSet::new(),
+        ));
+
+        // Like in the default context, failed components are left out of the update.
+        for failure in failures {
+            log::error!("{}", failure);
+        }
+
+        let mut world_state = protocol::WorldState::from(&world_state);
+
+        if let Some(components) = &self.components {
+            world_state.retain_components(|uid| components.contains(&uid));
+        }
+
+        world_state
+    }
+
+    pub(crate) fn initial_sync(
+        &self,
+        registered: &RegisteredComponentsResource,
+        serialization: &SerializationResource,
+    ) -> Result<Vec<u8>, ErrorKind> {
+        // The initial sync holds the same component subset as the state updates.
+        let mut subset = World::default();
+        let world = match &self.components {
+            Some(components) => {
+                subset.clone_from(
+                    &self.world,
+                    &registered.filter(),
+                    &mut registered.merger_of(|uid| components.contains(&uid)),
+                );
+                &subset
+            }
+            None => &self.world,
+        };
+
+        serialization
+            .serialize(&world.as_serializable(registered.filter(), registered.legion_registry()))
+    }
+}
+
+/// A replication context on the client, created when the first message of the context arrives.
+pub struct ClientContext<C: NetworkCommand> {
+    pub(crate) world: World,
+    pub(crate) allocator: UidAllocator<Entity>,
+    // Contexts are not predicted, those buffers stay empty.
+    pub(crate) client_buffer: ClientCommandBuffer<C>,
+    pub(crate) resimulation_buffer: ResimulationBuffer<C>,
+}
+
+impl<C: NetworkCommand> ClientContext<C> {
+    pub(crate) fn new() -> ClientContext<C> {
+        ClientContext {
+            world: World::default(),
+            allocator: UidAllocator::new(),
+            client_buffer: ClientCommandBuffer::with_capacity(0),
+            resimulation_buffer: ResimulationBuffer::new(),
+        }
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.world
+    }
+}
@@ -0,0 +1,249 @@
+//! Structured differences between two worlds, e.g. two `WorldHistory` snapshots.
+//!
+//! Entities are matched by their uid, the components are decoded with the registry.
+//! Useful when debugging persistence, replays and migration code.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+};
+
+use legion::{Entity, IntoQuery, Read, Universe, World};
+use serde_json::Value;
+
+use net_sync::uid::Uid;
+
+use crate::{
+    components::UidComponent,
+    error::ErrorKind,
+    resources::{RegisteredComponentsResource, SerializationResource},
+};
+
+/// A component that differs between the worlds, `None` if the entity does not have it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComponentDiff {
+    pub component: &'static str,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+/// The components of an entity that differ between the worlds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntityDiff {
+    pub uid: Uid,
+    pub components: Vec<ComponentDiff>,
+}
+
+/// The differences of the replicated entities of two worlds, ordered by uid.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WorldDiff {
+    /// Entities that are only in the second world.
+    pub added: Vec<Uid>,
+    /// Entities that are only in the first world.
+    pub removed: Vec<Uid>,
+    pub changed: Vec<EntityDiff>,
+    /// The components that could not be decoded, they are left out of the comparison.
+    pub undecoded: Vec<(Uid, &'static str)>,
+}
+
+impl WorldDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl Display for WorldDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for uid in self.added.iter() {
+            writeln!(f, "+ entity {}", uid)?;
+        }
+
+        for uid in self.removed.iter() {
+            writeln!(f, "- entity {}", uid)?;
+        }
+
+        for entity in self.changed.iter() {
+            writeln!(f, "~ entity {}", entity.uid)?;
+
+            for component in entity.components.iter() {
+                match (&component.before, &component.after) {
+                    (Some(before), Some(after)) => {
+                        writeln!(f, "    {}: {} -> {}", component.component, before, after)?
+                    }
+                    (None, Some(after)) => writeln!(f, "  + {}: {}", component.component, after)?,
+                    (Some(before), None) => {
+                        writeln!(f, "  - {}: {}", component.component, before)?
+                    }
+                    (None, None) => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Deserializes a world snapshot, e.g. of the `WorldHistory`, serialized with the strategy of the
+/// `SerializationResource`.
+pub fn load_snapshot(
+    snapshot: &[u8],
+    registered: &RegisteredComponentsResource,
+    serialization: &SerializationResource,
+    universe: &Universe,
+) -> Result<World, ErrorKind> {
+    serialization.deserialize_seed(
+        snapshot,
+        registered.legion_registry().as_deserialize(universe),
+    )
+}
+
+/// Compares two world snapshots, see `diff_worlds`.
+pub fn diff_snapshots(
+    before: &[u8],
+    after: &[u8],
+    registered: &RegisteredComponentsResource,
+    serialization: &SerializationResource,
+    universe: &Universe,
+) -> Result<WorldDiff, ErrorKind> {
+    let before = load_snapshot(before, registered, serialization, universe)?;
+    let after = load_snapshot(after, registered, serialization, universe)?;
+
+    Ok(diff_worlds(&before, &after, registered))
+}
+
+/// Compares the replicated entities of two worlds, e.g. a snapshot and the live world.
+pub fn diff_worlds(
+    before: &World,
+    after: &World,
+    registered: &RegisteredComponentsResource,
+) -> WorldDiff {
+    let before_entities = replicated_entities(before);
+    let after_entities = replicated_entities(after);
+
+    let mut diff = WorldDiff::default();
+
+    for (uid, before_entity) in before_entities.iter() {
+        let after_entity = match after_entities.get(uid) {
+            Some(after_entity) => *after_entity,
+            None => {
+                diff.removed.push(*uid);
+                continue;
+            }
+        };
+
+        let before_values = decode_entity(before, *before_entity, *uid, registered, &mut diff);
+        let after_values = decode_entity(after, after_entity, *uid, registered, &mut diff);
+
+        let mut components = Vec::new();
+
+        for (component, before_value) in before_values.iter() {
+            let after_value = after_values.get(component);
+
+            if after_value != Some(before_value) {
+                components.push(ComponentDiff {
+                    component,
+                    before: Some(before_value.clone()),
+                    after: after_value.cloned(),
+                });
+            }
+        }
+
+        for (component, after_value) in after_values.iter() {
+            if !before_values.contains_key(component) {
+                components.push(ComponentDiff {
+                    component,
+                    before: None,
+                    after: Some(after_value.clone()),
+                });
+            }
+        }
+
+        if !components.is_empty() {
+            components.sort_by_key(|component| component.component);
+            diff.changed.push(EntityDiff {
+                uid: *uid,
+                components,
+            });
+        }
+    }
+
+    diff.added = after_entities
+        .keys()
+        .filter(|uid| !before_entities.contains_key(uid))
+        .copied()
+        .collect();
+
+    diff
+}
+
+fn replicated_entities(world: &World) -> BTreeMap<Uid, Entity> {
+    <(Entity, Read<UidComponent>)>::query()
+        .iter(world)
+        .map(|(entity, uid)| (uid.uid(), *entity))
+        .collect()
+}
+
+/// Decodes the registered components of the entity, by type name.
+fn decode_entity(
+    world: &World,
+    entity: Entity,
+    uid: Uid,
+    registered: &RegisteredComponentsResource,
+    diff: &mut WorldDiff,
+) -> BTreeMap<&'static str, Value> {
+    let mut values = BTreeMap::new();
+
+    for (_, registration) in registered.slice_with_uid().iter() {
+        registration.serialize_if_exists_in_world(world, entity, &mut |serialize| {
+            match serde_json::to_value(serialize) {
+                Ok(value) => {
+                    values.insert(registration.type_name(), value);
+                }
+                Err(_) => diff.undecoded.push((uid, registration.type_name())),
+            }
+        });
+    }
+
+    values
+}
+
+#[cfg(test)]
+pub mod test {
+    use legion::World;
+
+    use crate::{
+        components::{DynamicComponent, UidComponent},
+        resources::RegisteredComponentsResource,
+        world::diff::diff_worlds,
+    };
+
+    #[test]
+    fn entities_and_components_are_compared_test() {
+        let registered = RegisteredComponentsResource::new();
+
+        let mut before = World::default();
+        before.push((UidComponent::new(1),));
+        before.push((
+            UidComponent::new(2),
+            DynamicComponent::new("health", serde_json::json!(10)),
+        ));
+        before.push((UidComponent::new(3),));
+
+        let mut after = World::default();
+        after.push((UidComponent::new(1),));
+        after.push((
+            UidComponent::new(2),
+            DynamicComponent::new("health", serde_json::json!(5)),
+        ));
+        after.push((UidComponent::new(4),));
+
+        let diff = diff_worlds(&before, &after, &registered);
+        assert_eq!(diff.removed, vec![3]);
+        assert_eq!(diff.added, vec![4]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].uid, 2);
+        assert!(diff.changed[0].components[0].before.is_some());
+
+        assert!(diff_worlds(&before, &before, &registered).is_empty());
+    }
+}
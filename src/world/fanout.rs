@@ -0,0 +1,198 @@
+//! Helpers to produce and enqueue the payloads of the clients on multiple threads.
+
+use std::collections::HashMap;
+
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
+
+use net_sync::{
+    synchronisation::{NetworkCommand, NetworkMessage},
+    transport::ClientId,
+};
+
+use crate::world::server::{ServerPostBox, ServerPostOffice};
+
+/// The post boxes of the clients of a `ServerPostOffice`, borrowed apart.
+///
+/// A post box that is taken out is borrowed independently from the other ones,
+/// so the clients can be handed to different threads, e.g. with `FanoutPool::par_map`.
+pub struct ClientPostBoxes<
+    'a,
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+> {
+    postboxes: HashMap<
+        ClientId,
+        &'a mut ServerPostBox<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>,
+    >,
+}
+
+impl<
+        'a,
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    > ClientPostBoxes<'a, ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>
+{
+    pub fn new(
+        postoffice: &'a mut ServerPostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >,
+    ) -> Self {
+        ClientPostBoxes {
+            postboxes: postoffice
+                .clients_mut()
+                .map(|(id, client)| (*id, client.postbox_mut()))
+                .collect(),
+        }
+    }
+
+    /// The clients whose post box was not taken out.
+    pub fn clients(&self) -> impl Iterator<Item = ClientId> + '_ {
+        self.postboxes.keys().copied()
+    }
+
+    pub fn contains(&self, client: ClientId) -> bool {
+        self.postboxes.contains_key(&client)
+    }
+
+    pub fn get_mut(
+        &mut self,
+        client: ClientId,
+    ) -> Option<
+        &mut ServerPostBox<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>,
+    > {
+        self.postboxes
+            .get_mut(&client)
+            .map(|postbox| &mut **postbox)
+    }
+
+    /// Takes the post box of the client out.
+    pub fn take(
+        &mut self,
+        client: ClientId,
+    ) -> Option<
+        &'a mut ServerPostBox<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>,
+    > {
+        self.postboxes.remove(&client)
+    }
+
+    /// Groups the items by client and takes out the post box of each client.
+    ///
+    /// The items of a client keep their order. Items of clients without post box are dropped.
+    pub fn pair<T>(
+        &mut self,
+        items: impl IntoIterator<Item = (ClientId, T)>,
+    ) -> Vec<(
+        ClientId,
+        Vec<T>,
+        &'a mut ServerPostBox<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>,
+    )> {
+        let mut order = Vec::new();
+        let mut grouped = HashMap::<ClientId, Vec<T>>::new();
+
+        for (client, item) in items {
+            if !self.contains(client) {
+                continue;
+            }
+
+            grouped
+                .entry(client)
+                .or_insert_with(|| {
+                    order.push(client);
+                    Vec::new()
+                })
+                .push(item);
+        }
+
+        order
+            .into_iter()
+            .map(|client| {
+                let items = grouped.remove(&client).unwrap();
+                (client, items, self.take(client).unwrap())
+            })
+            .collect()
+    }
+}
+
+/// The threads of `ServerConfig::fanout_threads`, started once and kept for the lifetime of the
+/// server instead of spawning threads every command frame.
+pub struct FanoutPool {
+    pool: Option<ThreadPool>,
+}
+
+impl FanoutPool {
+    /// Starts `threads` threads, with one thread the items are mapped on the calling thread.
+    ///
+    /// Falls back to the calling thread when the threads can not be started.
+    pub fn new(threads: usize) -> FanoutPool {
+        if threads <= 1 {
+            return FanoutPool { pool: None };
+        }
+
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|i| format!("legion-sync-fanout-{}", i))
+            .build();
+
+        match pool {
+            Ok(pool) => FanoutPool { pool: Some(pool) },
+            Err(e) => {
+                log::error!("Failed to start the fanout threads: {}", e);
+                FanoutPool { pool: None }
+            }
+        }
+    }
+
+    /// The number of threads the items are mapped on.
+    pub fn threads(&self) -> usize {
+        self.pool
+            .as_ref()
+            .map_or(1, |pool| pool.current_num_threads())
+    }
+
+    /// Maps the items on the threads, the results keep the order of the items.
+    ///
+    /// With one thread or a single item, the items are mapped on the calling thread.
+    pub fn par_map<T: Send, R: Send>(
+        &self,
+        items: Vec<T>,
+        f: impl Fn(T) -> R + Sync + Send,
+    ) -> Vec<R> {
+        match &self.pool {
+            Some(pool) if items.len() > 1 => {
+                pool.install(|| items.into_par_iter().map(f).collect())
+            }
+            _ => items.into_iter().map(f).collect(),
+        }
+    }
+}
+
+impl Default for FanoutPool {
+    fn default() -> Self {
+        FanoutPool::new(1)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::world::fanout::FanoutPool;
+
+    #[test]
+    fn par_map_keeps_the_order_test() {
+        let items = (0..100).collect::<Vec<u32>>();
+        let pool = FanoutPool::new(4);
+        assert_eq!(pool.threads(), 4);
+
+        assert_eq!(
+            pool.par_map(items.clone(), |x| x * 2),
+            items.iter().map(|x| x * 2).collect::<Vec<u32>>()
+        );
+        // The threads are reused by the next frames.
+        assert_eq!(pool.par_map(items.clone(), |x| x + 1)[99], 100);
+        assert_eq!(FanoutPool::new(1).par_map(items.clone(), |x| x + 1)[99], 100);
+        assert!(pool.par_map(Vec::<u32>::new(), |x| x).is_empty());
+    }
+}
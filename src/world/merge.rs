@@ -0,0 +1,451 @@
+//! Merges the world received with the initial state sync into the client world.
+
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    time::Duration,
+};
+
+use legion::{world::Duplicate, Entity, IntoQuery, Read, World};
+
+use net_sync::uid::Uid;
+
+use crate::{
+    components::UidComponent,
+    resources::{ClockResource, RegisteredComponentsResource, SerializationResource},
+};
+
+/// The replicated entities that were merged into the client world.
+#[derive(Debug, Default)]
+pub struct MergeResult {
+    /// Entities that were unknown to the client and are cloned into the client world.
+    pub inserted: Vec<(Uid, Entity)>,
+    /// Entities that already existed in the client world and of which the components are updated.
+    pub updated: Vec<(Uid, Entity)>,
+    /// Replicated entities of the client world that the server no longer has, they are removed
+    /// from the client world.
+    pub removed: Vec<(Uid, Entity)>,
+}
+
+impl MergeResult {
+    /// Returns the uid and client entity of every merged entity, without the removed ones.
+    pub fn iter(&self) -> impl Iterator<Item = &(Uid, Entity)> {
+        self.inserted.iter().chain(self.updated.iter())
+    }
+
+    pub fn len(&self) -> usize {
+        self.inserted.len() + self.updated.len()
+    }
+}
+
+/// Merges the `synced` world into the client `world`.
+///
+/// - Replicated entities (entities with an `UidComponent`) unknown to the client are cloned into the client world.
+/// - Replicated entities already in the client world keep their entity,
+///   their registered components are replaced by the received ones and client-only components are kept.
+///   Registered components the synced entity does not have are removed.
+/// - Replicated entities of the client world that are not in the `synced` world are removed,
+///   the initial sync holds every entity the server replicates to the client.
+/// - Entities without an `UidComponent`, spawned by the client before connecting (menus, local effects), are untouched.
+pub fn merge_initial_sync(
+    world: &mut World,
+    synced: &World,
+    registered: &RegisteredComponentsResource,
+    serialization: &SerializationResource,
+) -> MergeResult {
+    let existing = replicated_entities(world)
+        .into_iter()
+        .collect::<HashMap<Uid, Entity>>();
+
+    let mut merger = registered.merger();
+
+    let mut result = MergeResult::default();
+
+    for replicated in replicated_entities(synced) {
+        merge_entity(
+            world,
+            synced,
+            replicated,
+            &existing,
+            registered,
+            serialization,
+            &mut merger,
+            &mut result,
+        );
+    }
+
+    let synced_uids = replicated_entities(synced)
+        .into_iter()
+        .map(|(uid, _)| uid)
+        .collect::<HashSet<_>>();
+    remove_stale(world, stale_entities(&existing, &synced_uids), &mut result);
+
+    result
+}
+
+/// An initial sync that is merged into the client world over several ticks,
+/// see `ClientWorldBuilder::with_time_sliced_initial_sync`.
+pub struct SlicedMerge {
+    synced: World,
+    existing: HashMap<Uid, Entity>,
+    // The entities left to merge, the last one is merged first.
+    remaining: Vec<(Uid, Entity)>,
+    // The replicated client entities the server no longer has, removed with the last slice.
+    stale: Vec<(Uid, Entity)>,
+    total: usize,
+}
+
+impl SlicedMerge {
+    /// Prepares the merge of the `synced` world into the client `world`, nothing is merged yet.
+    pub fn new(world: &World, synced: World) -> SlicedMerge {
+        let existing = replicated_entities(world)
+            .into_iter()
+            .collect::<HashMap<Uid, Entity>>();
+
+        let mut remaining = replicated_entities(&synced);
+        remaining.reverse();
+
+        let synced_uids = remaining.iter().map(|(uid, _)| *uid).collect();
+        let stale = stale_entities(&existing, &synced_uids);
+
+        SlicedMerge {
+            total: remaining.len(),
+            synced,
+            existing,
+            remaining,
+            stale,
+        }
+    }
+
+    /// The number of entities that are not merged yet.
+    pub fn remaining(&self) -> usize {
+        self.remaining.len()
+    }
+
+    /// The number of replicated entities of the initial sync.
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.remaining.is_empty()
+    }
+
+    /// Merges up to `max_entities` entities like `merge_initial_sync`, and stops early once the
+    /// merge took `budget` on the clock. At least one entity is merged so the merge always
+    /// completes. The stale replicated entities are removed with the last slice.
+    pub fn merge_slice(
+        &mut self,
+        world: &mut World,
+        registered: &RegisteredComponentsResource,
+        serialization: &SerializationResource,
+        max_entities: usize,
+        budget: Duration,
+        clock: &ClockResource,
+    ) -> MergeResult {
+        let started = clock.now();
+        let mut merger = registered.merger();
+        let mut result = MergeResult::default();
+
+        while result.len() < max_entities.max(1) {
+            let next = match self.remaining.pop() {
+                Some(next) => next,
+                None => break,
+            };
+
+            merge_entity(
+                world,
+                &self.synced,
+                next,
+                &self.existing,
+                registered,
+                serialization,
+                &mut merger,
+                &mut result,
+            );
+
+            if clock.now().saturating_sub(started) >= budget {
+                break;
+            }
+        }
+
+        if self.remaining.is_empty() {
+            remove_stale(world, mem::take(&mut self.stale), &mut result);
+        }
+
+        result
+    }
+}
+
+// Merges the replicated synced entity with the uid into the client world.
+fn merge_entity(
+    world: &mut World,
+    synced: &World,
+    (uid, synced_entity): (Uid, Entity),
+    existing: &HashMap<Uid, Entity>,
+    registered: &RegisteredComponentsResource,
+    serialization: &SerializationResource,
+    merger: &mut Duplicate,
+    result: &mut MergeResult,
+) {
+    // The client might have despawned the entity between two slices, it is cloned again.
+    match existing.get(&uid).filter(|entity| world.contains(**entity)) {
+        Some(entity) => {
+            update_components(
+                world,
+                *entity,
+                synced,
+                synced_entity,
+                registered,
+                serialization,
+            );
+            result.updated.push((uid, *entity));
+        }
+        None => {
+            let entity = world.clone_from_single(synced, synced_entity, merger);
+            result.inserted.push((uid, entity));
+        }
+    }
+}
+
+fn replicated_entities(world: &World) -> Vec<(Uid, Entity)> {
+    let mut query = <(Entity, Read<UidComponent>)>::query();
+
+    query
+        .iter(world)
+        .map(|(entity, uid)| (uid.uid(), *entity))
+        .collect()
+}
+
+// The replicated client entities of which the uid is not synced, sorted by uid.
+fn stale_entities(existing: &HashMap<Uid, Entity>, synced: &HashSet<Uid>) -> Vec<(Uid, Entity)> {
+    let mut stale = existing
+        .iter()
+        .filter(|(uid, _)| !synced.contains(uid))
+        .map(|(uid, entity)| (*uid, *entity))
+        .collect::<Vec<_>>();
+    stale.sort_unstable_by_key(|(uid, _)| *uid);
+    stale
+}
+
+// The client might have despawned a stale entity itself, it is not reported then.
+fn remove_stale(world: &mut World, stale: Vec<(Uid, Entity)>, result: &mut MergeResult) {
+    for (uid, entity) in stale {
+        if world.remove(entity) {
+            result.removed.push((uid, entity));
+        }
+    }
+}
+
+// Replaces the registered components of `entity` by the ones of `synced_entity`, and removes the
+// registered components `synced_entity` does not have.
+fn update_components(
+    world: &mut World,
+    entity: Entity,
+    synced: &World,
+    synced_entity: Entity,
+    registered: &RegisteredComponentsResource,
+    serialization: &SerializationResource,
+) {
+    for (_uid, registration) in registered.slice_with_uid().iter() {
+        if !registration.exists_in_world(synced, synced_entity) {
+            if registration.exists_in_world(world, entity) {
+                registration.remove_component(world, entity);
+            }
+            continue;
+        }
+
+        let mut buffer = None;
+
+        registration.serialize_if_exists_in_world(synced, synced_entity, &mut |serialize| {
+            match serialization.serialize(&serialize) {
+                Ok(data) => buffer = Some(data),
+                Err(e) => log::error!(
+                    "Failed to serialize {} of the synced entity: {}",
+                    registration.type_name(),
+                    e
+                ),
+            }
+        });
+
+        if let Some(data) = buffer {
+            let result = serialization.deserialize_with(&data, |deserializer| {
+                registration.add_component(world, entity, deserializer)
+            });
+
+            if let Err(e) = result {
+                log::error!(
+                    "Failed to deserialize {} of the synced entity: {}",
+                    registration.type_name(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::time::Duration;
+
+    use legion::{world::EntityStore, World};
+
+    use crate::{
+        components::{DynamicComponent, UidComponent},
+        resources::{
+            ClockResource, ManualClock, RegisteredComponentsResource, SerializationResource,
+        },
+        world::merge::{merge_initial_sync, SlicedMerge},
+    };
+
+    struct ClientOnly;
+
+    #[test]
+    fn merge_keeps_client_only_entities_test() {
+        let registered = RegisteredComponentsResource::new();
+        let serialization = SerializationResource::default();
+
+        let mut world = World::default();
+        let menu = world.push((ClientOnly,));
+
+        let mut synced = World::default();
+        synced.push((UidComponent::new(1),));
+
+        let result = merge_initial_sync(&mut world, &synced, &registered, &serialization);
+
+        assert_eq!(world.len(), 2);
+        assert!(world.entry_ref(menu).unwrap().get_component::<ClientOnly>().is_ok());
+        assert_eq!(result.inserted.len(), 1);
+        assert_eq!(result.inserted[0].0, 1);
+    }
+
+    #[test]
+    fn merge_updates_known_replicated_entities_test() {
+        let registered = RegisteredComponentsResource::new();
+        let serialization = SerializationResource::default();
+
+        let mut world = World::default();
+        let known = world.push((
+            UidComponent::new(1),
+            DynamicComponent::new("health", serde_json::json!(10)),
+            ClientOnly,
+        ));
+
+        let mut synced = World::default();
+        synced.push((
+            UidComponent::new(1),
+            DynamicComponent::new("health", serde_json::json!(5)),
+        ));
+        synced.push((UidComponent::new(2),));
+
+        let result = merge_initial_sync(&mut world, &synced, &registered, &serialization);
+
+        assert_eq!(world.len(), 2);
+        assert_eq!(result.updated, vec![(1, known)]);
+        assert_eq!(result.inserted.len(), 1);
+        assert_eq!(result.len(), 2);
+
+        let entry = world.entry_ref(known).unwrap();
+        assert!(entry.get_component::<ClientOnly>().is_ok());
+        assert_eq!(
+            entry.get_component::<DynamicComponent>().unwrap().value(),
+            &serde_json::json!(5)
+        );
+    }
+
+    #[test]
+    fn merge_removes_what_the_server_no_longer_has_test() {
+        let registered = RegisteredComponentsResource::new();
+        let serialization = SerializationResource::default();
+
+        let mut world = World::default();
+        let known = world.push((
+            UidComponent::new(1),
+            DynamicComponent::new("health", serde_json::json!(10)),
+            ClientOnly,
+        ));
+        let zombie = world.push((UidComponent::new(2),));
+        let menu = world.push((ClientOnly,));
+
+        let mut synced = World::default();
+        synced.push((UidComponent::new(1),));
+
+        let result = merge_initial_sync(&mut world, &synced, &registered, &serialization);
+
+        assert_eq!(result.removed, vec![(2, zombie)]);
+        assert!(!world.contains(zombie));
+        assert!(world.contains(menu));
+
+        // The registered component is gone, the client-only component is kept.
+        let entry = world.entry_ref(known).unwrap();
+        assert!(entry.get_component::<DynamicComponent>().is_err());
+        assert!(entry.get_component::<ClientOnly>().is_ok());
+    }
+
+    #[test]
+    fn sliced_merge_removes_stale_entities_last_test() {
+        let registered = RegisteredComponentsResource::new();
+        let serialization = SerializationResource::default();
+
+        let mut world = World::default();
+        let zombie = world.push((UidComponent::new(9),));
+
+        let mut synced = World::default();
+        synced.push((UidComponent::new(1),));
+        synced.push((UidComponent::new(2),));
+
+        let mut merge = SlicedMerge::new(&world, synced);
+        let clock = ClockResource::new(ManualClock::new());
+        let budget = Duration::from_secs(60);
+
+        let first = merge.merge_slice(&mut world, &registered, &serialization, 1, budget, &clock);
+        assert!(first.removed.is_empty());
+        assert!(world.contains(zombie));
+
+        let last = merge.merge_slice(&mut world, &registered, &serialization, 1, budget, &clock);
+        assert_eq!(last.removed, vec![(9, zombie)]);
+        assert!(!world.contains(zombie));
+    }
+
+    #[test]
+    fn sliced_merge_is_bounded_per_slice_test() {
+        let registered = RegisteredComponentsResource::new();
+        let serialization = SerializationResource::default();
+
+        let mut world = World::default();
+        let known = world.push((UidComponent::new(1), ClientOnly));
+
+        let mut synced = World::default();
+        for uid in 1..=5 {
+            synced.push((UidComponent::new(uid),));
+        }
+
+        let mut merge = SlicedMerge::new(&world, synced);
+        assert_eq!(merge.total(), 5);
+
+        let clock = ClockResource::new(ManualClock::new());
+        let budget = Duration::from_secs(60);
+        let mut merged = Vec::new();
+        while !merge.is_complete() {
+            let result =
+                merge.merge_slice(&mut world, &registered, &serialization, 2, budget, &clock);
+            assert!(result.len() <= 2);
+            merged.extend(result.iter().map(|(uid, _)| *uid));
+        }
+
+        merged.sort_unstable();
+        assert_eq!(merged, vec![1, 2, 3, 4, 5]);
+        assert_eq!(world.len(), 5);
+        assert!(world.entry_ref(known).unwrap().get_component::<ClientOnly>().is_ok());
+
+        // A spent budget still merges one entity per slice.
+        let mut synced = World::default();
+        synced.push((UidComponent::new(6),));
+        synced.push((UidComponent::new(7),));
+        let mut merge = SlicedMerge::new(&world, synced);
+        let spent = Duration::from_secs(0);
+        let result = merge.merge_slice(&mut world, &registered, &serialization, 10, spent, &clock);
+        assert_eq!(result.len(), 1);
+        assert_eq!(merge.remaining(), 1);
+    }
+}
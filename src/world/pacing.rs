@@ -0,0 +1,207 @@
+//! Spreading of the state update sends of a command frame over the frame interval.
+
+use std::{collections::VecDeque, time::Duration};
+
+/// Queue of sends that are released in slots spread over the frame interval,
+/// instead of all at once at the start of the frame.
+///
+/// All sends of one client in a frame share a slot and keep their order,
+/// sends that are still queued when the next frame is scheduled are released first.
+pub(crate) struct SendPacer<K, T> {
+    queue: VecDeque<(Duration, K, T)>,
+}
+
+impl<K: PartialEq, T> SendPacer<K, T> {
+    pub(crate) fn new() -> SendPacer<K, T> {
+        SendPacer {
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Queues the sends of a frame that started at `now`, the clients get evenly spaced slots.
+    pub(crate) fn schedule(
+        &mut self,
+        now: Duration,
+        frame_duration: Duration,
+        sends: impl IntoIterator<Item = (K, T)>,
+    ) {
+        // The sends of the previous frame are released before the sends of this frame.
+        for (at, ..) in self.queue.iter_mut() {
+            *at = (*at).min(now);
+        }
+
+        let sends = sends.into_iter().collect::<Vec<(K, T)>>();
+
+        // The slot of a client is the order of its first send.
+        let mut first_sends: Vec<usize> = Vec::new();
+        let mut slots = Vec::with_capacity(sends.len());
+
+        for (index, (client, _)) in sends.iter().enumerate() {
+            let slot = match first_sends.iter().position(|first| sends[*first].0 == *client) {
+                Some(slot) => slot,
+                None => {
+                    first_sends.push(index);
+                    first_sends.len() - 1
+                }
+            };
+            slots.push(slot as u32);
+        }
+
+        let slot_count = (first_sends.len() as u32).max(1);
+
+        let mut timed = sends
+            .into_iter()
+            .zip(slots)
+            .map(|((client, send), slot)| (now + frame_duration * slot / slot_count, client, send))
+            .collect::<Vec<(Duration, K, T)>>();
+
+        // The sort is stable, the sends of a client keep their order.
+        timed.sort_by_key(|(at, ..)| *at);
+        self.queue.extend(timed);
+    }
+
+    /// Removes the sends of which the slot started.
+    pub(crate) fn due(&mut self, now: Duration) -> Vec<(K, T)> {
+        let mut due = Vec::new();
+
+        while self.queue.front().map_or(false, |(at, ..)| *at <= now) {
+            let (_, client, send) = self.queue.pop_front().unwrap();
+            due.push((client, send));
+        }
+
+        due
+    }
+
+    /// Removes all queued sends.
+    pub(crate) fn flush(&mut self) -> Vec<(K, T)> {
+        self.queue
+            .drain(..)
+            .map(|(_, client, send)| (client, send))
+            .collect()
+    }
+
+    /// Moves the queued sends of a client to another client.
+    pub(crate) fn rebind(&mut self, from: K, to: K)
+    where
+        K: Copy,
+    {
+        for (_, client, _) in self.queue.iter_mut() {
+            if *client == from {
+                *client = to;
+            }
+        }
+    }
+
+    /// Drops the queued sends of a client.
+    pub(crate) fn remove(&mut self, client: &K) {
+        self.queue.retain(|(_, queued, _)| queued != client);
+    }
+
+    /// The number of queued sends of a client.
+    pub(crate) fn queued(&self, client: &K) -> usize {
+        self.queue
+            .iter()
+            .filter(|(_, queued, _)| queued == client)
+            .count()
+    }
+
+    /// The queued sends of a client, in the order they are released.
+    pub(crate) fn queued_sends<'a>(&'a self, client: &'a K) -> impl Iterator<Item = &'a T> + 'a {
+        self.queue
+            .iter()
+            .filter(move |(_, queued, _)| queued == client)
+            .map(|(_, _, send)| send)
+    }
+
+    /// Folds the queued sends of a client into its newest send with `merge(older, newer)`,
+    /// the newest send keeps its slot. Returns the number of sends that were folded.
+    pub(crate) fn coalesce(&mut self, client: &K, mut merge: impl FnMut(T, T) -> T) -> usize {
+        let positions = self
+            .queue
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, queued, _))| queued == client)
+            .map(|(index, _)| index)
+            .collect::<Vec<usize>>();
+
+        let (newest, older) = match positions.split_last() {
+            Some((newest, older)) if !older.is_empty() => (*newest, older),
+            _ => return 0,
+        };
+
+        let mut folded = None;
+
+        // The later positions shift by the number of sends removed before them.
+        for (removed, index) in older.iter().enumerate() {
+            let (_, _, send) = self.queue.remove(index - removed).unwrap();
+
+            folded = Some(match folded {
+                Some(folded) => merge(folded, send),
+                None => send,
+            });
+        }
+
+        let newest = newest - older.len();
+        let (at, queued, send) = self.queue.remove(newest).unwrap();
+        self.queue
+            .insert(newest, (at, queued, merge(folded.unwrap(), send)));
+
+        older.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::time::Duration;
+
+    use crate::world::pacing::SendPacer;
+
+    #[test]
+    fn sends_spread_over_frame_test() {
+        let mut pacer = SendPacer::new();
+        let frame = Duration::from_millis(30);
+
+        let sends = vec![(1, 'a'), (2, 'b'), (1, 'c'), (3, 'd')];
+        pacer.schedule(Duration::from_millis(0), frame, sends);
+
+        assert_eq!(pacer.due(Duration::from_millis(0)), vec![(1, 'a'), (1, 'c')]);
+        assert_eq!(pacer.due(Duration::from_millis(15)), vec![(2, 'b')]);
+        assert_eq!(pacer.due(Duration::from_millis(20)), vec![(3, 'd')]);
+        assert!(pacer.is_empty());
+    }
+
+    #[test]
+    fn previous_frame_released_first_test() {
+        let mut pacer = SendPacer::new();
+        let frame = Duration::from_millis(30);
+
+        pacer.schedule(Duration::from_millis(0), frame, vec![(1, 'a'), (2, 'b')]);
+        assert_eq!(pacer.due(Duration::from_millis(0)), vec![(1, 'a')]);
+
+        // The send of client 2 was due at 15 milliseconds, it is released with the new frame.
+        pacer.schedule(Duration::from_millis(10), frame, vec![(1, 'c'), (2, 'd')]);
+
+        assert_eq!(pacer.due(Duration::from_millis(10)), vec![(2, 'b'), (1, 'c')]);
+        assert_eq!(pacer.flush(), vec![(2, 'd')]);
+    }
+
+    #[test]
+    fn queued_sends_are_coalesced_test() {
+        let mut pacer = SendPacer::new();
+        let frame = Duration::from_millis(30);
+
+        pacer.schedule(Duration::from_millis(0), frame, vec![(2, 1), (1, 2)]);
+        pacer.schedule(Duration::from_millis(10), frame, vec![(1, 3), (2, 4)]);
+        pacer.schedule(Duration::from_millis(20), frame, vec![(1, 5)]);
+
+        assert_eq!(pacer.coalesce(&1, |older, newer| older * 10 + newer), 2);
+        assert_eq!(pacer.queued(&1), 1);
+        assert_eq!(pacer.queued_sends(&1).collect::<Vec<_>>(), vec![&235]);
+        assert_eq!(pacer.coalesce(&3, |older, _| older), 0);
+        assert_eq!(pacer.flush(), vec![(2, 1), (2, 4), (1, 235)]);
+    }
+}
@@ -0,0 +1,227 @@
+//! Incremental persistence of the authoritative state of persistent-world servers.
+//!
+//! The components that changed are taken from the state updates, which are built from the
+//! modification buffer, so the world is not scanned for changes. A changed component is handed to
+//! the `PersistenceHooks` at most once per debounce window, with its value at the end of the
+//! window, e.g. to write it to a database.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    mem,
+};
+
+use legion::{Entity, World};
+
+use net_sync::{
+    synchronisation::{CommandFrame, WorldState},
+    uid::{Uid, UidAllocator},
+};
+
+use crate::resources::{RegisteredComponentsResource, SerializationResource};
+
+/// Server hooks called with the debounced changes of the replicated entities,
+/// see `ServerWorldBuilder::with_persistence_hooks`.
+pub trait PersistenceHooks: Send + Sync + 'static {
+    /// The component of the entity changed, `bytes` is its value serialized with the
+    /// `SerializationStrategy` of the server.
+    fn on_persist(&mut self, entity: Entity, uid: Uid, component_uid: Uid, bytes: &[u8]);
+
+    /// The component was removed from the entity.
+    fn on_component_removed(&mut self, _uid: Uid, _component_uid: Uid) {}
+
+    /// The entity was removed from the world, this is not debounced.
+    fn on_entity_removed(&mut self, _uid: Uid) {}
+}
+
+/// The components that changed since they were last persisted.
+pub(crate) struct PersistenceQueue {
+    hooks: Box<dyn PersistenceHooks>,
+    debounce: CommandFrame,
+    // The frame at which the component first changed after it was last persisted.
+    dirty: BTreeMap<(Uid, Uid), CommandFrame>,
+    removed: BTreeSet<Uid>,
+}
+
+impl PersistenceQueue {
+    pub(crate) fn new(hooks: Box<dyn PersistenceHooks>, debounce: CommandFrame) -> Self {
+        PersistenceQueue {
+            hooks,
+            debounce,
+            dirty: BTreeMap::new(),
+            removed: BTreeSet::new(),
+        }
+    }
+
+    /// Marks the components of the state update as changed.
+    pub(crate) fn mark(&mut self, state: &WorldState) {
+        let command_frame = state.command_frame;
+
+        let components = state
+            .inserted
+            .iter()
+            .flat_map(|inserted| {
+                inserted
+                    .components()
+                    .iter()
+                    .map(move |component| (inserted.entity_id(), component.component_id()))
+            })
+            .chain(
+                state
+                    .component_added
+                    .iter()
+                    .map(|added| (added.entity_id(), added.component_data().component_id())),
+            )
+            .chain(
+                state
+                    .changed
+                    .iter()
+                    .map(|changed| (changed.entity_id(), changed.component_data().component_id())),
+            )
+            .chain(
+                state
+                    .component_removed
+                    .iter()
+                    .map(|removed| (removed.entity_id(), removed.component_id())),
+            );
+
+        for key in components {
+            self.dirty.entry(key).or_insert(command_frame);
+        }
+
+        for uid in state.removed.iter() {
+            self.removed.insert(*uid);
+        }
+    }
+
+    /// Persists the components whose debounce window ended at the command frame,
+    /// all of them if `force` is set.
+    pub(crate) fn flush(
+        &mut self,
+        command_frame: CommandFrame,
+        force: bool,
+        world: &World,
+        registered: &RegisteredComponentsResource,
+        serialization: &SerializationResource,
+        allocator: &UidAllocator<Entity>,
+    ) {
+        for uid in mem::take(&mut self.removed) {
+            self.dirty.retain(|(entity_id, _), _| *entity_id != uid);
+            self.hooks.on_entity_removed(uid);
+        }
+
+        let debounce = self.debounce;
+        let due = self
+            .dirty
+            .iter()
+            .filter(|(_, changed)| force || command_frame.saturating_sub(**changed) >= debounce)
+            .map(|(key, _)| *key)
+            .collect::<Vec<(Uid, Uid)>>();
+
+        let registry_by_uid = registered.by_uid();
+
+        for (uid, component_uid) in due {
+            self.dirty.remove(&(uid, component_uid));
+
+            let registration = match registry_by_uid.get(&component_uid) {
+                Some(registration) => registration,
+                None => continue,
+            };
+
+            let entity = *allocator.get_by_val(&uid);
+            let hooks = &mut self.hooks;
+            let mut exists = false;
+
+            registration.serialize_if_exists_in_world(world, entity, &mut |serialize| {
+                exists = true;
+
+                match serialization.serialize(&serialize) {
+                    Ok(buffer) => hooks.on_persist(entity, uid, component_uid, &buffer),
+                    Err(e) => log::error!(
+                        "Failed to persist component {} of entity {}: {}",
+                        registration.type_name(),
+                        uid,
+                        e
+                    ),
+                }
+            });
+
+            if !exists {
+                hooks.on_component_removed(uid, component_uid);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::{
+        any::TypeId,
+        sync::{Arc, Mutex},
+    };
+
+    use legion::{Entity, World};
+
+    use net_sync::{
+        synchronisation::{ComponentData, WorldState},
+        uid::{Uid, UidAllocator},
+    };
+
+    use crate::{
+        components::{Region, UidComponent},
+        resources::{RegisteredComponentsResource, SerializationResource},
+        world::persistence::{PersistenceHooks, PersistenceQueue},
+    };
+
+    #[derive(Default)]
+    struct Recorded {
+        persisted: Vec<(Uid, Uid)>,
+        removed: Vec<Uid>,
+    }
+
+    struct RecordingHooks(Arc<Mutex<Recorded>>);
+
+    impl PersistenceHooks for RecordingHooks {
+        fn on_persist(&mut self, _entity: Entity, uid: Uid, component_uid: Uid, bytes: &[u8]) {
+            assert!(!bytes.is_empty());
+            self.0.lock().unwrap().persisted.push((uid, component_uid));
+        }
+
+        fn on_entity_removed(&mut self, uid: Uid) {
+            self.0.lock().unwrap().removed.push(uid);
+        }
+    }
+
+    #[test]
+    fn changes_are_persisted_once_per_window_test() {
+        let registered = RegisteredComponentsResource::new();
+        let serialization = SerializationResource::default();
+        let region = *registered.get_uid(&TypeId::of::<Region>()).unwrap();
+
+        let mut world = World::default();
+        let mut allocator = UidAllocator::<Entity>::new();
+        let entity = world.push((UidComponent::new(1), Region::new(3)));
+        allocator.allocate(entity, Some(1));
+
+        let recorded = Arc::new(Mutex::new(Recorded::default()));
+        let mut queue = PersistenceQueue::new(Box::new(RecordingHooks(recorded.clone())), 10);
+
+        for command_frame in 0..5 {
+            let mut state = WorldState::new(command_frame);
+            state.change(1, ComponentData::new(region, Vec::new()));
+            queue.mark(&state);
+            queue.flush(command_frame, false, &world, &registered, &serialization, &allocator);
+        }
+        assert!(recorded.lock().unwrap().persisted.is_empty());
+
+        queue.flush(10, false, &world, &registered, &serialization, &allocator);
+        assert_eq!(recorded.lock().unwrap().persisted, vec![(1, region)]);
+
+        let mut state = WorldState::new(11);
+        state.change(1, ComponentData::new(region, Vec::new()));
+        state.remove_entity(1);
+        queue.mark(&state);
+        queue.flush(11, true, &world, &registered, &serialization, &allocator);
+        assert_eq!(recorded.lock().unwrap().persisted.len(), 1);
+        assert_eq!(recorded.lock().unwrap().removed, vec![1]);
+    }
+}
@@ -0,0 +1,149 @@
+//! Pre-population of the server world with static content, e.g. the entities of a level.
+//!
+//! The entities are defined in json with the type names of the `Schema` export:
+//!
+//! ```json
+//! [{ "uid": 1, "components": { "legion_sync::components::Region": { "id": 3 } } }]
+//! ```
+//!
+//! They get the uid of their definition and are part of the baseline that clients receive with
+//! the initial sync, like every other replicated entity.
+
+use std::{
+    any::TypeId,
+    collections::{BTreeMap, HashMap, HashSet},
+};
+
+use legion::{Entity, World};
+use serde::{Deserialize, Serialize};
+
+use net_sync::uid::{Uid, UidAllocator};
+
+use crate::{
+    components::UidComponent, error::ErrorKind, resources::RegisteredComponentsResource, world,
+};
+
+/// A static entity with its stable uid and its components by type name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntityDefinition {
+    pub uid: Uid,
+    #[serde(default)]
+    pub components: BTreeMap<String, serde_json::Value>,
+}
+
+/// Parses a json list of entity definitions.
+pub fn parse_definitions(json: &str) -> Result<Vec<EntityDefinition>, ErrorKind> {
+    serde_json::from_str(json).map_err(|e| ErrorKind::SerializationError(e.to_string()))
+}
+
+/// Inserts the defined entities into the world and registers their uids with the allocator.
+///
+/// The definitions are validated first, on an error the world is left untouched.
+pub(crate) fn populate(
+    world: &mut World,
+    registered: &RegisteredComponentsResource,
+    allocator: &mut UidAllocator<Entity>,
+    definitions: &[EntityDefinition],
+) -> Result<Vec<Entity>, ErrorKind> {
+    let slice = registered.slice_with_uid();
+    let by_name = slice
+        .iter()
+        .map(|(_, registration)| (registration.type_name(), registration))
+        .collect::<HashMap<_, _>>();
+
+    let mut uids = HashSet::new();
+
+    for definition in definitions {
+        if !uids.insert(definition.uid) || world::entity_by_uid(world, definition.uid).is_some() {
+            return Err(ErrorKind::SerializationError(format!(
+                "Entity uid {} is defined more than once",
+                definition.uid
+            )));
+        }
+
+        if let Some(type_name) = definition
+            .components
+            .keys()
+            .find(|type_name| !by_name.contains_key(type_name.as_str()))
+        {
+            return Err(ErrorKind::SerializationError(format!(
+                "Component {} of entity {} is not registered",
+                type_name, definition.uid
+            )));
+        }
+    }
+
+    let mut entities = Vec::with_capacity(definitions.len());
+
+    for definition in definitions {
+        let entity = world.push((UidComponent::new(definition.uid),));
+
+        for (type_name, value) in definition.components.iter() {
+            let registration = by_name[type_name.as_str()];
+
+            // The uid of the definition is authoritative.
+            if registration.ty() == TypeId::of::<UidComponent>() {
+                continue;
+            }
+
+            let data = &mut erased_serde::Deserializer::erase(value.clone());
+            registration.add_component(world, entity, data);
+        }
+
+        allocator.allocate(entity, Some(definition.uid));
+        entities.push(entity);
+    }
+
+    Ok(entities)
+}
+
+#[cfg(test)]
+pub mod test {
+    use legion::{world::EntityStore, Entity, World};
+
+    use net_sync::uid::UidAllocator;
+
+    use crate::{
+        components::Region,
+        resources::RegisteredComponentsResource,
+        world::{
+            entity_by_uid,
+            populate::{parse_definitions, populate},
+        },
+    };
+
+    #[test]
+    fn definitions_are_populated_with_their_uids_test() {
+        let registered = RegisteredComponentsResource::new();
+        let mut allocator = UidAllocator::<Entity>::new();
+        let mut world = World::default();
+
+        let definitions = parse_definitions(
+            r#"[
+                { "uid": 7, "components": { "legion_sync::components::Region": { "id": 3 } } },
+                { "uid": 8 }
+            ]"#,
+        )
+        .unwrap();
+
+        let entities = populate(&mut world, &registered, &mut allocator, &definitions).unwrap();
+
+        assert_eq!(entity_by_uid(&world, 7), Some(entities[0]));
+        assert_eq!(*allocator.get_by_val(&8), entities[1]);
+        assert_eq!(
+            world
+                .entry_ref(entities[0])
+                .unwrap()
+                .get_component::<Region>()
+                .unwrap()
+                .id(),
+            3
+        );
+
+        // Known uids and unknown components are rejected without changing the world.
+        let unknown = parse_definitions(r#"[{ "uid": 9, "components": { "Unknown": 1 } }]"#);
+        assert!(populate(&mut world, &registered, &mut allocator, &unknown.unwrap()).is_err());
+        assert!(populate(&mut world, &registered, &mut allocator, &definitions).is_err());
+        assert_eq!(world.len(), 2);
+    }
+}
@@ -1,45 +1,185 @@
-use std::net::TcpListener;
+use std::{
+    any::TypeId,
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet},
+    fmt::{self, Display, Formatter},
+    hash::{Hash, Hasher},
+    mem,
+    net::{SocketAddr, TcpListener, UdpSocket},
+    sync::Arc,
+    time::Duration,
+};
 
+use itertools::Itertools;
 use legion::{
-    any,
+    query::{IntoQuery, Read},
     systems::{Builder, Resource},
+    storage::Component,
     Entity, Resources, Universe, World,
 };
 use serde::export::PhantomData;
 
 use net_sync::{
     compression::{lz4::Lz4, CompressionStrategy},
+    event::{NetworkEvent, NetworkEventQueue},
     synchronisation::{
-        CommandFrameTicker, ComponentData, ModifiedComponentsBuffer, NetworkCommand,
-        NetworkMessage, WorldState,
+        CommandFrame, ComponentData, ModifiedComponentsBuffer, NetworkCommand, NetworkMessage,
+        WorldState,
     },
     transport,
-    transport::PostOffice,
-    uid::UidAllocator,
+    transport::{tcp::TcpListenerResource, ClientId, PostBox, PostOffice},
+    uid::{Uid, UidAllocator},
 };
 
+#[cfg(feature = "chat")]
+use crate::chat::{ChatMessage, ChatRejection, ChatRelay};
 use crate::{
-    event::{LegionEvent, LegionEventHandler},
-    resources::{EventResource, RegisteredComponentsResource, ResourcesExt},
+    components::{NetworkEntity, Owner, UidComponent},
+    error::ErrorKind,
+    event::{LegionEvent, LegionEventHandler, ServerEvent, ServerEvents},
+    protocol::{
+        self, ClientMessage, ContextId, DisconnectReason, InitialSync, LocalPlayer, ServerAction,
+        ServerMessage, ServerProtocol,
+    },
+    resources::{
+        AreaOfInterest, Clock, ClockResource, CommandFrameTicker, CommandReplayGuard,
+        CommandResultQueue, ComponentConstraints, ComponentVersions, ConnectionHooks,
+        ConnectionLifecycle, ConnectionQuality, DisconnectPolicy, EntityReferences, EventResource,
+        InterestBudget, InterestChange, InterestHooks, InterestRadii, InterestScopes, ListenerGate,
+        LoadShedding, MatchBarrier, MatchPhase, OwnershipRules, PlayerCommands, PlayerOwnership,
+        QualityThresholds, ReferencePolicy, RegionStreaming, RegisteredComponentsResource,
+        Relevancy, RelevancyOverrides, ResourcesExt, SerializationResource, SerializationStrategy,
+        SerializedStateCache, ServerMetrics, SessionToken, SessionTokens, SimulationMerge,
+        SimulationRejection, SocketOptions, StallPolicy, StateBaselines, SyncedRng, TickerEvent,
+        TransportEvent, TrustedSimulators, UdpConfig, UdpServerResource,
+    },
     systems::BuilderExt,
-    world::{world_instance::WorldInstance, WorldBuilder},
+    world::{
+        self,
+        archive::{self, ArchiveStore},
+        context::ReplicationContext,
+        fanout::{ClientPostBoxes, FanoutPool},
+        pacing::SendPacer,
+        persistence::{PersistenceHooks, PersistenceQueue},
+        populate::{self, EntityDefinition},
+        tracker::WorldTracker,
+        world_instance::WorldInstance,
+        BuildError, BuildReport, SystemGroup, WorldBuilder,
+    },
 };
 use bincode::Options;
 use net_sync::re_exports::bincode;
-use std::time::Instant;
 
-pub struct ServerConfig {}
+/// The post office resource of the server world.
+///
+/// User messages sent to clients have to be wrapped in `ServerMessage::User`, user messages of
+/// the clients are wrapped in `ClientMessage::User`.
+pub type ServerPostOffice<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand> =
+    PostOffice<
+        ServerMessage<ServerToClientMessage, ClientToServerCommand>,
+        ClientMessage<ClientToServerMessage, ClientToServerCommand>,
+        ClientToServerCommand,
+    >;
+
+/// The post box of a client in the `ServerPostOffice`, see `fanout::ClientPostBoxes`.
+pub type ServerPostBox<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand> =
+    PostBox<
+        transport::ClientToServerMessage<
+            ClientMessage<ClientToServerMessage, ClientToServerCommand>,
+            ClientToServerCommand,
+        >,
+        transport::ServerToClientMessage<ServerMessage<ServerToClientMessage, ClientToServerCommand>>,
+    >;
+
+/// A mutation of the server world scheduled with `ServerWorld::at_frame`.
+pub type ScheduledAction = Box<dyn FnOnce(&mut World, &mut Resources) + Send>;
+
+/// Forgets a disconnected client (`None`) or moves it to another client id in a resource that is
+/// generic over a game type, e.g. the `PlayerCommands` of `with_local_players`.
+type ClientCleanup = Box<dyn Fn(&Resources, ClientId, Option<ClientId>) + Send + Sync>;
+
+/// Bytes a `StateUpdatePart` adds to its state: the message tags, part number and marker.
+const STATE_PART_OVERHEAD: usize = 16;
+
+pub struct ServerConfig {
+    /// Thresholds at which a client connection is considered degraded or bad.
+    pub quality_thresholds: QualityThresholds,
+    /// Send state updates to degraded clients every `n` command frames.
+    pub degraded_update_interval: u32,
+    /// Send state updates to bad clients every `n` command frames.
+    pub bad_update_interval: u32,
+    /// State updates bigger than this are split into multiple `ServerMessage::StateUpdatePart`s.
+    pub max_packet_size: Option<usize>,
+    /// Stop replicating an entity when one of its components fails to serialize,
+    /// see `ServerEvent::SerializationFailed`.
+    pub quarantine_failed_entities: bool,
+    /// Spread the state update sends of a command frame over the frame interval,
+    /// instead of sending to all clients at once. The updates of a client stay in order.
+    pub pace_state_updates: bool,
+    /// Adapt the interest radius of clients to their bandwidth usage and connection quality,
+    /// see `InterestRadii`.
+    pub interest_budget: Option<InterestBudget>,
+    /// What the `CommandFrameTicker` does after the server missed many command frames,
+    /// stalls are raised as `ServerEvent::TickStalled`.
+    pub stall_policy: StallPolicy,
+    /// Send the synchronisation messages a client receives in a tick, i.e. the pings, baselines,
+    /// state updates, command results and manifests, in one `ServerMessage::Bundle` instead of a
+    /// transport frame each. Bundles are split at `max_packet_size`.
+    pub bundle_messages: bool,
+    /// Threads that filter, serialize and enqueue the state updates of the clients, started
+    /// when the server is built. With `1` everything runs on the thread of the tick.
+    pub fanout_threads: usize,
+    /// What happens with the entities of a client when its connection is lost,
+    /// see `ConnectionLifecycle`.
+    pub disconnect_policy: DisconnectPolicy,
+    /// Send a `ServerMessage::Ping` to the clients every `n` command frames, the round trip
+    /// times decide the quality of their connection. `0` disables the pings.
+    pub ping_interval: u32,
+}
+
+impl ServerConfig {
+    /// Returns every how many command frames state updates are sent to a client with the given quality.
+    pub fn update_interval(&self, quality: ConnectionQuality) -> u32 {
+        match quality {
+            ConnectionQuality::Good => 1,
+            ConnectionQuality::Degraded => self.degraded_update_interval.max(1),
+            ConnectionQuality::Bad => self.bad_update_interval.max(1),
+        }
+    }
+}
 
 impl Default for ServerConfig {
     fn default() -> Self {
-        ServerConfig {}
+        ServerConfig {
+            quality_thresholds: QualityThresholds::default(),
+            degraded_update_interval: 2,
+            bad_update_interval: 4,
+            max_packet_size: None,
+            quarantine_failed_entities: false,
+            pace_state_updates: false,
+            interest_budget: None,
+            stall_policy: StallPolicy::default(),
+            bundle_messages: false,
+            fanout_threads: 1,
+            disconnect_policy: DisconnectPolicy::default(),
+            ping_interval: 30,
+        }
     }
 }
 
 pub struct ServerWorldBuilder<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand> {
     resources: Resources,
-    system_builder: Builder,
+    systems: Vec<SystemGroup>,
+    without_systems: Vec<&'static str>,
     config: ServerConfig,
+    interest_hooks: Option<Box<dyn InterestHooks>>,
+    connection_hooks: Option<Box<dyn ConnectionHooks>>,
+    client_cleanups: Vec<ClientCleanup>,
+    archive: Option<Box<dyn ArchiveStore>>,
+    persistence: Option<PersistenceQueue>,
+    socket_options: SocketOptions,
+    tcp_addr: Option<SocketAddr>,
+    udp_config: UdpConfig,
+    transport: bool,
 
     stcm: PhantomData<ServerToClientMessage>,
     ctsm: PhantomData<ClientToServerMessage>,
@@ -54,17 +194,9 @@ impl<
     for ServerWorldBuilder<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>
 {
     fn default() -> Self {
-        ServerWorldBuilder {
-            resources: Default::default(),
-            system_builder: Builder::default(),
-            config: ServerConfig::default(),
-
-            stcm: PhantomData,
-            ctsm: PhantomData,
-            ctsc: PhantomData,
-        }
-        .default_systems()
-        .default_resources::<Lz4>()
+        ServerWorldBuilder::bare()
+            .default_systems()
+            .default_resources::<Lz4>()
     }
 }
 
@@ -81,13 +213,13 @@ impl<
     fn default_resources<C: CompressionStrategy + 'static>(self) -> Self {
         let mut s = self;
         s.resources
-            .insert_server_resources::<C, ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>(C::default());
+            .insert_server_resources::<C, ServerMessage<ServerToClientMessage, ClientToServerCommand>, ClientMessage<ClientToServerMessage, ClientToServerCommand>, ClientToServerCommand>(C::default());
         s
     }
 
     fn default_systems(self) -> Self {
         let mut s = self;
-        s.system_builder = s.system_builder.add_server_systems();
+        s.systems.push((world::SERVER_SYSTEMS, <Builder as BuilderExt>::add_server_systems));
         s
     }
 
@@ -99,11 +231,27 @@ impl<
 
     fn register_systems(self, user_system_builder: fn(Builder) -> Builder) -> Self {
         let mut s = self;
-        s.system_builder = user_system_builder(s.system_builder);
+        s.systems.push((world::USER_SYSTEMS, user_system_builder));
+        s
+    }
+
+    fn without_system(self, name: &'static str) -> Self {
+        let mut s = self;
+        s.without_systems.push(name);
+        s
+    }
+
+    fn replace_resource<R: Resource>(self, resource: R) -> Self {
+        let mut s = self;
+        s.resources.insert(resource);
         s
     }
 
-    fn build(self) -> Self::BuildResult {
+    fn build(self) -> Result<Self::BuildResult, BuildError> {
+        if let Some(error) = self.dry_run().errors.first() {
+            return Err(*error);
+        }
+
         let mut s = self;
 
         let universe = Universe::new();
@@ -112,9 +260,84 @@ impl<
         s.resources.insert(EventResource::new(&mut main_world));
         s.resources.insert(universe);
 
-        let world = WorldInstance::new(main_world, s.system_builder.build());
+        if let Some(budget) = s.config.interest_budget.clone() {
+            s.resources.insert(InterestRadii::new(budget));
+        }
+        if let Some(mut ticker) = s.resources.get_mut::<CommandFrameTicker>() {
+            ticker.set_stall_policy(s.config.stall_policy);
+        }
+
+        let system_builder = world::enabled_systems(&s.systems, &s.without_systems)
+            .fold(Builder::default(), |builder, (_, add_systems)| add_systems(builder));
+
+        let world = WorldInstance::new(main_world, system_builder.build());
+
+        let mut server = ServerWorld::new(s.resources, world);
+        server.fanout = FanoutPool::new(s.config.fanout_threads);
+        server.config = s.config;
+        server.interest_hooks = s.interest_hooks;
+        server.connection_hooks = s.connection_hooks;
+        server.client_cleanups = s.client_cleanups;
+        server.archive = s.archive;
+        server.persistence = s.persistence;
+        server.socket_options = s.socket_options;
+        server.listener_addr = s.tcp_addr;
+        Ok(server)
+    }
+
+    fn dry_run(&self) -> BuildReport {
+        let mut resources = present_resources!(
+            self.resources,
+            ServerMetrics,
+            ServerEvents,
+            InterestScopes,
+            PlayerOwnership,
+            ConnectionLifecycle,
+            CommandReplayGuard,
+            SessionTokens,
+            ComponentVersions,
+            RegionStreaming,
+            AreaOfInterest,
+            RelevancyOverrides,
+            StateBaselines,
+            TrustedSimulators,
+            ComponentConstraints,
+            LoadShedding,
+            SerializedStateCache,
+            SyncedRng,
+            ClockResource,
+            SerializationResource,
+            CommandFrameTicker,
+            RegisteredComponentsResource,
+            TcpListenerResource,
+            ListenerGate,
+            UdpServerResource,
+        );
+        // Inserted on build.
+        if self.config.interest_budget.is_some() {
+            resources.push(stringify!(InterestRadii));
+        }
+        #[cfg(feature = "chat")]
+        resources.extend(present_resources!(self.resources, ChatRelay));
+
+        let (components, has_game_components) = world::registered_components();
+
+        let mut errors = Vec::new();
+        if !has_game_components {
+            errors.push(BuildError::NoRegisteredComponents);
+        }
+        if !self.transport {
+            errors.push(BuildError::NoTransport);
+        }
 
-        ServerWorld::new(s.resources, world)
+        BuildReport {
+            systems: world::enabled_systems(&self.systems, &self.without_systems)
+                .map(|(name, _)| *name)
+                .collect(),
+            resources,
+            components,
+            errors,
+        }
     }
 }
 
@@ -124,12 +347,108 @@ impl<
         ClientToServerCommand: NetworkCommand,
     > ServerWorldBuilder<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>
 {
+    /// Creates a builder without the default systems and resources.
+    ///
+    /// Call `default_resources` with the compression of choice, or insert all resources yourself.
+    pub fn bare() -> Self {
+        ServerWorldBuilder {
+            resources: Default::default(),
+            systems: Vec::new(),
+            without_systems: Vec::new(),
+            config: ServerConfig::default(),
+            interest_hooks: None,
+            connection_hooks: None,
+            client_cleanups: Vec::new(),
+            archive: None,
+            persistence: None,
+            socket_options: SocketOptions::default(),
+            tcp_addr: None,
+            udp_config: UdpConfig::default(),
+            transport: false,
+
+            stcm: PhantomData,
+            ctsm: PhantomData,
+            ctsc: PhantomData,
+        }
+    }
+
+    /// Tunes the sockets of the client connections, see `SocketOptions`.
+    ///
+    /// The options are set on the listener of `with_tcp` and inherited by the accepted streams,
+    /// call it before `with_tcp` or `with_tcp_addr`.
+    pub fn with_socket_options(mut self, options: SocketOptions) -> Self {
+        self.socket_options = options;
+        self
+    }
+
     pub fn with_tcp(mut self, listener: TcpListener) -> Self {
         listener
             .set_nonblocking(true)
             .expect("Cannot set non-blocking on TCP socket.");
+        self.socket_options
+            .apply_to_listener(&listener)
+            .expect("Cannot set the socket options on TCP socket.");
+        self.tcp_addr = listener.local_addr().ok();
         self.resources.insert_tcp_listener_resources(listener);
-        self.system_builder = self.system_builder.add_tcp_server_systems::<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>();
+        self.systems.push((
+            world::TCP_SERVER_SYSTEMS,
+            <Builder as BuilderExt>::add_tcp_server_systems::<
+                ServerMessage<ServerToClientMessage, ClientToServerCommand>,
+                ClientMessage<ClientToServerMessage, ClientToServerCommand>,
+                ClientToServerCommand,
+            >,
+        ));
+        self.transport = true;
+        self
+    }
+
+    /// Binds the TCP listener on the address with the `SocketOptions`, so
+    /// `SocketOptions::reuse_address` applies, e.g. for a server that restarts on its port.
+    ///
+    /// # Panics
+    ///
+    /// Panics when the address cannot be bound.
+    pub fn with_tcp_addr(self, addr: SocketAddr) -> Self {
+        let listener = self
+            .socket_options
+            .bind_listener(addr)
+            .expect("Cannot bind the TCP listener.");
+        self.with_tcp(listener)
+    }
+
+    /// Sets the reliability of the messages and the timing of the UDP transport,
+    /// call it before `with_udp`.
+    pub fn with_udp_config(mut self, config: UdpConfig) -> Self {
+        self.udp_config = config;
+        self
+    }
+
+    /// Serves the clients over UDP on the bound socket, see `UdpConfig` for the reliability
+    /// of the messages.
+    ///
+    /// A client is accepted on its first datagram and dropped after `UdpConfig::timeout`.
+    pub fn with_udp(mut self, socket: UdpSocket) -> Self {
+        socket
+            .set_nonblocking(true)
+            .expect("Cannot set non-blocking on UDP socket.");
+        self.resources
+            .insert_udp_server_resources(socket, self.udp_config.clone());
+        self.systems.push((
+            world::UDP_SERVER_SYSTEMS,
+            <Builder as BuilderExt>::add_udp_server_systems::<
+                ServerMessage<ServerToClientMessage, ClientToServerCommand>,
+                ClientMessage<ClientToServerMessage, ClientToServerCommand>,
+                ClientToServerCommand,
+            >,
+        ));
+        self.transport = true;
+        self
+    }
+
+    /// Marks the transport as set up by the user, who moves the messages
+    /// between the `ServerPostOffice` and the clients with their own systems or resources.
+    pub fn with_custom_transport(mut self) -> Self {
+        self.transport = true;
         self
     }
 
@@ -137,6 +456,168 @@ impl<
         self.config = config;
         self
     }
+
+    /// Replaces the default `RealClock` by the given clock.
+    pub fn with_clock<C: Clock>(mut self, clock: C) -> Self {
+        self.resources.insert(ClockResource::new(clock));
+        self
+    }
+
+    /// Serializes the initial state syncs with the strategy, the clients must use the same one.
+    /// See `SerializationStrategy`.
+    pub fn with_serialization<S: SerializationStrategy>(mut self, strategy: S) -> Self {
+        self.resources.insert(SerializationResource::new(strategy));
+        self
+    }
+
+    /// Calls the hooks when entities enter or leave the `InterestScopes` of a client.
+    pub fn with_interest_hooks<H: InterestHooks>(mut self, hooks: H) -> Self {
+        self.interest_hooks = Some(Box::new(hooks));
+        self
+    }
+
+    /// Tracks the connected clients and applies the `ServerConfig::disconnect_policy` to the
+    /// entities of the clients that lost their connection, see `ConnectionLifecycle`.
+    pub fn with_connection_lifecycle(mut self) -> Self {
+        self.resources.insert(ConnectionLifecycle::new());
+        self
+    }
+
+    /// Calls the hooks when clients connect, lose their connection and reconnect,
+    /// see `ConnectionLifecycle`.
+    pub fn with_connection_hooks<H: ConnectionHooks>(mut self, hooks: H) -> Self {
+        self.connection_hooks = Some(Box::new(hooks));
+        self
+    }
+
+    /// Seeds the `SyncedRng` with a fixed seed instead of a random one.
+    pub fn with_rng_seed(mut self, seed: u64) -> Self {
+        self.resources.insert(SyncedRng::new(seed));
+        self
+    }
+
+    /// Registers a component field that references another entity, see `EntityReferences`.
+    pub fn with_entity_reference<T: Component>(
+        mut self,
+        policy: ReferencePolicy,
+        field: fn(&mut T) -> &mut NetworkEntity,
+    ) -> Self {
+        if !self.resources.contains::<EntityReferences>() {
+            self.resources.insert(EntityReferences::new());
+        }
+        self.resources
+            .get_mut::<EntityReferences>()
+            .unwrap()
+            .register(policy, field);
+        self
+    }
+
+    /// Buffers the commands of at most `max_players` local players per client,
+    /// see `PlayerCommands`. `C` is the command inside the `PlayerCommand`s the clients send.
+    pub fn with_local_players<C: NetworkCommand>(mut self, max_players: LocalPlayer) -> Self {
+        self.resources.insert(PlayerCommands::<C>::new(max_players));
+        self.client_cleanups.push(Box::new(
+            |resources: &Resources, client: ClientId, to: Option<ClientId>| {
+                if let Some(mut commands) = resources.get_mut::<PlayerCommands<C>>() {
+                    match to {
+                        Some(to) => commands.rebind(client, to),
+                        None => commands.remove_client(client),
+                    }
+                }
+            },
+        ));
+        self
+    }
+
+    /// Only accepts the commands of a client for the entities it owns in the `PlayerOwnership`,
+    /// and replicates the owners with the `Owner` component. See `OwnershipRules`.
+    pub fn with_ownership_rules(mut self, rules: OwnershipRules<ClientToServerCommand>) -> Self {
+        self.resources.insert(rules);
+        self
+    }
+
+    /// Translates the outgoing component data for clients with an older schema version,
+    /// see `ComponentVersions`.
+    pub fn with_component_versions(mut self, versions: ComponentVersions) -> Self {
+        self.resources.insert(versions);
+        self
+    }
+
+    /// Streams only the declared regions of the world to the clients, see `RegionStreaming`.
+    pub fn with_region_streaming(mut self) -> Self {
+        self.resources.insert(RegionStreaming::new());
+        self
+    }
+
+    /// Sends each client only the entities that pass its `InterestFilter`, see `AreaOfInterest`.
+    ///
+    /// `position` reads the position of an entity for the radius filters.
+    pub fn with_area_of_interest(
+        mut self,
+        position: fn(&World, Entity) -> Option<[f32; 3]>,
+    ) -> Self {
+        self.resources.insert(AreaOfInterest::new(position));
+        self
+    }
+
+    /// Diffs the state updates against the last state the client acknowledged,
+    /// see `StateBaselines`. Keeps up to `capacity` unacknowledged updates per client.
+    pub fn with_state_acknowledgements(mut self, capacity: usize) -> Self {
+        self.resources.insert(StateBaselines::new(capacity));
+        self
+    }
+
+    /// Accepts authoritative state from trusted headless clients, see `TrustedSimulators`.
+    pub fn with_trusted_simulators(mut self) -> Self {
+        self.resources.insert(TrustedSimulators::new());
+        self
+    }
+
+    /// Routes the chat messages of the clients with the relay, see `ServerWorld::send_chat`.
+    #[cfg(feature = "chat")]
+    pub fn with_chat(mut self, relay: ChatRelay) -> Self {
+        self.resources.insert(relay);
+        self
+    }
+
+    /// Degrades the replication fidelity while the server is overloaded, see `LoadShedding`.
+    pub fn with_load_shedding(mut self, shedding: LoadShedding) -> Self {
+        self.resources.insert(shedding);
+        self
+    }
+
+    /// Rejects client-writable state in which a component `T` does not satisfy the predicate,
+    /// see `ComponentConstraints`.
+    pub fn with_constraint<T: Component>(
+        mut self,
+        is_valid: impl Fn(&T) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        if !self.resources.contains::<ComponentConstraints>() {
+            self.resources.insert(ComponentConstraints::new());
+        }
+        self.resources
+            .get_mut::<ComponentConstraints>()
+            .unwrap()
+            .register_constraint(is_valid);
+        self
+    }
+
+    /// Archives the entities tagged with `Inactive` into the given store, see `world::archive`.
+    pub fn with_archive_store<S: ArchiveStore>(mut self, store: S) -> Self {
+        self.archive = Some(Box::new(store));
+        self
+    }
+
+    /// Calls the hooks with the changes of the replicated components, each component at most
+    /// once per `debounce` command frames, see `world::persistence`.
+    pub fn with_persistence_hooks<H: PersistenceHooks>(
+        mut self,
+        hooks: H,
+        debounce: CommandFrame,
+    ) -> Self {
+        self.persistence = Some(PersistenceQueue::new(Box::new(hooks), debounce));
+        self
+    }
 }
 
 pub struct ServerWorld<
@@ -149,7 +630,21 @@ pub struct ServerWorld<
     pub(crate) resources: Resources,
     pub(crate) state_update_sequence: u16,
 
-    pub(crate) last_tick: Instant,
+    pub(crate) protocol: ServerProtocol<ClientId, WorldState>,
+    pub(crate) contexts: HashMap<ContextId, ReplicationContext>,
+    interest_hooks: Option<Box<dyn InterestHooks>>,
+    connection_hooks: Option<Box<dyn ConnectionHooks>>,
+    client_cleanups: Vec<ClientCleanup>,
+    archive: Option<Box<dyn ArchiveStore>>,
+    persistence: Option<PersistenceQueue>,
+    socket_options: SocketOptions,
+    listener_addr: Option<SocketAddr>,
+    fanout: FanoutPool,
+    scheduled: BTreeMap<CommandFrame, Vec<ScheduledAction>>,
+    quarantined: HashSet<Uid>,
+    pacer: SendPacer<ClientId, (WorldState, u64)>,
+    tracked: HashMap<(Uid, TypeId), Vec<u8>>,
+    last_updated: HashMap<Uid, HashMap<ClientId, CommandFrame>>,
 
     stcm: PhantomData<ServerToClientMessage>,
     ctsm: PhantomData<ClientToServerMessage>,
@@ -172,7 +667,22 @@ impl<
             config: ServerConfig::default(),
             state_update_sequence: 0,
 
-            last_tick: Instant::now(),
+            // Batched updates are sent as one update.
+            protocol: ServerProtocol::new().with_merge(world::merge_states),
+            contexts: HashMap::new(),
+            interest_hooks: None,
+            connection_hooks: None,
+            client_cleanups: Vec::new(),
+            archive: None,
+            persistence: None,
+            socket_options: SocketOptions::default(),
+            listener_addr: None,
+            fanout: FanoutPool::default(),
+            scheduled: BTreeMap::new(),
+            quarantined: HashSet::new(),
+            pacer: SendPacer::new(),
+            tracked: HashMap::new(),
+            last_updated: HashMap::new(),
 
             stcm: PhantomData,
             ctsm: PhantomData,
@@ -181,206 +691,3107 @@ impl<
     }
 
     pub fn tick(&mut self) {
+        let tick_started = self.resources.get::<ClockResource>().unwrap().now();
+
+        // Raise the connections the transport made and lost since the last tick.
+        self.update_connections();
+
         let resources = &mut self.resources;
 
-        self.world.execute(resources);
+        // Run the actions due in this command frame before the systems,
+        // so their changes are part of the state update of this frame.
+        let command_frame = resources
+            .get::<CommandFrameTicker>()
+            .unwrap()
+            .command_frame();
+        // A staged match keeps its frame-zero state until it starts.
+        let running = resources
+            .get::<MatchBarrier>()
+            .map_or(true, |barrier| barrier.is_running());
 
-        let mut command_ticker = resources.get_mut::<CommandFrameTicker>().unwrap();
+        if running {
+            let later = self.scheduled.split_off(&(command_frame + 1));
+
+            for (_, actions) in mem::replace(&mut self.scheduled, later) {
+                for action in actions {
+                    action(&mut self.world.world, resources);
+                }
+            }
+
+            self.world.execute(resources);
+        }
+
+        // Let game code follow the scope changes made by the systems of this tick.
+        if let Some(hooks) = self.interest_hooks.as_mut() {
+            let changes = resources
+                .get_mut::<InterestScopes>()
+                .map(|mut scopes| scopes.drain_changes())
+                .unwrap_or_default();
+
+            for change in changes {
+                match change {
+                    InterestChange::Relevant(client, entity) => {
+                        hooks.on_entity_relevant(client, entity, resources)
+                    }
+                    InterestChange::Irrelevant(client, entity) => {
+                        hooks.on_entity_irrelevant(client, entity, resources)
+                    }
+                }
+            }
+        }
+
+        // Archived entities are sent as removed entities with the state update of this frame.
+        if let Some(store) = self.archive.as_mut() {
+            let registered = resources.get::<RegisteredComponentsResource>().unwrap();
+            let serialization = resources.get::<SerializationResource>().unwrap();
+            let (_, errors) = archive::archive_inactive(
+                &mut self.world.world,
+                &registered,
+                &serialization,
+                &mut **store,
+            );
+
+            for (uid, error) in errors {
+                log::error!("Failed to archive entity {}: {}", uid, error);
+            }
+        }
+
+        if let Some(mut references) = resources.get_mut::<EntityReferences>() {
+            references.destroy_pending(&mut self.world.world);
+        }
 
-        if command_ticker.try_tick() {
-            let last_tick = self.last_tick;
+        // Owner changes are sent with the state update of this frame.
+        if resources.contains::<OwnershipRules<ClientToServerCommand>>() {
+            if let Some(mut ownership) = resources.get_mut::<PlayerOwnership>() {
+                let registered = resources.get::<RegisteredComponentsResource>().unwrap();
+                let mut tracker = WorldTracker::new(&registered, &mut self.tracked);
+                sync_owners(&mut self.world.world, &mut ownership, &mut tracker);
+            }
+        }
+
+        let mut command_ticker = resources.get_mut::<CommandFrameTicker>().unwrap();
+        let clock = resources.get::<ClockResource>().unwrap();
 
+        if command_ticker.try_tick(clock.now()) {
             // This state packet is for the previous command frame.
             let previous_command_frame = command_ticker.command_frame() - 1;
             let mut world_state = WorldState::new(previous_command_frame);
 
             // Setup resources
+            let mut shedding = resources.get_mut::<LoadShedding>();
             let mut allocator = resources.get_mut::<UidAllocator<Entity>>().unwrap();
             let components = resources.get::<RegisteredComponentsResource>().unwrap();
+            let serialization = resources.get::<SerializationResource>().unwrap();
             let event_resource = resources.get_mut::<EventResource>().unwrap();
             let mut modified_buffer = resources.get_mut::<ModifiedComponentsBuffer>().unwrap();
 
             // Add the serializes differences to the world state.
-            add_differences_to_state(
+            let mut failures = add_differences_to_state(
                 &components,
+                &serialization,
                 &mut world_state,
                 &mut modified_buffer,
+                &mut self.tracked,
                 &self.world.world,
                 &allocator,
+                &self.quarantined,
+                shedding.as_deref().and_then(|shedding| shedding.deferred()),
             );
 
-            handle_world_events(
+            failures.extend(handle_world_events(
                 &self.world.world,
                 &mut allocator,
                 &components,
+                &serialization,
                 &event_resource,
                 &mut world_state,
-            );
+                &self.quarantined,
+            ));
 
-            let mut postoffice =
-                resources
-                    .get_mut::<PostOffice<
-                        ServerToClientMessage,
-                        ClientToServerMessage,
-                        ClientToServerCommand,
-                    >>()
-                    .unwrap();
-
-            // First do an state update to each new client.
-            let new_clients = postoffice
-                .clients()
-                .filter(|x| x.1.connected_at() > last_tick)
-                .count();
-
-            if new_clients != 0 {
-                let new_clients = postoffice
-                    .clients_mut()
-                    .filter(|x| x.1.connected_at() > last_tick);
-
-                let bytes = bincode::serialize(
-                    &self
-                        .world
-                        .world
-                        .as_serializable(any(), components.legion_registry()),
-                )
+            // Deferred changes of removed entities are not sent anymore.
+            if !world_state.removed.is_empty() {
+                self.tracked
+                    .retain(|(uid, _), _| !world_state.removed.contains(uid));
+            }
+
+            if let Some(persistence) = self.persistence.as_mut() {
+                persistence.mark(&world_state);
+                persistence.flush(
+                    previous_command_frame,
+                    false,
+                    &self.world.world,
+                    &components,
+                    &serialization,
+                    &allocator,
+                );
+            }
+
+            let mut postoffice = resources
+                .get_mut::<ServerPostOffice<
+                    ServerToClientMessage,
+                    ClientToServerMessage,
+                    ClientToServerCommand,
+                >>()
                 .unwrap();
 
-                if bytes.len() != 0 {
-                    let universe = resources.get_mut::<Universe>().unwrap();
+            // Sent command results only to the client that issued the command.
+            let mut command_results = resources
+                .get_mut::<CommandResultQueue<ClientToServerCommand>>()
+                .unwrap();
 
-                    let registry = components.legion_registry();
+            let results = command_results
+                .drain()
+                .map(|(id, result)| (id, ServerMessage::CommandResult(result)))
+                .into_group_map();
 
-                    for (_id, client) in new_clients {
-                        client.postbox_mut().send(
-                            transport::ServerToClientMessage::InitialStateSync(bytes.clone()),
-                        )
+            for (id, results) in results {
+                if let Some((_, client)) = postoffice.clients_mut().find(|x| *x.0 == id) {
+                    for result in results {
+                        client
+                            .postbox_mut()
+                            .send(transport::ServerToClientMessage::Message(result));
                     }
                 }
             }
 
-            // Sent state update to all clients.
-            if !world_state.is_empty() {
-                // Then broadcast the world state to all clients.
-                postoffice.broadcast(transport::ServerToClientMessage::StateUpdate(world_state));
-            }
+            let mut metrics = resources.get_mut::<ServerMetrics>().unwrap();
+            let mut events = resources.get_mut::<ServerEvents>().unwrap();
 
-            self.last_tick = Instant::now();
-        }
-    }
+            // Recorded for `ServerWorld::world_stats`, also when no client is connected.
+            record_frame_stats(&mut metrics, &components, &world_state);
 
-    pub fn resources(&self) -> &Resources {
-        &self.resources
-    }
+            // The answered pings measure the round trip times the quality is evaluated with, the
+            // acknowledged state updates move the baselines of the merged updates along.
+            let ping_interval = self.config.ping_interval;
+            let send_ping = ping_interval > 0 && previous_command_frame % ping_interval == 0;
+            let mut baselines = resources.get_mut::<StateBaselines>();
 
-    pub fn resources_mut(&mut self) -> &mut Resources {
-        &mut self.resources
-    }
-}
+            for (id, client) in postoffice.clients_mut() {
+                unbundle_inbox(client.postbox_mut());
 
-// Handle the events from above merge operation.
-fn handle_world_events(
-    world: &World,
-    allocator: &mut UidAllocator<Entity>,
-    components: &RegisteredComponentsResource,
-    event_resource: &EventResource,
-    world_state: &mut WorldState,
-) {
-    let mut event_handler = LegionEventHandler::new();
+                let answers = client.postbox_mut().drain_inbox(|message| {
+                    matches!(
+                        message,
+                        transport::ClientToServerMessage::Message(ClientMessage::Pong(_))
+                            | transport::ClientToServerMessage::Message(ClientMessage::StateAck(_))
+                    )
+                });
 
-    let events = event_handler.handle(&event_resource.legion_receiver(), world, &components);
+                for answer in answers {
+                    match answer {
+                        transport::ClientToServerMessage::Message(ClientMessage::Pong(sent)) => {
+                            metrics.record_round_trip(*id, clock.now().saturating_sub(sent));
+                        }
+                        transport::ClientToServerMessage::Message(ClientMessage::StateAck(
+                            command_frame,
+                        )) => {
+                            if let Some(baselines) = baselines.as_deref_mut() {
+                                baselines.acknowledge(*id, command_frame);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
 
-    for legion_event in events {
-        match legion_event {
-            LegionEvent::ComponentAdded(entity, _component_count) => {
-                let identifier = allocator.get(&entity);
-                world_state.add_component(identifier, ComponentData::new(0, vec![]))
-            }
-            LegionEvent::ComponentRemoved(entity, _component_count) => {
-                let identifier = allocator.get(&entity);
-                world_state.remove_component(identifier, 0);
+                if send_ping {
+                    client
+                        .postbox_mut()
+                        .send(transport::ServerToClientMessage::Message(ServerMessage::Ping(
+                            clock.now(),
+                        )));
+                }
             }
-            LegionEvent::EntityRemoved(entity) => {
-                let identifier = allocator.get(&entity);
-                world_state.remove_entity(identifier);
 
-                // TODO?
-                // let identifier = allocator
-                //     .deallocate(to_remove)
-                //     .expect("Entity should be allocated.");
+            for (client, quality) in metrics.evaluate_quality(&self.config.quality_thresholds) {
+                events.push(ServerEvent::ConnectionQualityChanged { client, quality });
             }
-            LegionEvent::EntityInserted(entity, _component_count) => {
-                let identifier = allocator.get(&entity);
-
-                let mut entity_components = Vec::new();
 
-                for component in components.slice_with_uid().iter() {
-                    component
-                        .1
-                        .serialize_if_exists_in_world(&world, entity, &mut |serialize| {
-                            let mut buffer = Vec::new();
-                            let serializer = &mut bincode::Serializer::new(
-                                &mut buffer,
-                                bincode::DefaultOptions::new()
-                                    .with_fixint_encoding()
-                                    .allow_trailing_bytes(),
-                            );
+            // A server without `SyncedRng` syncs seed 0.
+            let rng_seed = resources.get::<SyncedRng>().map_or(0, |rng| rng.seed());
 
-                            if let Ok(_) = erased_serde::serialize(&serialize, serializer) {
-                                entity_components.push(ComponentData::new(component.0, buffer));
-                            }
-                        });
+            for event in command_ticker.drain_events() {
+                // The clients would take long to adjust their simulation speed to the jump.
+                if let TickerEvent::FramesJumped { .. } = event {
+                    for (_, client) in postoffice.clients_mut() {
+                        client
+                            .postbox_mut()
+                            .send(transport::ServerToClientMessage::Message(
+                                ServerMessage::Resync(rng_seed),
+                            ));
+                    }
                 }
 
-                world_state.insert_entity(identifier, entity_components);
+                events.push(ServerEvent::TickStalled(event));
             }
-        }
-    }
-}
-
-fn add_differences_to_state(
-    components: &RegisteredComponentsResource,
-    world_state: &mut WorldState,
-    modification_buffer: &mut ModifiedComponentsBuffer,
-    world: &World,
-    allocator: &UidAllocator<Entity>,
-) {
-    let entries = modification_buffer.drain_entries();
 
-    for entry in entries {
-        for ((entity_id, component_type), unchanged) in entry.1 {
-            let component_id = components.get_uid(&component_type).expect("Should exist");
-            let entity = allocator.get_by_val(&entity_id);
+            // Failed components are left out of the update, the rest of the world is still sent.
+            for failure in failures {
+                report_failure(failure, &self.config, &mut self.quarantined, &mut events);
+            }
 
-            let components = components.by_type_id();
-            let registered_component = components.get(&component_type).expect("Should exist");
+            // Degraded clients receive their updates in batches.
+            let clients = postoffice
+                .clients()
+                .map(|(id, _)| {
+                    let interval = self.config.update_interval(metrics.quality(id));
+                    let interval = shedding
+                        .as_deref()
+                        .map_or(interval, |shedding| shedding.update_interval(interval));
+                    (*id, interval)
+                })
+                .collect::<Vec<_>>();
 
-            let mut buffer = Vec::new();
-            let serializer = &mut bincode::Serializer::new(
-                &mut buffer,
-                bincode::DefaultOptions::new()
-                    .with_fixint_encoding()
-                    .allow_trailing_bytes(),
+            // Every client receives the same state of a frame, it is serialized once.
+            // Batched states are kept until the client with the longest interval received them.
+            let mut state_cache = resources.get_mut::<SerializedStateCache>().unwrap();
+            let longest_interval = clients.iter().map(|(_, interval)| *interval).max();
+            state_cache.evict_before(
+                previous_command_frame.saturating_sub(longest_interval.unwrap_or(1)),
             );
 
-            let unchanged = &mut bincode::Deserializer::from_slice(
-                &unchanged,
-                bincode::DefaultOptions::new()
-                    .with_fixint_encoding()
-                    .allow_trailing_bytes(),
-            );
+            // The overrides of removed entities are forgotten once the removal was sent.
+            let removed = world_state.removed.clone();
+            let world_state = Some(world_state).filter(|state| !state.is_empty());
+            let actions = self
+                .protocol
+                .frame(previous_command_frame, world_state, clients);
 
-            let is_different = registered_component
-                .serialize_difference_with_current(
-                    world,
-                    *entity,
-                    &mut erased_serde::Deserializer::erase(unchanged),
-                    &mut erased_serde::Serializer::erase(serializer),
-                )
-                .unwrap();
+            // The initial state sync is only serialized when a new client needs it.
+            let mut initial_sync = None;
+            let mut sync_failures = Vec::new();
+            let mut paced = Vec::new();
+            let mut deltas = Vec::new();
 
-            if is_different {
-                world_state.change(entity_id, ComponentData::new(*component_id, buffer));
-            }
-        }
+            let versions = resources.get::<ComponentVersions>();
+            let outdated = |id: ClientId| versions.as_deref().and_then(|v| v.outdated(id));
+
+            // Clients whose resident regions changed receive the populations of the regions
+            // before the state update of the frame.
+            let mut regions = resources.get_mut::<RegionStreaming>();
+            let mut relevancy = resources.get_mut::<RelevancyOverrides>();
+
+            if let Some(regions) = regions.as_deref_mut() {
+                let mut scopes = resources.get_mut::<InterestScopes>();
+                let (transitions, errors) = regions.update(
+                    &self.world.world,
+                    &components,
+                    &serialization,
+                    &self.quarantined,
+                    previous_command_frame,
+                    scopes.as_deref_mut(),
+                    relevancy.as_deref(),
+                );
+
+                for (uid, error) in errors {
+                    log::error!("Failed to stream entity {}: {}", uid, error);
+                }
+
+                // Clients that were not synced yet receive their regions after the initial sync.
+                let protocol = &self.protocol;
+
+                for transition in transitions
+                    .into_iter()
+                    .filter(|transition| protocol.is_synced(transition.client))
+                {
+                    let (state, _) = downgrade_for(
+                        transition.client,
+                        transition.state,
+                        versions.as_deref(),
+                        &self.world.world,
+                        &components,
+                        &serialization,
+                    );
+
+                    if let Some((_, client)) =
+                        postoffice.clients_mut().find(|x| *x.0 == transition.client)
+                    {
+                        let postbox = client.postbox_mut();
+                        for manifest in transition.manifests {
+                            postbox.send(transport::ServerToClientMessage::Message(
+                                ServerMessage::RegionManifest(manifest),
+                            ));
+                        }
+                        if !state.is_empty() {
+                            record_sent(&mut self.last_updated, transition.client, &state);
+                            if let Some(baselines) = baselines.as_deref_mut() {
+                                baselines.record(transition.client, &state);
+                            }
+                            postbox.send(transport::ServerToClientMessage::StateUpdate(state));
+                        }
+                    }
+                }
+            }
+
+            // Clients whose filtered entities changed receive the entities that started or
+            // stopped passing the filter before the state update of the frame.
+            let mut area = resources.get_mut::<AreaOfInterest>();
+
+            if let Some(area) = area.as_deref_mut() {
+                let mut scopes = resources.get_mut::<InterestScopes>();
+                let radii = resources.get::<InterestRadii>();
+                let (transitions, errors) = area.update(
+                    &self.world.world,
+                    &components,
+                    &serialization,
+                    &self.quarantined,
+                    previous_command_frame,
+                    scopes.as_deref_mut(),
+                    regions.as_deref(),
+                    relevancy.as_deref(),
+                    radii.as_deref(),
+                );
+
+                for (uid, error) in errors {
+                    log::error!("Failed to serialize entity of interest {}: {}", uid, error);
+                }
+
+                // Clients that were not synced yet are filtered after the initial sync.
+                let protocol = &self.protocol;
+
+                for (id, state) in transitions
+                    .into_iter()
+                    .filter(|(id, _)| protocol.is_synced(*id))
+                {
+                    let (state, _) = downgrade_for(
+                        id,
+                        state,
+                        versions.as_deref(),
+                        &self.world.world,
+                        &components,
+                        &serialization,
+                    );
+
+                    if let Some((_, client)) = postoffice.clients_mut().find(|x| *x.0 == id) {
+                        record_sent(&mut self.last_updated, id, &state);
+                        if let Some(baselines) = baselines.as_deref_mut() {
+                            baselines.record(id, &state);
+                        }
+                        client
+                            .postbox_mut()
+                            .send(transport::ServerToClientMessage::StateUpdate(state));
+                    }
+                }
+            }
+
+            // Clients whose relevancy overrides changed receive the entities that became
+            // relevant or irrelevant before the state update of the frame.
+            if let Some(relevancy) = relevancy.as_deref_mut() {
+                let mut scopes = resources.get_mut::<InterestScopes>();
+                let mut transitions = HashMap::<ClientId, WorldState>::new();
+                let mut errors = Vec::new();
+
+                for change in relevancy.drain_changes() {
+                    // Clients that are not synced yet receive the overrides with the initial sync.
+                    if !self.protocol.is_synced(change.client)
+                        || self.quarantined.contains(&change.uid)
+                    {
+                        continue;
+                    }
+
+                    let visible = match change.relevancy {
+                        Some(relevancy) => relevancy == Relevancy::Always,
+                        None => {
+                            regions.as_deref().map_or(true, |regions| {
+                                regions.is_resident(change.client, change.uid)
+                            }) && area
+                                .as_deref()
+                                .map_or(true, |area| area.passes(change.client, change.uid))
+                        }
+                    };
+                    let known = self
+                        .last_updated
+                        .get(&change.uid)
+                        .map_or(false, |clients| clients.contains_key(&change.client));
+
+                    let state = transitions
+                        .entry(change.client)
+                        .or_insert_with(|| WorldState::new(previous_command_frame));
+
+                    if known && !visible {
+                        state.remove_entity(change.uid);
+
+                        if let Some(scopes) = scopes.as_deref_mut() {
+                            scopes.remove(change.client, change.entity);
+                        }
+                    } else if !known && visible && self.world.world.contains(change.entity) {
+                        let serialized = world::serialize_entity(
+                            &self.world.world,
+                            &components,
+                            &serialization,
+                            change.entity,
+                            change.uid,
+                            &mut errors,
+                        );
+
+                        state.insert_entity(change.uid, serialized);
+                        relevancy.inserted(change.client, change.uid);
+
+                        if let Some(scopes) = scopes.as_deref_mut() {
+                            scopes.insert(change.client, change.entity);
+                        }
+                    }
+                }
+
+                for (uid, error) in errors {
+                    log::error!("Failed to serialize relevant entity {}: {}", uid, error);
+                }
+
+                for (id, state) in transitions.into_iter().filter(|(_, state)| !state.is_empty()) {
+                    let (state, _) = downgrade_for(
+                        id,
+                        state,
+                        versions.as_deref(),
+                        &self.world.world,
+                        &components,
+                        &serialization,
+                    );
+
+                    if let Some((_, client)) = postoffice.clients_mut().find(|x| *x.0 == id) {
+                        record_sent(&mut self.last_updated, id, &state);
+                        if let Some(baselines) = baselines.as_deref_mut() {
+                            baselines.record(id, &state);
+                        }
+                        client
+                            .postbox_mut()
+                            .send(transport::ServerToClientMessage::StateUpdate(state));
+                    }
+                }
+            }
+
+            for action in actions {
+                match action {
+                    ServerAction::SendInitialSync(id) if outdated(id).is_some() => {
+                        let versions = versions.as_deref().unwrap();
+                        let version = outdated(id).unwrap();
+
+                        // The legion world is serialized in the current schema, outdated clients
+                        // receive an empty world and all entities as inserted entities instead.
+                        let bytes = serialize_world(&World::default(), &components, &serialization)
+                            .and_then(|world| {
+                                serialization.serialize(&InitialSync { rng_seed, world })
+                            });
+                        let bytes = match bytes {
+                            Ok(bytes) => bytes,
+                            Err(error) => {
+                                log::error!(
+                                    "Failed to serialize the initial sync of client {}: {}",
+                                    id,
+                                    error
+                                );
+                                // The client receives the initial sync again on the next frame.
+                                self.protocol.disconnect(id);
+                                continue;
+                            }
+                        };
+
+                        let (state, errors) = versions.initial_state(
+                            version,
+                            &self.world.world,
+                            &components,
+                            &serialization,
+                            &self.quarantined,
+                            previous_command_frame,
+                        );
+
+                        for (uid, error) in errors {
+                            log::error!("Failed to downgrade entity {}: {}", uid, error);
+                        }
+
+                        if let Some(regions) = regions.as_deref_mut() {
+                            regions.synced(id);
+                        }
+                        if let Some(area) = area.as_deref_mut() {
+                            area.synced(id);
+                        }
+                        if let Some(baselines) = baselines.as_deref_mut() {
+                            baselines.synced(id);
+                        }
+
+                        let state = match relevancy.as_deref() {
+                            Some(relevancy) if relevancy.is_filtering(id) => {
+                                relevancy.filter_state(id, &state)
+                            }
+                            _ => state,
+                        };
+
+                        record_sent(&mut self.last_updated, id, &state);
+
+                        if let Some((_, client)) = postoffice.clients_mut().find(|x| *x.0 == id) {
+                            let postbox = client.postbox_mut();
+                            postbox.send(transport::ServerToClientMessage::InitialStateSync(bytes));
+                            postbox.send(transport::ServerToClientMessage::StateUpdate(state));
+                        }
+                    }
+                    ServerAction::SendInitialSync(id) => {
+                        if let Some(regions) = regions.as_deref_mut() {
+                            regions.synced(id);
+                        }
+                        if let Some(area) = area.as_deref_mut() {
+                            area.synced(id);
+                        }
+                        if let Some(baselines) = baselines.as_deref_mut() {
+                            baselines.synced(id);
+                        }
+
+                        let sync = initial_sync.get_or_insert_with(|| {
+                            let (world, failures) = serialize_initial_sync_world(
+                                &self.world.world,
+                                &components,
+                                &serialization,
+                                &self.quarantined,
+                            );
+                            sync_failures.extend(failures);
+
+                            world.and_then(|(world, uids)| {
+                                if world.is_empty() {
+                                    return Ok(None);
+                                }
+
+                                let bytes =
+                                    serialization.serialize(&InitialSync { rng_seed, world })?;
+                                Ok(Some((bytes, uids)))
+                            })
+                        });
+
+                        let sync = match sync {
+                            Ok(sync) => sync.as_ref(),
+                            Err(error) => {
+                                log::error!(
+                                    "Failed to serialize the initial sync of client {}: {}",
+                                    id,
+                                    error
+                                );
+                                // The client receives the initial sync again on the next frame.
+                                self.protocol.disconnect(id);
+                                continue;
+                            }
+                        };
+
+                        if let (Some((bytes, uids)), Some((_, client))) =
+                            (sync, postoffice.clients_mut().find(|x| *x.0 == id))
+                        {
+                            client.postbox_mut().send(
+                                transport::ServerToClientMessage::InitialStateSync(bytes.clone()),
+                            );
+
+                            // Only the entities of the sync are known to the client.
+                            for uid in uids.iter() {
+                                self.last_updated
+                                    .entry(*uid)
+                                    .or_default()
+                                    .insert(id, previous_command_frame);
+                            }
+
+                            // The initial sync carries the whole world.
+                            if let Some(relevancy) = relevancy.as_deref() {
+                                let mut state = WorldState::new(previous_command_frame);
+                                for uid in relevancy.never_relevant(id) {
+                                    state.remove_entity(uid);
+                                }
+
+                                if !state.is_empty() {
+                                    record_sent(&mut self.last_updated, id, &state);
+                                    client
+                                        .postbox_mut()
+                                        .send(transport::ServerToClientMessage::StateUpdate(state));
+                                }
+                            }
+                        }
+                    }
+                    ServerAction::SendStateUpdate(id, state) => {
+                        let (state, filter) = downgrade_for(
+                            id,
+                            state,
+                            versions.as_deref(),
+                            &self.world.world,
+                            &components,
+                            &serialization,
+                        );
+
+                        deltas.push((id, state, filter));
+                    }
+                    ServerAction::SendMergedStateUpdate(id, state) => {
+                        let (state, filter) = downgrade_for(
+                            id,
+                            state,
+                            versions.as_deref(),
+                            &self.world.world,
+                            &components,
+                            &serialization,
+                        );
+
+                        // The merged state is only sent to this client, it can not share cached
+                        // bytes with the state of its command frame.
+                        let mut hasher = DefaultHasher::new();
+                        (filter, id, state.command_frame).hash(&mut hasher);
+
+                        deltas.push((id, state, hasher.finish()));
+                    }
+                }
+            }
+
+            // Entities that failed to serialize are left out of the initial sync.
+            for failure in sync_failures {
+                report_failure(failure, &self.config, &mut self.quarantined, &mut events);
+            }
+
+            // The states of the clients are filtered on the fanout threads.
+            let deltas = {
+                let regions = regions.as_deref();
+                let area = area.as_deref();
+                let relevancy = relevancy.as_deref();
+
+                self.fanout.par_map(deltas, |(id, state, filter)| {
+                    let (state, filter) = match regions {
+                        Some(regions) if regions.is_streaming(id) => (
+                            regions.filter_state(id, &state, relevancy),
+                            regions.cache_filter(id, filter),
+                        ),
+                        _ => (state, filter),
+                    };
+
+                    let (state, filter) = match area {
+                        Some(area) if area.is_filtering(id) => (
+                            area.filter_state(id, &state, relevancy),
+                            area.cache_filter(id, filter),
+                        ),
+                        _ => (state, filter),
+                    };
+
+                    match relevancy {
+                        Some(relevancy) if relevancy.is_filtering(id) => (
+                            id,
+                            relevancy.filter_state(id, &state),
+                            relevancy.cache_filter(id, filter),
+                        ),
+                        _ => (id, state, filter),
+                    }
+                })
+            };
+
+            // The clients that acknowledge their updates receive the changes since the last
+            // acknowledged state, a lost update does not leave them behind. The client restores
+            // the changed components to the announced baseline before it applies the update.
+            let deltas = match baselines.as_deref_mut() {
+                Some(baselines) => deltas
+                    .into_iter()
+                    .map(|(id, state, filter)| {
+                        let (state, filter, baseline) = baselines.delta(id, state, filter);
+
+                        if let Some((_, client)) = postoffice.clients_mut().find(|x| *x.0 == id) {
+                            client
+                                .postbox_mut()
+                                .send(transport::ServerToClientMessage::Message(
+                                    ServerMessage::StateBaseline(state.command_frame, baseline),
+                                ));
+                        }
+
+                        (id, state, filter)
+                    })
+                    .collect(),
+                None => deltas,
+            };
+
+            if self.config.pace_state_updates {
+                paced.extend(
+                    deltas
+                        .into_iter()
+                        .map(|(id, state, filter)| (id, (state, filter))),
+                );
+            } else {
+                send_state_updates(
+                    &mut postoffice,
+                    deltas,
+                    &self.config,
+                    &self.fanout,
+                    &mut metrics,
+                    &mut state_cache,
+                    &serialization,
+                    &mut self.last_updated,
+                );
+            }
+
+            if let Some(relevancy) = relevancy.as_deref_mut() {
+                for uid in removed {
+                    relevancy.remove_entity(uid);
+                }
+            }
+
+            if self.config.pace_state_updates {
+                self.pacer
+                    .schedule(clock.now(), command_ticker.frame_duration(), paced);
+            }
+
+            // The staged match starts once the expected clients were sent the initial sync,
+            // the clients align their simulation to the first frame of the match.
+            if let Some(mut barrier) = resources.get_mut::<MatchBarrier>() {
+                let protocol = &self.protocol;
+                let synced = postoffice
+                    .clients()
+                    .map(|(client, _)| *client)
+                    .filter(|client| protocol.is_synced(*client))
+                    .collect_vec();
+
+                for client in synced {
+                    if barrier.set_ready(client) {
+                        events.push(ServerEvent::MatchClientReady {
+                            client,
+                            ready: barrier.ready().count(),
+                            expected: barrier.expected(),
+                        });
+                    }
+                }
+
+                if barrier.try_start(command_ticker.command_frame()) {
+                    for (_, client) in postoffice.clients_mut() {
+                        client
+                            .postbox_mut()
+                            .send(transport::ServerToClientMessage::Message(
+                                ServerMessage::Resync(rng_seed),
+                            ));
+                    }
+
+                    events.push(ServerEvent::MatchPhaseChanged(barrier.phase()));
+                }
+            }
+
+            if let Some(mut radii) = resources.get_mut::<InterestRadii>() {
+                if let Some(shedding) = shedding.as_deref() {
+                    radii.set_budget_scale(shedding.budget_scale());
+                }
+
+                // Clients with a worse connection get a smaller radius right away.
+                for (client, client_metrics) in metrics.clients() {
+                    let bytes_sent = client_metrics.bytes_sent();
+                    let quality = client_metrics.quality();
+
+                    if let Some(radius) = radii.update(*client, bytes_sent, quality) {
+                        events.push(ServerEvent::InterestRadiusChanged {
+                            client: *client,
+                            radius,
+                        });
+                    }
+                }
+            }
+
+            // Replicate the additional contexts over the same connections.
+            let mut context_messages = HashMap::<ClientId, Vec<_>>::new();
+
+            for context in self.contexts.values_mut() {
+                let context_state =
+                    context.state_update(previous_command_frame, &components, &serialization);
+
+                for (id, _) in postoffice.clients() {
+                    let messages = context_messages.entry(*id).or_default();
+
+                    if !context.synced_clients.contains(id) {
+                        let bytes = context
+                            .initial_sync(&components, &serialization)
+                            .and_then(|world| {
+                                serialization.serialize(&InitialSync { rng_seed, world })
+                            });
+
+                        match bytes {
+                            Ok(bytes) => {
+                                messages
+                                    .push(ServerMessage::ContextInitialSync(context.id(), bytes));
+                                context.synced_clients.insert(*id);
+                            }
+                            Err(error) => log::error!(
+                                "Failed to serialize the initial sync of context {}: {}",
+                                context.id(),
+                                error
+                            ),
+                        }
+                    } else if !context_state.is_empty() {
+                        messages.push(ServerMessage::ContextStateUpdate(
+                            context.id(),
+                            context_state.clone(),
+                        ));
+                    }
+                }
+            }
+
+            for (id, client) in postoffice.clients_mut() {
+                if let Some(messages) = context_messages.remove(id) {
+                    for message in messages {
+                        client
+                            .postbox_mut()
+                            .send(transport::ServerToClientMessage::Message(message));
+                    }
+                }
+            }
+
+            // The lowered fidelity applies from the next command frame.
+            if let Some(shedding) = shedding.as_deref_mut() {
+                let tick_duration = clock.now().saturating_sub(tick_started);
+
+                if let Some(fidelity) = shedding.record_tick(tick_duration) {
+                    events.push(ServerEvent::FidelityChanged {
+                        fidelity,
+                        tick_duration,
+                    });
+                }
+            }
+        }
+
+        // Paced state updates are released during the frame, also in ticks without a new frame.
+        let due = self.pacer.due(clock.now());
+
+        if !due.is_empty() {
+            let mut postoffice = resources
+                .get_mut::<ServerPostOffice<
+                    ServerToClientMessage,
+                    ClientToServerMessage,
+                    ClientToServerCommand,
+                >>()
+                .unwrap();
+            let mut metrics = resources.get_mut::<ServerMetrics>().unwrap();
+            let mut state_cache = resources.get_mut::<SerializedStateCache>().unwrap();
+            let serialization = resources.get::<SerializationResource>().unwrap();
+
+            send_state_updates(
+                &mut postoffice,
+                due.into_iter()
+                    .map(|(id, (state, filter))| (id, state, filter))
+                    .collect(),
+                &self.config,
+                &self.fanout,
+                &mut metrics,
+                &mut state_cache,
+                &serialization,
+                &mut self.last_updated,
+            );
+        }
+
+        // Everything the clients were sent in this tick leaves with the next transport send.
+        if self.config.bundle_messages {
+            let mut postoffice = resources
+                .get_mut::<ServerPostOffice<
+                    ServerToClientMessage,
+                    ClientToServerMessage,
+                    ClientToServerCommand,
+                >>()
+                .unwrap();
+
+            for (_, client) in postoffice.clients_mut() {
+                bundle_outgoing(client.postbox_mut(), self.config.max_packet_size);
+            }
+        }
+    }
+
+    /// Schedules a mutation of the world at the start of the given command frame,
+    /// e.g. a respawn or the expiry of a buff.
+    ///
+    /// Actions of past command frames run in the next tick.
+    /// Like in systems, component modifications have to be tracked to be replicated.
+    pub fn at_frame(
+        &mut self,
+        command_frame: CommandFrame,
+        action: impl FnOnce(&mut World, &mut Resources) + Send + 'static,
+    ) {
+        self.scheduled
+            .entry(command_frame)
+            .or_insert_with(Vec::new)
+            .push(Box::new(action));
+    }
+
+    /// The number of scheduled actions that did not run yet.
+    pub fn scheduled_actions(&self) -> usize {
+        self.scheduled.values().map(|actions| actions.len()).sum()
+    }
+
+    /// Adds a replication context that is synchronized next to this world.
+    pub fn add_context(&mut self, context: ReplicationContext) {
+        self.contexts.insert(context.id(), context);
+    }
+
+    pub fn context(&self, id: ContextId) -> Option<&ReplicationContext> {
+        self.contexts.get(&id)
+    }
+
+    pub fn context_mut(&mut self, id: ContextId) -> Option<&mut ReplicationContext> {
+        self.contexts.get_mut(&id)
+    }
+
+    pub fn remove_context(&mut self, id: ContextId) -> Option<ReplicationContext> {
+        self.contexts.remove(&id)
+    }
+
+    /// The secret a client presents to `migrate_client` from a new connection, issued on the
+    /// first call. Game code sends it to the client, e.g. in a user message.
+    pub fn session_token(&mut self, client: ClientId) -> SessionToken {
+        self.resources
+            .get_mut::<SessionTokens>()
+            .unwrap()
+            .issue(client)
+    }
+
+    /// Continues the session of a client under a new client id, e.g. when it reconnected
+    /// from a new address after roaming from Wi-Fi to LTE, with the `session_token` of `from`.
+    ///
+    /// The synchronisation state, metrics and interest of the client are kept,
+    /// it does not receive a new initial sync. The messages the old connection did not send or
+    /// handle yet move to the postbox of the new one, the old postbox is removed from the
+    /// `ServerPostOffice`. The client keeps the id of the new connection.
+    ///
+    /// Returns `false`, and changes nothing, if the token was not issued to `from`.
+    pub fn migrate_client(&mut self, from: ClientId, to: ClientId, token: SessionToken) -> bool {
+        let verified = self
+            .resources
+            .get::<SessionTokens>()
+            .map_or(false, |tokens| tokens.verify(from, token));
+
+        if !verified {
+            return false;
+        }
+
+        self.move_postbox(from, to);
+        self.protocol.rebind(from, to);
+        self.pacer.rebind(from, to);
+
+        for clients in self.last_updated.values_mut() {
+            if let Some(command_frame) = clients.remove(&from) {
+                clients.insert(to, command_frame);
+            }
+        }
+
+        for context in self.contexts.values_mut() {
+            if context.synced_clients.remove(&from) {
+                context.synced_clients.insert(to);
+            }
+        }
+
+        if let Some(mut tokens) = self.resources.get_mut::<SessionTokens>() {
+            tokens.rebind(from, to);
+        }
+        if let Some(mut lifecycle) = self.resources.get_mut::<ConnectionLifecycle>() {
+            lifecycle.rebind(from, to);
+        }
+        if let Some(mut metrics) = self.resources.get_mut::<ServerMetrics>() {
+            metrics.rebind(from, to);
+        }
+        if let Some(mut scopes) = self.resources.get_mut::<InterestScopes>() {
+            scopes.rebind(from, to);
+        }
+        if let Some(mut radii) = self.resources.get_mut::<InterestRadii>() {
+            radii.rebind(from, to);
+        }
+        if let Some(mut relevancy) = self.resources.get_mut::<RelevancyOverrides>() {
+            relevancy.rebind(from, to);
+        }
+        if let Some(mut ownership) = self.resources.get_mut::<PlayerOwnership>() {
+            ownership.rebind(from, to);
+        }
+        if let Some(mut versions) = self.resources.get_mut::<ComponentVersions>() {
+            versions.rebind(from, to);
+        }
+        if let Some(mut guard) = self.resources.get_mut::<CommandReplayGuard>() {
+            guard.rebind(from, to);
+        }
+        if let Some(mut regions) = self.resources.get_mut::<RegionStreaming>() {
+            regions.rebind(from, to);
+        }
+        if let Some(mut area) = self.resources.get_mut::<AreaOfInterest>() {
+            area.rebind(from, to);
+        }
+        if let Some(mut baselines) = self.resources.get_mut::<StateBaselines>() {
+            baselines.rebind(from, to);
+        }
+        if let Some(mut simulators) = self.resources.get_mut::<TrustedSimulators>() {
+            simulators.rebind(from, to);
+        }
+        if let Some(mut barrier) = self.resources.get_mut::<MatchBarrier>() {
+            barrier.rebind(from, to);
+        }
+        #[cfg(feature = "chat")]
+        if let Some(mut relay) = self.resources.get_mut::<ChatRelay>() {
+            relay.rebind(from, to);
+        }
+        for cleanup in self.client_cleanups.iter() {
+            cleanup(&self.resources, from, Some(to));
+        }
+        true
+    }
+
+    /// Moves the pending messages of the postbox of `from` in front of the ones of `to`, and
+    /// removes the postbox of `from`.
+    fn move_postbox(&mut self, from: ClientId, to: ClientId) {
+        let postoffice = self.resources.get_mut::<ServerPostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >>();
+        let mut postoffice = match postoffice {
+            Some(postoffice) => postoffice,
+            None => return,
+        };
+
+        let (received, queued) = match postoffice.clients_mut().find(|x| *x.0 == from) {
+            Some((_, connection)) => {
+                let postbox = connection.postbox_mut();
+                (
+                    postbox.drain_inbox(|_| true),
+                    postbox.drain_outgoing(|_| true),
+                )
+            }
+            None => return,
+        };
+
+        if let Some((_, connection)) = postoffice.clients_mut().find(|x| *x.0 == to) {
+            let postbox = connection.postbox_mut();
+            let inbox = received
+                .into_iter()
+                .chain(postbox.drain_inbox(|_| true))
+                .collect::<Vec<_>>();
+            let outgoing = queued
+                .into_iter()
+                .chain(postbox.drain_outgoing(|_| true))
+                .collect::<Vec<_>>();
+
+            for message in inbox {
+                postbox.add_to_inbox(message);
+            }
+            for message in outgoing {
+                postbox.send(message);
+            }
+        }
+
+        postoffice.remove_client(from);
+    }
+
+    /// The address of the TCP listener, also while it is closed.
+    pub fn listener_addr(&self) -> Option<SocketAddr> {
+        self.listener_addr
+    }
+
+    /// Stops accepting TCP connections, e.g. for a maintenance window. The connected clients stay
+    /// in the `ServerPostOffice` and keep being served.
+    ///
+    /// The port stays bound, connections the operating system queues in the meantime are
+    /// accepted by `open_listener`. Moving to another port takes a new server, bound with
+    /// `SocketOptions::reuse_address` to take over a port that was just released.
+    ///
+    /// Returns `false` if the listener was already closed or the server has no TCP listener.
+    pub fn close_listener(&mut self) -> bool {
+        self.resources
+            .get_mut::<ListenerGate>()
+            .map_or(false, |mut gate| gate.close())
+    }
+
+    /// Accepts TCP connections again after `close_listener`.
+    ///
+    /// Returns `false` if the listener was already open or the server has no TCP listener.
+    pub fn open_listener(&mut self) -> bool {
+        self.resources
+            .get_mut::<ListenerGate>()
+            .map_or(false, |mut gate| gate.open())
+    }
+
+    /// Sends the reason as last message to the client and forgets its synchronisation state,
+    /// the client raises `ClientEvent::Disconnected`.
+    ///
+    /// The connection itself is closed by game code, after the transport flushed the message.
+    pub fn disconnect(&mut self, client: ClientId, reason: DisconnectReason) {
+        let postoffice = self.resources.get_mut::<ServerPostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >>();
+
+        if let Some(mut postoffice) = postoffice {
+            if let Some((_, connection)) = postoffice.clients_mut().find(|x| *x.0 == client) {
+                connection
+                    .postbox_mut()
+                    .send(transport::ServerToClientMessage::Message(
+                        ServerMessage::Disconnect(reason),
+                    ));
+            }
+        }
+
+        self.protocol.disconnect(client);
+        self.pacer.remove(&client);
+
+        for clients in self.last_updated.values_mut() {
+            clients.remove(&client);
+        }
+        self.last_updated.retain(|_, clients| !clients.is_empty());
+
+        for context in self.contexts.values_mut() {
+            context.synced_clients.remove(&client);
+        }
+
+        if let Some(mut tokens) = self.resources.get_mut::<SessionTokens>() {
+            tokens.remove_client(client);
+        }
+        if let Some(mut metrics) = self.resources.get_mut::<ServerMetrics>() {
+            metrics.remove_client(&client);
+        }
+        if let Some(mut scopes) = self.resources.get_mut::<InterestScopes>() {
+            scopes.remove_client(client);
+        }
+        if let Some(mut radii) = self.resources.get_mut::<InterestRadii>() {
+            radii.remove_client(client);
+        }
+        if let Some(mut relevancy) = self.resources.get_mut::<RelevancyOverrides>() {
+            relevancy.remove_client(client);
+        }
+        if let Some(mut ownership) = self.resources.get_mut::<PlayerOwnership>() {
+            ownership.remove_client(client);
+        }
+        if let Some(mut versions) = self.resources.get_mut::<ComponentVersions>() {
+            versions.remove_client(client);
+        }
+        if let Some(mut guard) = self.resources.get_mut::<CommandReplayGuard>() {
+            guard.remove_client(client);
+        }
+        if let Some(mut regions) = self.resources.get_mut::<RegionStreaming>() {
+            regions.remove_client(client);
+        }
+        if let Some(mut area) = self.resources.get_mut::<AreaOfInterest>() {
+            area.remove_client(client);
+        }
+        if let Some(mut baselines) = self.resources.get_mut::<StateBaselines>() {
+            baselines.remove_client(client);
+        }
+        if let Some(mut simulators) = self.resources.get_mut::<TrustedSimulators>() {
+            simulators.remove_client(client);
+        }
+        if let Some(mut barrier) = self.resources.get_mut::<MatchBarrier>() {
+            barrier.remove_client(client);
+        }
+        #[cfg(feature = "chat")]
+        if let Some(mut relay) = self.resources.get_mut::<ChatRelay>() {
+            relay.remove_client(client);
+        }
+        for cleanup in self.client_cleanups.iter() {
+            cleanup(&self.resources, client, None);
+        }
+        if let Some(mut lifecycle) = self.resources.get_mut::<ConnectionLifecycle>() {
+            lifecycle.close(client);
+        }
+        if let Some(mut events) = self.resources.get_mut::<ServerEvents>() {
+            events.push(ServerEvent::ClientDisconnected { client, reason });
+        }
+    }
+
+    /// Handles the lost connection of a client like `disconnect` with the
+    /// `DisconnectReason::Timeout`, and applies the `ServerConfig::disconnect_policy` to the
+    /// entities it owned in the `PlayerOwnership`.
+    ///
+    /// The tick calls it for the clients the transport reported as disconnected in the
+    /// `NetworkEventQueue` and for the clients that are gone from the `ServerPostOffice`, call it
+    /// for transports that report neither.
+    pub fn connection_lost(&mut self, client: ClientId) {
+        let owned = self
+            .resources
+            .get::<PlayerOwnership>()
+            .map(|ownership| ownership.owned_by_client(client).collect::<Vec<_>>())
+            .unwrap_or_default();
+        let uids = owned.iter().map(|(uid, _)| *uid).collect::<Vec<_>>();
+
+        self.disconnect(client, DisconnectReason::Timeout);
+
+        if let Some(hooks) = self.connection_hooks.as_mut() {
+            hooks.on_client_disconnected(client, &uids, &mut self.world.world, &mut self.resources);
+        }
+
+        match self.config.disconnect_policy {
+            DisconnectPolicy::Keep | DisconnectPolicy::Callback => {}
+            DisconnectPolicy::Despawn => {
+                for uid in uids {
+                    self.destroy(uid);
+                }
+            }
+            DisconnectPolicy::Reserve(duration) => {
+                let expires = self.resources.get::<ClockResource>().unwrap().now() + duration;
+
+                if let Some(mut lifecycle) = self.resources.get_mut::<ConnectionLifecycle>() {
+                    lifecycle.reserve(client, expires, owned);
+                }
+            }
+        }
+    }
+
+    /// Continues the session of a client that lost its connection under its new client id,
+    /// it owns the entities reserved by `DisconnectPolicy::Reserve` again. Authenticating the
+    /// reconnect, e.g. with a session token, is up to game code.
+    ///
+    /// Unlike `migrate_client` the synchronisation state was forgotten, the client receives a new
+    /// initial sync. Returns `false` if nothing was reserved for `from`, e.g. because the
+    /// reservation expired.
+    pub fn reconnect(&mut self, from: ClientId, to: ClientId) -> bool {
+        let owned = self
+            .resources
+            .get_mut::<ConnectionLifecycle>()
+            .and_then(|mut lifecycle| lifecycle.take_reserved(from));
+        let owned = match owned {
+            Some(owned) => owned,
+            None => return false,
+        };
+
+        if let Some(mut ownership) = self.resources.get_mut::<PlayerOwnership>() {
+            for (uid, player) in owned {
+                if world::entity_by_uid(&self.world.world, uid).is_some() {
+                    ownership.set_owner(uid, to, player);
+                }
+            }
+        }
+        if let Some(mut events) = self.resources.get_mut::<ServerEvents>() {
+            events.push(ServerEvent::ClientReconnected { from, to });
+        }
+        if let Some(hooks) = self.connection_hooks.as_mut() {
+            hooks.on_client_reconnected(from, to, &mut self.resources);
+        }
+        true
+    }
+
+    /// Compares the clients of the `ServerPostOffice` with the last tick and destroys the
+    /// entities of the expired reservations, see `ConnectionLifecycle`.
+    fn update_connections(&mut self) {
+        let (connected, lost, expired) = {
+            let mut lifecycle = match self.resources.get_mut::<ConnectionLifecycle>() {
+                Some(lifecycle) => lifecycle,
+                None => return,
+            };
+            let present = self
+                .resources
+                .get::<ServerPostOffice<
+                    ServerToClientMessage,
+                    ClientToServerMessage,
+                    ClientToServerCommand,
+                >>()
+                .map(|postoffice| {
+                    postoffice
+                        .clients()
+                        .map(|(id, _)| *id)
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            let events = self
+                .resources
+                .get_mut::<NetworkEventQueue>()
+                .map(|mut queue| drain_transport_events(&mut queue))
+                .unwrap_or_default();
+            let now = self.resources.get::<ClockResource>().unwrap().now();
+
+            let (connected, lost) = lifecycle.update(present.into_iter(), events);
+            (connected, lost, lifecycle.expired(now))
+        };
+
+        for client in connected {
+            if let Some(mut events) = self.resources.get_mut::<ServerEvents>() {
+                events.push(ServerEvent::ClientConnected { client });
+            }
+            if let Some(hooks) = self.connection_hooks.as_mut() {
+                hooks.on_client_connected(client, &mut self.resources);
+            }
+        }
+
+        for client in lost {
+            self.connection_lost(client);
+        }
+
+        for (client, owned) in expired {
+            log::debug!(
+                "The reservation of client {} expired, destroying {} entities",
+                client,
+                owned.len()
+            );
+            for uid in owned {
+                self.destroy(uid);
+            }
+        }
+    }
+
+    /// Routes a chat message the client sent with a user message of its own, see `ChatRelay`.
+    ///
+    /// The message is sent to its recipients wrapped by `into_message`, e.g. in a chat variant
+    /// of the user message type. Returns the number of recipients.
+    ///
+    /// Panics when the server was built without `ServerWorldBuilder::with_chat`.
+    #[cfg(feature = "chat")]
+    pub fn send_chat(
+        &mut self,
+        sender: ClientId,
+        mut message: ChatMessage,
+        into_message: impl Fn(ChatMessage) -> ServerToClientMessage,
+    ) -> Result<usize, ChatRejection> {
+        let command_frame = self
+            .resources
+            .get::<CommandFrameTicker>()
+            .unwrap()
+            .command_frame();
+
+        let mut relay = self
+            .resources
+            .get_mut::<ChatRelay>()
+            .expect("Chat requires `ServerWorldBuilder::with_chat`.");
+        let mut postoffice = match self.resources.get_mut::<ServerPostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >>() {
+            Some(postoffice) => postoffice,
+            None => return Err(ChatRejection::UnknownRecipient),
+        };
+
+        let connected = postoffice.clients().map(|(client, _)| *client).collect_vec();
+        let recipients = relay.route(sender, &mut message, command_frame, &connected)?;
+
+        for (client, connection) in postoffice.clients_mut() {
+            if recipients.contains(client) {
+                connection
+                    .postbox_mut()
+                    .send(transport::ServerToClientMessage::Message(
+                        ServerMessage::User(into_message(message.clone())),
+                    ));
+            }
+        }
+
+        Ok(recipients.len())
+    }
+
+    /// Stages a match of `expected` clients, the systems are held back until they are synced,
+    /// see `MatchBarrier`.
+    ///
+    /// Call it before the players connect, e.g. after populating the world of the match.
+    pub fn stage_match(&mut self, expected: usize) {
+        self.resources.insert(MatchBarrier::new(expected));
+
+        if let Some(mut events) = self.resources.get_mut::<ServerEvents>() {
+            events.push(ServerEvent::MatchPhaseChanged(MatchPhase::Staging));
+        }
+    }
+
+    /// Replicates the entity to the client whatever the interest rules, e.g. its own character.
+    ///
+    /// Returns `false` if the entity is not replicated, see `RelevancyOverrides`.
+    pub fn always_relevant(&mut self, client: ClientId, entity: Entity) -> bool {
+        self.override_relevancy(client, entity, Some(Relevancy::Always))
+    }
+
+    /// Never replicates the entity to the client, e.g. an enemy in stealth.
+    ///
+    /// Returns `false` if the entity is not replicated, see `RelevancyOverrides`.
+    pub fn never_relevant(&mut self, client: ClientId, entity: Entity) -> bool {
+        self.override_relevancy(client, entity, Some(Relevancy::Never))
+    }
+
+    /// Replicates the entity to the client by the interest rules again.
+    pub fn clear_relevancy(&mut self, client: ClientId, entity: Entity) -> bool {
+        self.override_relevancy(client, entity, None)
+    }
+
+    fn override_relevancy(
+        &mut self,
+        client: ClientId,
+        entity: Entity,
+        relevancy: Option<Relevancy>,
+    ) -> bool {
+        let uid = match world::uid_of(&self.world.world, entity) {
+            Some(uid) => uid,
+            None => return false,
+        };
+
+        if !self.resources.contains::<RelevancyOverrides>() {
+            self.resources.insert(RelevancyOverrides::new());
+        }
+        self.resources
+            .get_mut::<RelevancyOverrides>()
+            .unwrap()
+            .set(client, entity, uid, relevancy);
+        true
+    }
+
+    /// Takes the received commands of all clients, without duplicates and replays,
+    /// see `CommandReplayGuard`.
+    pub fn drain_commands(&mut self) -> Vec<(ClientId, CommandFrame, ClientToServerCommand)> {
+        let postoffice = self.resources.get_mut::<ServerPostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >>();
+        let mut postoffice = match postoffice {
+            Some(postoffice) => postoffice,
+            None => return Vec::new(),
+        };
+        let mut guard = self.resources.get_mut::<CommandReplayGuard>();
+        let mut rules = self
+            .resources
+            .get_mut::<OwnershipRules<ClientToServerCommand>>();
+        let ownership = self.resources.get::<PlayerOwnership>();
+        let mut events = self.resources.get_mut::<ServerEvents>();
+        let mut commands = Vec::new();
+
+        for (client, connection) in postoffice.clients_mut() {
+            unbundle_inbox(connection.postbox_mut());
+
+            let received = connection.postbox_mut().drain_inbox(|message| {
+                matches!(message, transport::ClientToServerMessage::Command(..))
+            });
+
+            for message in received {
+                if let transport::ClientToServerMessage::Command(command_frame, command) = message {
+                    let accepted = guard
+                        .as_deref_mut()
+                        .map_or(true, |guard| guard.accept(*client, command_frame, &command));
+
+                    if !accepted {
+                        continue;
+                    }
+
+                    let authorized = rules.as_deref_mut().map_or(Ok(()), |rules| {
+                        rules.authorize(*client, &command, ownership.as_deref())
+                    });
+
+                    match authorized {
+                        Ok(()) => commands.push((*client, command_frame, command)),
+                        Err(entity) => {
+                            log::debug!(
+                                "Dropped command of client {} for entity {} it does not own",
+                                client,
+                                entity
+                            );
+                            if let Some(events) = events.as_deref_mut() {
+                                events.push(ServerEvent::CommandRejected {
+                                    client: *client,
+                                    command_frame,
+                                    entity,
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        commands
+    }
+
+    /// Returns the uids of the entities that are not replicated anymore,
+    /// see `ServerConfig::quarantine_failed_entities`.
+    pub fn quarantined(&self) -> impl Iterator<Item = Uid> + '_ {
+        self.quarantined.iter().copied()
+    }
+
+    /// Replicates a quarantined entity again, its next modifications are sent to the clients.
+    ///
+    /// Returns `false` if the entity was not quarantined.
+    pub fn release_quarantined(&mut self, uid: Uid) -> bool {
+        self.quarantined.remove(&uid)
+    }
+
+    /// Destroys the entity at the end of the tick, following the policies of the fields that
+    /// reference it. See `EntityReferences`.
+    pub fn destroy(&mut self, uid: Uid) {
+        self.resources
+            .get_mut::<EntityReferences>()
+            .unwrap()
+            .destroy(uid);
+    }
+
+    /// Hands all changes that are still debounced to the `PersistenceHooks`, e.g. before a shutdown.
+    pub fn flush_persistence(&mut self) {
+        let persistence = match self.persistence.as_mut() {
+            Some(persistence) => persistence,
+            None => return,
+        };
+
+        let command_frame = self
+            .resources
+            .get::<CommandFrameTicker>()
+            .unwrap()
+            .command_frame();
+        let registered = self.resources.get::<RegisteredComponentsResource>().unwrap();
+        let serialization = self.resources.get::<SerializationResource>().unwrap();
+        let allocator = self.resources.get::<UidAllocator<Entity>>().unwrap();
+
+        persistence.flush(
+            command_frame,
+            true,
+            &self.world.world,
+            &registered,
+            &serialization,
+            &allocator,
+        );
+    }
+
+    /// Brings an archived entity back into the world under its original uid,
+    /// the clients receive it as inserted entity. See `ServerWorldBuilder::with_archive_store`.
+    ///
+    /// Returns `None` if no archive store is configured or the entity is not archived.
+    pub fn rehydrate(&mut self, uid: Uid) -> Result<Option<Entity>, ErrorKind> {
+        let store = match self.archive.as_mut() {
+            Some(store) => store,
+            None => return Ok(None),
+        };
+
+        let registered = self.resources.get::<RegisteredComponentsResource>().unwrap();
+        let serialization = self.resources.get::<SerializationResource>().unwrap();
+        let mut allocator = self.resources.get_mut::<UidAllocator<Entity>>().unwrap();
+
+        archive::rehydrate(
+            &mut self.world.world,
+            &registered,
+            &serialization,
+            &mut allocator,
+            &mut **store,
+            uid,
+        )
+    }
+
+    /// Inserts static entities, e.g. the content of a level, under the uids of their definitions,
+    /// see `world::populate`.
+    ///
+    /// Call it at startup, before clients connect: the entities are baseline state that clients
+    /// receive with the initial sync, they are not sent as inserted entities.
+    pub fn populate(
+        &mut self,
+        definitions: &[EntityDefinition],
+    ) -> Result<Vec<Entity>, ErrorKind> {
+        let registered = self.resources.get::<RegisteredComponentsResource>().unwrap();
+        let mut allocator = self.resources.get_mut::<UidAllocator<Entity>>().unwrap();
+
+        let entities =
+            populate::populate(&mut self.world.world, &registered, &mut allocator, definitions)?;
+
+        // Drop the insert events of the population.
+        if let Some(events) = self.resources.get::<EventResource>() {
+            events.legion_receiver().try_iter().for_each(drop);
+        }
+
+        Ok(entities)
+    }
+
+    /// The state updates that are held back for the client, e.g. after a stall.
+    pub fn outgoing(&self, client: ClientId) -> OutgoingUpdates {
+        let command_frame = self
+            .resources
+            .get::<CommandFrameTicker>()
+            .unwrap()
+            .command_frame();
+
+        let oldest = self
+            .protocol
+            .pending_states(client)
+            .chain(self.pacer.queued_sends(&client).map(|(state, _)| state))
+            .map(|state| state.command_frame)
+            .min();
+
+        OutgoingUpdates {
+            batched: self.protocol.pending(client),
+            paced: self.pacer.queued(&client),
+            oldest_age: oldest.map(|oldest| command_frame.saturating_sub(oldest)),
+        }
+    }
+
+    /// Folds the held back state updates of the client into its newest update, so a client
+    /// that recovers from a stall receives one update instead of a burst of stale ones.
+    ///
+    /// Returns the number of updates that were cancelled.
+    pub fn coalesce_outgoing(&mut self, client: ClientId) -> usize {
+        let cancelled = self.protocol.coalesce_pending(client, world::merge_states);
+        let has_baselines = self.resources.contains::<StateBaselines>();
+
+        cancelled
+            + self.pacer.coalesce(&client, |(older, _), (newer, filter)| {
+                // The newer update holds the changes since the baseline, older ones included.
+                if has_baselines {
+                    return (newer, filter);
+                }
+
+                // The merged state is only sent to this client, it can not share cached bytes.
+                let mut hasher = DefaultHasher::new();
+                (filter, client, older.command_frame).hash(&mut hasher);
+
+                (world::merge_states(older, newer), hasher.finish())
+            })
+    }
+
+    /// Acknowledges the state updates the client received, see `StateBaselines`.
+    ///
+    /// The world handles the `ClientMessage::StateAck`s of the clients itself. Returns whether
+    /// the acknowledgement was new, `false` without `StateBaselines`.
+    pub fn acknowledge_state(&mut self, client: ClientId, command_frame: CommandFrame) -> bool {
+        self.resources
+            .get_mut::<StateBaselines>()
+            .map_or(false, |mut baselines| {
+                baselines.acknowledge(client, command_frame)
+            })
+    }
+
+    /// Summarizes what the server believes the client knows, e.g. to debug why a client does
+    /// not see an entity.
+    ///
+    /// The report contains the state the server sent, the acknowledgements are only known with
+    /// `StateBaselines`.
+    pub fn debug_client_view(&self, client: ClientId) -> ClientViewReport {
+        let world = &self.world.world;
+
+        let mut entities_in_scope = self
+            .resources
+            .get::<InterestScopes>()
+            .map(|scopes| {
+                scopes
+                    .relevant(client)
+                    .filter_map(|entity| world::uid_of(world, entity))
+                    .collect::<Vec<Uid>>()
+            })
+            .unwrap_or_default();
+        entities_in_scope.sort();
+
+        let metrics = self.resources.get::<ServerMetrics>();
+        let client_metrics = metrics.as_deref().and_then(|metrics| metrics.client(&client));
+        let baselines = self.resources.get::<StateBaselines>();
+
+        let mut synced_contexts = self
+            .contexts
+            .values()
+            .filter(|context| context.synced_clients.contains(&client))
+            .map(|context| context.id())
+            .collect::<Vec<ContextId>>();
+        synced_contexts.sort();
+
+        ClientViewReport {
+            client,
+            synced: self.protocol.is_synced(client),
+            synced_contexts,
+            entities_in_scope,
+            last_state_frame: client_metrics.and_then(|metrics| metrics.last_state_frame()),
+            acked_state_frame: baselines
+                .as_deref()
+                .and_then(|baselines| baselines.acked(client)),
+            unacked_updates: baselines
+                .as_deref()
+                .map_or(0, |baselines| baselines.unacked(client)),
+            batched_updates: self.protocol.pending(client),
+            paced_sends: self.pacer.queued(&client),
+            quality: metrics
+                .as_deref()
+                .map_or(ConnectionQuality::Good, |metrics| metrics.quality(&client)),
+            round_trip_time: client_metrics.and_then(|metrics| metrics.round_trip_time()),
+            recent_state_sizes: client_metrics
+                .map(|metrics| metrics.recent_state_sizes().collect())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Sends the entities to the client again, e.g. in answer to the resync requests of
+    /// a `StaleSweep`. Entities the client should not have anymore are sent as removed.
+    ///
+    /// Quarantined entities are left out.
+    pub fn resync_entities(&mut self, client: ClientId, uids: impl IntoIterator<Item = Uid>) {
+        let world = &self.world.world;
+        let registered = self.resources.get::<RegisteredComponentsResource>().unwrap();
+        let serialization = self.resources.get::<SerializationResource>().unwrap();
+        let regions = self.resources.get::<RegionStreaming>();
+        let area = self.resources.get::<AreaOfInterest>();
+        let command_frame = self
+            .resources
+            .get::<CommandFrameTicker>()
+            .unwrap()
+            .command_frame()
+            .saturating_sub(1);
+
+        let mut state = WorldState::new(command_frame);
+        let mut errors = Vec::new();
+
+        for uid in uids.into_iter().filter(|uid| !self.quarantined.contains(uid)) {
+            let resident = regions.as_deref().map_or(true, |regions| {
+                !regions.is_streaming(client)
+                    || regions
+                        .region_of(uid)
+                        .map_or(true, |region| regions.resident(client).any(|x| x == region))
+            }) && area.as_deref().map_or(true, |area| area.passes(client, uid));
+
+            match world::entity_by_uid(world, uid).filter(|_| resident) {
+                Some(entity) => {
+                    let components = world::serialize_entity(
+                        world,
+                        &registered,
+                        &serialization,
+                        entity,
+                        uid,
+                        &mut errors,
+                    );
+                    state.insert_entity(uid, components);
+                }
+                None => state.remove_entity(uid),
+            }
+        }
+
+        let versions = self.resources.get::<ComponentVersions>();
+        let (state, _) = downgrade_for(
+            client,
+            state,
+            versions.as_deref(),
+            world,
+            &registered,
+            &serialization,
+        );
+
+        for (uid, error) in errors {
+            log::error!("Failed to resync entity {}: {}", uid, error);
+        }
+
+        if state.is_empty() {
+            return;
+        }
+
+        let postoffice = self.resources.get_mut::<ServerPostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >>();
+
+        if let Some(mut postoffice) = postoffice {
+            if let Some((_, connection)) = postoffice.clients_mut().find(|x| *x.0 == client) {
+                record_sent(&mut self.last_updated, client, &state);
+                if let Some(mut baselines) = self.resources.get_mut::<StateBaselines>() {
+                    baselines.record(client, &state);
+                }
+                connection
+                    .postbox_mut()
+                    .send(transport::ServerToClientMessage::StateUpdate(state));
+            }
+        }
+    }
+
+    /// Merges the state a trusted simulator submitted into the world, the merged changes are sent
+    /// to the clients with the next state update. See `TrustedSimulators`.
+    ///
+    /// The changed, added and removed components of the entities the simulator owns are applied,
+    /// the other entities are rejected as a whole. Component types that are not registered are
+    /// ignored. Entities that violate a constraint of the `ComponentConstraints` afterwards are
+    /// restored and rejected.
+    pub fn merge_simulation_state(
+        &mut self,
+        client: ClientId,
+        state: &WorldState,
+    ) -> SimulationMerge {
+        let world = &mut self.world.world;
+        let quarantined = &self.quarantined;
+        let registered = self.resources.get::<RegisteredComponentsResource>().unwrap();
+        let serialization = self.resources.get::<SerializationResource>().unwrap();
+        let simulators = self.resources.get::<TrustedSimulators>();
+        let ownership = self.resources.get::<PlayerOwnership>();
+
+        let mut merge = SimulationMerge::default();
+
+        for uid in state
+            .inserted
+            .iter()
+            .map(|inserted| inserted.entity_id())
+            .chain(state.removed.iter().copied())
+        {
+            merge
+                .rejected
+                .push((uid, SimulationRejection::EntityLifecycle));
+        }
+
+        let uids = state
+            .component_removed
+            .iter()
+            .map(|removed| removed.entity_id())
+            .chain(state.component_added.iter().map(|added| added.entity_id()))
+            .chain(state.changed.iter().map(|changed| changed.entity_id()))
+            .collect::<HashSet<Uid>>();
+
+        let mut entities = HashMap::new();
+
+        for uid in uids {
+            let authorized = simulators
+                .as_deref()
+                .map_or(Err(SimulationRejection::NotTrusted), |simulators| {
+                    simulators.authorize(client, uid, ownership.as_deref())
+                })
+                .and_then(|_| {
+                    world::entity_by_uid(world, uid)
+                        .filter(|_| !quarantined.contains(&uid))
+                        .ok_or(SimulationRejection::UnknownEntity)
+                });
+
+            match authorized {
+                Ok(entity) => {
+                    entities.insert(uid, entity);
+                    merge.merged.push(uid);
+                }
+                Err(rejection) => merge.rejected.push((uid, rejection)),
+            }
+        }
+
+        merge.merged.sort();
+
+        // The components the state touches, restored if the entity violates a constraint.
+        let constraints = self.resources.get::<ComponentConstraints>();
+        let mut snapshots = HashMap::new();
+
+        if constraints.is_some() {
+            let mut errors = Vec::new();
+
+            for (uid, entity) in entities.iter() {
+                let components = world::serialize_entity(
+                    world,
+                    &registered,
+                    &serialization,
+                    *entity,
+                    *uid,
+                    &mut errors,
+                );
+                snapshots.insert(*uid, components);
+            }
+
+            for (uid, error) in errors {
+                log::error!("Failed to snapshot entity {} before the merge: {}", uid, error);
+            }
+        }
+
+        let registry_by_uid = registered.by_uid();
+        let mut tracker = WorldTracker::new(&registered, &mut self.tracked);
+
+        for removed in state.component_removed.iter() {
+            if let (Some(entity), Some(registration)) = (
+                entities.get(&removed.entity_id()),
+                registry_by_uid.get(&removed.component_id()),
+            ) {
+                registration.remove_component(world, *entity);
+            }
+        }
+
+        for added in state.component_added.iter() {
+            let data = added.component_data();
+
+            if let (Some(entity), Some(registration)) = (
+                entities.get(&added.entity_id()),
+                registry_by_uid.get(&data.component_id()),
+            ) {
+                let result = serialization.deserialize_with(data.data(), |data| {
+                    registration.add_component(world, *entity, data)
+                });
+
+                if let Err(error) = result {
+                    log::error!(
+                        "Failed to add component to entity {}: {}",
+                        added.entity_id(),
+                        error
+                    );
+                }
+            }
+        }
+
+        for changed in state.changed.iter() {
+            let data = changed.component_data();
+
+            if let (Some(entity), Some(registration)) = (
+                entities.get(&changed.entity_id()),
+                registry_by_uid.get(&data.component_id()),
+            ) {
+                // Sent as the difference with the value before the first merge of this frame.
+                tracker.track_type(world, *entity, registration.ty());
+
+                let applied = serialization.deserialize_with(data.data(), |data| {
+                    registration.apply_changes(world, *entity, data)
+                });
+
+                if let Err(error) = applied {
+                    log::error!(
+                        "Failed to apply changes to entity {}: {}",
+                        changed.entity_id(),
+                        error
+                    );
+                }
+            }
+        }
+
+        drop(registry_by_uid);
+
+        if let Some(constraints) = constraints.as_deref() {
+            for (uid, entity) in entities.iter() {
+                let violations = constraints.violations(world, *entity);
+                if violations.is_empty() {
+                    continue;
+                }
+
+                log::warn!(
+                    "Rejected the simulation state of entity {} from client {}, it violates {}.",
+                    uid,
+                    client,
+                    violations.join(", ")
+                );
+
+                let touched = state
+                    .component_removed
+                    .iter()
+                    .filter(|removed| removed.entity_id() == *uid)
+                    .map(|removed| removed.component_id())
+                    .chain(
+                        state
+                            .component_added
+                            .iter()
+                            .filter(|added| added.entity_id() == *uid)
+                            .map(|added| added.component_data().component_id()),
+                    )
+                    .chain(
+                        state
+                            .changed
+                            .iter()
+                            .filter(|changed| changed.entity_id() == *uid)
+                            .map(|changed| changed.component_data().component_id()),
+                    )
+                    .collect::<HashSet<Uid>>();
+
+                restore_components(
+                    world,
+                    &registered,
+                    &serialization,
+                    *entity,
+                    &snapshots[uid],
+                    &touched,
+                );
+
+                merge.merged.retain(|merged| merged != uid);
+                merge
+                    .rejected
+                    .push((*uid, SimulationRejection::ConstraintViolation));
+            }
+        }
+
+        merge.rejected.sort_by_key(|(uid, _)| *uid);
+        merge
+    }
+
+    /// Reports how far the entity is replicated, e.g. for debugging tools or to wait until every
+    /// client in scope received a change.
+    ///
+    /// Like `debug_client_view` it contains the state updates the server sent,
+    /// not what the clients acknowledged.
+    pub fn replication_status(&self, entity: Entity) -> ReplicationStatus {
+        let uid = world::uid_of(&self.world.world, entity);
+
+        let mut clients_in_scope = self
+            .resources
+            .get::<InterestScopes>()
+            .map(|scopes| scopes.clients_of(entity).collect::<Vec<ClientId>>())
+            .unwrap_or_default();
+        clients_in_scope.sort();
+
+        let mut last_updated = uid
+            .and_then(|uid| self.last_updated.get(&uid))
+            .map(|clients| clients.iter().map(|(client, frame)| (*client, *frame)).collect())
+            .unwrap_or_else(Vec::new);
+        last_updated.sort();
+
+        let mut queued_updates = Vec::new();
+        let postoffice = self.resources.get::<ServerPostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >>();
+
+        if let (Some(uid), Some(postoffice)) = (uid, postoffice) {
+            let touches = |state: &WorldState| {
+                state.removed.contains(&uid) || changed_entities(state).any(|x| x == uid)
+            };
+
+            for (client, _) in postoffice.clients() {
+                let batched = self.protocol.pending_states(*client).filter(|x| touches(x));
+                let paced = self.pacer.queued_sends(client).filter(|x| touches(&x.0));
+                let queued = batched.count() + paced.count();
+
+                if queued > 0 {
+                    queued_updates.push((*client, queued));
+                }
+            }
+        }
+        queued_updates.sort();
+
+        ReplicationStatus {
+            entity,
+            uid,
+            #[cfg(feature = "debug-names")]
+            name: world::name_of(&self.world.world, entity),
+            clients_in_scope,
+            last_updated,
+            queued_updates,
+            pending_changes: uid.map_or(0, |uid| {
+                self.tracked
+                    .keys()
+                    .filter(|(tracked, _)| *tracked == uid)
+                    .count()
+            }),
+        }
+    }
+
+    /// Summarizes the replicated world for capacity planning, e.g. for a dashboard that polls it.
+    ///
+    /// The component sizes are estimated from the last serialized component of each type,
+    /// the changes are averaged over the recent frames.
+    pub fn world_stats(&self) -> WorldStats {
+        let world = &self.world.world;
+        let registered = self.resources.get::<RegisteredComponentsResource>().unwrap();
+        let metrics = self.resources.get::<ServerMetrics>();
+
+        let entities = <(Entity, Read<UidComponent>)>::query()
+            .iter(world)
+            .map(|(entity, _)| *entity)
+            .collect::<Vec<Entity>>();
+
+        let mut components = registered
+            .slice_with_uid()
+            .iter()
+            .map(|(_, registration)| {
+                let count = entities
+                    .iter()
+                    .filter(|entity| registration.exists_in_world(world, **entity))
+                    .count();
+                let size = metrics
+                    .as_deref()
+                    .and_then(|metrics| metrics.component_size(registration.type_name()));
+
+                ComponentStats {
+                    type_name: registration.type_name(),
+                    count,
+                    size,
+                    estimated_bytes: size.map(|size| size * count),
+                }
+            })
+            .collect::<Vec<ComponentStats>>();
+        components.sort_by_key(|stats| stats.type_name);
+
+        let clients = self
+            .resources
+            .get::<ServerPostOffice<
+                ServerToClientMessage,
+                ClientToServerMessage,
+                ClientToServerCommand,
+            >>()
+            .map_or(0, |postoffice| postoffice.clients().count());
+
+        WorldStats {
+            entities: entities.len(),
+            components,
+            clients,
+            average_changes_per_frame: metrics
+                .as_deref()
+                .map_or(0., |metrics| metrics.average_changes_per_frame()),
+        }
+    }
+
+    /// The world with the replicated entities, mutate it with `modify`.
+    pub fn world(&self) -> &World {
+        &self.world.world
+    }
+
+    /// Mutates the world outside of the systems, the changes are sent with the next state update.
+    ///
+    /// Inserted and removed entities and components are tracked by the world events, components
+    /// modified in place have to go through `WorldTracker::modify` or `WorldTracker::track`.
+    ///
+    /// ```ignore
+    /// server.modify(|world, tracker| {
+    ///     tracker.modify(world, entity, |health: &mut Health| health.value = 100);
+    /// });
+    /// ```
+    pub fn modify<R>(&mut self, modify: impl FnOnce(&mut World, &mut WorldTracker) -> R) -> R {
+        let registered = self
+            .resources
+            .get::<RegisteredComponentsResource>()
+            .unwrap();
+        let mut tracker = WorldTracker::new(&registered, &mut self.tracked);
+
+        modify(&mut self.world.world, &mut tracker)
+    }
+
+    /// Returns the replicated entity with the given uid.
+    pub fn entity_by_uid(&self, uid: Uid) -> Option<Entity> {
+        world::entity_by_uid(&self.world.world, uid)
+    }
+
+    /// Returns the uid of the entity, `None` for entities that are not replicated.
+    pub fn uid_of(&self, entity: Entity) -> Option<Uid> {
+        world::uid_of(&self.world.world, entity)
+    }
+
+    /// Returns the first replicated entity with the given `DebugName`.
+    #[cfg(feature = "debug-names")]
+    pub fn entity_by_name(&self, name: &str) -> Option<Entity> {
+        world::entity_by_name(&self.world.world, name)
+    }
+
+    /// Returns the `DebugName` of the entity, `None` for entities without a name.
+    #[cfg(feature = "debug-names")]
+    pub fn name_of(&self, entity: Entity) -> Option<String> {
+        world::name_of(&self.world.world, entity)
+    }
+
+    pub fn resources(&self) -> &Resources {
+        &self.resources
+    }
+
+    pub fn resources_mut(&mut self) -> &mut Resources {
+        &mut self.resources
+    }
+}
+
+/// The state updates the server holds back for a client, see `ServerWorld::outgoing`.
+///
+/// Messages that were handed to the postbox of the transport are not included.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OutgoingUpdates {
+    /// State updates that wait for the next send frame of a degraded client.
+    pub batched: usize,
+    /// State updates that wait for their slot, see `ServerConfig::pace_state_updates`.
+    pub paced: usize,
+    /// The command frames since the oldest held back update was created.
+    pub oldest_age: Option<CommandFrame>,
+}
+
+/// What the server believes a client knows, see `ServerWorld::debug_client_view`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientViewReport {
+    pub client: ClientId,
+    /// Whether the client received the initial state sync.
+    pub synced: bool,
+    /// The additional replication contexts of which the client received the initial sync.
+    pub synced_contexts: Vec<ContextId>,
+    /// The uids of the entities in the interest scope of the client, see `InterestScopes`.
+    pub entities_in_scope: Vec<Uid>,
+    /// The command frame of the last state update that was sent to the client.
+    pub last_state_frame: Option<CommandFrame>,
+    /// The command frame of the last state update the client acknowledged, see `StateBaselines`.
+    pub acked_state_frame: Option<CommandFrame>,
+    /// State updates that were sent but not acknowledged yet.
+    pub unacked_updates: usize,
+    /// State updates that wait for the next send frame of a degraded client.
+    pub batched_updates: usize,
+    /// State updates that wait for their slot, see `ServerConfig::pace_state_updates`.
+    pub paced_sends: usize,
+    pub quality: ConnectionQuality,
+    pub round_trip_time: Option<Duration>,
+    /// The sizes in bytes of the last sent state updates, the oldest first.
+    pub recent_state_sizes: Vec<usize>,
+}
+
+impl Display for ClientViewReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "client {:?}:", self.client)?;
+        writeln!(f, "  synced: {}", self.synced)?;
+        writeln!(f, "  synced contexts: {:?}", self.synced_contexts)?;
+        writeln!(f, "  entities in scope: {:?}", self.entities_in_scope)?;
+        writeln!(f, "  last state frame: {:?}", self.last_state_frame)?;
+        writeln!(f, "  acked state frame: {:?}", self.acked_state_frame)?;
+        writeln!(f, "  unacked updates: {}", self.unacked_updates)?;
+        writeln!(f, "  batched updates: {}", self.batched_updates)?;
+        writeln!(f, "  paced sends: {}", self.paced_sends)?;
+        writeln!(f, "  quality: {:?}", self.quality)?;
+        writeln!(f, "  round trip time: {:?}", self.round_trip_time)?;
+        writeln!(f, "  recent state sizes: {:?}", self.recent_state_sizes)
+    }
+}
+
+/// How far an entity is replicated, see `ServerWorld::replication_status`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplicationStatus {
+    pub entity: Entity,
+    /// The uid of the entity, `None` if it is not replicated.
+    pub uid: Option<Uid>,
+    /// The `DebugName` of the entity.
+    #[cfg(feature = "debug-names")]
+    pub name: Option<String>,
+    /// The clients that have the entity in their interest scope, see `InterestScopes`.
+    pub clients_in_scope: Vec<ClientId>,
+    /// The command frame of the last state update or initial sync with the entity, per client.
+    pub last_updated: Vec<(ClientId, CommandFrame)>,
+    /// The batched and paced state updates with a change of the entity, per client.
+    pub queued_updates: Vec<(ClientId, usize)>,
+    /// Components modified with `ServerWorld::modify` that are sent with the next state update.
+    pub pending_changes: usize,
+}
+
+impl ReplicationStatus {
+    /// The command frame in which the client was last sent the entity.
+    pub fn last_updated(&self, client: ClientId) -> Option<CommandFrame> {
+        self.last_updated
+            .iter()
+            .find(|(updated, _)| *updated == client)
+            .map(|(_, command_frame)| *command_frame)
+    }
+
+    /// Whether every client in scope was sent the entity at or after the command frame,
+    /// and no change of the entity is still queued.
+    pub fn is_sent_since(&self, command_frame: CommandFrame) -> bool {
+        self.pending_changes == 0
+            && self.queued_updates.is_empty()
+            && self.clients_in_scope.iter().all(|client| {
+                self.last_updated(*client)
+                    .map_or(false, |updated| updated >= command_frame)
+            })
+    }
+}
+
+impl Display for ReplicationStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "entity {:?} (uid {:?}):", self.entity, self.uid)?;
+        #[cfg(feature = "debug-names")]
+        writeln!(f, "  name: {:?}", self.name)?;
+        writeln!(f, "  clients in scope: {:?}", self.clients_in_scope)?;
+        writeln!(f, "  last updated: {:?}", self.last_updated)?;
+        writeln!(f, "  queued updates: {:?}", self.queued_updates)?;
+        writeln!(f, "  pending changes: {}", self.pending_changes)
+    }
+}
+
+/// Summary of the replicated world, see `ServerWorld::world_stats`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorldStats {
+    /// The number of replicated entities.
+    pub entities: usize,
+    /// The replicated entities per registered component type, sorted by type name.
+    pub components: Vec<ComponentStats>,
+    /// The number of connected clients.
+    pub clients: usize,
+    /// The inserted and removed entities and the added, removed and changed components per frame.
+    pub average_changes_per_frame: f32,
+}
+
+impl WorldStats {
+    /// The estimated size in bytes of the replicated components,
+    /// the component types that were not serialized yet are left out.
+    pub fn estimated_bytes(&self) -> usize {
+        self.components
+            .iter()
+            .filter_map(|stats| stats.estimated_bytes)
+            .sum()
+    }
+}
+
+impl Display for WorldStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "entities: {}", self.entities)?;
+        writeln!(f, "clients: {}", self.clients)?;
+        writeln!(f, "changes per frame: {:.1}", self.average_changes_per_frame)?;
+        writeln!(f, "estimated bytes: {}", self.estimated_bytes())?;
+
+        for stats in self.components.iter() {
+            writeln!(
+                f,
+                "  {}: {} entities, {:?} bytes each",
+                stats.type_name, stats.count, stats.size
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The replicated entities with a component type, see `WorldStats`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComponentStats {
+    pub type_name: &'static str,
+    /// The number of replicated entities with the component.
+    pub count: usize,
+    /// The size in bytes of the last serialized component, `None` if it was not serialized yet.
+    pub size: Option<usize>,
+    pub estimated_bytes: Option<usize>,
+}
+
+fn send_state_updates<
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    postoffice: &mut ServerPostOffice<
+        ServerToClientMessage,
+        ClientToServerMessage,
+        ClientToServerCommand,
+    >,
+    updates: Vec<(ClientId, WorldState, u64)>,
+    config: &ServerConfig,
+    fanout: &FanoutPool,
+    metrics: &mut ServerMetrics,
+    state_cache: &mut SerializedStateCache,
+    serialization: &SerializationResource,
+    last_updated: &mut HashMap<Uid, HashMap<ClientId, CommandFrame>>,
+) {
+    let mut postboxes = ClientPostBoxes::new(postoffice);
+    let updates = updates
+        .into_iter()
+        .filter(|(id, _, _)| postboxes.contains(*id))
+        .collect::<Vec<_>>();
+
+    // The payloads that are not cached yet are serialized on the fanout threads, once per filter.
+    let mut missing = HashMap::new();
+    for (_, state, filter) in updates.iter() {
+        if state_cache.get(state.command_frame, *filter).is_none() {
+            missing.entry((state.command_frame, *filter)).or_insert(state);
+        }
+    }
+    let results = fanout.par_map(missing.into_iter().collect(), |(key, state)| {
+        (key, serialization.serialize(state))
+    });
+
+    let mut serialized = HashMap::new();
+    let mut failed = HashSet::new();
+    for ((command_frame, filter), result) in results {
+        match result {
+            Ok(bytes) => {
+                serialized.insert((command_frame, filter), bytes);
+            }
+            Err(e) => {
+                log::error!(
+                    "Failed to serialize the state update of frame {}: {}",
+                    command_frame,
+                    e
+                );
+                failed.insert((command_frame, filter));
+            }
+        }
+    }
+
+    // The clients of a state that failed to serialize skip the update of this frame.
+    let updates = updates
+        .into_iter()
+        .filter(|(_, state, filter)| !failed.contains(&(state.command_frame, *filter)))
+        .filter_map(|(id, state, filter)| {
+            let bytes = state_cache.get_or_serialize(state.command_frame, filter, || {
+                serialized
+                    .remove(&(state.command_frame, filter))
+                    .map_or_else(|| serialization.serialize(&state), Ok)
+            });
+            let bytes = match bytes {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    log::error!(
+                        "Failed to serialize the state update of frame {} for client {}: {}",
+                        state.command_frame,
+                        id,
+                        e
+                    );
+                    return None;
+                }
+            };
+            metrics.record_state_update(id, state.command_frame, bytes.len());
+            record_sent(last_updated, id, &state);
+
+            Some((id, (state, bytes)))
+        })
+        .collect::<Vec<_>>();
+
+    // The updates of a client stay in order, the clients are split and enqueued in parallel.
+    let max_packet_size = config.max_packet_size;
+    fanout.par_map(postboxes.pair(updates), |(_, updates, postbox)| {
+        for (state, bytes) in updates {
+            enqueue_state_update(postbox, state, bytes, max_packet_size);
+        }
+    });
+}
+
+fn enqueue_state_update<
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    postbox: &mut ServerPostBox<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>,
+    state: WorldState,
+    bytes: Arc<Vec<u8>>,
+    max_packet_size: Option<usize>,
+) {
+    match max_packet_size {
+        Some(max_packet_size) if bytes.len() > max_packet_size => {
+            let parts = protocol::WorldState::from(&state)
+                .split(max_packet_size.saturating_sub(STATE_PART_OVERHEAD));
+
+            for part in parts {
+                postbox.send(transport::ServerToClientMessage::Message(
+                    ServerMessage::StateUpdatePart(part),
+                ));
+            }
+        }
+        // The clients share the bytes of the state, it is not serialized again per client.
+        _ => postbox.send(transport::ServerToClientMessage::Message(
+            ServerMessage::SerializedStateUpdate(bytes),
+        )),
+    }
+}
+
+/// Packs the messages a client was sent in this tick into bundles, in order. A bundle is split
+/// where its serialized payloads would exceed the maximum packet size, the state sync envelopes
+/// and state update parts are sent on their own.
+fn bundle_outgoing<
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    postbox: &mut ServerPostBox<
+        ServerToClientMessage,
+        ClientToServerMessage,
+        ClientToServerCommand,
+    >,
+    max_packet_size: Option<usize>,
+) {
+    let mut sent = Vec::new();
+    let mut bundle = Vec::new();
+    let mut bundle_size = 0;
+
+    for message in postbox.drain_outgoing(|_| true) {
+        match message {
+            transport::ServerToClientMessage::Message(message)
+                if !matches!(message, ServerMessage::StateUpdatePart(_)) =>
+            {
+                let messages = match message {
+                    ServerMessage::Bundle(messages) => messages,
+                    message => vec![message],
+                };
+
+                for message in messages {
+                    let size = payload_size(&message);
+
+                    if max_packet_size.map_or(false, |max| bundle_size + size > max) {
+                        sent.extend(
+                            ServerMessage::bundle(mem::take(&mut bundle))
+                                .map(transport::ServerToClientMessage::Message),
+                        );
+                        bundle_size = 0;
+                    }
+
+                    bundle_size += size;
+                    bundle.push(message);
+                }
+            }
+            message => {
+                sent.extend(
+                    ServerMessage::bundle(mem::take(&mut bundle))
+                        .map(transport::ServerToClientMessage::Message),
+                );
+                bundle_size = 0;
+                sent.push(message);
+            }
+        }
+    }
+
+    sent.extend(ServerMessage::bundle(bundle).map(transport::ServerToClientMessage::Message));
+
+    for message in sent {
+        postbox.send(message);
+    }
+}
+
+/// Size of the serialized payload a message carries, the other messages are small.
+fn payload_size<M, C>(message: &ServerMessage<M, C>) -> usize {
+    match message {
+        ServerMessage::SerializedStateUpdate(bytes) => bytes.len(),
+        ServerMessage::ContextInitialSync(_, bytes) => bytes.len(),
+        _ => 0,
+    }
+}
+
+/// Moves the messages of the bundles a client sent back into its inbox, in order, where they are
+/// handled like the messages sent on their own.
+fn unbundle_inbox<
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    postbox: &mut ServerPostBox<
+        ServerToClientMessage,
+        ClientToServerMessage,
+        ClientToServerCommand,
+    >,
+) {
+    let bundles = postbox.drain_inbox(|message| {
+        matches!(
+            message,
+            transport::ClientToServerMessage::Message(ClientMessage::Bundle(_))
+                | transport::ClientToServerMessage::Message(ClientMessage::Command(..))
+        )
+    });
+
+    for message in bundles {
+        if let transport::ClientToServerMessage::Message(message) = message {
+            unbundle(postbox, message);
+        }
+    }
+}
+
+fn unbundle<
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    postbox: &mut ServerPostBox<
+        ServerToClientMessage,
+        ClientToServerMessage,
+        ClientToServerCommand,
+    >,
+    message: ClientMessage<ClientToServerMessage, ClientToServerCommand>,
+) {
+    match message {
+        ClientMessage::Bundle(messages) => {
+            for message in messages {
+                unbundle(postbox, message);
+            }
+        }
+        ClientMessage::Command(command_frame, command) => postbox.add_to_inbox(
+            transport::ClientToServerMessage::Command(command_frame, command),
+        ),
+        message => postbox.add_to_inbox(transport::ClientToServerMessage::Message(message)),
+    }
+}
+
+/// Remembers the command frame in which the client was sent the changes of the entities,
+/// removed entities are forgotten.
+fn record_sent(
+    last_updated: &mut HashMap<Uid, HashMap<ClientId, CommandFrame>>,
+    client: ClientId,
+    state: &WorldState,
+) {
+    for uid in changed_entities(state) {
+        last_updated
+            .entry(uid)
+            .or_default()
+            .insert(client, state.command_frame);
+    }
+
+    for uid in state.removed.iter() {
+        if let Some(clients) = last_updated.get_mut(uid) {
+            clients.remove(&client);
+
+            if clients.is_empty() {
+                last_updated.remove(uid);
+            }
+        }
+    }
+}
+
+/// Records the changes of the frame and the sizes of the serialized components.
+///
+/// Changed components are serialized as diffs, only inserted and added components are sizes.
+fn record_frame_stats(
+    metrics: &mut ServerMetrics,
+    registered: &RegisteredComponentsResource,
+    state: &WorldState,
+) {
+    let registry_by_uid = registered.by_uid();
+
+    let components = state
+        .inserted
+        .iter()
+        .flat_map(|inserted| inserted.components().iter())
+        .chain(state.component_added.iter().map(|added| added.component_data()));
+
+    for component in components {
+        if let Some(registration) = registry_by_uid.get(&component.component_id()) {
+            metrics.record_component_size(registration.type_name(), component.data().len());
+        }
+    }
+
+    metrics.record_frame_changes(
+        state.inserted.len()
+            + state.removed.len()
+            + state.component_added.len()
+            + state.component_removed.len()
+            + state.changed.len(),
+    );
+}
+
+/// The entities of which the state inserts or changes components.
+fn changed_entities(state: &WorldState) -> impl Iterator<Item = Uid> + '_ {
+    state
+        .inserted
+        .iter()
+        .map(|inserted| inserted.entity_id())
+        .chain(state.component_removed.iter().map(|x| x.entity_id()))
+        .chain(state.component_added.iter().map(|x| x.entity_id()))
+        .chain(state.changed.iter().map(|x| x.entity_id()))
+}
+
+/// A component of an entity that could not be serialized into a state update.
+pub(crate) struct SerializationFailure {
+    pub(crate) entity: Uid,
+    pub(crate) component: &'static str,
+    pub(crate) error: ErrorKind,
+}
+
+impl Display for SerializationFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Failed to serialize {} of entity {}: {}",
+            self.component, self.entity, self.error
+        )
+    }
+}
+
+/// Logs and reports the failure, the entity is quarantined with
+/// `ServerConfig::quarantine_failed_entities`.
+fn report_failure(
+    failure: SerializationFailure,
+    config: &ServerConfig,
+    quarantined: &mut HashSet<Uid>,
+    events: &mut ServerEvents,
+) {
+    log::error!("{}", failure);
+
+    if config.quarantine_failed_entities {
+        quarantined.insert(failure.entity);
+    }
+
+    events.push(ServerEvent::SerializationFailed {
+        entity: failure.entity,
+        component: failure.component,
+        error: failure.error.to_string(),
+    });
+}
+
+/// Serializes the world of an initial sync without the excluded entities, returns the bytes
+/// with the uids of the synced entities.
+///
+/// When the world fails to serialize, the entities with a failing component are left out like
+/// quarantined entities and returned as failures, the rest of the world is still synced.
+fn serialize_initial_sync_world(
+    world: &World,
+    components: &RegisteredComponentsResource,
+    serialization: &SerializationResource,
+    excluded: &HashSet<Uid>,
+) -> (
+    Result<(Vec<u8>, Vec<Uid>), ErrorKind>,
+    Vec<SerializationFailure>,
+) {
+    let (synced, uids) = world::initial_sync_world(world, components, excluded);
+    let error = match serialize_world(&synced, components, serialization) {
+        Ok(bytes) => return (Ok((bytes, uids)), Vec::new()),
+        Err(error) => error,
+    };
+
+    let failures = component_failures(&synced, components, serialization);
+    if failures.is_empty() {
+        return (Err(error), failures);
+    }
+
+    let mut excluded = excluded.clone();
+    excluded.extend(failures.iter().map(|failure| failure.entity));
+
+    let (synced, uids) = world::initial_sync_world(world, components, &excluded);
+    let bytes = serialize_world(&synced, components, serialization);
+    (bytes.map(|bytes| (bytes, uids)), failures)
+}
+
+/// Serializes the registered components of the legion world.
+fn serialize_world(
+    world: &World,
+    components: &RegisteredComponentsResource,
+    serialization: &SerializationResource,
+) -> Result<Vec<u8>, ErrorKind> {
+    serialization
+        .serialize(&world.as_serializable(components.filter(), components.legion_registry()))
+}
+
+/// Serializes the registered components of the replicated entities one by one, returns the
+/// ones that fail.
+fn component_failures(
+    world: &World,
+    components: &RegisteredComponentsResource,
+    serialization: &SerializationResource,
+) -> Vec<SerializationFailure> {
+    let mut failures = Vec::new();
+
+    for (entity, uid) in <(Entity, Read<UidComponent>)>::query().iter(world) {
+        for (component_uid, registration) in components.slice_with_uid().iter() {
+            let component = world::serialize_component(
+                world,
+                components,
+                serialization,
+                *entity,
+                *component_uid,
+            );
+
+            if let Some(Err(error)) = component {
+                failures.push(SerializationFailure {
+                    entity: uid.uid(),
+                    component: registration.type_name(),
+                    error,
+                });
+            }
+        }
+    }
+
+    failures
+}
+
+/// Translates the state for a client with an older schema version, see `ComponentVersions`.
+///
+/// Returns the state with the `SerializedStateCache` filter of the version of the client.
+fn downgrade_for(
+    client: ClientId,
+    state: WorldState,
+    versions: Option<&ComponentVersions>,
+    world: &World,
+    registered: &RegisteredComponentsResource,
+    serialization: &SerializationResource,
+) -> (WorldState, u64) {
+    let versions = match versions {
+        Some(versions) => versions,
+        None => return (state, SerializedStateCache::UNFILTERED),
+    };
+    let version = match versions.outdated(client) {
+        Some(version) => version,
+        None => return (state, SerializedStateCache::UNFILTERED),
+    };
+
+    let (state, errors) =
+        versions.downgrade_state(version, &state, world, registered, serialization);
+
+    for (uid, error) in errors {
+        log::error!("Failed to downgrade entity {}: {}", uid, error);
+    }
+
+    (state, ComponentVersions::cache_filter(version))
+}
+
+/// Replicates the owners that changed in the `PlayerOwnership` with the `Owner` component.
+/// Takes the connect and disconnect events of the transport, the other events stay queued.
+fn drain_transport_events(queue: &mut NetworkEventQueue) -> Vec<TransportEvent> {
+    let mut transport_events = Vec::new();
+
+    for event in queue.drain().collect::<Vec<_>>() {
+        match event {
+            NetworkEvent::Connected(client) => {
+                transport_events.push(TransportEvent::Connected(client))
+            }
+            NetworkEvent::Disconnected(client) => {
+                transport_events.push(TransportEvent::Disconnected(client))
+            }
+            event => queue.push(event),
+        }
+    }
+
+    transport_events
+}
+
+fn sync_owners(world: &mut World, ownership: &mut PlayerOwnership, tracker: &mut WorldTracker) {
+    for uid in ownership.drain_changes() {
+        let entity = match world::entity_by_uid(world, uid) {
+            Some(entity) => entity,
+            None => continue,
+        };
+
+        match ownership.owner(uid) {
+            Some((client, player)) => {
+                let owner = Owner::new(client, player);
+
+                if tracker
+                    .modify(world, entity, |current: &mut Owner| *current = owner)
+                    .is_none()
+                {
+                    if let Some(mut entry) = world.entry(entity) {
+                        entry.add_component(owner);
+                    }
+                }
+            }
+            None => {
+                if let Some(mut entry) = world.entry(entity) {
+                    entry.remove_component::<Owner>();
+                }
+            }
+        }
+    }
+}
+
+/// Restores the components of the entity to the snapshot, components that are not in the
+/// snapshot are removed.
+fn restore_components(
+    world: &mut World,
+    registered: &RegisteredComponentsResource,
+    serialization: &SerializationResource,
+    entity: Entity,
+    snapshot: &[ComponentData],
+    components: &HashSet<Uid>,
+) {
+    for (component_uid, registration) in registered.slice_with_uid().iter() {
+        if !components.contains(component_uid) {
+            continue;
+        }
+
+        match snapshot.iter().find(|x| x.component_id() == *component_uid) {
+            Some(component) => {
+                let restored = serialization.deserialize_with(component.data(), |data| {
+                    registration.add_component(world, entity, data)
+                });
+
+                if let Err(error) = restored {
+                    log::error!("Failed to restore {}: {}", registration.type_name(), error);
+                }
+            }
+            None => {
+                if registration.exists_in_world(world, entity) {
+                    registration.remove_component(world, entity);
+                }
+            }
+        }
+    }
+}
+
+// Handle the events from above merge operation.
+pub(crate) fn handle_world_events(
+    world: &World,
+    allocator: &mut UidAllocator<Entity>,
+    components: &RegisteredComponentsResource,
+    serialization: &SerializationResource,
+    event_resource: &EventResource,
+    world_state: &mut WorldState,
+    quarantined: &HashSet<Uid>,
+) -> Vec<SerializationFailure> {
+    let mut failures = Vec::new();
+    let mut event_handler = LegionEventHandler::new();
+
+    let events = event_handler.handle(&event_resource.legion_receiver(), world, &components);
+
+    for legion_event in events {
+        match legion_event {
+            LegionEvent::ComponentAdded(entity, _component_count) => {
+                let identifier = allocator.get(&entity);
+
+                if !quarantined.contains(&identifier) {
+                    world_state.add_component(identifier, ComponentData::new(0, vec![]))
+                }
+            }
+            LegionEvent::ComponentRemoved(entity, _component_count) => {
+                let identifier = allocator.get(&entity);
+
+                if !quarantined.contains(&identifier) {
+                    world_state.remove_component(identifier, 0);
+                }
+            }
+            LegionEvent::EntityRemoved(entity) => {
+                let identifier = allocator.get(&entity);
+                world_state.remove_entity(identifier);
+
+                // TODO?
+                // let identifier = allocator
+                //     .deallocate(to_remove)
+                //     .expect("Entity should be allocated.");
+            }
+            LegionEvent::EntityInserted(entity, _component_count) => {
+                let identifier = allocator.get(&entity);
+
+                let mut entity_components = Vec::new();
+
+                for component in components.slice_with_uid().iter() {
+                    component
+                        .1
+                        .serialize_if_exists_in_world(&world, entity, &mut |serialize| {
+                            match serialization.serialize(&serialize) {
+                                Ok(buffer) => entity_components
+                                    .push(ComponentData::new(component.0, buffer)),
+                                Err(error) => failures.push(SerializationFailure {
+                                    entity: identifier,
+                                    component: component.1.type_name(),
+                                    error,
+                                }),
+                            }
+                        });
+                }
+
+                world_state.insert_entity(identifier, entity_components);
+            }
+        }
+    }
+
+    failures
+}
+
+pub(crate) fn add_differences_to_state(
+    components: &RegisteredComponentsResource,
+    serialization: &SerializationResource,
+    world_state: &mut WorldState,
+    modification_buffer: &mut ModifiedComponentsBuffer,
+    tracked: &mut HashMap<(Uid, TypeId), Vec<u8>>,
+    world: &World,
+    allocator: &UidAllocator<Entity>,
+    quarantined: &HashSet<Uid>,
+    deferred: Option<&HashSet<TypeId>>,
+) -> Vec<SerializationFailure> {
+    let mut failures = Vec::new();
+    let entries = modification_buffer.drain_entries();
+
+    // Deferred components stay tracked, they are diffed once they are not deferred anymore.
+    let is_deferred = |component_type: &TypeId| {
+        deferred.map_or(false, |deferred| deferred.contains(component_type))
+    };
+    let (still_tracked, tracked_now) = mem::take(tracked)
+        .into_iter()
+        .partition::<HashMap<_, _>, _>(|((_, component_type), _)| is_deferred(component_type));
+    *tracked = still_tracked;
+
+    // Order the modifications by component type and then by their location in the archetypes,
+    // so every type is looked up once and the component storage is visited in order.
+    let mut modifications = Vec::new();
+
+    let mut push = |entity_id: Uid, component_type: TypeId, unchanged: Vec<u8>| {
+        let entity = *allocator.get_by_val(&entity_id);
+
+        if let Some(location) = world.entry_ref(entity).map(|entry| entry.location()) {
+            modifications.push((
+                component_type,
+                location.archetype(),
+                location.component(),
+                entity_id,
+                entity,
+                unchanged,
+            ));
+        }
+    };
+
+    for entry in entries {
+        for ((entity_id, component_type), unchanged) in entry.1 {
+            // The value tracked outside of the systems is older, its difference includes this one.
+            if quarantined.contains(&entity_id)
+                || tracked_now.contains_key(&(entity_id, component_type))
+            {
+                continue;
+            }
+
+            if is_deferred(&component_type) {
+                tracked.entry((entity_id, component_type)).or_insert(unchanged);
+                continue;
+            }
+
+            push(entity_id, component_type, unchanged);
+        }
+    }
+
+    for ((entity_id, component_type), unchanged) in tracked_now {
+        if !quarantined.contains(&entity_id) {
+            push(entity_id, component_type, unchanged);
+        }
+    }
+
+    modifications.sort_by_key(|(component_type, archetype, component, ..)| {
+        (*component_type, *archetype, *component)
+    });
+
+    let registrations = components.by_type_id();
+
+    for (component_type, modifications) in &modifications.into_iter().group_by(|m| m.0) {
+        let component_id = *components.get_uid(&component_type).expect("Should exist");
+        let registered_component = registrations.get(&component_type).expect("Should exist");
+
+        for (_, _, _, entity_id, entity, unchanged) in modifications {
+            // The unchanged value is recorded by the change tracker, in its bincode format.
+            let unchanged = &mut bincode::Deserializer::from_slice(
+                &unchanged,
+                bincode::DefaultOptions::new()
+                    .with_fixint_encoding()
+                    .allow_trailing_bytes(),
+            );
+
+            let mut changed = false;
+            let difference = serialization.serialize_with(|serializer| {
+                changed = registered_component.serialize_difference_with_current(
+                    world,
+                    entity,
+                    &mut erased_serde::Deserializer::erase(unchanged),
+                    serializer,
+                )?;
+                Ok(())
+            });
+
+            match difference {
+                Ok(buffer) if changed => {
+                    world_state.change(entity_id, ComponentData::new(component_id, buffer))
+                }
+                Ok(_) => {}
+                Err(error) => failures.push(SerializationFailure {
+                    entity: entity_id,
+                    component: registered_component.type_name(),
+                    error,
+                }),
+            }
+        }
+    }
+
+    failures
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::net::TcpStream;
+
+    use serde::{Deserialize, Serialize};
+
+    use net_sync::{
+        synchronisation::{NetworkCommand, NetworkMessage},
+        transport,
+    };
+
+    use crate::{
+        protocol::ServerMessage,
+        world::{
+            server::{ServerPostOffice, ServerWorldBuilder},
+            WorldBuilder,
+        },
+    };
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    struct TestMessage;
+
+    impl NetworkMessage for TestMessage {}
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    struct TestCommand;
+
+    impl NetworkCommand for TestCommand {}
+
+    type TestPostOffice = ServerPostOffice<TestMessage, TestMessage, TestCommand>;
+
+    #[test]
+    fn closed_listener_leaves_connections_queued_test() {
+        let mut server = ServerWorldBuilder::<TestMessage, TestMessage, TestCommand>::default()
+            .with_tcp_addr("127.0.0.1:0".parse().unwrap())
+            .build()
+            .unwrap();
+
+        assert!(server.close_listener());
+        assert!(!server.close_listener());
+
+        // The loopback connection is queued by the operating system once `connect` returns.
+        let addr = server.listener_addr().unwrap();
+        let _client = TcpStream::connect(addr).unwrap();
+        server.tick();
+        assert_eq!(server.world_stats().clients, 0);
+
+        assert!(server.open_listener());
+        server.tick();
+        assert_eq!(server.world_stats().clients, 1);
+    }
+    #[test]
+    fn migration_needs_the_session_token_test() {
+        let mut server = ServerWorldBuilder::<TestMessage, TestMessage, TestCommand>::default()
+            .with_custom_transport()
+            .build()
+            .unwrap();
+
+        let (from, to) = {
+            let mut postoffice = server.resources_mut().get_mut::<TestPostOffice>().unwrap();
+            let from = postoffice.add_client();
+            let to = postoffice.add_client();
+
+            let (_, connection) = postoffice.clients_mut().find(|x| *x.0 == from).unwrap();
+            let message = transport::ServerToClientMessage::Message(ServerMessage::Resync(0));
+            connection.postbox_mut().send(message);
+            (from, to)
+        };
+        let token = server.session_token(from);
+
+        assert!(!server.migrate_client(from, to, token.wrapping_add(1)));
+        assert!(server.migrate_client(from, to, token));
+        assert!(!server.migrate_client(from, to, token));
+
+        // The queued message of the old connection is sent over the new one.
+        let mut postoffice = server.resources_mut().get_mut::<TestPostOffice>().unwrap();
+        assert!(postoffice.clients().all(|(client, _)| *client != from));
+        let (_, connection) = postoffice.clients_mut().find(|x| *x.0 == to).unwrap();
+        assert_eq!(connection.postbox_mut().drain_outgoing(|_| true).len(), 1);
     }
 }
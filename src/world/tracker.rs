@@ -0,0 +1,146 @@
+use std::{any::TypeId, collections::HashMap};
+
+use legion::{storage::Component, Entity, World};
+
+use net_sync::uid::Uid;
+
+use crate::{
+    resources::RegisteredComponentsResource,
+    tracking::re_exports::bincode,
+    world::{self, default_options},
+};
+
+/// Tracks the components game code modifies outside of systems, see `ServerWorld::modify`.
+///
+/// The value of a component is serialized before its first modification, the next state update
+/// sends the difference with its value at that time.
+pub struct WorldTracker<'a> {
+    registered: &'a RegisteredComponentsResource,
+    modifications: &'a mut HashMap<(Uid, TypeId), Vec<u8>>,
+}
+
+impl<'a> WorldTracker<'a> {
+    pub(crate) fn new(
+        registered: &'a RegisteredComponentsResource,
+        modifications: &'a mut HashMap<(Uid, TypeId), Vec<u8>>,
+    ) -> WorldTracker<'a> {
+        WorldTracker {
+            registered,
+            modifications,
+        }
+    }
+
+    /// Records the current value of the component, call it before the component is modified.
+    ///
+    /// Returns `false` if the entity is not replicated, does not have the component
+    /// or the component type is not registered.
+    pub fn track<T: Component>(&mut self, world: &World, entity: Entity) -> bool {
+        self.track_type(world, entity, TypeId::of::<T>())
+    }
+
+    /// Tracks the component with the given type id, see `track`.
+    pub(crate) fn track_type(&mut self, world: &World, entity: Entity, ty: TypeId) -> bool {
+        let uid = match world::uid_of(world, entity) {
+            Some(uid) => uid,
+            None => return false,
+        };
+        let key = (uid, ty);
+
+        if self.modifications.contains_key(&key) {
+            return true;
+        }
+
+        let registry_by_type = self.registered.by_type_id();
+        let registration = match registry_by_type.get(&key.1) {
+            Some(registration) => registration,
+            None => return false,
+        };
+
+        let mut unchanged = None;
+        registration.serialize_if_exists_in_world(world, entity, &mut |serialize| {
+            let mut buffer = Vec::new();
+            let serializer = &mut bincode::Serializer::new(&mut buffer, default_options());
+
+            if erased_serde::serialize(&serialize, serializer).is_ok() {
+                unchanged = Some(buffer);
+            }
+        });
+
+        match unchanged {
+            Some(unchanged) => {
+                self.modifications.insert(key, unchanged);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Tracks the component and modifies it, `None` if the entity does not have the component.
+    pub fn modify<T: Component, R>(
+        &mut self,
+        world: &mut World,
+        entity: Entity,
+        modify: impl FnOnce(&mut T) -> R,
+    ) -> Option<R> {
+        self.track::<T>(world, entity);
+
+        let mut entry = world.entry(entity)?;
+        let component = entry.get_component_mut::<T>().ok()?;
+        Some(modify(component))
+    }
+
+    /// The number of components modified since the last state update.
+    pub fn len(&self) -> usize {
+        self.modifications.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.modifications.is_empty()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::{any::TypeId, collections::HashMap};
+
+    use legion::World;
+
+    use crate::{
+        components::{DynamicComponent, UidComponent},
+        resources::RegisteredComponentsResource,
+        tracking::re_exports::bincode,
+        world::{default_options, tracker::WorldTracker},
+    };
+    use bincode::Options;
+
+    #[test]
+    fn first_value_is_kept_test() {
+        let registered = RegisteredComponentsResource::new();
+        let mut modifications = HashMap::new();
+        let mut world = World::default();
+        let entity = world.push((
+            UidComponent::new(1),
+            DynamicComponent::new("health", serde_json::json!(10)),
+        ));
+        let untracked = world.push((DynamicComponent::default(),));
+
+        let mut tracker = WorldTracker::new(&registered, &mut modifications);
+
+        for health in [5, 3].iter() {
+            tracker.modify(&mut world, entity, |component: &mut DynamicComponent| {
+                *component.value_mut() = serde_json::json!(health);
+            });
+        }
+        assert!(!tracker.track::<DynamicComponent>(&world, untracked));
+        assert_eq!(tracker.len(), 1);
+
+        let unchanged = &modifications[&(1, TypeId::of::<DynamicComponent>())];
+        assert_eq!(
+            default_options()
+                .deserialize::<DynamicComponent>(unchanged)
+                .unwrap()
+                .value(),
+            &serde_json::json!(10)
+        );
+    }
+}